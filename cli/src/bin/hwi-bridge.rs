@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::io::{stdin, stdout};
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+
+use async_hwi::ledger::{serve_bridge, Duplex, TransportHID};
+use clap::Parser;
+
+/// Agent forwarding the commands of a remote wallet to the Ledger plugged into this machine.
+/// Without port the commands are read from stdin, for `ssh <host> hwi-bridge`.
+///
+/// The agent does not authenticate the hosts and forwards any command to the device.
+/// It listens on the loopback interface, remote hosts reach it through an ssh tunnel:
+/// `ssh -L 9999:127.0.0.1:9999 <host> hwi-bridge --listen 9999`.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Port to listen on, one host is served at a time.
+    #[arg(long, value_name = "PORT")]
+    listen: Option<u16>,
+    /// Interface to listen on. Any other than the loopback exposes the device
+    /// to the network without authentication.
+    #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    bind: IpAddr,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let ctx = async_hwi::init()?;
+    if let Some(port) = args.listen {
+        let listener = TcpListener::bind((args.bind, port))?;
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+            let transport = match TransportHID::open(&ctx) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = serve_bridge(&mut stream, &transport) {
+                eprintln!("{}", e);
+            }
+        }
+        Ok(())
+    } else {
+        let transport = TransportHID::open(&ctx)?;
+        let mut stream = Duplex {
+            reader: stdin().lock(),
+            writer: stdout().lock(),
+        };
+        serve_bridge(&mut stream, &transport)
+    }
+}
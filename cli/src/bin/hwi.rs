@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use async_hwi::{AddressScript, DeviceKind};
-use async_hwi_cli::command;
+use bp_hwi_cli::command;
 
 use bitcoin::{
     bip32::{DerivationPath, Fingerprint},
@@ -32,6 +32,8 @@ enum Commands {
     Device(DeviceCommands),
     #[command(subcommand)]
     Psbt(PsbtCommands),
+    /// Print the JSON schema of the serialized types.
+    Schema,
     #[command(subcommand)]
     Wallet(WalletCommands),
     #[command(subcommand)]
@@ -50,7 +52,16 @@ enum AddressCommands {
         #[arg(long)]
         hmac: Option<String>,
         #[arg(long, value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
+        p2pkh: Option<DerivationPath>,
+        #[arg(long, value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
+        p2sh_wpkh: Option<DerivationPath>,
+        #[arg(long, value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
+        p2wpkh: Option<DerivationPath>,
+        #[arg(long, value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
         p2tr: Option<DerivationPath>,
+        /// Get the address without displaying it on the device.
+        #[arg(long)]
+        no_display: bool,
     },
 }
 
@@ -97,92 +108,118 @@ enum XpubCommands {
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
+    let ctx = async_hwi::init()?;
     match args.command {
         Commands::Address(AddressCommands::Display {
             index,
             wallet_name,
             wallet_policy,
             hmac,
+            p2pkh,
+            p2sh_wpkh,
+            p2wpkh,
             p2tr,
+            no_display,
         }) => {
             if let Some(policy) = wallet_policy {
                 for device in command::list(
+                    &ctx,
                     args.network,
                     Some(command::Wallet {
                         name: wallet_name.as_ref(),
                         policy: Some(&policy),
                         hmac: hmac.as_ref(),
                     }),
-                )
-                .await?
-                {
+                )? {
                     if let Some(fg) = args.fingerprint {
-                        if fg != device.get_master_fingerprint().await? {
+                        if fg != device.get_master_fingerprint()? {
                             continue;
                         }
                     }
-                    device
-                        .display_address(&AddressScript::Miniscript {
+                    let address = device.get_address(
+                        &AddressScript::Miniscript {
                             index: index.expect("Must be present"),
                             change: false,
-                        })
-                        .await?;
+                        },
+                        !no_display,
+                    )?;
+                    eprintln!(
+                        "{}",
+                        async_hwi::utils::check_address_network(address, args.network)?
+                            .assume_checked()
+                    );
                     break;
                 }
-            } else if let Some(path) = p2tr {
-                for device in command::list(args.network, None).await? {
+            } else if let Some(script) = p2pkh
+                .map(AddressScript::P2PKH)
+                .or(p2sh_wpkh.map(AddressScript::P2SHWPKH))
+                .or(p2wpkh.map(AddressScript::P2WPKH))
+                .or(p2tr.map(AddressScript::P2TR))
+            {
+                for device in command::list(&ctx, args.network, None)? {
                     {
                         if let Some(fg) = args.fingerprint {
-                            if fg != device.get_master_fingerprint().await? {
+                            if fg != device.get_master_fingerprint()? {
                                 continue;
                             }
                         }
-                        device.display_address(&AddressScript::P2TR(path)).await?;
+                        let address = device.get_address(&script, !no_display)?;
+                        eprintln!(
+                            "{}",
+                            async_hwi::utils::check_address_network(address, args.network)?
+                                .assume_checked()
+                        );
                         break;
                     }
                 }
             }
         }
+        Commands::Schema => {
+            println!("{}", async_hwi::schema::JSON_SCHEMA);
+        }
         Commands::Device(DeviceCommands::List) => {
-            for device in command::list(args.network, None).await? {
-                eprint!("{}", device.get_master_fingerprint().await?);
+            for device in command::list(&ctx, args.network, None)? {
+                eprint!("{}", device.get_master_fingerprint()?);
                 eprint!(" {}", device.device_kind());
-                if let Ok(version) = device.get_version().await.map(|v| v.to_string()) {
+                if let Ok(version) = device.get_version().map(|v| v.to_string()) {
                     eprint!(" {}", version);
                 }
                 eprintln!();
             }
         }
         Commands::Xpub(XpubCommands::Get { path }) => {
-            for device in command::list(args.network, None).await? {
+            for device in command::list(&ctx, args.network, None)? {
                 if let Some(fg) = args.fingerprint {
-                    if fg != device.get_master_fingerprint().await? {
+                    if fg != device.get_master_fingerprint()? {
                         continue;
                     }
                 }
-                eprintln!("{}", device.get_extended_pubkey(&path).await?);
+                eprintln!("{}", device.get_extended_pubkey(&path)?);
             }
         }
         Commands::Wallet(WalletCommands::Register { name, policy }) => {
-            for device in command::list(args.network, None).await? {
+            for device in command::list(&ctx, args.network, None)? {
                 if let Some(fg) = args.fingerprint {
-                    if fg != device.get_master_fingerprint().await? {
+                    if fg != device.get_master_fingerprint()? {
                         continue;
                     }
                 }
 
-                if let Some(hmac) = device.register_wallet(&name, &policy).await? {
+                let registration = device.register_wallet(&name, &policy)?;
+                if let Some(hmac) = registration.hmac {
                     eprintln!("{}", hex::encode(hmac));
                 }
+                if let Some(id) = registration.id {
+                    eprintln!("id: {}", hex::encode(id));
+                }
             }
         }
         Commands::Wallet(WalletCommands::IsRegistered { name, policy }) => {
-            for device in command::list(args.network, None).await? {
+            for device in command::list(&ctx, args.network, None)? {
                 if let Some(fg) = args.fingerprint {
-                    if fg != device.get_master_fingerprint().await? {
+                    if fg != device.get_master_fingerprint()? {
                         continue;
                     }
                 }
@@ -190,10 +227,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     DeviceKind::Ledger
                     | DeviceKind::LedgerSimulator
                     | DeviceKind::Coldcard
-                    | DeviceKind::Jade => (name.clone().expect("name is required"), policy.clone()),
+                    | DeviceKind::Jade
+                    | DeviceKind::JadeSimulator => {
+                        (name.clone().expect("name is required"), policy.clone())
+                    }
                     _ => ("".into(), policy.clone()),
                 };
-                let res = device.is_wallet_registered(&name, &policy).await?;
+                let res = device.is_wallet_registered(&name, &policy)?;
                 eprintln!("{}", res);
             }
         }
@@ -204,21 +244,26 @@ async fn main() -> Result<(), Box<dyn Error>> {
             hmac,
         }) => {
             for device in command::list(
+                &ctx,
                 args.network,
                 Some(command::Wallet {
                     name: wallet_name.as_ref(),
                     policy: wallet_policy.as_ref(),
                     hmac: hmac.as_ref(),
                 }),
-            )
-            .await?
-            {
+            )? {
                 if let Some(fg) = args.fingerprint {
-                    if fg != device.get_master_fingerprint().await? {
+                    if fg != device.get_master_fingerprint()? {
                         continue;
                     }
                 }
-                device.sign_tx(&mut psbt).await?;
+                let res = device.sign_tx(&mut psbt)?;
+                for warning in res.warnings {
+                    eprintln!("warning: {:?}", warning);
+                }
+                for signed in res.signed_inputs {
+                    eprintln!("signed input {}: {:?}", signed.input, signed.key);
+                }
                 eprintln!("{}", psbt);
             }
         }
@@ -2,10 +2,10 @@ pub mod command {
     use async_hwi::{
         bitbox::{api::runtime, BitBox02, PairingBitbox02WithLocalCache},
         coldcard,
-        jade::{self, Jade},
-        ledger::{HidApi, Ledger, LedgerSimulator, TransportHID},
+        jade::{self, Jade, JadeSimulator},
+        ledger::{Ledger, LedgerSimulator, TransportHID},
         specter::{Specter, SpecterSimulator},
-        HWI,
+        Context, HWI,
     };
     use bitcoin::{hashes::hex::FromHex, Network};
     use std::error::Error;
@@ -16,30 +16,35 @@ pub mod command {
         pub hmac: Option<&'a String>,
     }
 
-    pub async fn list(
+    pub fn list(
+        ctx: &Context,
         network: Network,
         wallet: Option<Wallet<'_>>,
     ) -> Result<Vec<Box<dyn HWI + Send>>, Box<dyn Error>> {
         let mut hws = Vec::new();
 
-        if let Ok(device) = SpecterSimulator::try_connect().await {
+        if let Ok(device) = SpecterSimulator::try_connect() {
             hws.push(device.into());
         }
 
-        if let Ok(devices) = Specter::enumerate().await {
+        if let Ok(devices) = Specter::enumerate() {
             for device in devices {
                 hws.push(device.into());
             }
         }
 
-        match Jade::enumerate().await {
+        if let Ok(device) = JadeSimulator::try_connect(None) {
+            hws.push(device.with_network(network).into());
+        }
+
+        match Jade::enumerate() {
             Err(e) => println!("{:?}", e),
             Ok(devices) => {
                 for device in devices {
-                    let device = device.with_network(network);
-                    if let Ok(info) = device.get_info().await {
+                    let device = device.with_network(network).with_context(ctx);
+                    if let Ok(info) = device.get_info() {
                         if info.jade_state == jade::api::JadeState::Locked {
-                            if let Err(e) = device.auth().await {
+                            if let Err(e) = device.auth() {
                                 eprintln!("auth {:?}", e);
                                 continue;
                             }
@@ -51,24 +56,26 @@ pub mod command {
             }
         }
 
-        if let Ok(device) = LedgerSimulator::try_connect().await {
-            hws.push(device.into());
+        if let Ok(device) = LedgerSimulator::try_connect() {
+            hws.push(device.with_network(network).into());
         }
 
-        let api = Box::new(HidApi::new().unwrap());
-
-        for device_info in api.device_list() {
-            if async_hwi::bitbox::is_bitbox02(device_info) {
-                if let Ok(device) = device_info.open_device(&api) {
+        for device_info in ctx.hid_devices()? {
+            if async_hwi::bitbox::is_bitbox02(&device_info) {
+                let device = device_info.open_device(&*ctx.hid_api()?);
+                if let Ok(device) = device {
                     if let Ok(device) =
-                        PairingBitbox02WithLocalCache::<runtime::TokioRuntime>::connect(
+                        PairingBitbox02WithLocalCache::<runtime::DefaultRuntime>::connect(
                             device, None,
                         )
-                        .await
                     {
-                        if let Ok((device, _)) = device.wait_confirm().await {
+                        let paired = device.wait_confirm_with(|code| {
+                            eprintln!("BitBox02 pairing code, confirm it on the device:");
+                            eprintln!("{}", code);
+                        });
+                        if let Ok((device, _)) = paired {
                             let mut bb02 = BitBox02::from(device).with_network(network);
-                            if let Some(ref policy) = wallet.as_ref().map(|w| w.policy).flatten() {
+                            if let Some(policy) = wallet.as_ref().and_then(|w| w.policy) {
                                 bb02 = bb02.with_policy(policy)?;
                             }
                             hws.push(bb02.into());
@@ -80,8 +87,9 @@ pub mod command {
                 && device_info.product_id() == coldcard::api::CKCC_PID
             {
                 if let Some(sn) = device_info.serial_number() {
-                    if let Ok((cc, _)) = coldcard::api::Coldcard::open(&api, sn, None) {
-                        let mut hw = coldcard::Coldcard::from(cc);
+                    let cc = coldcard::api::Api::from_borrowed(&mut *ctx.hid_api()?).open(sn, None);
+                    if let Ok((cc, _)) = cc {
+                        let mut hw = coldcard::Coldcard::from(cc).with_network(network);
                         if let Some(ref wallet) = wallet {
                             hw = hw.with_wallet_name(
                                 wallet
@@ -98,8 +106,9 @@ pub mod command {
             }
         }
 
-        for detected in Ledger::<TransportHID>::enumerate(&api) {
-            if let Ok(mut device) = Ledger::<TransportHID>::connect(&api, detected) {
+        for detected in Ledger::<TransportHID>::enumerate_with(ctx)? {
+            if let Ok(device) = Ledger::<TransportHID>::connect(ctx, &detected) {
+                let mut device = device.with_auto_reconnect().with_network(network);
                 if let Some(ref wallet) = wallet {
                     let hmac = if let Some(s) = wallet.hmac {
                         let mut h = [b'\0'; 32];
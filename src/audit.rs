@@ -0,0 +1,138 @@
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+
+use crate::{Error, HWI};
+
+/// Difference between a device and the first one of the audit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Discrepancy {
+    /// The device has another master fingerprint, it does not hold the same seed.
+    Fingerprint {
+        device: usize,
+        expected: Fingerprint,
+        found: Fingerprint,
+    },
+    /// The device derives another xpub at the path, for example with a passphrase.
+    Xpub {
+        device: usize,
+        path: DerivationPath,
+        expected: Box<Xpub>,
+        found: Box<Xpub>,
+    },
+}
+
+/// Result of compare_devices, the expected values are the ones of the first device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditReport {
+    pub fingerprint: Fingerprint,
+    pub xpubs: Vec<(DerivationPath, Xpub)>,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl AuditReport {
+    /// True if all the devices hold the same seed for the audited paths.
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Fetches the xpubs of the paths from the devices claiming the same seed, like a primary
+/// device and its backup, and reports the ones differing from the first device.
+/// The network of the xpubs is not compared, only the derived keys.
+/// An error of a device, for example if it is locked, stops the audit.
+pub fn compare_devices<T: HWI>(
+    devices: &[T],
+    paths: &[DerivationPath],
+) -> Result<AuditReport, Error> {
+    let (first, others) = devices
+        .split_first()
+        .ok_or_else(|| Error::InvalidParameter("devices", "no device to compare".to_string()))?;
+    let mut report = AuditReport {
+        fingerprint: first.get_master_fingerprint()?,
+        xpubs: paths
+            .iter()
+            .map(|path| Ok((path.clone(), first.get_extended_pubkey(path)?)))
+            .collect::<Result<_, Error>>()?,
+        discrepancies: Vec::new(),
+    };
+    for (i, device) in others.iter().enumerate() {
+        let fingerprint = device.get_master_fingerprint()?;
+        if fingerprint != report.fingerprint {
+            report.discrepancies.push(Discrepancy::Fingerprint {
+                device: i + 1,
+                expected: report.fingerprint,
+                found: fingerprint,
+            });
+        }
+        for (path, expected) in &report.xpubs {
+            let found = device.get_extended_pubkey(path)?;
+            if !same_key(expected, &found) {
+                report.discrepancies.push(Discrepancy::Xpub {
+                    device: i + 1,
+                    path: path.clone(),
+                    expected: Box::new(*expected),
+                    found: Box::new(found),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn same_key(a: &Xpub, b: &Xpub) -> bool {
+    Xpub {
+        network: b.network,
+        ..*a
+    } == *b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockDevice, DeviceKind};
+    use bitcoin::{bip32::Xpriv, Network};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_compare_devices() {
+        let seed = |network, byte| {
+            MockDevice::with_seed(
+                DeviceKind::Jade,
+                Xpriv::new_master(network, &[byte; 32]).unwrap(),
+            )
+        };
+        let paths = [
+            DerivationPath::from_str("m/84'/1'/0'").unwrap(),
+            DerivationPath::from_str("m/48'/1'/0'/2'").unwrap(),
+        ];
+
+        // The backup is set on another network, its xpubs have another version.
+        let report = compare_devices(
+            &[seed(Network::Testnet, 1), seed(Network::Bitcoin, 1)],
+            &paths,
+        )
+        .unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.xpubs.len(), 2);
+
+        let devices = [
+            seed(Network::Testnet, 1),
+            seed(Network::Testnet, 1),
+            seed(Network::Testnet, 2),
+        ];
+        let report = compare_devices(&devices, &paths).unwrap();
+        assert!(!report.is_consistent());
+        assert_eq!(report.discrepancies.len(), 3);
+        assert!(matches!(
+            report.discrepancies[0],
+            Discrepancy::Fingerprint { device: 2, .. }
+        ));
+        assert!(matches!(
+            &report.discrepancies[2],
+            Discrepancy::Xpub { device: 2, path, .. } if *path == paths[1]
+        ));
+
+        assert!(compare_devices::<MockDevice>(&[], &paths).is_err());
+    }
+}
@@ -9,6 +9,7 @@ pub enum Wildcard {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ParseError(&'static str);
 
 impl std::fmt::Display for ParseError {
@@ -0,0 +1,120 @@
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Xpriv},
+    hashes::{hmac, sha512, Hash, HashEngine},
+    secp256k1::{Secp256k1, Signing},
+};
+
+use crate::Error;
+
+/// See https://github.com/bitcoin/bips/blob/master/bip-0085.mediawiki
+pub const PURPOSE: u32 = 83696968;
+
+/// Application of the entropy derived with BIP85.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Application {
+    /// BIP39 mnemonic with the BIP85 language code and the number of words.
+    Bip39 { language: u32, words: u32 },
+    /// HD seed encoded in the wallet import format.
+    HdSeedWif,
+    /// Chain code followed by the private key of an extended private key.
+    Xprv,
+    /// Raw entropy of 16 to 64 bytes.
+    Hex { num_bytes: u32 },
+}
+
+impl Application {
+    /// Derivation path of the application entropy for the given index.
+    pub fn derivation_path(&self, index: u32) -> Result<DerivationPath, Error> {
+        let indexes = match self {
+            Self::Bip39 { language, words } => vec![39, *language, *words, index],
+            Self::HdSeedWif => vec![2, index],
+            Self::Xprv => vec![32, index],
+            Self::Hex { num_bytes } => vec![128169, *num_bytes, index],
+        };
+        std::iter::once(PURPOSE)
+            .chain(indexes)
+            .map(|i| {
+                ChildNumber::from_hardened_idx(i)
+                    .map_err(|e| Error::InvalidParameter("index", e.to_string()))
+            })
+            .collect()
+    }
+
+    /// Number of bytes of entropy used by the application.
+    pub fn entropy_len(&self) -> Result<usize, Error> {
+        match self {
+            Self::Bip39 { words: 12, .. } => Ok(16),
+            Self::Bip39 { words: 18, .. } => Ok(24),
+            Self::Bip39 { words: 24, .. } => Ok(32),
+            Self::Bip39 { words, .. } => Err(Error::InvalidParameter(
+                "words",
+                format!("{} words mnemonic is not supported", words),
+            )),
+            Self::HdSeedWif => Ok(32),
+            Self::Xprv => Ok(64),
+            Self::Hex { num_bytes } if (16..=64).contains(num_bytes) => Ok(*num_bytes as usize),
+            Self::Hex { num_bytes } => Err(Error::InvalidParameter(
+                "num_bytes",
+                format!("{} is not between 16 and 64", num_bytes),
+            )),
+        }
+    }
+}
+
+/// Derives the application entropy from the master private key.
+pub fn derive_entropy<C: Signing>(
+    secp: &Secp256k1<C>,
+    master: &Xpriv,
+    application: &Application,
+    index: u32,
+) -> Result<Vec<u8>, Error> {
+    let path = application.derivation_path(index)?;
+    let len = application.entropy_len()?;
+    let key = master
+        .derive_priv(secp, &path)
+        .map_err(|e| Error::InvalidParameter("master", e.to_string()))?;
+    let mut engine = hmac::HmacEngine::<sha512::Hash>::new(b"bip-entropy-from-k");
+    engine.input(&key.private_key.secret_bytes());
+    let entropy = hmac::Hmac::<sha512::Hash>::from_engine(engine);
+    Ok(entropy[..len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_derive_entropy() {
+        let secp = Secp256k1::signing_only();
+        let master = Xpriv::from_str("xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb").unwrap();
+
+        let application = Application::Bip39 {
+            language: 0,
+            words: 12,
+        };
+        assert_eq!(
+            application.derivation_path(0).unwrap(),
+            DerivationPath::from_str("m/83696968'/39'/0'/12'/0'").unwrap()
+        );
+        assert_eq!(
+            derive_entropy(&secp, &master, &application, 0).unwrap(),
+            Vec::<u8>::from_hex("6250b68daf746d12a24d58b4787a714b").unwrap()
+        );
+
+        let application = Application::Hex { num_bytes: 64 };
+        assert_eq!(
+            derive_entropy(&secp, &master, &application, 0).unwrap(),
+            Vec::<u8>::from_hex("492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f878555d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c").unwrap()
+        );
+
+        assert!(Application::Bip39 {
+            language: 0,
+            words: 13
+        }
+        .entropy_len()
+        .is_err());
+        assert!(Application::Hex { num_bytes: 65 }.entropy_len().is_err());
+    }
+}
@@ -1,6 +1,9 @@
-use crate::{bip389, parse_version, AddressScript, DeviceKind, Error as HWIError, HWI};
+use crate::{
+    bip389, blocking::block_on, context::open_error, management::Management, parse_version, policy,
+    utils, AddressScript, Context, DeviceDetails, DeviceKind, DeviceModel, Error as HWIError,
+    SignResult, TransportErrorKind, WalletRegistration, HWI,
+};
 use api::btc::make_script_config_simple;
-use async_trait::async_trait;
 use bitbox_api::{
     btc::KeyOriginInfo,
     error::{BitBoxError, Error},
@@ -9,8 +12,10 @@ use bitbox_api::{
     Keypath, PairedBitBox, PairingBitBox,
 };
 use bitcoin::{
+    address::NetworkUnchecked,
     bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
     psbt::Psbt,
+    Address,
 };
 use regex::Regex;
 use std::{
@@ -25,6 +30,34 @@ pub use bitbox_api::{
     ConfigError, NoiseConfig, NoiseConfigData, NoiseConfigNoCache,
 };
 
+/// Details of a BitBox02 found by the HID enumeration, see is_bitbox02.
+/// The product name tells the edition: "BitBox02BTC" for the Bitcoin-only one.
+pub fn details(info: &hidapi::DeviceInfo) -> DeviceDetails {
+    let model = info.product_string().map(|product| {
+        if product.contains("BTC") {
+            DeviceModel::BitBox02BtcOnly
+        } else {
+            DeviceModel::BitBox02Multi
+        }
+    });
+    DeviceDetails::from_hid(DeviceKind::BitBox02, model, info)
+}
+
+/// Pairs the BitBox02 at the HID path, the first one found if no path is given.
+/// The pairing is not kept, see PairingBitbox02WithLocalCache to persist it.
+pub fn connect_hid(
+    ctx: &Context,
+    path: Option<&str>,
+    on_pairing_code: impl FnOnce(&str),
+) -> Result<BitBox02<api::runtime::DefaultRuntime>, HWIError> {
+    let info = ctx.hid_device(path, is_bitbox02)?;
+    let device = info
+        .open_device(&*ctx.hid_api()?)
+        .map_err(|_| open_error(info.path()))?;
+    let client = PairingBitbox02::connect(device, None)?.wait_confirm_with(on_pairing_code)?;
+    Ok(BitBox02::from(client))
+}
+
 #[derive(Clone)]
 struct Cache(Arc<Mutex<Option<NoiseConfigData>>>);
 
@@ -52,7 +85,7 @@ pub struct PairingBitbox02WithLocalCache<T: Runtime> {
 }
 
 impl<T: Runtime> PairingBitbox02WithLocalCache<T> {
-    pub async fn connect(
+    pub fn connect(
         device: hidapi::HidDevice,
         pairing_data: Option<NoiseConfigData>,
     ) -> Result<Self, HWIError> {
@@ -61,9 +94,11 @@ impl<T: Runtime> PairingBitbox02WithLocalCache<T> {
         } else {
             Cache(Arc::new(Mutex::new(None)))
         };
-        let bitbox =
-            bitbox_api::BitBox::<T>::from_hid_device(device, Box::new(local_cache.clone())).await?;
-        let pairing_bitbox = bitbox.unlock_and_pair().await?;
+        let bitbox = block_on(bitbox_api::BitBox::<T>::from_hid_device(
+            device,
+            Box::new(local_cache.clone()),
+        ))?;
+        let pairing_bitbox = block_on(bitbox.unlock_and_pair())?;
         Ok(PairingBitbox02WithLocalCache {
             client: pairing_bitbox,
             local_cache,
@@ -74,8 +109,21 @@ impl<T: Runtime> PairingBitbox02WithLocalCache<T> {
         self.client.get_pairing_code()
     }
 
-    pub async fn wait_confirm(self) -> Result<(PairedBitBox<T>, NoiseConfigData), HWIError> {
-        let client = self.client.wait_confirm().await?;
+    /// Gives the pairing code to the host, which shows it to the user to compare it with
+    /// the code displayed by the device, then waits for the confirmation on the device.
+    /// The callback is not called if the device was already paired.
+    pub fn wait_confirm_with(
+        self,
+        on_pairing_code: impl FnOnce(&str),
+    ) -> Result<(PairedBitBox<T>, NoiseConfigData), HWIError> {
+        if let Some(code) = self.pairing_code() {
+            on_pairing_code(&code);
+        }
+        self.wait_confirm()
+    }
+
+    pub fn wait_confirm(self) -> Result<(PairedBitBox<T>, NoiseConfigData), HWIError> {
+        let client = block_on(self.client.wait_confirm())?;
         let mut cache = self
             .local_cache
             .0
@@ -95,13 +143,16 @@ pub struct PairingBitbox02<T: Runtime> {
 }
 
 impl<T: Runtime> PairingBitbox02<T> {
-    pub async fn connect(
+    pub fn connect(
         device: hidapi::HidDevice,
         pairing: Option<Box<dyn NoiseConfig>>,
     ) -> Result<Self, HWIError> {
         let noise_config = pairing.unwrap_or_else(|| Box::new(NoiseConfigNoCache {}));
-        let bitbox = bitbox_api::BitBox::<T>::from_hid_device(device, noise_config).await?;
-        let pairing_bitbox = bitbox.unlock_and_pair().await?;
+        let bitbox = block_on(bitbox_api::BitBox::<T>::from_hid_device(
+            device,
+            noise_config,
+        ))?;
+        let pairing_bitbox = block_on(bitbox.unlock_and_pair())?;
         Ok(PairingBitbox02 {
             client: pairing_bitbox,
         })
@@ -111,11 +162,23 @@ impl<T: Runtime> PairingBitbox02<T> {
         self.client.get_pairing_code()
     }
 
-    pub async fn wait_confirm(self) -> Result<PairedBitBox<T>, HWIError> {
-        self.client.wait_confirm().await.map_err(|e| e.into())
+    /// See PairingBitbox02WithLocalCache::wait_confirm_with.
+    pub fn wait_confirm_with(
+        self,
+        on_pairing_code: impl FnOnce(&str),
+    ) -> Result<PairedBitBox<T>, HWIError> {
+        if let Some(code) = self.pairing_code() {
+            on_pairing_code(&code);
+        }
+        self.wait_confirm()
+    }
+
+    pub fn wait_confirm(self) -> Result<PairedBitBox<T>, HWIError> {
+        block_on(self.client.wait_confirm()).map_err(|e| e.into())
     }
 }
 
+/// The calls block on the futures of bitbox-api, see blocking::block_on.
 pub struct BitBox02<T: Runtime> {
     pub network: bitcoin::Network,
     pub display_xpub: bool,
@@ -154,81 +217,88 @@ impl<T: Runtime> BitBox02<T> {
         Ok(self)
     }
 
-    pub async fn is_policy_registered(&self, policy: &str) -> Result<bool, HWIError> {
-        let pb_network = coin_from_network(self.network);
+    pub fn is_policy_registered(&self, policy: &str) -> Result<bool, HWIError> {
+        let pb_network = coin_from_network(self.network)?;
         let policy = extract_script_config_policy(policy)?;
-        self.client
-            .btc_is_script_config_registered(pb_network, &policy.into(), None)
-            .await
-            .map_err(|e| e.into())
+        block_on(
+            self.client
+                .btc_is_script_config_registered(pb_network, &policy.into(), None),
+        )
+        .map_err(|e| e.into())
     }
 }
 
-#[async_trait]
 impl<T: Runtime + Sync + Send> HWI for BitBox02<T> {
     fn device_kind(&self) -> DeviceKind {
         DeviceKind::BitBox02
     }
 
     fn get_version(&self) -> Result<super::Version, HWIError> {
-        let info = self
-            .client
-            .device_info()
-            .await
-            .map_err(|e| HWIError::Device(e.to_string()))?;
-        Ok(parse_version(&info.version)?)
+        let info =
+            block_on(self.client.device_info()).map_err(|e| HWIError::Device(e.to_string()))?;
+        parse_version(&info.version)
     }
 
-    async fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
-        let fg = self
-            .client
-            .root_fingerprint()
-            .await
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
+        let fg = block_on(self.client.root_fingerprint())
             .map_err(|e| HWIError::Device(e.to_string()))?;
-        Ok(Fingerprint::from_str(&fg).map_err(|e| HWIError::Device(e.to_string()))?)
+        Fingerprint::from_str(&fg).map_err(|e| HWIError::Device(e.to_string()))
     }
 
-    async fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
-        let fg = self
-            .client
-            .btc_xpub(
-                if self.network == bitcoin::Network::Bitcoin {
-                    pb::BtcCoin::Btc
-                } else {
-                    pb::BtcCoin::Tbtc
-                },
-                &Keypath::from(path),
-                if self.network == bitcoin::Network::Bitcoin {
-                    pb::btc_pub_request::XPubType::Xpub
-                } else {
-                    pb::btc_pub_request::XPubType::Tpub
-                },
-                self.display_xpub,
-            )
-            .await
-            .map_err(|e| HWIError::Device(e.to_string()))?;
-        Ok(Xpub::from_str(&fg).map_err(|e| HWIError::Device(e.to_string()))?)
-    }
-
-    async fn display_address(&self, script: &AddressScript) -> Result<(), HWIError> {
-        match script {
-            AddressScript::P2TR(path) => {
-                self.client
-                    .btc_address(
-                        if self.network == bitcoin::Network::Bitcoin {
-                            pb::BtcCoin::Btc
-                        } else {
-                            pb::BtcCoin::Tbtc
-                        },
-                        &Keypath::from(path),
-                        &make_script_config_simple(pb::btc_script_config::SimpleType::P2tr),
-                        true,
-                    )
-                    .await?;
+    fn displays_xpub(&self, _path: &DerivationPath) -> bool {
+        self.display_xpub
+    }
+
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
+        utils::check_coin_type(path, self.network)?;
+        let fg = block_on(self.client.btc_xpub(
+            coin_from_network(self.network)?,
+            &Keypath::from(path),
+            if self.network == bitcoin::Network::Bitcoin {
+                pb::btc_pub_request::XPubType::Xpub
+            } else {
+                pb::btc_pub_request::XPubType::Tpub
+            },
+            self.display_xpub,
+        ))
+        .map_err(|e| HWIError::Device(e.to_string()))?;
+        Xpub::from_str(&fg).map_err(|e| HWIError::Device(e.to_string()))
+    }
+
+    fn display_address(
+        &self,
+        script: &AddressScript,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        self.get_address(script, true)
+    }
+
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        check_displayed_network(self.network)?;
+        let address = match script {
+            // BitBox02 does not support legacy addresses.
+            AddressScript::P2PKH(_) => return Err(HWIError::UnsupportedInput),
+            AddressScript::P2SHWPKH(path)
+            | AddressScript::P2WPKH(path)
+            | AddressScript::P2TR(path) => {
+                let simple_type = match script {
+                    AddressScript::P2SHWPKH(_) => pb::btc_script_config::SimpleType::P2wpkhP2sh,
+                    AddressScript::P2WPKH(_) => pb::btc_script_config::SimpleType::P2wpkh,
+                    _ => pb::btc_script_config::SimpleType::P2tr,
+                };
+                block_on(self.client.btc_address(
+                    coin_from_network(self.network)?,
+                    &Keypath::from(path),
+                    &make_script_config_simple(simple_type),
+                    display,
+                ))?
             }
             AddressScript::Miniscript { index, change } => {
-                let policy = self.policy.clone().ok_or_else(|| HWIError::MissingPolicy)?;
-                let fg = self.get_master_fingerprint().await?;
+                let policy = self.policy.clone().ok_or(HWIError::MissingPolicy)?;
+                let fg = self.get_master_fingerprint()?;
                 let mut path = DerivationPath::master();
                 for (key_index, key) in policy.pubkeys.iter().enumerate() {
                     if Some(fg) == key.master_fingerprint {
@@ -261,67 +331,61 @@ impl<T: Runtime + Sync + Send> HWI for BitBox02<T> {
                         break;
                     }
                 }
-                self.client
-                    .btc_address(
-                        if self.network == bitcoin::Network::Bitcoin {
-                            pb::BtcCoin::Btc
-                        } else {
-                            pb::BtcCoin::Tbtc
-                        },
-                        &Keypath::from(&path),
-                        &policy.into(),
-                        true,
-                    )
-                    .await?;
+                block_on(self.client.btc_address(
+                    coin_from_network(self.network)?,
+                    &Keypath::from(&path),
+                    &policy.into(),
+                    display,
+                ))?
             }
-        }
-        Ok(())
+        };
+        let address = Address::from_str(&address).map_err(|e| HWIError::Device(e.to_string()))?;
+        utils::check_address_network(address, self.network)
     }
 
-    async fn register_wallet(
-        &self,
-        name: &str,
-        policy: &str,
-    ) -> Result<Option<[u8; 32]>, HWIError> {
-        let pb_network = coin_from_network(self.network);
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, HWIError> {
+        utils::validate_wallet_name(DeviceKind::BitBox02, name)?;
+        crate::policy::WalletPolicy::from_descriptor(name, policy)?
+            .check_for_device(self.get_master_fingerprint()?)?;
+        let pb_network = coin_from_network(self.network)?;
         let policy = extract_script_config_policy(policy)?;
-        if self
-            .client
-            .btc_is_script_config_registered(pb_network, &policy.clone().into(), None)
-            .await?
-        {
-            return Ok(None);
+        if block_on(self.client.btc_is_script_config_registered(
+            pb_network,
+            &policy.clone().into(),
+            None,
+        ))? {
+            return Ok(WalletRegistration {
+                already_registered: true,
+                ..Default::default()
+            });
         }
-        self.client
-            .btc_register_script_config(
-                pb_network,
-                &policy.into(),
-                None,
-                pb::btc_register_script_config_request::XPubType::AutoXpubTpub,
-                Some(name),
-            )
-            .await
-            .map(|_| None)
-            .map_err(|e| e.into())
-    }
-
-    async fn is_wallet_registered(&self, _name: &str, policy: &str) -> Result<bool, HWIError> {
-        let pb_network = coin_from_network(self.network);
-        let policy = extract_script_config_policy(policy)?;
-        self.client
-            .btc_is_script_config_registered(pb_network, &policy.clone().into(), None)
-            .await
-            .map_err(|e| e.into())
+        block_on(self.client.btc_register_script_config(
+            pb_network,
+            &policy.into(),
+            None,
+            pb::btc_register_script_config_request::XPubType::AutoXpubTpub,
+            Some(name),
+        ))
+        .map(|_| WalletRegistration::default())
+        .map_err(|e| e.into())
+    }
+
+    fn is_wallet_registered(&self, _name: &str, policy: &str) -> Result<bool, HWIError> {
+        self.is_policy_registered(policy)
     }
 
     /// Bitbox and Coldcard sign with the first bip32_derivation that matches its fingerprint.
     /// It may be useful to user utils::Bip32DerivationFilter to filter already signed derivations
     /// and derivations collusion in case of multiple spending path per outputs.
-    async fn sign_tx(&self, psbt: &mut Psbt) -> Result<(), HWIError> {
+    /// The outputs are reviewed with their addresses, regtest is rejected.
+    fn sign_tx(&self, psbt: &mut Psbt) -> Result<SignResult, HWIError> {
+        check_displayed_network(self.network)?;
+        utils::check_op_return(&self.capabilities(), psbt)?;
+        let unsigned = psbt.clone();
         let policy: Option<pb::BtcScriptConfigWithKeypath> =
             if let Some(policy) = self.policy.clone() {
                 let mut path = DerivationPath::master();
-                let fg = self.get_master_fingerprint().await?;
+                let fg = self.get_master_fingerprint()?;
                 for key in &policy.pubkeys {
                     if Some(fg) == key.master_fingerprint {
                         if let Some(p) = &key.path {
@@ -338,39 +402,95 @@ impl<T: Runtime + Sync + Send> HWI for BitBox02<T> {
                 None
             };
 
-        self.client
-            .btc_sign_psbt(
-                coin_from_network(self.network),
-                psbt,
-                policy,
-                pb::btc_sign_init_request::FormatUnit::Default,
-            )
-            .await?;
+        block_on(self.client.btc_sign_psbt(
+            coin_from_network(self.network)?,
+            psbt,
+            policy,
+            pb::btc_sign_init_request::FormatUnit::Default,
+        ))?;
+
+        Ok(SignResult::new(&unsigned, psbt))
+    }
 
+    fn get_label(&self) -> Result<Option<String>, HWIError> {
+        let info =
+            block_on(self.client.device_info()).map_err(|e| HWIError::Device(e.to_string()))?;
+        Ok(Some(info.name).filter(|name| !name.is_empty()))
+    }
+
+    fn set_network(&mut self, network: bitcoin::Network) -> Result<(), HWIError> {
+        self.network = network;
+        Ok(())
+    }
+
+    /// The device identifies the registered policies by their script config.
+    fn load_wallet(
+        &mut self,
+        _name: &str,
+        policy: &str,
+        _hmac: Option<[u8; 32]>,
+    ) -> Result<(), HWIError> {
+        self.policy = Some(extract_script_config_policy(policy)?);
         Ok(())
     }
 }
 
-fn coin_from_network(network: bitcoin::Network) -> pb::BtcCoin {
-    if network == bitcoin::Network::Bitcoin {
-        pb::BtcCoin::Btc
-    } else {
-        pb::BtcCoin::Tbtc
+/// Testnet, signet and regtest share the SLIP-44 coin type 1 and the tpub
+/// version of Tbtc, the keys and the registered policies are the same.
+fn coin_from_network(network: bitcoin::Network) -> Result<pb::BtcCoin, HWIError> {
+    match network {
+        bitcoin::Network::Bitcoin => Ok(pb::BtcCoin::Btc),
+        bitcoin::Network::Testnet | bitcoin::Network::Signet | bitcoin::Network::Regtest => {
+            Ok(pb::BtcCoin::Tbtc)
+        }
+        network => Err(HWIError::UnsupportedNetwork(network)),
+    }
+}
+
+/// Signet addresses are encoded as the testnet ones, but the firmware has no
+/// regtest coin and would display testnet addresses instead of bcrt1 ones.
+fn check_displayed_network(network: bitcoin::Network) -> Result<(), HWIError> {
+    match network {
+        bitcoin::Network::Regtest => Err(HWIError::UnsupportedNetwork(network)),
+        network => coin_from_network(network).map(|_| ()),
+    }
+}
+
+/// The BitBox02 displays the recovery words for the user to check them against the backup.
+impl<T: Runtime + Sync + Send> Management for BitBox02<T> {
+    fn check_backup(&self) -> Result<(), HWIError> {
+        block_on(self.client.show_mnemonic()).map_err(|e| e.into())
     }
 }
 
 impl From<UsbError> for HWIError {
     fn from(value: UsbError) -> Self {
-        HWIError::Device(value.to_string())
+        HWIError::Transport(TransportErrorKind::Io, value.to_string())
     }
 }
 
 impl From<Error> for HWIError {
     fn from(e: Error) -> Self {
-        if let Error::BitBox(BitBoxError::UserAbort) = e {
-            HWIError::UserRefused
-        } else {
-            HWIError::Device(e.to_string())
+        match e {
+            Error::BitBox(BitBoxError::UserAbort) => HWIError::UserRefused,
+            // The requirement is a semver comparison like ">=9.10.0".
+            Error::Version(required) => match required
+                .strip_prefix(">=")
+                .and_then(|v| parse_version(v).ok())
+            {
+                Some(required) => HWIError::UnsupportedFirmware {
+                    required,
+                    found: None,
+                },
+                None => HWIError::Device(e.to_string()),
+            },
+            Error::Hid(_) | Error::Communication(_) => {
+                HWIError::Transport(TransportErrorKind::Io, e.to_string())
+            }
+            Error::UnexpectedResponse | Error::ProtobufDecode => {
+                HWIError::Transport(TransportErrorKind::Protocol, e.to_string())
+            }
+            _ => HWIError::Device(e.to_string()),
         }
     }
 }
@@ -396,64 +516,9 @@ impl<T: Runtime + Sync + Send + 'static> From<BitBox02<T>>
 }
 
 pub fn extract_script_config_policy(policy: &str) -> Result<Policy, HWIError> {
-    let re = Regex::new(r"((\[.+?\])?[xyYzZtuUvV]pub[1-9A-HJ-NP-Za-km-z]{79,108})").unwrap();
-    let mut descriptor_template = policy.to_string();
-    let mut pubkeys_str: Vec<&str> = Vec::new();
-    for capture in re.find_iter(policy) {
-        if !pubkeys_str.contains(&capture.as_str()) {
-            pubkeys_str.push(capture.as_str());
-        }
-    }
-
-    let mut pubkeys: Vec<KeyInfo> = Vec::new();
-    for (i, key_str) in pubkeys_str.iter().enumerate() {
-        descriptor_template = descriptor_template.replace(key_str, &format!("@{}", i));
-        let pubkey = if let Ok(key) = Xpub::from_str(key_str) {
-            KeyInfo {
-                path: None,
-                master_fingerprint: None,
-                xpub: key,
-            }
-        } else {
-            let (keysource_str, xpub_str) = key_str
-                .strip_prefix('[')
-                .and_then(|s| s.rsplit_once(']'))
-                .ok_or(HWIError::InvalidParameter(
-                    "policy",
-                    "Invalid key source".to_string(),
-                ))?;
-            let (f_str, path_str) = keysource_str.split_once('/').unwrap_or((keysource_str, ""));
-            let fingerprint = Fingerprint::from_str(f_str)
-                .map_err(|e| HWIError::InvalidParameter("policy", e.to_string()))?;
-            let derivation_path = if path_str.is_empty() {
-                DerivationPath::master()
-            } else {
-                DerivationPath::from_str(&format!("m/{}", path_str))
-                    .map_err(|e| HWIError::InvalidParameter("policy", e.to_string()))?
-            };
-
-            KeyInfo {
-                xpub: Xpub::from_str(xpub_str)
-                    .map_err(|e| HWIError::InvalidParameter("policy", e.to_string()))?,
-                path: Some(derivation_path),
-                master_fingerprint: Some(fingerprint),
-            }
-        };
-        pubkeys.push(pubkey);
-    }
-    // Do not include the hash in the descriptor template.
-    let descriptor_template =
-        if let Some((descriptor_template, _hash)) = descriptor_template.rsplit_once('#') {
-            descriptor_template
-        } else {
-            &descriptor_template
-        };
-
-    //Ok(
-    Ok(Policy {
-        template: descriptor_template.to_string(),
-        pubkeys,
-    })
+    let policy = policy::WalletPolicy::from_descriptor("", policy)?;
+    policy.check_internal_key()?;
+    Ok(Policy::from(&policy))
 }
 
 pub fn extract_first_appended_derivation_with_some_wildcard(
@@ -480,6 +545,23 @@ pub struct Policy {
     pubkeys: Vec<KeyInfo>,
 }
 
+impl From<&policy::WalletPolicy> for Policy {
+    fn from(policy: &policy::WalletPolicy) -> Policy {
+        Policy {
+            template: policy.template.clone(),
+            pubkeys: policy
+                .keys
+                .iter()
+                .map(|key| KeyInfo {
+                    path: key.origin.as_ref().map(|(_, path)| path.clone()),
+                    master_fingerprint: key.origin.as_ref().map(|(fg, _)| *fg),
+                    xpub: key.xpub,
+                })
+                .collect(),
+        }
+    }
+}
+
 impl From<Policy> for BtcScriptConfig {
     fn from(p: Policy) -> BtcScriptConfig {
         let keys: Vec<KeyOriginInfo> = p.pubkeys.into_iter().map(|k| k.into()).collect();
@@ -525,6 +607,38 @@ mod tests {
             );
     }
 
+    #[test]
+    fn test_version_error() {
+        match HWIError::from(Error::Version(">=9.10.0")) {
+            HWIError::UnsupportedFirmware {
+                required,
+                found: None,
+            } => assert_eq!(required, parse_version("9.10.0").unwrap()),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(matches!(
+            HWIError::from(Error::Version("<9.0.0")),
+            HWIError::Device(_)
+        ));
+    }
+
+    #[test]
+    fn test_network() {
+        use bitcoin::Network;
+        assert_eq!(
+            coin_from_network(Network::Bitcoin).unwrap(),
+            pb::BtcCoin::Btc
+        );
+        for network in [Network::Testnet, Network::Signet, Network::Regtest] {
+            assert_eq!(coin_from_network(network).unwrap(), pb::BtcCoin::Tbtc);
+        }
+        assert!(check_displayed_network(Network::Signet).is_ok());
+        assert!(matches!(
+            check_displayed_network(Network::Regtest),
+            Err(HWIError::UnsupportedNetwork(Network::Regtest))
+        ));
+    }
+
     #[test]
     fn test_extract_first_appended_derivation_with_some_wildcard() {
         let (paths, wildcard) = extract_first_appended_derivation_with_some_wildcard(
@@ -0,0 +1,111 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+/// The HWI methods block the calling thread until the device answers, for every
+/// backend: the sync interface for the CLI tools and the applications without an
+/// async runtime is the HWI trait itself.
+pub use crate::HWI as BlockingHWI;
+
+thread_local! {
+    /// Runtime of the thread, providing the io and timer reactor to the futures.
+    static RUNTIME: Option<tokio::runtime::Runtime> = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .ok();
+}
+
+/// Drives the future to completion on the calling thread. Used to call the async
+/// api of the BitBox02. Outside of a tokio runtime the future runs on a runtime of
+/// the thread, within one the thread is parked while the future is pending and the
+/// reactor of the calling runtime must be driven by another thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return park_on(future);
+    }
+    RUNTIME.with(move |runtime| match runtime {
+        Some(runtime) => runtime.block_on(future),
+        None => park_on(future),
+    })
+}
+
+/// Polls the future on the calling thread, which is parked while it is pending.
+fn park_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = Pin::as_mut(&mut future).poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// Unparks the thread blocked on the future.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    /// Future completed by another thread.
+    struct Remote {
+        done: Arc<AtomicBool>,
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    impl Future for Remote {
+        type Output = u32;
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            if self.done.load(Ordering::SeqCst) {
+                return Poll::Ready(42);
+            }
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_block_on() {
+        assert_eq!(block_on(async { 1 + 1 }), 2);
+
+        let done = Arc::new(AtomicBool::new(false));
+        let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+        let remote = Remote {
+            done: done.clone(),
+            waker: waker.clone(),
+        };
+        let handle = thread::spawn(move || loop {
+            if let Some(waker) = waker.lock().unwrap().take() {
+                done.store(true, Ordering::SeqCst);
+                waker.wake();
+                break;
+            }
+            thread::yield_now();
+        });
+        assert_eq!(block_on(remote), 42);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_block_on_reactor() {
+        let start = std::time::Instant::now();
+        block_on(async { tokio::time::sleep(std::time::Duration::from_millis(10)).await });
+        assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+    }
+}
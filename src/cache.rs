@@ -0,0 +1,228 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint, Xpub},
+    Network,
+};
+
+use crate::{stats::Operation, Error, ScriptType, HWI};
+
+#[derive(Debug, Default)]
+struct Session {
+    fingerprint: Option<Fingerprint>,
+    xpubs: HashMap<DerivationPath, Xpub>,
+}
+
+/// Cached wraps a device and keeps its master fingerprint and xpubs in memory,
+/// so that the hot paths do not query the device each time.
+/// The cache is cleared when the connection is reset, the network is changed or the
+/// device is reported disconnected, another device may have been plugged in.
+#[derive(Debug)]
+pub struct Cached<T> {
+    device: T,
+    session: Mutex<Session>,
+}
+
+impl<T: HWI> Cached<T> {
+    pub fn new(device: T) -> Self {
+        Self {
+            device,
+            session: Mutex::new(Session::default()),
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut session) = self.session.lock() {
+            *session = Session::default();
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    /// Clears the cache if the device is gone.
+    fn observe<R>(&self, res: Result<R, Error>) -> Result<R, Error> {
+        if matches!(
+            res,
+            Err(Error::DeviceDisconnected) | Err(Error::DeviceNotFound)
+        ) {
+            self.clear();
+        }
+        res
+    }
+}
+
+impl<T: HWI> crate::delegate::Delegate for Cached<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.device
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(&mut self.device)
+    }
+
+    fn call<R>(
+        &self,
+        _operation: Operation,
+        call: impl FnOnce(&T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.observe(call(&self.device))
+    }
+
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, Error> {
+        if let Some(fingerprint) = self.session.lock().ok().and_then(|s| s.fingerprint) {
+            return Ok(fingerprint);
+        }
+        let fingerprint = self.observe(self.device.get_master_fingerprint())?;
+        if let Ok(mut session) = self.session.lock() {
+            session.fingerprint = Some(fingerprint);
+        }
+        Ok(fingerprint)
+    }
+
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+        HWI::get_extended_pubkeys(self, std::slice::from_ref(path)).map(|xpubs| xpubs[0])
+    }
+
+    /// Only the xpubs missing from the cache are queried, in one call. The xpubs the
+    /// device displays are always queried, so that the user verifies them.
+    fn get_extended_pubkeys(&self, paths: &[DerivationPath]) -> Result<Vec<Xpub>, Error> {
+        let cached: Vec<Option<Xpub>> = {
+            let session = self.session.lock().ok();
+            paths
+                .iter()
+                .map(|path| {
+                    session
+                        .as_ref()
+                        .filter(|_| !self.device.displays_xpub(path))
+                        .and_then(|s| s.xpubs.get(path).copied())
+                })
+                .collect()
+        };
+        let missing: Vec<DerivationPath> = paths
+            .iter()
+            .zip(&cached)
+            .filter(|(_, xpub)| xpub.is_none())
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut fetched = if missing.is_empty() {
+            Vec::new()
+        } else {
+            self.observe(self.device.get_extended_pubkeys(&missing))?
+        };
+        if fetched.len() != missing.len() {
+            return Err(Error::Unexpected("number of xpubs returned by the device"));
+        }
+        if let Ok(mut session) = self.session.lock() {
+            session
+                .xpubs
+                .extend(missing.into_iter().zip(fetched.iter().copied()));
+        }
+        fetched.reverse();
+        cached
+            .into_iter()
+            .map(|xpub| xpub.or_else(|| fetched.pop()))
+            .collect::<Option<_>>()
+            .ok_or(Error::Unexpected("xpub missing from the cache"))
+    }
+
+    /// Built with the cached xpubs, see HWI::get_descriptor.
+    fn get_descriptor(
+        &self,
+        script: ScriptType,
+        network: Network,
+        account: u32,
+    ) -> Result<String, Error> {
+        let path = crate::utils::account_path(script, network, account)?;
+        let xpub = HWI::get_extended_pubkey(self, &path)?;
+        Ok(crate::utils::account_descriptor(
+            script,
+            HWI::get_master_fingerprint(self)?,
+            &path,
+            &xpub,
+        ))
+    }
+
+    /// The xpubs are encoded for the network.
+    fn set_network(&mut self, network: Network) -> Result<(), Error> {
+        self.clear();
+        self.device.set_network(network)
+    }
+
+    fn reset_connection(&self) -> Result<(), Error> {
+        self.clear();
+        self.device.reset_connection()
+    }
+}
+
+delegate_hwi!([T: HWI] Cached<T>);
+
+impl<T: 'static + HWI + Send> From<Cached<T>> for Box<dyn HWI + Send> {
+    fn from(s: Cached<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockDevice, DeviceKind};
+    use bitcoin::bip32::Xpriv;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_cached() {
+        let path = |s| DerivationPath::from_str(s).unwrap();
+        let seed = Xpriv::new_master(Network::Testnet, &[1; 32]).unwrap();
+        let device = Cached::new(MockDevice::with_seed(DeviceKind::Specter, seed));
+        let xpub = device.get_extended_pubkey(&path("m/84'/1'/0'")).unwrap();
+        let xpubs = device
+            .get_extended_pubkeys(&[path("m/86'/1'/0'"), path("m/84'/1'/0'")])
+            .unwrap();
+        assert_eq!(xpubs[1], xpub);
+        assert_eq!(
+            device
+                .device
+                .get_extended_pubkey(&path("m/86'/1'/0'"))
+                .unwrap(),
+            xpubs[0]
+        );
+        assert_eq!(
+            *device.device.queried.lock().unwrap(),
+            vec![
+                path("m/84'/1'/0'"),
+                path("m/86'/1'/0'"),
+                path("m/86'/1'/0'")
+            ]
+        );
+
+        assert!(device.get_master_fingerprint().is_ok());
+        device.device.fail_with(Some(Error::DeviceDisconnected));
+        // Served from the cache.
+        assert!(device.get_master_fingerprint().is_ok());
+        device.clear();
+        assert!(matches!(
+            device.get_master_fingerprint(),
+            Err(Error::DeviceDisconnected)
+        ));
+
+        device.get_extended_pubkey(&path("m/84'/1'/0'")).unwrap();
+        assert_eq!(device.device.queried.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_cached_displayed_xpubs() {
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let seed = Xpriv::new_master(Network::Testnet, &[1; 32]).unwrap();
+        let device = Cached::new(MockDevice {
+            display_xpub: true,
+            ..MockDevice::with_seed(DeviceKind::Ledger, seed)
+        });
+        let xpub = device.get_extended_pubkey(&path).unwrap();
+        assert_eq!(device.get_extended_pubkey(&path).unwrap(), xpub);
+        assert_eq!(device.device.queried.lock().unwrap().len(), 2);
+    }
+}
@@ -3,19 +3,36 @@ use std::{
     sync::{Arc, Mutex, MutexGuard},
 };
 
-use async_trait::async_trait;
 use bitcoin::{
+    address::NetworkUnchecked,
     bip32::{DerivationPath, Fingerprint, Xpub},
     psbt::Psbt,
+    Address, Network,
 };
 
-use crate::{parse_version, AddressScript, DeviceKind, Error as HWIError, Version, HWI};
+use crate::{
+    parse_version, utils, AddressScript, Attestation, Capabilities, Context, DeviceKind,
+    Error as HWIError, SignResult, TransportErrorKind, Version, WalletRegistration, HWI,
+};
 pub use coldcard as api;
 
+pub fn is_coldcard(info: &hidapi::DeviceInfo) -> bool {
+    info.vendor_id() == api::COINKITE_VID && info.product_id() == api::CKCC_PID
+}
+
+/// Opens the Coldcard at the HID path, the first one found if no path is given.
+pub fn connect_hid(ctx: &Context, path: Option<&str>) -> Result<Coldcard, HWIError> {
+    let info = ctx.hid_device(path, is_coldcard)?;
+    let sn = info.serial_number().ok_or(HWIError::DeviceNotFound)?;
+    let (cc, _) = api::Api::from_borrowed(&mut *ctx.hid_api()?).open(sn, None)?;
+    Ok(Coldcard::from(cc))
+}
+
 #[derive(Debug)]
 pub struct Coldcard {
     device: Arc<Mutex<coldcard::Coldcard>>,
     wallet_name: Option<String>,
+    network: Option<Network>,
 }
 
 impl Coldcard {
@@ -24,11 +41,50 @@ impl Coldcard {
         self
     }
 
+    /// The network is otherwise the chain configured on the device.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    fn check_coin_type(&self, path: &DerivationPath) -> Result<(), HWIError> {
+        match self.network {
+            Some(network) => utils::check_coin_type(path, network),
+            None => Ok(()),
+        }
+    }
+
     fn device(&self) -> Result<MutexGuard<'_, coldcard::Coldcard>, HWIError> {
         self.device
             .lock()
             .map_err(|_| HWIError::Unexpected("Failed to unlock"))
     }
+
+    /// The first semver version returned by coldcard is the firmware version.
+    fn version(&self) -> Result<Version, HWIError> {
+        let s = self.device()?.version()?;
+        for line in s.split('\n') {
+            if let Ok(version) = parse_version(line) {
+                return Ok(version);
+            }
+        }
+        Err(HWIError::UnsupportedVersion)
+    }
+
+    /// Displays the single signature address of the BIP44 path with the given purpose.
+    fn display_single_sig_address(
+        &self,
+        path: &DerivationPath,
+        purpose: u32,
+        format: api::protocol::AddressFormat,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        utils::bip44_path_child_numbers(path.clone(), purpose)?;
+        self.check_coin_type(path)?;
+        let path = coldcard::protocol::DerivationPath::new(&path.to_string())
+            .map_err(|e| HWIError::InvalidParameter("path", format!("{:?}", e)))?;
+        let s = self.device()?.address(path, format)?;
+        Address::from_str(&s).map_err(|e| HWIError::Device(e.to_string()))
+    }
 }
 
 impl From<coldcard::Coldcard> for Coldcard {
@@ -36,85 +92,108 @@ impl From<coldcard::Coldcard> for Coldcard {
         Coldcard {
             device: Arc::new(Mutex::new(cc)),
             wallet_name: None,
+            network: None,
         }
     }
 }
 
-#[async_trait]
 impl HWI for Coldcard {
     fn device_kind(&self) -> DeviceKind {
         DeviceKind::Coldcard
     }
 
-    /// The first semver version returned by coldcard is the firmware version.
-    async fn get_version(&self) -> Result<Version, HWIError> {
-        let s = self.device()?.version()?;
-        for line in s.split('\n') {
-            if let Ok(version) = parse_version(line) {
-                return Ok(version);
-            }
-        }
-        Err(HWIError::UnsupportedVersion)
+    fn get_version(&self) -> Result<Version, HWIError> {
+        self.version()
     }
 
-    async fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
         let s = self.device()?.xpub(None)?;
         let xpub = Xpub::from_str(&s).map_err(|e| HWIError::Device(e.to_string()))?;
         Ok(xpub.fingerprint())
     }
 
-    async fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
+        self.check_coin_type(path)?;
         let path = coldcard::protocol::DerivationPath::new(&path.to_string())
             .map_err(|e| HWIError::InvalidParameter("path", format!("{:?}", e)))?;
         let s = self.device()?.xpub(Some(path))?;
         Xpub::from_str(&s).map_err(|e| HWIError::Device(e.to_string()))
     }
 
-    async fn display_address(&self, script: &AddressScript) -> Result<(), HWIError> {
-        if let Some(name) = &self.wallet_name {
-            let descriptor_name = coldcard::protocol::DescriptorName::new(name)
-                .map_err(|_| HWIError::UnsupportedInput)?;
-            if let AddressScript::Miniscript { index, change } = script {
-                self.device()?
+    /// The device is locked once for all the paths.
+    fn get_extended_pubkeys(&self, paths: &[DerivationPath]) -> Result<Vec<Xpub>, HWIError> {
+        let mut cc = self.device()?;
+        let mut xpubs = Vec::with_capacity(paths.len());
+        for path in paths {
+            self.check_coin_type(path)?;
+            let path = coldcard::protocol::DerivationPath::new(&path.to_string())
+                .map_err(|e| HWIError::InvalidParameter("path", format!("{:?}", e)))?;
+            let s = cc.xpub(Some(path))?;
+            xpubs.push(Xpub::from_str(&s).map_err(|e| HWIError::Device(e.to_string()))?);
+        }
+        Ok(xpubs)
+    }
+
+    fn display_address(
+        &self,
+        script: &AddressScript,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        let address = match script {
+            AddressScript::P2PKH(path) => {
+                self.display_single_sig_address(path, 44, api::protocol::AddressFormat::P2PKH)
+            }
+            AddressScript::P2SHWPKH(path) => {
+                self.display_single_sig_address(path, 49, api::protocol::AddressFormat::P2WPKH_P2SH)
+            }
+            AddressScript::P2WPKH(path) => {
+                self.display_single_sig_address(path, 84, api::protocol::AddressFormat::P2WPKH)
+            }
+            AddressScript::P2TR(_) => Err(HWIError::UnimplementedMethod),
+            AddressScript::Miniscript { index, change } => {
+                let name = self
+                    .wallet_name
+                    .as_ref()
+                    .ok_or(HWIError::UnimplementedMethod)?;
+                let descriptor_name = coldcard::protocol::DescriptorName::new(name)
+                    .map_err(|_| HWIError::UnsupportedInput)?;
+                let s = self
+                    .device()?
                     .miniscript_address(descriptor_name, *change, *index)?;
-                Ok(())
-            } else {
-                Err(HWIError::UnimplementedMethod)
+                Address::from_str(&s).map_err(|e| HWIError::Device(e.to_string()))
             }
-        } else {
-            Err(HWIError::UnimplementedMethod)
+        }?;
+        match self.network {
+            Some(network) => utils::check_address_network(address, network),
+            None => Ok(address),
         }
     }
 
-    async fn register_wallet(
-        &self,
-        name: &str,
-        policy: &str,
-    ) -> Result<Option<[u8; 32]>, HWIError> {
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, HWIError> {
+        utils::validate_wallet_name(DeviceKind::Coldcard, name)?;
+        crate::policy::WalletPolicy::from_descriptor(name, policy)?
+            .check_for_device(self.get_master_fingerprint()?)?;
         let payload = format!("{{\"name\":\"{}\",\"desc\":\"{}\"}}", name, policy);
-        let _ = self.device()?.miniscript_enroll(payload.as_bytes())?;
-        Ok(None)
+        self.device()?.miniscript_enroll(payload.as_bytes())?;
+        Ok(WalletRegistration::default())
     }
 
-    async fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, HWIError> {
+    fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, HWIError> {
         let descriptor_name = coldcard::protocol::DescriptorName::new(name)
             .map_err(|_| HWIError::UnsupportedInput)?;
         let desc = self.device()?.miniscript_get(descriptor_name)?;
         if let Some(desc) = desc {
-            if let Some((policy, _)) = policy.replace('\'', "h").split_once('#') {
-                Ok(desc.contains(policy))
-            } else {
-                Ok(desc.contains(policy))
-            }
+            let policy = utils::strip_checksum(policy)?.replace('\'', "h");
+            Ok(desc.contains(&policy))
         } else {
             Ok(false)
         }
     }
 
-    async fn sign_tx(&self, psbt: &mut Psbt) -> Result<(), HWIError> {
+    fn sign_tx(&self, psbt: &mut Psbt) -> Result<SignResult, HWIError> {
+        let unsigned = psbt.clone();
         let mut cc = self.device()?;
 
-        let _ = cc.sign_psbt(&psbt.serialize(), api::SignMode::Signed)?;
+        cc.sign_psbt(&psbt.serialize(), api::SignMode::Signed)?;
 
         let tx = loop {
             if let Some(tx) = cc.get_signed_tx()? {
@@ -136,16 +215,50 @@ impl HWI for Coldcard {
             }
         }
 
+        Ok(SignResult::new(&unsigned, psbt))
+    }
+
+    /// The Coldcard has no attestation key, it reports the number of the tamper-evident
+    /// bag it was shipped in, set at the factory.
+    fn verify_attestation(&self) -> Result<Attestation, HWIError> {
+        let bag_number = self.device()?.bag_number()?;
+        Ok(Attestation::BagNumber(bag_number))
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<(), HWIError> {
+        self.network = Some(network);
         Ok(())
     }
+
+    /// The device stores the registered descriptors, only the name is kept.
+    fn load_wallet(
+        &mut self,
+        name: &str,
+        _policy: &str,
+        _hmac: Option<[u8; 32]>,
+    ) -> Result<(), HWIError> {
+        self.wallet_name = Some(name.to_string());
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(DeviceKind::Coldcard, self.version().ok().as_ref())
+    }
 }
 
 impl From<api::Error> for HWIError {
     fn from(e: api::Error) -> Self {
-        if let api::Error::UnexpectedResponse(api::protocol::Response::Refused) = e {
-            HWIError::UserRefused
-        } else {
-            HWIError::Device(e.to_string())
+        match e {
+            api::Error::UnexpectedResponse(api::protocol::Response::Refused) => {
+                HWIError::UserRefused
+            }
+            api::Error::Hid(_) | api::Error::TransmissionFailed => {
+                HWIError::Transport(TransportErrorKind::Io, e.to_string())
+            }
+            api::Error::Encoding(_) | api::Error::Decoding(_) | api::Error::ChecksumMismatch => {
+                HWIError::Transport(TransportErrorKind::Protocol, e.to_string())
+            }
+            _ => HWIError::Device(e.to_string()),
         }
     }
 }
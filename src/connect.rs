@@ -0,0 +1,453 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bitcoin::{bip32::Fingerprint, Network};
+
+use crate::{context::Context, utils, DeviceDetails, DeviceKind, Error, HWI};
+
+/// Connects the devices of any kind with the same configuration, instead of the
+/// builder of each backend, e.g.
+/// `Connector::new().network(network).wallet(name, policy, hmac).connect(kind)`.
+/// The BitBox02 is paired on each connection, see Connector::on_pairing_code.
+#[derive(Debug, Clone, Default)]
+pub struct Connector {
+    ctx: Option<Context>,
+    path: Option<String>,
+    network: Option<Network>,
+    wallet: Option<(String, String, Option<[u8; 32]>)>,
+    display_xpub: bool,
+    allow_unusual_paths: bool,
+    on_pairing_code: Option<PairingCode>,
+}
+
+/// Callback of Connector::on_pairing_code.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "bitbox"), allow(dead_code))]
+struct PairingCode(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl Debug for PairingCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PairingCode").finish_non_exhaustive()
+    }
+}
+
+impl Connector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The global context is otherwise used, see Context::global.
+    pub fn context(mut self, ctx: &Context) -> Self {
+        self.ctx = Some(ctx.clone());
+        self
+    }
+
+    /// HID path of a Ledger, a BitBox02 or a Coldcard, serial port of a Jade or a
+    /// Specter, or address of the Ledger or Jade simulator. The first device found is otherwise connected.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Ignored by the Specter, which does not check the network.
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Wallet loaded once connected, see HWI::load_wallet.
+    pub fn wallet(
+        mut self,
+        name: impl Into<String>,
+        policy: impl Into<String>,
+        hmac: Option<[u8; 32]>,
+    ) -> Self {
+        self.wallet = Some((name.into(), policy.into(), hmac));
+        self
+    }
+
+    /// Only the Ledger and the BitBox02 display the requested xpubs.
+    pub fn display_xpub(mut self, display: bool) -> Self {
+        self.display_xpub = display;
+        self
+    }
+
+    /// Only the Ledger has an option to export the xpubs of the unusual paths,
+    /// see ledger::Ledger::allow_unusual_paths.
+    pub fn allow_unusual_paths(mut self, allow: bool) -> Self {
+        self.allow_unusual_paths = allow;
+        self
+    }
+
+    /// Receives the code of the BitBox02 pairing, which the host shows to the user
+    /// to compare it with the code displayed by the device before the confirmation.
+    pub fn on_pairing_code(mut self, callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_pairing_code = Some(PairingCode(Arc::new(callback)));
+        self
+    }
+
+    #[cfg(any(feature = "hidapi", feature = "jade"))]
+    fn context_or_global(&self) -> Result<Context, Error> {
+        match &self.ctx {
+            Some(ctx) => Ok(ctx.clone()),
+            None => Context::global(),
+        }
+    }
+
+    pub fn connect(&self, kind: DeviceKind) -> Result<Box<dyn HWI + Send>, Error> {
+        let device: Result<Box<dyn HWI + Send>, Error> = match kind {
+            #[cfg(feature = "ledger")]
+            DeviceKind::Ledger | DeviceKind::LedgerSimulator => {
+                crate::ledger::connect_with_options(
+                    &self.context_or_global()?,
+                    &self.ledger_options(kind)?,
+                )
+            }
+            #[cfg(feature = "jade")]
+            DeviceKind::Jade | DeviceKind::JadeSimulator => {
+                let options = crate::jade::ConnectOptions {
+                    port: self.path.clone().filter(|_| kind == DeviceKind::Jade),
+                    simulator: if kind == DeviceKind::JadeSimulator {
+                        Some(self.path.clone().unwrap_or_else(|| {
+                            crate::jade::TcpTransport::DEFAULT_ADDRESS.to_string()
+                        }))
+                    } else {
+                        None
+                    },
+                    network: self.network,
+                    wallet: None,
+                    pinserver_url: None,
+                };
+                crate::jade::connect_with_options(&self.context_or_global()?, &options)
+            }
+            #[cfg(feature = "specter")]
+            DeviceKind::Specter | DeviceKind::SpecterSimulator => {
+                let options = if kind == DeviceKind::Specter {
+                    crate::specter::ConnectOptions::Serial {
+                        port: self.path.clone(),
+                    }
+                } else {
+                    crate::specter::ConnectOptions::Simulator
+                };
+                crate::specter::connect_with_options(&options)
+            }
+            #[cfg(feature = "bitbox")]
+            DeviceKind::BitBox02 => {
+                let device = crate::bitbox::connect_hid(
+                    &self.context_or_global()?,
+                    self.path.as_deref(),
+                    |code| {
+                        if let Some(PairingCode(callback)) = &self.on_pairing_code {
+                            callback(code);
+                        }
+                    },
+                )?
+                .display_xpub(self.display_xpub);
+                Ok(match self.network {
+                    Some(network) => device.with_network(network),
+                    None => device,
+                }
+                .into())
+            }
+            #[cfg(feature = "coldcard")]
+            DeviceKind::Coldcard => {
+                let device =
+                    crate::coldcard::connect_hid(&self.context_or_global()?, self.path.as_deref())?;
+                Ok(match self.network {
+                    Some(network) => device.with_network(network),
+                    None => device,
+                }
+                .into())
+            }
+            DeviceKind::HotSigner => Err(Error::InvalidParameter(
+                "kind",
+                format!("{} cannot be connected with the connector", kind),
+            )),
+            #[allow(unreachable_patterns)]
+            _ => Err(Error::InvalidParameter(
+                "kind",
+                format!("the {} backend is not enabled", kind),
+            )),
+        };
+        let mut device = device?;
+        if let Some((name, policy, hmac)) = &self.wallet {
+            device.load_wallet(name, policy, *hmac)?;
+        }
+        Ok(device)
+    }
+
+    /// Lists the devices of the enabled backends, their path is given back to
+    /// Connector::path to connect them. The backends are probed on their own thread,
+    /// so that a wedged device does not delay the others: the backends which did not
+    /// answer before the timeout report Error::Timeout, and are left to finish in the
+    /// background.
+    #[allow(clippy::vec_init_then_push)]
+    pub fn enumerate(&self, timeout: Duration) -> Result<Enumeration, Error> {
+        #[allow(unused_mut)]
+        let mut probes: Vec<(Vec<DeviceKind>, Probe)> = Vec::new();
+        #[cfg(feature = "hidapi")]
+        {
+            let ctx = self.context_or_global()?;
+            probes.push((hid_kinds(), Box::new(move || hid_devices(&ctx))));
+        }
+        #[cfg(feature = "jade")]
+        probes.push((vec![DeviceKind::Jade], Box::new(jade_devices)));
+        #[cfg(feature = "specter")]
+        probes.push((vec![DeviceKind::Specter], Box::new(specter_devices)));
+        Ok(probe_all(probes, timeout))
+    }
+
+    /// Connects the first device with the master fingerprint among the devices listed by
+    /// Connector::enumerate, to route a PSBT to the right cosigner, see
+    /// utils::find_device_by_fingerprint. Every device listed before the match is
+    /// connected, the BitBox02 are paired.
+    pub fn connect_fingerprint(
+        &self,
+        fingerprint: Fingerprint,
+        timeout: Duration,
+    ) -> Result<Box<dyn HWI + Send>, Error> {
+        let devices = self
+            .enumerate(timeout)?
+            .devices
+            .into_iter()
+            .filter_map(|details| {
+                let connector = match details.path {
+                    Some(path) => self.clone().path(path),
+                    None => self.clone(),
+                };
+                connector.connect(details.kind).ok()
+            });
+        utils::find_device_by_fingerprint(devices, fingerprint).ok_or(Error::DeviceNotFound)
+    }
+
+    #[cfg(feature = "ledger")]
+    fn ledger_options(&self, kind: DeviceKind) -> Result<crate::ledger::ConnectOptions, Error> {
+        use crate::ledger::ConnectTransport;
+        let transport = if kind == DeviceKind::LedgerSimulator {
+            ConnectTransport::Simulator {
+                address: self.path.as_deref().map(str::parse).transpose().map_err(
+                    |e: std::net::AddrParseError| Error::InvalidParameter("path", e.to_string()),
+                )?,
+            }
+        } else {
+            ConnectTransport::Hid {
+                path: self.path.clone(),
+            }
+        };
+        Ok(crate::ledger::ConnectOptions {
+            transport,
+            network: self.network,
+            display_xpub: self.display_xpub,
+            allow_unusual_paths: self.allow_unusual_paths,
+            ..Default::default()
+        })
+    }
+}
+
+/// Devices found by Connector::enumerate, with the errors of the backends which failed
+/// or did not answer in time.
+#[derive(Debug, Clone, Default)]
+pub struct Enumeration {
+    pub devices: Vec<DeviceDetails>,
+    pub errors: Vec<(DeviceKind, Error)>,
+}
+
+/// Lists the devices of the kinds it is given with, a probe of the HID devices lists
+/// the devices of all the HID backends.
+type Probe = Box<dyn FnOnce() -> Result<Vec<DeviceDetails>, Error> + Send>;
+
+fn probe_all(probes: Vec<(Vec<DeviceKind>, Probe)>, timeout: Duration) -> Enumeration {
+    let (sender, receiver) = mpsc::channel();
+    let mut pending = Vec::with_capacity(probes.len());
+    for (i, (kinds, probe)) in probes.into_iter().enumerate() {
+        pending.push(Some(kinds));
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send((i, probe()));
+        });
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + timeout;
+    let mut enumeration = Enumeration::default();
+    let error = loop {
+        if pending.iter().all(Option::is_none) {
+            break Error::Timeout;
+        }
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((i, res)) => {
+                let kinds = pending[i].take().unwrap_or_default();
+                match res {
+                    Ok(devices) => enumeration.devices.extend(devices),
+                    Err(e) => enumeration
+                        .errors
+                        .extend(kinds.into_iter().map(|kind| (kind, e.clone()))),
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => break Error::Timeout,
+            Err(RecvTimeoutError::Disconnected) => {
+                break Error::Unexpected("Device enumeration panicked")
+            }
+        }
+    };
+    for kind in pending.into_iter().flatten().flatten() {
+        enumeration.errors.push((kind, error.clone()));
+    }
+    enumeration
+}
+
+#[cfg(feature = "hidapi")]
+fn hid_kinds() -> Vec<DeviceKind> {
+    [
+        #[cfg(feature = "ledger")]
+        DeviceKind::Ledger,
+        #[cfg(feature = "bitbox")]
+        DeviceKind::BitBox02,
+        #[cfg(feature = "coldcard")]
+        DeviceKind::Coldcard,
+    ]
+    .to_vec()
+}
+
+/// The HID devices are listed once for all the HID backends.
+#[cfg(feature = "hidapi")]
+#[allow(unused_variables, unused_mut)]
+fn hid_devices(ctx: &Context) -> Result<Vec<DeviceDetails>, Error> {
+    let infos = ctx.hid_devices()?;
+    let mut devices = Vec::new();
+    #[cfg(feature = "ledger")]
+    devices.extend(crate::ledger::Ledger::enumerate_details(ctx)?);
+    for info in &infos {
+        #[cfg(feature = "bitbox")]
+        if crate::bitbox::is_bitbox02(info) {
+            devices.push(crate::bitbox::details(info));
+        }
+        #[cfg(feature = "coldcard")]
+        if crate::coldcard::is_coldcard(info) {
+            devices.push(DeviceDetails::from_hid(DeviceKind::Coldcard, None, info));
+        }
+    }
+    Ok(devices)
+}
+
+#[cfg(feature = "jade")]
+fn jade_devices() -> Result<Vec<DeviceDetails>, Error> {
+    let ports = crate::jade::SerialTransport::enumerate_potential_ports()?;
+    Ok(serial_devices(DeviceKind::Jade, ports))
+}
+
+#[cfg(feature = "specter")]
+fn specter_devices() -> Result<Vec<DeviceDetails>, Error> {
+    let ports = crate::specter::SerialTransport::enumerate_potential_ports()?;
+    Ok(serial_devices(DeviceKind::Specter, ports))
+}
+
+#[cfg(any(feature = "jade", feature = "specter"))]
+fn serial_devices(kind: DeviceKind, ports: Vec<String>) -> Vec<DeviceDetails> {
+    ports
+        .into_iter()
+        .map(|port| DeviceDetails {
+            kind,
+            model: None,
+            serial: None,
+            path: Some(port),
+            product: None,
+            user_label: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connector() {
+        let connector = Connector::new().network(Network::Testnet);
+        assert!(matches!(
+            connector.connect(DeviceKind::HotSigner),
+            Err(Error::InvalidParameter("kind", _))
+        ));
+    }
+
+    #[cfg(all(feature = "bitbox", feature = "coldcard"))]
+    #[test]
+    fn test_connector_hid() {
+        let connector = Connector::new()
+            .path("/dev/hidraw-missing")
+            .on_pairing_code(|_| panic!("no device to pair"));
+        for kind in [DeviceKind::BitBox02, DeviceKind::Coldcard] {
+            assert!(matches!(
+                connector.connect(kind),
+                Err(Error::DeviceNotFound)
+            ));
+        }
+    }
+
+    #[cfg(feature = "ledger")]
+    #[test]
+    fn test_connector_ledger() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let connector = Connector::new()
+            .path(listener.local_addr().unwrap().to_string())
+            .display_xpub(true);
+        let device = connector.connect(DeviceKind::LedgerSimulator).unwrap();
+        assert_eq!(device.device_kind(), DeviceKind::LedgerSimulator);
+
+        assert!(matches!(
+            connector
+                .path("laptop")
+                .connect(DeviceKind::LedgerSimulator),
+            Err(Error::InvalidParameter("path", _))
+        ));
+    }
+
+    #[test]
+    fn test_probe_all() {
+        let details = |kind| DeviceDetails {
+            kind,
+            model: None,
+            serial: None,
+            path: None,
+            product: None,
+            user_label: None,
+        };
+        let probes: Vec<(Vec<DeviceKind>, Probe)> = vec![
+            (
+                vec![DeviceKind::Ledger, DeviceKind::BitBox02],
+                Box::new(move || Ok(vec![details(DeviceKind::Ledger)])),
+            ),
+            (
+                vec![DeviceKind::Jade],
+                Box::new(|| Err(Error::DeviceNotFound)),
+            ),
+            (
+                vec![DeviceKind::Specter],
+                Box::new(|| {
+                    thread::sleep(Duration::from_secs(5));
+                    Ok(Vec::new())
+                }),
+            ),
+        ];
+        let start = Instant::now();
+        let enumeration = probe_all(probes, Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(enumeration.devices, vec![details(DeviceKind::Ledger)]);
+        assert_eq!(enumeration.errors.len(), 2);
+        assert!(enumeration
+            .errors
+            .iter()
+            .any(|(kind, e)| *kind == DeviceKind::Jade && matches!(e, Error::DeviceNotFound)));
+        assert!(enumeration
+            .errors
+            .iter()
+            .any(|(kind, e)| *kind == DeviceKind::Specter && matches!(e, Error::Timeout)));
+    }
+}
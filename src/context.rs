@@ -0,0 +1,138 @@
+use std::sync::Mutex;
+#[cfg(feature = "hidapi")]
+use std::sync::{Arc, MutexGuard};
+
+#[cfg(feature = "hidapi")]
+use hidapi::{DeviceInfo, HidApi};
+
+use crate::Error;
+#[cfg(feature = "hidapi")]
+use crate::TransportErrorKind;
+
+/// Creates a context owning the resources shared by the backends.
+pub fn init() -> Result<Context, Error> {
+    Context::new()
+}
+
+/// Resources shared by the backends, released when the context and the devices
+/// connected with it are dropped. Clones share the resources, separate contexts
+/// are isolated from each other.
+#[derive(Clone)]
+pub struct Context {
+    #[cfg(feature = "hidapi")]
+    hid: Arc<Mutex<HidApi>>,
+    /// Client of the Jade pin servers.
+    #[cfg(feature = "jade")]
+    http: reqwest::Client,
+}
+
+/// Context of the process, see Context::global.
+static GLOBAL: Mutex<Option<Context>> = Mutex::new(None);
+
+impl Context {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            #[cfg(feature = "hidapi")]
+            hid: Arc::new(Mutex::new(HidApi::new().map_err(hid_error)?)),
+            #[cfg(feature = "jade")]
+            http: reqwest::Client::new(),
+        })
+    }
+
+    /// Context shared by the process, created on first use, for the applications
+    /// which do not hold their own context.
+    pub fn global() -> Result<Self, Error> {
+        let mut global = GLOBAL
+            .lock()
+            .map_err(|_| Error::Unexpected("Failed to unlock"))?;
+        match &*global {
+            Some(ctx) => Ok(ctx.clone()),
+            None => Ok(global.insert(Self::new()?).clone()),
+        }
+    }
+
+    /// HID api of the context, the lock must be released before connecting a device
+    /// with the context.
+    #[cfg(feature = "hidapi")]
+    pub fn hid_api(&self) -> Result<MutexGuard<'_, HidApi>, Error> {
+        self.hid
+            .lock()
+            .map_err(|_| Error::Unexpected("Failed to unlock"))
+    }
+
+    /// Refreshes and returns the list of the connected HID devices.
+    #[cfg(feature = "hidapi")]
+    pub fn hid_devices(&self) -> Result<Vec<DeviceInfo>, Error> {
+        let mut api = self.hid_api()?;
+        api.refresh_devices().map_err(hid_error)?;
+        Ok(api.device_list().cloned().collect())
+    }
+
+    /// HID device at the path, or the first one accepted by the filter if no path
+    /// is given.
+    #[cfg(feature = "hidapi")]
+    pub fn hid_device(
+        &self,
+        path: Option<&str>,
+        filter: impl Fn(&DeviceInfo) -> bool,
+    ) -> Result<DeviceInfo, Error> {
+        self.hid_devices()?
+            .into_iter()
+            .filter(|info| filter(info))
+            .find(|info| match path {
+                Some(path) => info.path().to_string_lossy() == path,
+                None => true,
+            })
+            .ok_or(Error::DeviceNotFound)
+    }
+
+    #[cfg(feature = "jade")]
+    pub fn http_client(&self) -> &reqwest::Client {
+        &self.http
+    }
+}
+
+impl std::fmt::Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context").finish()
+    }
+}
+
+#[cfg(feature = "hidapi")]
+fn hid_error(e: hidapi::HidError) -> Error {
+    Error::Transport(TransportErrorKind::Io, e.to_string())
+}
+
+/// Error of a HID device the hidapi failed to open. The hidapi does not tell why, on
+/// Linux the hidraw node is opened again to tell a missing udev rule from a device gone.
+#[cfg(feature = "hidapi")]
+#[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+pub fn open_error(path: &std::ffi::CStr) -> Error {
+    #[cfg(target_os = "linux")]
+    {
+        let path = path.to_string_lossy();
+        if let Err(e) = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&*path)
+        {
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                return Error::PermissionDenied {
+                    path: path.into_owned(),
+                };
+            }
+        }
+    }
+    Error::DeviceNotFound
+}
+
+#[cfg(all(test, feature = "hidapi"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_error() {
+        let path = std::ffi::CString::new("/dev/hidraw-missing").unwrap();
+        assert!(matches!(open_error(&path), Error::DeviceNotFound));
+    }
+}
@@ -0,0 +1,326 @@
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{DerivationPath, Fingerprint, Xpub},
+    psbt::{self, Psbt},
+    Address, Network,
+};
+
+use crate::{
+    bip85, slip19, stats::Operation, AddressScript, Attestation, Capabilities, DeviceKind,
+    DeviceModel, Error, InputSignature, RegisteredWallet, ScriptType, SignResult,
+    SilentPaymentKeys, Version, WalletRegistration, HWI,
+};
+
+/// Device wrapping another one. The methods forward to the inner device and the
+/// wrappers override the ones they change, delegate_hwi implements HWI with them.
+pub(crate) trait Delegate {
+    type Inner: HWI + ?Sized;
+
+    fn inner(&self) -> &Self::Inner;
+
+    /// Fails if the inner device cannot be changed, e.g. while it is shared.
+    fn inner_mut(&mut self) -> Result<&mut Self::Inner, Error>;
+
+    /// Called around each operation reaching the device, the local settings
+    /// set_network and load_wallet and the reset of the connection are not.
+    fn call<R>(
+        &self,
+        _operation: Operation,
+        call: impl FnOnce(&Self::Inner) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        call(self.inner())
+    }
+
+    fn device_kind(&self) -> DeviceKind {
+        self.inner().device_kind()
+    }
+
+    fn device_model(&self) -> Option<DeviceModel> {
+        self.inner().device_model()
+    }
+
+    fn get_version(&self) -> Result<Version, Error> {
+        self.call(Operation::GetVersion, |device| device.get_version())
+    }
+
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, Error> {
+        self.call(Operation::GetMasterFingerprint, |device| {
+            device.get_master_fingerprint()
+        })
+    }
+
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+        self.call(Operation::GetExtendedPubkey, |device| {
+            device.get_extended_pubkey(path)
+        })
+    }
+
+    fn get_extended_pubkeys(&self, paths: &[DerivationPath]) -> Result<Vec<Xpub>, Error> {
+        self.call(Operation::GetExtendedPubkey, |device| {
+            device.get_extended_pubkeys(paths)
+        })
+    }
+
+    fn displays_xpub(&self, path: &DerivationPath) -> bool {
+        self.inner().displays_xpub(path)
+    }
+
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, Error> {
+        self.call(Operation::RegisterWallet, |device| {
+            device.register_wallet(name, policy)
+        })
+    }
+
+    fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, Error> {
+        self.call(Operation::IsWalletRegistered, |device| {
+            device.is_wallet_registered(name, policy)
+        })
+    }
+
+    fn display_address(&self, script: &AddressScript) -> Result<Address<NetworkUnchecked>, Error> {
+        self.call(Operation::DisplayAddress, |device| {
+            device.display_address(script)
+        })
+    }
+
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        self.call(Operation::GetAddress, |device| {
+            device.get_address(script, display)
+        })
+    }
+
+    fn sign_tx(&self, tx: &mut Psbt) -> Result<SignResult, Error> {
+        self.call(Operation::SignTx, |device| device.sign_tx(tx))
+    }
+
+    fn sign_tx_progressive(
+        &self,
+        tx: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, Error> {
+        self.call(Operation::SignTx, |device| {
+            device.sign_tx_progressive(tx, on_signature)
+        })
+    }
+
+    fn sign_txs(&self, txs: &mut [Psbt]) -> Result<Vec<SignResult>, Error> {
+        self.call(Operation::SignTx, |device| device.sign_txs(txs))
+    }
+
+    fn derive_bip85(&self, application: &bip85::Application, index: u32) -> Result<Vec<u8>, Error> {
+        self.call(Operation::DeriveBip85, |device| {
+            device.derive_bip85(application, index)
+        })
+    }
+
+    fn prove_ownership(
+        &self,
+        input: &psbt::Input,
+        commitment_data: &[u8],
+    ) -> Result<slip19::OwnershipProof, Error> {
+        self.call(Operation::ProveOwnership, |device| {
+            device.prove_ownership(input, commitment_data)
+        })
+    }
+
+    fn get_silent_payment_keys(&self, account: u32) -> Result<SilentPaymentKeys, Error> {
+        self.call(Operation::GetSilentPaymentKeys, |device| {
+            device.get_silent_payment_keys(account)
+        })
+    }
+
+    fn get_label(&self) -> Result<Option<String>, Error> {
+        self.call(Operation::GetLabel, |device| device.get_label())
+    }
+
+    fn list_registered_wallets(&self) -> Result<Vec<RegisteredWallet>, Error> {
+        self.call(Operation::ListRegisteredWallets, |device| {
+            device.list_registered_wallets()
+        })
+    }
+
+    fn verify_attestation(&self) -> Result<Attestation, Error> {
+        self.call(Operation::VerifyAttestation, |device| {
+            device.verify_attestation()
+        })
+    }
+
+    fn get_descriptor(
+        &self,
+        script: ScriptType,
+        network: Network,
+        account: u32,
+    ) -> Result<String, Error> {
+        self.call(Operation::GetDescriptor, |device| {
+            device.get_descriptor(script, network, account)
+        })
+    }
+
+    fn set_label(&self, label: &str) -> Result<(), Error> {
+        self.call(Operation::SetLabel, |device| device.set_label(label))
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<(), Error> {
+        self.inner_mut()?.set_network(network)
+    }
+
+    fn load_wallet(
+        &mut self,
+        name: &str,
+        policy: &str,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<(), Error> {
+        self.inner_mut()?.load_wallet(name, policy, hmac)
+    }
+
+    fn reset_connection(&self) -> Result<(), Error> {
+        self.inner().reset_connection()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner().capabilities()
+    }
+}
+
+/// Implements HWI for a type implementing Delegate, the generics are given in brackets:
+/// `delegate_hwi!([T: HWI] ReadOnly<T>);`
+macro_rules! delegate_hwi {
+    ([$($generics:tt)*] $type:ty) => {
+        impl<$($generics)*> $crate::HWI for $type {
+            fn device_kind(&self) -> $crate::DeviceKind {
+                $crate::delegate::Delegate::device_kind(self)
+            }
+            fn device_model(&self) -> Option<$crate::DeviceModel> {
+                $crate::delegate::Delegate::device_model(self)
+            }
+            fn get_version(&self) -> Result<$crate::Version, $crate::Error> {
+                $crate::delegate::Delegate::get_version(self)
+            }
+            fn get_master_fingerprint(
+                &self,
+            ) -> Result<bitcoin::bip32::Fingerprint, $crate::Error> {
+                $crate::delegate::Delegate::get_master_fingerprint(self)
+            }
+            fn get_extended_pubkey(
+                &self,
+                path: &bitcoin::bip32::DerivationPath,
+            ) -> Result<bitcoin::bip32::Xpub, $crate::Error> {
+                $crate::delegate::Delegate::get_extended_pubkey(self, path)
+            }
+            fn get_extended_pubkeys(
+                &self,
+                paths: &[bitcoin::bip32::DerivationPath],
+            ) -> Result<Vec<bitcoin::bip32::Xpub>, $crate::Error> {
+                $crate::delegate::Delegate::get_extended_pubkeys(self, paths)
+            }
+            fn displays_xpub(&self, path: &bitcoin::bip32::DerivationPath) -> bool {
+                $crate::delegate::Delegate::displays_xpub(self, path)
+            }
+            fn register_wallet(
+                &self,
+                name: &str,
+                policy: &str,
+            ) -> Result<$crate::WalletRegistration, $crate::Error> {
+                $crate::delegate::Delegate::register_wallet(self, name, policy)
+            }
+            fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, $crate::Error> {
+                $crate::delegate::Delegate::is_wallet_registered(self, name, policy)
+            }
+            fn display_address(
+                &self,
+                script: &$crate::AddressScript,
+            ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, $crate::Error> {
+                $crate::delegate::Delegate::display_address(self, script)
+            }
+            fn get_address(
+                &self,
+                script: &$crate::AddressScript,
+                display: bool,
+            ) -> Result<bitcoin::Address<bitcoin::address::NetworkUnchecked>, $crate::Error> {
+                $crate::delegate::Delegate::get_address(self, script, display)
+            }
+            fn sign_tx(
+                &self,
+                tx: &mut bitcoin::psbt::Psbt,
+            ) -> Result<$crate::SignResult, $crate::Error> {
+                $crate::delegate::Delegate::sign_tx(self, tx)
+            }
+            fn sign_tx_progressive(
+                &self,
+                tx: &mut bitcoin::psbt::Psbt,
+                on_signature: &mut dyn FnMut(usize, $crate::InputSignature),
+            ) -> Result<$crate::SignResult, $crate::Error> {
+                $crate::delegate::Delegate::sign_tx_progressive(self, tx, on_signature)
+            }
+            fn sign_txs(
+                &self,
+                txs: &mut [bitcoin::psbt::Psbt],
+            ) -> Result<Vec<$crate::SignResult>, $crate::Error> {
+                $crate::delegate::Delegate::sign_txs(self, txs)
+            }
+            fn derive_bip85(
+                &self,
+                application: &$crate::bip85::Application,
+                index: u32,
+            ) -> Result<Vec<u8>, $crate::Error> {
+                $crate::delegate::Delegate::derive_bip85(self, application, index)
+            }
+            fn prove_ownership(
+                &self,
+                input: &bitcoin::psbt::Input,
+                commitment_data: &[u8],
+            ) -> Result<$crate::slip19::OwnershipProof, $crate::Error> {
+                $crate::delegate::Delegate::prove_ownership(self, input, commitment_data)
+            }
+            fn get_silent_payment_keys(
+                &self,
+                account: u32,
+            ) -> Result<$crate::SilentPaymentKeys, $crate::Error> {
+                $crate::delegate::Delegate::get_silent_payment_keys(self, account)
+            }
+            fn get_label(&self) -> Result<Option<String>, $crate::Error> {
+                $crate::delegate::Delegate::get_label(self)
+            }
+            fn list_registered_wallets(
+                &self,
+            ) -> Result<Vec<$crate::RegisteredWallet>, $crate::Error> {
+                $crate::delegate::Delegate::list_registered_wallets(self)
+            }
+            fn verify_attestation(&self) -> Result<$crate::Attestation, $crate::Error> {
+                $crate::delegate::Delegate::verify_attestation(self)
+            }
+            fn get_descriptor(
+                &self,
+                script: $crate::ScriptType,
+                network: bitcoin::Network,
+                account: u32,
+            ) -> Result<String, $crate::Error> {
+                $crate::delegate::Delegate::get_descriptor(self, script, network, account)
+            }
+            fn set_label(&self, label: &str) -> Result<(), $crate::Error> {
+                $crate::delegate::Delegate::set_label(self, label)
+            }
+            fn set_network(&mut self, network: bitcoin::Network) -> Result<(), $crate::Error> {
+                $crate::delegate::Delegate::set_network(self, network)
+            }
+            fn load_wallet(
+                &mut self,
+                name: &str,
+                policy: &str,
+                hmac: Option<[u8; 32]>,
+            ) -> Result<(), $crate::Error> {
+                $crate::delegate::Delegate::load_wallet(self, name, policy, hmac)
+            }
+            fn reset_connection(&self) -> Result<(), $crate::Error> {
+                $crate::delegate::Delegate::reset_connection(self)
+            }
+            fn capabilities(&self) -> $crate::Capabilities {
+                $crate::delegate::Delegate::capabilities(self)
+            }
+        }
+    };
+}
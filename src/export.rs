@@ -0,0 +1,160 @@
+use crate::{ur::Ur, utils, Error};
+
+/// Wallet files imported by air-gapped devices, for which registering a wallet
+/// means importing the file or the QR code encoding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalletFormat {
+    /// Multisig configuration file in the Coldcard format, scanned by Keystone as a
+    /// bytes UR.
+    Keystone,
+    /// Multisig configuration file in the Coldcard format, scanned by Passport as a
+    /// bytes UR. The file itself is imported from the microSD, see multisig_wallet_file.
+    Passport,
+    /// Output descriptor with multipath keys, imported by SeedSigner.
+    SeedSigner,
+}
+
+/// Exports the wallet policy in the format imported by the device: the single-part
+/// UR for Keystone and Passport, the descriptor for SeedSigner.
+pub fn export_wallet(format: WalletFormat, name: &str, policy: &str) -> Result<String, Error> {
+    match format {
+        WalletFormat::Keystone | WalletFormat::Passport => {
+            Ok(export_wallet_ur(format, name, policy)?.to_string())
+        }
+        WalletFormat::SeedSigner => Ok(multipath_descriptor(policy)),
+    }
+}
+
+/// UR of the wallet policy scanned by Keystone and Passport, see Ur::encoder for the
+/// animated QR codes of the multi-part encoding. SeedSigner scans the descriptor itself.
+pub fn export_wallet_ur(format: WalletFormat, name: &str, policy: &str) -> Result<Ur, Error> {
+    match format {
+        WalletFormat::Keystone | WalletFormat::Passport => Ok(Ur::from_bytes(
+            multisig_wallet_file(name, policy)?.as_bytes(),
+        )),
+        WalletFormat::SeedSigner => Err(Error::UnsupportedInput),
+    }
+}
+
+/// Script types of the multisig configuration file with their policy prefix.
+const MULTISIG_FORMATS: [(&str, &str); 3] = [
+    ("sh(wsh(sortedmulti(", "P2SH-P2WSH"),
+    ("wsh(sortedmulti(", "P2WSH"),
+    ("sh(sortedmulti(", "P2SH"),
+];
+
+/// Multisig configuration file of a sortedmulti policy,
+/// every key must have an origin and derive the receive and change keychains.
+pub fn multisig_wallet_file(name: &str, policy: &str) -> Result<String, Error> {
+    let (template, keys) = utils::extract_keys_and_template::<String>(policy)?;
+    let (threshold, format) = MULTISIG_FORMATS
+        .iter()
+        .find_map(|(prefix, format)| {
+            template
+                .strip_prefix(prefix)
+                .and_then(|s| s.split_once(','))
+                .map(|(threshold, _)| (threshold, format))
+        })
+        .ok_or(Error::UnsupportedInput)?;
+    let threshold: usize = threshold
+        .parse()
+        .map_err(|_| Error::InvalidParameter("policy", "invalid threshold".to_string()))?;
+    if threshold == 0 || threshold > keys.len() {
+        return Err(Error::InvalidParameter(
+            "policy",
+            format!("threshold {} of {} keys", threshold, keys.len()),
+        ));
+    }
+
+    let mut file = format!(
+        "Name: {}\nPolicy: {} of {}\nFormat: {}\n",
+        name,
+        threshold,
+        keys.len(),
+        format
+    );
+    for (i, key) in keys.iter().enumerate() {
+        if !template.contains(&format!("@{}/**", i)) {
+            return Err(Error::UnsupportedInput);
+        }
+        match utils::parse_key_with_origin(key)? {
+            (Some((fg, path)), xpub) => {
+                file.push_str(&format!(
+                    "\nDerivation: {}\n{}: {}\n",
+                    path,
+                    fg.to_string().to_uppercase(),
+                    xpub
+                ));
+            }
+            (None, _) => {
+                return Err(Error::InvalidParameter(
+                    "policy",
+                    format!("key {} has no origin", key),
+                ))
+            }
+        }
+    }
+    Ok(file)
+}
+
+/// Output descriptor of the policy with the receive and change keychains
+/// expressed as multipath keys.
+pub fn multipath_descriptor(policy: &str) -> String {
+    let policy = policy.rsplit_once('#').map_or(policy, |(policy, _)| policy);
+    policy.replace("/**", "/<0;1>/*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "wsh(sortedmulti(2,[1a1ffd98/48'/1'/0'/2']tpubDFZqzTvGijYb13BC73CkS1er8DrP5YdzMhziN3kWCKUFaW51Yj6ggvf99YpdrkTJy4RT85mxQMHXDiFAKRxzf6BykQgT4pRRBNPshSJJcKo/**,[de6eb005/48'/1'/0'/2']tpubDFGuYfS2JwiUSEXiQuNGdT3R7WTDhbaE6jbUhgYSSdhmfQcSx7ZntMPPv7nrkvAqjpj3jX9wbhSGMeKVao4qAzhbNyBi7iQmv5xxQk6H6jz/**))";
+
+    #[test]
+    fn test_multisig_wallet_file() {
+        assert_eq!(
+            multisig_wallet_file("Vault", POLICY).unwrap(),
+            "Name: Vault\n\
+             Policy: 2 of 2\n\
+             Format: P2WSH\n\
+             \n\
+             Derivation: m/48'/1'/0'/2'\n\
+             1A1FFD98: tpubDFZqzTvGijYb13BC73CkS1er8DrP5YdzMhziN3kWCKUFaW51Yj6ggvf99YpdrkTJy4RT85mxQMHXDiFAKRxzf6BykQgT4pRRBNPshSJJcKo\n\
+             \n\
+             Derivation: m/48'/1'/0'/2'\n\
+             DE6EB005: tpubDFGuYfS2JwiUSEXiQuNGdT3R7WTDhbaE6jbUhgYSSdhmfQcSx7ZntMPPv7nrkvAqjpj3jX9wbhSGMeKVao4qAzhbNyBi7iQmv5xxQk6H6jz\n"
+        );
+        assert!(multisig_wallet_file("Vault", &POLICY.replace("2,", "3,")).is_err());
+        assert!(multisig_wallet_file("Vault", &POLICY.replace("sortedmulti", "multi")).is_err());
+    }
+
+    #[test]
+    fn test_export_wallet() {
+        let file = multisig_wallet_file("Vault", POLICY).unwrap();
+        for format in [WalletFormat::Keystone, WalletFormat::Passport] {
+            let ur: Ur = export_wallet(format, "Vault", POLICY)
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(ur.ur_type, crate::ur::BYTES);
+            assert_eq!(ur.to_bytes().unwrap(), file.as_bytes());
+            assert_eq!(export_wallet_ur(format, "Vault", POLICY).unwrap(), ur);
+        }
+        assert_eq!(
+            export_wallet(WalletFormat::SeedSigner, "Vault", POLICY).unwrap(),
+            multipath_descriptor(POLICY)
+        );
+        assert!(matches!(
+            export_wallet_ur(WalletFormat::SeedSigner, "Vault", POLICY),
+            Err(Error::UnsupportedInput)
+        ));
+    }
+
+    #[test]
+    fn test_multipath_descriptor() {
+        assert_eq!(
+            multipath_descriptor("wpkh([1a1ffd98/84'/1'/0']tpubD/**)#abcdefgh"),
+            "wpkh([1a1ffd98/84'/1'/0']tpubD/<0;1>/*)"
+        );
+    }
+}
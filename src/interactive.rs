@@ -0,0 +1,267 @@
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use bitcoin::{address::NetworkUnchecked, psbt::Psbt, Address};
+
+use crate::{AddressScript, Error, InputSignature, SignResult, WalletRegistration, HWI};
+
+/// Time spent waiting for the user, given to the callback of Interactive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitStatus {
+    pub elapsed: Duration,
+    /// Time left before the deadline, zero once it passed.
+    pub remaining: Duration,
+}
+
+/// Decision of the host while the device is waiting for the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitDecision {
+    /// Keep waiting until the deadline, the operation fails with Error::Timeout after it.
+    Continue,
+    /// Push the deadline back by the duration, from now if it already passed.
+    Extend(Duration),
+    /// Stop waiting, the operation fails with Error::Cancelled.
+    Abort,
+}
+
+type OnWaiting = Box<dyn FnMut(WaitStatus) -> WaitDecision + Send>;
+
+/// Interactive wraps a device and time-boxes the operations waiting for the user:
+/// address display, wallet registration and signing. The callback is called at each
+/// interval and at the deadline, so that the UI can show that the device is still
+/// waiting and let the user extend the deadline or cancel.
+/// The operation runs on its own thread, once the host stopped waiting the device keeps
+/// the request on screen and the next operations wait for the user to answer it.
+pub struct Interactive<T> {
+    device: Arc<T>,
+    deadline: Duration,
+    interval: Duration,
+    on_waiting: Mutex<OnWaiting>,
+}
+
+enum Event<R> {
+    Signature(usize, InputSignature),
+    Done(Result<R, Error>),
+}
+
+impl<T: HWI + Send + Sync + 'static> Interactive<T> {
+    pub fn new<F>(device: T, deadline: Duration, interval: Duration, on_waiting: F) -> Self
+    where
+        F: FnMut(WaitStatus) -> WaitDecision + Send + 'static,
+    {
+        Self {
+            device: Arc::new(device),
+            deadline,
+            interval,
+            on_waiting: Mutex::new(Box::new(on_waiting)),
+        }
+    }
+
+    pub fn device(&self) -> &T {
+        &self.device
+    }
+
+    fn wait<R, F>(
+        &self,
+        operation: F,
+        mut on_signature: Option<&mut dyn FnMut(usize, InputSignature)>,
+    ) -> Result<R, Error>
+    where
+        R: Send + 'static,
+        F: FnOnce(&T, &Sender<Event<R>>) -> Result<R, Error> + Send + 'static,
+    {
+        let mut on_waiting = self
+            .on_waiting
+            .lock()
+            .map_err(|_| Error::Unexpected("Failed to unlock"))?;
+        let (sender, receiver) = mpsc::channel();
+        let device = self.device.clone();
+        thread::spawn(move || {
+            let res = operation(&device, &sender);
+            let _ = sender.send(Event::Done(res));
+        });
+
+        let start = Instant::now();
+        let mut deadline = start + self.deadline;
+        loop {
+            let now = Instant::now();
+            let timeout = self.interval.min(deadline.saturating_duration_since(now));
+            match receiver.recv_timeout(timeout) {
+                Ok(Event::Done(res)) => return res,
+                Ok(Event::Signature(i, sig)) => {
+                    if let Some(on_signature) = on_signature.as_mut() {
+                        on_signature(i, sig);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::Unexpected("Device operation panicked"))
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    let status = WaitStatus {
+                        elapsed: now - start,
+                        remaining: deadline.saturating_duration_since(now),
+                    };
+                    match (*on_waiting)(status) {
+                        WaitDecision::Abort => return Err(Error::Cancelled),
+                        WaitDecision::Extend(duration) => {
+                            deadline = deadline.max(now) + duration;
+                        }
+                        WaitDecision::Continue if now >= deadline => return Err(Error::Timeout),
+                        WaitDecision::Continue => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Interactive<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Interactive")
+            .field("device", &self.device)
+            .field("deadline", &self.deadline)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: HWI + Send + Sync + 'static> crate::delegate::Delegate for Interactive<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.device
+    }
+
+    /// Fails with Error::Unexpected while an operation the host stopped waiting for
+    /// is still running.
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Arc::get_mut(&mut self.device).ok_or(Error::Unexpected("Device is busy"))
+    }
+
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, Error> {
+        let (name, policy) = (name.to_string(), policy.to_string());
+        self.wait(
+            move |device, _| device.register_wallet(&name, &policy),
+            None,
+        )
+    }
+
+    fn display_address(&self, script: &AddressScript) -> Result<Address<NetworkUnchecked>, Error> {
+        let script = script.clone();
+        self.wait(move |device, _| device.display_address(&script), None)
+    }
+
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        if !display {
+            return self.inner().get_address(script, false);
+        }
+        HWI::display_address(self, script)
+    }
+
+    fn sign_tx(&self, tx: &mut Psbt) -> Result<SignResult, Error> {
+        let mut psbt = tx.clone();
+        let (signed, res) = self.wait(
+            move |device, _| {
+                let res = device.sign_tx(&mut psbt)?;
+                Ok((psbt, res))
+            },
+            None,
+        )?;
+        *tx = signed;
+        Ok(res)
+    }
+
+    fn sign_tx_progressive(
+        &self,
+        tx: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, Error> {
+        let mut psbt = tx.clone();
+        let (signed, res) = self.wait(
+            move |device, sender| {
+                let res = device.sign_tx_progressive(&mut psbt, &mut |i, sig| {
+                    let _ = sender.send(Event::Signature(i, sig));
+                })?;
+                Ok((psbt, res))
+            },
+            Some(on_signature),
+        )?;
+        *tx = signed;
+        Ok(res)
+    }
+
+    /// Each psbt is time-boxed on its own.
+    fn sign_txs(&self, txs: &mut [Psbt]) -> Result<Vec<SignResult>, Error> {
+        txs.iter_mut().map(|tx| HWI::sign_tx(self, tx)).collect()
+    }
+}
+
+delegate_hwi!([T: HWI + Send + Sync + 'static] Interactive<T>);
+
+impl<T: 'static + HWI + Send + Sync> From<Interactive<T>> for Box<dyn HWI + Send> {
+    fn from(s: Interactive<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockDevice, DeviceKind};
+
+    fn display(
+        user: Duration,
+        decide: impl FnMut(WaitStatus) -> WaitDecision + Send + 'static,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        let ms = Duration::from_millis;
+        // The user takes the given time to check the address, then refuses it.
+        let slow = MockDevice {
+            address: Err(Error::UserRefused),
+            delay: user,
+            ..MockDevice::new(DeviceKind::Ledger)
+        };
+        let device = Interactive::new(slow, ms(100), ms(20), decide);
+        device.display_address(&AddressScript::Miniscript {
+            index: 0,
+            change: false,
+        })
+    }
+
+    #[test]
+    fn test_interactive() {
+        let ms = Duration::from_millis;
+        // Answered before the deadline.
+        assert!(matches!(
+            display(ms(0), |_| WaitDecision::Continue),
+            Err(Error::UserRefused)
+        ));
+        assert!(matches!(
+            display(ms(300), |_| WaitDecision::Continue),
+            Err(Error::Timeout)
+        ));
+        assert!(matches!(
+            display(ms(300), |_| WaitDecision::Abort),
+            Err(Error::Cancelled)
+        ));
+        // Extended once the deadline passed.
+        assert!(matches!(
+            display(ms(300), |status| if status.remaining.is_zero() {
+                WaitDecision::Extend(Duration::from_secs(5))
+            } else {
+                WaitDecision::Continue
+            }),
+            Err(Error::UserRefused)
+        ));
+    }
+}
@@ -168,6 +168,13 @@ pub struct DescriptorAddressParams<'a> {
     pub descriptor_name: &'a str,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SingleSigAddressParams<'a> {
+    pub network: &'a str,
+    pub variant: &'a str,
+    pub path: Vec<u32>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignPsbtParams<'a> {
     pub network: &'a str,
@@ -175,6 +182,11 @@ pub struct SignPsbtParams<'a> {
     pub psbt: Vec<u8>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetMasterBlindingKeyParams {
+    pub only_if_silent: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetExtendedDataParams<'a> {
     pub origid: &'a str,
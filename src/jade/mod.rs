@@ -4,32 +4,33 @@ pub mod pinserver;
 use std::{
     collections::BTreeMap,
     fmt::Debug,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
     str::FromStr,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use bitcoin::{
+    address::NetworkUnchecked,
     bip32::{DerivationPath, Fingerprint, Xpub},
     psbt::Psbt,
-    Network,
+    Address, Network,
 };
 
 use serialport::{available_ports, SerialPort, SerialPortType};
-use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
-    sync::Mutex,
-};
-use tokio_serial::SerialPortBuilderExt;
 
-pub use tokio_serial::SerialStream;
+/// Serial port of the device.
+pub type SerialStream = Box<dyn SerialPort>;
 
 use crate::{parse_version, utils};
 
-use super::{AddressScript, DeviceKind, Error as HWIError, HWI};
-use async_trait::async_trait;
+use super::{
+    AddressScript, Context, DeviceKind, Error as HWIError, RegisteredWallet, SignResult,
+    TransportErrorKind, WalletRegistration, HWI,
+};
 
 pub const JADE_NETWORK_MAINNET: &str = "mainnet";
 pub const JADE_NETWORK_TESTNET: &str = "testnet";
@@ -40,6 +41,7 @@ pub struct Jade<T> {
     network: &'static str,
     kind: DeviceKind,
     descriptor_name: Option<String>,
+    pinserver: pinserver::PinServerClient,
 }
 
 impl<T: Transport + Sync + Send> Jade<T> {
@@ -49,15 +51,28 @@ impl<T: Transport + Sync + Send> Jade<T> {
             network: JADE_NETWORK_MAINNET,
             kind: DeviceKind::Jade,
             descriptor_name: None,
+            pinserver: pinserver::PinServerClient::new(),
         }
     }
 
+    /// The pin server requests of the authentication use the http client of the context.
+    pub fn with_context(mut self, ctx: &Context) -> Self {
+        self.pinserver = pinserver::PinServerClient {
+            url: self.pinserver.url.take(),
+            ..pinserver::PinServerClient::with_context(ctx)
+        };
+        self
+    }
+
+    /// Pin server unlocking the device instead of the Blockstream one,
+    /// see pinserver::PinServerClient::with_url.
+    pub fn with_pinserver_url(mut self, url: impl Into<String>) -> Self {
+        self.pinserver = self.pinserver.with_url(url);
+        self
+    }
+
     pub fn with_network(mut self, network: Network) -> Self {
-        if network == Network::Bitcoin {
-            self.network = JADE_NETWORK_MAINNET;
-        } else {
-            self.network = JADE_NETWORK_TESTNET;
-        }
+        self.network = jade_network(network);
         self
     }
 
@@ -66,25 +81,23 @@ impl<T: Transport + Sync + Send> Jade<T> {
         self
     }
 
-    pub async fn ping(&self) -> Result<(), JadeError> {
+    pub fn ping(&self) -> Result<(), JadeError> {
         let _res: u64 = self
             .transport
-            .request("ping", Option::<api::EmptyRequest>::None)
-            .await?
+            .request("ping", Option::<api::EmptyRequest>::None)?
             .into_result()?;
         Ok(())
     }
 
-    pub async fn get_info(&self) -> Result<api::GetInfoResponse, HWIError> {
+    pub fn get_info(&self) -> Result<api::GetInfoResponse, HWIError> {
         let info: api::GetInfoResponse = self
             .transport
-            .request("get_version_info", Option::<api::EmptyRequest>::None)
-            .await?
+            .request("get_version_info", Option::<api::EmptyRequest>::None)?
             .into_result()?;
         Ok(info)
     }
 
-    pub async fn get_registered_descriptors(
+    pub fn get_registered_descriptors(
         &self,
     ) -> Result<BTreeMap<String, api::DescriptorInfoResponse>, HWIError> {
         let descriptors: BTreeMap<String, api::DescriptorInfoResponse> = self
@@ -92,13 +105,12 @@ impl<T: Transport + Sync + Send> Jade<T> {
             .request(
                 "get_registered_descriptors",
                 Option::<api::EmptyRequest>::None,
-            )
-            .await?
+            )?
             .into_result()?;
         Ok(descriptors)
     }
 
-    pub async fn get_registered_descriptor(
+    pub fn get_registered_descriptor(
         &self,
         name: &str,
     ) -> Result<api::GetRegisteredDescriptorResponse, HWIError> {
@@ -109,13 +121,29 @@ impl<T: Transport + Sync + Send> Jade<T> {
                 Some(api::GetRegisteredDescriptorParams {
                     descriptor_name: name,
                 }),
-            )
-            .await?
+            )?
             .into_result()?;
         Ok(registered)
     }
 
-    pub async fn auth(&self) -> Result<(), JadeError> {
+    /// SLIP-77 master blinding key of the wallet, the user confirms the export on the device.
+    #[cfg(feature = "liquid")]
+    pub fn get_master_blinding_key(&self) -> Result<crate::liquid::MasterBlindingKey, HWIError> {
+        let key: serde_bytes::ByteBuf = self
+            .transport
+            .request(
+                "get_master_blinding_key",
+                Some(api::GetMasterBlindingKeyParams {
+                    only_if_silent: false,
+                }),
+            )?
+            .into_result()?;
+        let key: [u8; 32] = std::convert::TryFrom::try_from(key.as_slice())
+            .map_err(|_| HWIError::Device("Invalid master blinding key".to_string()))?;
+        Ok(crate::liquid::MasterBlindingKey::new(key))
+    }
+
+    pub fn auth(&self) -> Result<(), JadeError> {
         let res: api::AuthUserResponse = self
             .transport
             .request(
@@ -128,17 +156,15 @@ impl<T: Transport + Sync + Send> Jade<T> {
                         .map(|t| t.as_secs())
                         .unwrap_or(0),
                 }),
-            )
-            .await?
+            )?
             .into_result()?;
 
         if let api::AuthUserResponse::PinServerRequired { http_request } = res {
-            let client = pinserver::PinServerClient::new();
-            let pin_params: api::PinParams = client.request(http_request.params).await?;
+            let pin_params: api::PinParams = self.pinserver.request(http_request.params)?;
+            // The method answering the pin server is given by the device.
             let handshake_completed: bool = self
                 .transport
-                .request("pin", Some(pin_params))
-                .await?
+                .request(&http_request.onreply, Some(pin_params))?
                 .into_result()?;
             if !handshake_completed {
                 return Err(JadeError::HandShakeRefused);
@@ -148,23 +174,22 @@ impl<T: Transport + Sync + Send> Jade<T> {
     }
 }
 
-#[async_trait]
 impl<T: Transport + Sync + Send> HWI for Jade<T> {
     fn device_kind(&self) -> DeviceKind {
         self.kind
     }
 
-    async fn get_version(&self) -> Result<super::Version, HWIError> {
-        let info = self.get_info().await?;
+    fn get_version(&self) -> Result<super::Version, HWIError> {
+        let info = self.get_info()?;
         parse_version(&info.jade_version)
     }
 
-    async fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
-        let xpub = self.get_extended_pubkey(&DerivationPath::master()).await?;
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
+        let xpub = self.get_extended_pubkey(&DerivationPath::master())?;
         Ok(xpub.fingerprint())
     }
 
-    async fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
         let s: String = self
             .transport
             .request(
@@ -173,17 +198,40 @@ impl<T: Transport + Sync + Send> HWI for Jade<T> {
                     network: self.network,
                     path: path.to_u32_vec(),
                 }),
-            )
-            .await?
+            )?
             .into_result()?;
         let xpub = Xpub::from_str(&s).map_err(|e| HWIError::Device(e.to_string()))?;
         Ok(xpub)
     }
 
-    async fn display_address(&self, script: &AddressScript) -> Result<(), HWIError> {
+    fn display_address(
+        &self,
+        script: &AddressScript,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        let single_sig = match script {
+            AddressScript::P2PKH(path) => Some((path, 44, "pkh(k)")),
+            AddressScript::P2SHWPKH(path) => Some((path, 49, "sh(wpkh(k))")),
+            AddressScript::P2WPKH(path) => Some((path, 84, "wpkh(k)")),
+            _ => None,
+        };
+        if let Some((path, purpose, variant)) = single_sig {
+            utils::bip44_path_child_numbers(path.clone(), purpose)?;
+            let address: String = self
+                .transport
+                .request(
+                    "get_receive_address",
+                    Some(api::SingleSigAddressParams {
+                        network: self.network,
+                        variant,
+                        path: path.to_u32_vec(),
+                    }),
+                )?
+                .into_result()?;
+            return Address::from_str(&address).map_err(|e| HWIError::Device(e.to_string()));
+        }
         match (self.descriptor_name.as_ref(), script) {
             (Some(descriptor_name), AddressScript::Miniscript { index, change }) => {
-                let _address: String = self
+                let address: String = self
                     .transport
                     .request(
                         "get_receive_address",
@@ -193,20 +241,18 @@ impl<T: Transport + Sync + Send> HWI for Jade<T> {
                             pointer: *index,
                             descriptor_name,
                         }),
-                    )
-                    .await?
+                    )?
                     .into_result()?;
-                Ok(())
+                Address::from_str(&address).map_err(|e| HWIError::Device(e.to_string()))
             }
             _ => Err(HWIError::UnimplementedMethod),
         }
     }
 
-    async fn register_wallet(
-        &self,
-        name: &str,
-        policy: &str,
-    ) -> Result<Option<[u8; 32]>, HWIError> {
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, HWIError> {
+        utils::validate_wallet_name(self.kind, name)?;
+        crate::policy::WalletPolicy::from_descriptor(name, policy)?
+            .check_for_device(self.get_master_fingerprint()?)?;
         let (descriptor_template, keys) = utils::extract_keys_and_template::<String>(policy)?;
         let registered: bool = self
             .transport
@@ -222,23 +268,22 @@ impl<T: Transport + Sync + Send> HWI for Jade<T> {
                         .map(|(i, key)| (format!("@{}", i), key))
                         .collect(),
                 }),
-            )
-            .await?
+            )?
             .into_result()?;
         if !registered {
             Err(HWIError::UserRefused)
         } else {
-            Ok(None)
+            Ok(WalletRegistration::default())
         }
     }
 
-    async fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, HWIError> {
-        let registered_descriptors = self.get_registered_descriptors().await?;
+    fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, HWIError> {
+        let registered_descriptors = self.get_registered_descriptors()?;
         if !registered_descriptors.contains_key(name) {
             return Ok(false);
         }
 
-        let registered = self.get_registered_descriptor(name).await?;
+        let registered = self.get_registered_descriptor(name)?;
 
         let (descriptor_template, keys) = utils::extract_keys_and_template::<String>(policy)?;
         let datavalues: BTreeMap<String, String> = keys
@@ -252,17 +297,38 @@ impl<T: Transport + Sync + Send> HWI for Jade<T> {
             && registered.datavalues == datavalues)
     }
 
-    async fn sign_tx(&self, psbt: &mut Psbt) -> Result<(), HWIError> {
-        let first: api::Response<serde_bytes::ByteBuf> = self
-            .transport
-            .request(
-                "sign_psbt",
-                Some(api::SignPsbtParams {
-                    network: self.network,
-                    psbt: Psbt::serialize(psbt),
-                }),
-            )
-            .await?;
+    fn list_registered_wallets(&self) -> Result<Vec<RegisteredWallet>, HWIError> {
+        let mut wallets = Vec::new();
+        for name in self.get_registered_descriptors()?.into_keys() {
+            let registered = self.get_registered_descriptor(&name)?;
+            // The datavalues are sorted as strings, @10 before @2.
+            let keys = (0..registered.datavalues.len())
+                .map(|i| {
+                    registered
+                        .datavalues
+                        .get(&format!("@{}", i))
+                        .ok_or_else(|| {
+                            HWIError::Device(format!("Missing key @{} of wallet {}", i, name))
+                        })
+                        .and_then(|key| crate::policy::PolicyKey::from_str(key))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let descriptor = crate::policy::WalletPolicy::new(&name, registered.descriptor, keys)
+                .to_descriptor()?;
+            wallets.push(RegisteredWallet { name, descriptor });
+        }
+        Ok(wallets)
+    }
+
+    fn sign_tx(&self, psbt: &mut Psbt) -> Result<SignResult, HWIError> {
+        let unsigned = psbt.clone();
+        let first: api::Response<serde_bytes::ByteBuf> = self.transport.request(
+            "sign_psbt",
+            Some(api::SignPsbtParams {
+                network: self.network,
+                psbt: Psbt::serialize(psbt),
+            }),
+        )?;
 
         if let Some(e) = first.error {
             return Err(JadeError::Rpc(e).into());
@@ -275,18 +341,15 @@ impl<T: Transport + Sync + Send> HWI for Jade<T> {
         if let (Some(mut seqlen), Some(mut seqnum)) = (first.seqlen, first.seqnum) {
             if seqlen > 1 {
                 while seqnum < seqlen {
-                    let mut res: api::Response<serde_bytes::ByteBuf> = self
-                        .transport
-                        .request(
-                            "get_extended_data",
-                            Some(api::GetExtendedDataParams {
-                                origid: &first.id,
-                                orig: "sign_psbt",
-                                seqnum: seqnum + 1,
-                                seqlen,
-                            }),
-                        )
-                        .await?;
+                    let mut res: api::Response<serde_bytes::ByteBuf> = self.transport.request(
+                        "get_extended_data",
+                        Some(api::GetExtendedDataParams {
+                            origid: &first.id,
+                            orig: "sign_psbt",
+                            seqnum: seqnum + 1,
+                            seqlen,
+                        }),
+                    )?;
 
                     if let Some(e) = res.error {
                         return Err(JadeError::Rpc(e).into());
@@ -312,27 +375,50 @@ impl<T: Transport + Sync + Send> HWI for Jade<T> {
             Psbt::deserialize(&psbt_bytes).map_err(|e| HWIError::Device(e.to_string()))?;
         utils::merge_signatures(psbt, &signed_psbt);
 
+        Ok(SignResult::new(&unsigned, psbt))
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<(), HWIError> {
+        self.network = jade_network(network);
+        Ok(())
+    }
+
+    /// The device stores the registered descriptors, only the name is kept.
+    fn load_wallet(
+        &mut self,
+        name: &str,
+        _policy: &str,
+        _hmac: Option<[u8; 32]>,
+    ) -> Result<(), HWIError> {
+        self.descriptor_name = Some(name.to_string());
         Ok(())
     }
 }
 
+fn jade_network(network: Network) -> &'static str {
+    if network == Network::Bitcoin {
+        JADE_NETWORK_MAINNET
+    } else {
+        JADE_NETWORK_TESTNET
+    }
+}
+
 impl<T: 'static + Transport + Sync + Send> From<Jade<T>> for Box<dyn HWI + Send> {
     fn from(s: Jade<T>) -> Box<dyn HWI + Send> {
         Box::new(s)
     }
 }
 
-async fn exchange<S, D>(
-    transport: &mut SerialStream,
+fn exchange<S, D, T>(
+    transport: &mut T,
     method: &str,
     params: Option<S>,
 ) -> Result<api::Response<D>, JadeError>
 where
-    S: Serialize + Unpin,
-    D: DeserializeOwned + Unpin,
+    S: Serialize,
+    D: DeserializeOwned,
+    T: Stream + ?Sized,
 {
-    let (reader, mut writer) = tokio::io::split(transport);
-
     let id = std::process::id();
     let req = serde_cbor::to_vec(&api::Request {
         id: &id.to_string(),
@@ -341,9 +427,16 @@ where
     })
     .map_err(TransportError::from)?;
 
-    writer.write_all(&req).await.map_err(TransportError::from)?;
+    // The params may hold a psbt or a descriptor, only the method is logged.
+    debug_exchange!(id, method, len = req.len(), "jade request");
+    transport.write_all(&req).map_err(TransportError::from)?;
 
-    let response = read_stream(reader).await?;
+    let response = read_stream(transport)?;
+    debug_exchange!(
+        id = %response.id,
+        error = ?response.error.as_ref().map(|e| e.code),
+        "jade response"
+    );
 
     if response.id != id.to_string() {
         return Err(TransportError::NonceMismatch.into());
@@ -352,31 +445,32 @@ where
     Ok(response)
 }
 
-async fn read_stream<D: DeserializeOwned, S: AsyncRead + Unpin>(
-    mut stream: S,
+/// The first bytes of the response wait for the user, the rest of it follows
+/// within a second.
+fn read_stream<D: DeserializeOwned, S: Stream + ?Sized>(
+    stream: &mut S,
 ) -> Result<api::Response<D>, TransportError> {
     let mut buf = Vec::<u8>::new();
     let mut chunk = [0; 1024];
-    let n = stream.read(&mut chunk).await?;
+    stream.set_read_timeout(None)?;
+    let n = stream.read(&mut chunk)?;
     buf.extend_from_slice(&chunk[..n]);
     if let Ok(response) = serde_cbor::from_slice(&buf) {
         return Ok(response);
     }
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
     loop {
-        tokio::select! {
-            res = stream.read(&mut chunk) => {
-                let n = res?;
-                if n == 0 {
-                    break;
-                }
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => {
                 buf.extend_from_slice(&chunk[..n]);
                 if let Ok(response) = serde_cbor::from_slice(&buf) {
                     return Ok(response);
                 }
             }
-            _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {
-                break;
-            }
+            Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
         }
     }
     match serde_cbor::from_slice(&buf) {
@@ -385,9 +479,85 @@ async fn read_stream<D: DeserializeOwned, S: AsyncRead + Unpin>(
     }
 }
 
-#[async_trait]
+/// Byte stream of a transport.
+pub trait Stream: Read + Write {
+    /// None blocks the reads until bytes are received.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Stream for SerialStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        // The timeout of the serial port is clamped to the maximum of the platform.
+        Ok(self.set_timeout(timeout.unwrap_or(Duration::MAX))?)
+    }
+}
+
+fn lock<T: ?Sized>(stream: &Mutex<T>) -> Result<MutexGuard<'_, T>, TransportError> {
+    stream
+        .lock()
+        .map_err(|_| TransportError::Io(std::io::Error::other("Failed to unlock")))
+}
+
+/// Declarative options of a Jade connection, see connect_with_options.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectOptions {
+    /// Serial port of the device, the first Jade answering if none is given.
+    pub port: Option<String>,
+    /// Address of the qemu emulator, e.g. TcpTransport::DEFAULT_ADDRESS, used instead
+    /// of the serial port.
+    pub simulator: Option<String>,
+    pub network: Option<Network>,
+    /// Name of the registered descriptor used to sign and display addresses.
+    pub wallet: Option<String>,
+    /// See Jade::with_pinserver_url.
+    pub pinserver_url: Option<String>,
+}
+
+/// Connects the Jade described by the options, the pin server requests of the
+/// authentication use the http client of the context.
+pub fn connect_with_options(
+    ctx: &Context,
+    options: &ConnectOptions,
+) -> Result<Box<dyn HWI + Send>, HWIError> {
+    if let Some(address) = &options.simulator {
+        let mut device = JadeSimulator::try_connect(Some(address))?;
+        if let Some(network) = options.network {
+            device = device.with_network(network);
+        }
+        if let Some(wallet) = &options.wallet {
+            device = device.with_wallet(wallet.clone());
+        }
+        return Ok(device.into());
+    }
+    let mut device = match &options.port {
+        Some(port) => Jade::new(SerialTransport::new(port.clone()).map_err(JadeError::from)?),
+        None => Jade::enumerate()?
+            .into_iter()
+            .next()
+            .ok_or(HWIError::DeviceNotFound)?,
+    }
+    .with_context(ctx);
+    if let Some(url) = &options.pinserver_url {
+        device = device.with_pinserver_url(url.clone());
+    }
+    if let Some(network) = options.network {
+        device = device.with_network(network);
+    }
+    if let Some(wallet) = &options.wallet {
+        device = device.with_wallet(wallet.clone());
+    }
+    Ok(device.into())
+}
+
 pub trait Transport: Debug {
-    async fn request<S: Serialize + Send + Unpin, D: DeserializeOwned + Unpin + Send>(
+    fn request<S: Serialize, D: DeserializeOwned>(
         &self,
         method: &str,
         params: Option<S>,
@@ -395,11 +565,11 @@ pub trait Transport: Debug {
 }
 
 impl Jade<SerialTransport> {
-    pub async fn enumerate() -> Result<Vec<Self>, JadeError> {
+    pub fn enumerate() -> Result<Vec<Self>, JadeError> {
         let mut res = Vec::new();
         for port_name in SerialTransport::enumerate_potential_ports()? {
             let jade = Jade::<SerialTransport>::new(SerialTransport::new(port_name)?);
-            jade.ping().await?;
+            jade.ping()?;
             res.push(jade);
         }
         Ok(res)
@@ -420,8 +590,8 @@ pub const JADE_DEVICE_IDS: [(u16, u16); 4] = [
 
 impl SerialTransport {
     pub fn new(port_name: String) -> Result<Self, TransportError> {
-        let mut transport = tokio_serial::new(port_name, DEFAULT_JADE_BAUD_RATE)
-            .open_native_async()
+        let mut transport = serialport::new(port_name, DEFAULT_JADE_BAUD_RATE)
+            .open()
             .map_err(TransportError::from)?;
         // Ensure RTS and DTR are not set (as this can cause the hw to reboot)
         // according to https://github.com/Blockstream/Jade/blob/master/jadepy/jade_serial.py#L56
@@ -457,15 +627,58 @@ impl SerialTransport {
 
 const DEFAULT_JADE_BAUD_RATE: u32 = 115200;
 
-#[async_trait]
 impl Transport for SerialTransport {
-    async fn request<S: Serialize + Send + Unpin, D: DeserializeOwned + Unpin + Send>(
+    fn request<S: Serialize, D: DeserializeOwned>(
         &self,
         method: &str,
         params: Option<S>,
     ) -> Result<api::Response<D>, JadeError> {
-        let mut stream = self.stream.lock().await;
-        exchange(&mut stream, method, params).await
+        let mut stream = lock(&self.stream)?;
+        exchange(&mut *stream, method, params)
+    }
+}
+
+/// Transport to communicate with the Jade qemu emulator, clones share the connection.
+#[derive(Debug, Clone)]
+pub struct TcpTransport {
+    stream: Arc<Mutex<TcpStream>>,
+}
+
+impl TcpTransport {
+    /// Default address of the emulator serial port forwarded over TCP.
+    pub const DEFAULT_ADDRESS: &'static str = "127.0.0.1:30121";
+
+    pub fn connect(address: &str) -> Result<Self, TransportError> {
+        let stream = TcpStream::connect(address)?;
+        Ok(Self {
+            stream: Arc::new(Mutex::new(stream)),
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn request<S: Serialize, D: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Option<S>,
+    ) -> Result<api::Response<D>, JadeError> {
+        let mut stream = lock(&self.stream)?;
+        exchange(&mut *stream, method, params)
+    }
+}
+
+pub type JadeSimulator = Jade<TcpTransport>;
+
+impl JadeSimulator {
+    /// Connects to the emulator at the address, TcpTransport::DEFAULT_ADDRESS if none is given.
+    /// The emulator is not locked, no pin server authentication is required.
+    pub fn try_connect(address: Option<&str>) -> Result<Self, HWIError> {
+        let transport = TcpTransport::connect(address.unwrap_or(TcpTransport::DEFAULT_ADDRESS))
+            .map_err(JadeError::from)?;
+        let mut jade = Jade::new(transport);
+        jade.kind = DeviceKind::JadeSimulator;
+        jade.ping()?;
+        Ok(jade)
     }
 }
 
@@ -542,16 +755,28 @@ impl std::fmt::Display for JadeError {
 impl From<JadeError> for HWIError {
     fn from(e: JadeError) -> HWIError {
         match e {
-            JadeError::Transport(e) => HWIError::Device(e.to_string()),
+            JadeError::Transport(e @ TransportError::Io(_))
+            | JadeError::Transport(e @ TransportError::Serial(_)) => {
+                HWIError::Transport(TransportErrorKind::Io, e.to_string())
+            }
+            JadeError::Transport(e) => {
+                HWIError::Transport(TransportErrorKind::Protocol, e.to_string())
+            }
             JadeError::Rpc(e) => {
                 if e.code == api::ErrorCode::UserCancelled as i32 {
                     HWIError::UserRefused
+                } else if e.code == api::ErrorCode::HwLocked as i32 {
+                    HWIError::DeviceLocked { code: Some(e.code) }
                 } else if e.code == api::ErrorCode::NetworkMismatch as i32 {
                     HWIError::NetworkMismatch
                 } else {
                     HWIError::Device(format!("{:?}", e))
                 }
             }
+            JadeError::PinServer(pinserver::Error::Offline(e)) => HWIError::Transport(
+                TransportErrorKind::Io,
+                format!("Pin server unreachable, the Jade cannot be unlocked: {}", e),
+            ),
             JadeError::PinServer(e) => HWIError::Device(format!("{:?}", e)),
             JadeError::HandShakeRefused => {
                 HWIError::Device("Handshake with pinserver refused".to_string())
@@ -559,3 +784,47 @@ impl From<JadeError> for HWIError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_read_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let response = serde_cbor::to_vec(&api::Response {
+            id: "1".to_string(),
+            seqlen: None,
+            seqnum: None,
+            result: Some(42u64),
+            error: None,
+        })
+        .unwrap();
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // The device answers after the user, in two chunks.
+            std::thread::sleep(Duration::from_millis(1500));
+            let (first, last) = response.split_at(response.len() / 2);
+            stream.write_all(first).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            stream.write_all(last).unwrap();
+            // Truncated response.
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&response[..3]).unwrap();
+            std::thread::sleep(Duration::from_millis(1500));
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let res: api::Response<u64> = read_stream(&mut stream).unwrap();
+        assert_eq!(res.result, Some(42));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        assert!(matches!(
+            read_stream::<u64, _>(&mut stream),
+            Err(TransportError::NoErrorOrResult)
+        ));
+        handle.join().unwrap();
+    }
+}
@@ -1,7 +1,11 @@
 use super::api;
+use crate::Context;
 
+#[derive(Debug, Clone)]
 pub struct PinServerClient {
     pub client: reqwest::Client,
+    /// Pin server used instead of the one requested by the device.
+    pub url: Option<String>,
 }
 
 impl Default for PinServerClient {
@@ -14,19 +18,55 @@ impl PinServerClient {
     pub fn new() -> Self {
         Self {
             client: reqwest::Client::new(),
+            url: None,
         }
     }
 
-    pub async fn request<D>(&self, req: api::PinServerRequestParams) -> Result<D, Error>
+    /// Uses the http client of the context.
+    pub fn with_context(ctx: &Context) -> Self {
+        Self {
+            client: ctx.http_client().clone(),
+            url: None,
+        }
+    }
+
+    /// The device must be configured with the public key of the pin server,
+    /// it otherwise rejects its answer.
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// The request is run on a runtime of its own, the calling thread must not
+    /// be driving another one.
+    pub fn request<D>(&self, req: api::PinServerRequestParams) -> Result<D, Error>
     where
         D: serde::de::DeserializeOwned,
     {
-        let url = match &req.urls {
-            api::PinServerUrls::Array(urls) => urls.first().ok_or(Error::NoUrlProvided)?,
-            api::PinServerUrls::Object { url, .. } => url,
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::Runtime)?
+            .block_on(self.post(req))
+    }
+
+    async fn post<D>(&self, req: api::PinServerRequestParams) -> Result<D, Error>
+    where
+        D: serde::de::DeserializeOwned,
+    {
+        let url = match (&self.url, &req.urls) {
+            (Some(url), _) => url,
+            (None, api::PinServerUrls::Array(urls)) => urls.first().ok_or(Error::NoUrlProvided)?,
+            (None, api::PinServerUrls::Object { url, .. }) => url,
         };
 
-        let res = self.client.post(url).json(&req.data).send().await?;
+        let res = match self.client.post(url).json(&req.data).send().await {
+            Ok(res) => res,
+            Err(e) if e.is_connect() || e.is_timeout() => {
+                return Err(Error::Offline(format!("{}: {}", url, e)))
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         if res.status().is_success() {
             res.json().await.map_err(Error::from)
@@ -39,8 +79,12 @@ impl PinServerClient {
 #[derive(Debug)]
 pub enum Error {
     NoUrlProvided,
+    /// The pin server is unreachable, the device stays locked.
+    Offline(String),
     Client(reqwest::Error),
     Server(String),
+    /// The runtime of the request could not be started.
+    Runtime(std::io::Error),
 }
 
 impl From<reqwest::Error> for Error {
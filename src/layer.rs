@@ -0,0 +1,56 @@
+use std::{fmt::Debug, sync::Arc};
+
+/// Hooks called around each exchange of a LayeredTransport,
+/// `Req` and `Res` are the request and response types of the wrapped transport.
+pub trait Layer<Req: ?Sized, Res>: Send + Sync {
+    /// Called before each attempt to send the request.
+    fn pre(&self, _req: &Req) {}
+    /// Called after each attempt with the response or the transport error.
+    fn post(&self, _req: &Req, _res: Result<&Res, &dyn Debug>) {}
+    /// Called after a failed attempt, the request is sent again if it returns true.
+    /// Attempts are counted from 1.
+    fn retry(&self, _req: &Req, _attempt: u32) -> bool {
+        false
+    }
+}
+
+/// Transport wrapped with a layer of user hooks, clones share the layer.
+/// The backends implement their transport trait for it.
+pub struct LayeredTransport<T, L> {
+    pub(crate) transport: T,
+    pub(crate) layer: Arc<L>,
+}
+
+impl<T, L> LayeredTransport<T, L> {
+    pub fn new(transport: T, layer: L) -> Self {
+        Self {
+            transport,
+            layer: Arc::new(layer),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn layer(&self) -> &L {
+        &self.layer
+    }
+}
+
+impl<T: Debug, L> Debug for LayeredTransport<T, L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LayeredTransport")
+            .field("transport", &self.transport)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Clone, L> Clone for LayeredTransport<T, L> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
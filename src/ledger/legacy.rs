@@ -0,0 +1,551 @@
+//! Compatibility with the Bitcoin app before 2.1.
+//! The apps 2.0.x speak the current protocol with the wallet policies v1,
+//! the apps before 2.0 only answer the commands of the legacy btchip protocol:
+//! single key xpubs, addresses and segwit v0 signatures.
+
+use std::convert::TryFrom;
+
+use bitcoin::{
+    bip32::{ChainCode, ChildNumber, DerivationPath, Fingerprint, Xpub},
+    consensus::encode::{serialize, VarInt},
+    ecdsa,
+    hashes::Hash,
+    psbt::Psbt,
+    secp256k1, Amount, Network, PublicKey, Script, ScriptBuf, TxIn,
+};
+use ledger_bitcoin_client::{
+    apdu::{APDUCommand, StatusWord},
+    error::BitcoinClientError,
+    wallet::Version as WalletVersion,
+    WalletPolicy, WalletPubKey,
+};
+
+use super::{error_from_status_word, Transport};
+use crate::{Error as HWIError, InputSignature, Version};
+
+/// First version of the app speaking the current protocol.
+pub const MIN_VERSION_POLICY_V1: Version = Version {
+    major: 2,
+    minor: 0,
+    patch: 0,
+    prerelease: None,
+    flavor: None,
+};
+
+/// First version of the app using the wallet policies v2.
+pub const MIN_VERSION_POLICY_V2: Version = Version {
+    major: 2,
+    minor: 1,
+    patch: 0,
+    prerelease: None,
+    flavor: None,
+};
+
+/// Protocol of the Bitcoin app, selected from its version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppProtocol {
+    /// Apps before 2.0, btchip commands.
+    Legacy,
+    /// Apps 2.0.x, wallet policies v1.
+    PolicyV1,
+    /// Apps from 2.1, wallet policies v2.
+    PolicyV2,
+}
+
+impl AppProtocol {
+    pub fn from_version(version: &Version) -> Self {
+        if *version >= MIN_VERSION_POLICY_V2 {
+            Self::PolicyV2
+        } else if *version >= MIN_VERSION_POLICY_V1 {
+            Self::PolicyV1
+        } else {
+            Self::Legacy
+        }
+    }
+}
+
+/// Converts a wallet policy v2 for the apps 2.0.x, the derivation suffix of each
+/// key placeholder moves from the descriptor template to the key.
+pub fn to_policy_v1(wallet: &WalletPolicy) -> Result<WalletPolicy, HWIError> {
+    let mut template = String::new();
+    let mut suffixes: Vec<Option<&str>> = vec![None; wallet.keys.len()];
+    let mut rest = wallet.descriptor_template.as_str();
+    while let Some(at) = rest.find('@') {
+        template.push_str(&rest[..=at]);
+        rest = &rest[at + 1..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let index: usize = rest[..digits]
+            .parse()
+            .map_err(|_| HWIError::UnsupportedInput)?;
+        template.push_str(&rest[..digits]);
+        rest = &rest[digits..];
+        let suffix_len = if rest.starts_with("/**") {
+            3
+        } else if rest.starts_with("/<") {
+            rest.find(">/*").ok_or(HWIError::UnsupportedInput)? + 3
+        } else {
+            0
+        };
+        let suffix = &rest[..suffix_len];
+        rest = &rest[suffix_len..];
+        // A v1 key has a single suffix for all its placeholders.
+        match suffixes.get_mut(index).ok_or(HWIError::UnsupportedInput)? {
+            Some(s) if *s != suffix => return Err(HWIError::UnsupportedInput),
+            s => *s = Some(suffix),
+        }
+    }
+    template.push_str(rest);
+    let keys = wallet
+        .keys
+        .iter()
+        .zip(suffixes)
+        .map(|(key, suffix)| WalletPubKey {
+            inner: key.inner,
+            source: key.source.clone(),
+            multipath: suffix.filter(|s| !s.is_empty()).map(str::to_string),
+        })
+        .collect::<Vec<_>>();
+    Ok(WalletPolicy::new(
+        wallet.name.clone(),
+        WalletVersion::V1,
+        template,
+        keys,
+    ))
+}
+
+const CLA_BTCHIP: u8 = 0xE0;
+const INS_GET_WALLET_PUBLIC_KEY: u8 = 0x40;
+const INS_HASH_INPUT_START: u8 = 0x44;
+const INS_HASH_SIGN: u8 = 0x48;
+const INS_HASH_INPUT_FINALIZE_FULL: u8 = 0x4A;
+const INS_GET_FIRMWARE_VERSION: u8 = 0xC4;
+
+/// Largest block of script or outputs sent in one command.
+const MAX_SCRIPT_BLOCK: usize = 50;
+
+/// Address formats of the get wallet public key command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFormat {
+    Legacy = 0,
+    NestedSegwit = 1,
+    NativeSegwit = 2,
+}
+
+/// Client of the legacy btchip protocol.
+pub struct LegacyClient<'a, T> {
+    transport: &'a T,
+}
+
+impl<'a, T: Transport> LegacyClient<'a, T>
+where
+    T::Error: 'static,
+{
+    pub fn new(transport: &'a T) -> Self {
+        Self { transport }
+    }
+
+    fn exchange(&self, ins: u8, p1: u8, p2: u8, data: Vec<u8>) -> Result<Vec<u8>, HWIError> {
+        let (status, data) = self
+            .transport
+            .exchange(&APDUCommand {
+                cla: CLA_BTCHIP,
+                ins,
+                p1,
+                p2,
+                data,
+            })
+            .map_err(|e| HWIError::from(BitcoinClientError::<T::Error>::Transport(e)))?;
+        match status {
+            StatusWord::OK => Ok(data),
+            status => Err(error_from_status_word(status as u16)),
+        }
+    }
+
+    /// Version of the app, answered by the apps too old for the version command of the OS.
+    pub fn get_firmware_version(&self) -> Result<Version, HWIError> {
+        match self.exchange(INS_GET_FIRMWARE_VERSION, 0x00, 0x00, Vec::new())?[..] {
+            [_flags, _arch, major, minor, patch, ..] => Ok(Version {
+                major: major.into(),
+                minor: minor.into(),
+                patch: patch.into(),
+                prerelease: None,
+                flavor: None,
+            }),
+            _ => Err(invalid_response()),
+        }
+    }
+
+    /// Public key, address and chain code at the path, the address is displayed if asked.
+    pub fn get_wallet_public_key(
+        &self,
+        path: &DerivationPath,
+        display: bool,
+        format: AddressFormat,
+    ) -> Result<(secp256k1::PublicKey, String, ChainCode), HWIError> {
+        let data = self.exchange(
+            INS_GET_WALLET_PUBLIC_KEY,
+            display.into(),
+            format as u8,
+            serialize_path(path),
+        )?;
+        parse_wallet_public_key(&data).ok_or_else(invalid_response)
+    }
+
+    pub fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
+        let (key, _, _) =
+            self.get_wallet_public_key(&DerivationPath::master(), false, AddressFormat::Legacy)?;
+        Ok(fingerprint(&key))
+    }
+
+    /// The parent key is queried for the fingerprint of the xpub.
+    pub fn get_extended_pubkey(
+        &self,
+        path: &DerivationPath,
+        network: Network,
+        display: bool,
+    ) -> Result<Xpub, HWIError> {
+        let children: &[ChildNumber] = path.as_ref();
+        let (child_number, parent_fingerprint) = match children.split_last() {
+            Some((child, parent)) => {
+                let parent = DerivationPath::from(parent);
+                let (key, _, _) =
+                    self.get_wallet_public_key(&parent, false, AddressFormat::Legacy)?;
+                (*child, fingerprint(&key))
+            }
+            None => (ChildNumber::Normal { index: 0 }, Fingerprint::default()),
+        };
+        let (public_key, _, chain_code) =
+            self.get_wallet_public_key(path, display, AddressFormat::Legacy)?;
+        Ok(Xpub {
+            network,
+            depth: children.len() as u8,
+            parent_fingerprint,
+            child_number,
+            public_key,
+            chain_code,
+        })
+    }
+
+    /// Signs the p2wpkh and p2sh-wpkh inputs of the key with the given fingerprint,
+    /// each signature is passed to the callback once the input is signed.
+    pub fn sign_psbt(
+        &self,
+        psbt: &Psbt,
+        fingerprint: Fingerprint,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<Vec<(usize, InputSignature)>, HWIError> {
+        let tx = &psbt.unsigned_tx;
+        let amounts = psbt
+            .inputs
+            .iter()
+            .zip(&tx.input)
+            .map(|(input, txin)| {
+                input
+                    .witness_utxo
+                    .as_ref()
+                    .or_else(|| {
+                        input
+                            .non_witness_utxo
+                            .as_ref()?
+                            .output
+                            .get(txin.previous_output.vout as usize)
+                    })
+                    .map(|utxo| utxo.value)
+                    .ok_or(HWIError::UnsupportedInput)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The whole transaction is hashed first, without the input scripts.
+        let inputs = tx
+            .input
+            .iter()
+            .zip(&amounts)
+            .map(|(txin, amount)| (txin, *amount, Script::new()))
+            .collect::<Vec<_>>();
+        self.hash_input_start(tx.version.0, &inputs, true)?;
+        let outputs = serialize(&tx.output);
+        let blocks = outputs.chunks(MAX_SCRIPT_BLOCK).count();
+        for (i, block) in outputs.chunks(MAX_SCRIPT_BLOCK).enumerate() {
+            let p1 = if i + 1 == blocks { 0x80 } else { 0x00 };
+            self.exchange(INS_HASH_INPUT_FINALIZE_FULL, p1, 0x00, block.to_vec())?;
+        }
+
+        let mut signatures = Vec::new();
+        for (i, input) in psbt.inputs.iter().enumerate() {
+            let utxo_script = match (&input.witness_utxo, &input.redeem_script) {
+                (_, Some(redeem_script)) => redeem_script.clone(),
+                (Some(utxo), None) => utxo.script_pubkey.clone(),
+                (None, None) => continue,
+            };
+            let key = input.bip32_derivation.iter().find(|(key, (fg, _))| {
+                *fg == fingerprint && p2wpkh(key).as_ref() == Some(&utxo_script)
+            });
+            let (key, (_, path)) = match key {
+                Some(key) => key,
+                None => continue,
+            };
+            let key = PublicKey::new(*key);
+            let script_code = ScriptBuf::new_p2pkh(&key.pubkey_hash());
+            self.hash_input_start(
+                tx.version.0,
+                &[(&tx.input[i], amounts[i], &script_code)],
+                false,
+            )?;
+            let sighash_type = input.sighash_type.map(|s| s.to_u32()).unwrap_or(1) as u8;
+            let mut data = serialize_path(path);
+            // No user validation code.
+            data.push(0x00);
+            data.extend(tx.lock_time.to_consensus_u32().to_be_bytes());
+            data.push(sighash_type);
+            let mut sig = self.exchange(INS_HASH_SIGN, 0x00, 0x00, data)?;
+            // The first byte of the DER encoding carries the parity of the nonce point.
+            if let Some(first) = sig.first_mut() {
+                *first = 0x30;
+            }
+            let sig = ecdsa::Signature::from_slice(&sig).map_err(|_| invalid_response())?;
+            on_signature(i, InputSignature::Ecdsa(key, sig));
+            signatures.push((i, InputSignature::Ecdsa(key, sig)));
+        }
+        Ok(signatures)
+    }
+
+    /// Starts hashing the inputs of a segwit transaction, the scripts are empty for
+    /// the whole transaction and hold the script code of the input to sign after.
+    fn hash_input_start(
+        &self,
+        version: i32,
+        inputs: &[(&TxIn, Amount, &Script)],
+        new_transaction: bool,
+    ) -> Result<(), HWIError> {
+        let mut data = version.to_le_bytes().to_vec();
+        data.extend(serialize(&VarInt(inputs.len() as u64)));
+        let p2 = if new_transaction { 0x02 } else { 0x80 };
+        self.exchange(INS_HASH_INPUT_START, 0x00, p2, data)?;
+        for (txin, amount, script) in inputs {
+            let mut data = vec![0x02];
+            data.extend(serialize(&txin.previous_output));
+            data.extend(amount.to_sat().to_le_bytes());
+            data.extend(serialize(&VarInt(script.len() as u64)));
+            self.exchange(INS_HASH_INPUT_START, 0x80, 0x00, data)?;
+            let mut data = script.to_bytes();
+            data.extend(txin.sequence.0.to_le_bytes());
+            for block in data.chunks(MAX_SCRIPT_BLOCK) {
+                self.exchange(INS_HASH_INPUT_START, 0x80, 0x00, block.to_vec())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn p2wpkh(key: &secp256k1::PublicKey) -> Option<ScriptBuf> {
+    Some(ScriptBuf::new_p2wpkh(&PublicKey::new(*key).wpubkey_hash()?))
+}
+
+fn fingerprint(key: &secp256k1::PublicKey) -> Fingerprint {
+    let hash = PublicKey::new(*key).pubkey_hash().to_byte_array();
+    Fingerprint::from([hash[0], hash[1], hash[2], hash[3]])
+}
+
+fn serialize_path(path: &DerivationPath) -> Vec<u8> {
+    let children: &[ChildNumber] = path.as_ref();
+    let mut data = vec![children.len() as u8];
+    for child in children {
+        data.extend(u32::from(*child).to_be_bytes());
+    }
+    data
+}
+
+fn parse_wallet_public_key(data: &[u8]) -> Option<(secp256k1::PublicKey, String, ChainCode)> {
+    let (&len, data) = data.split_first()?;
+    let (key, data) = (data.get(..len.into())?, data.get(len.into()..)?);
+    let (&len, data) = data.split_first()?;
+    let (address, data) = (data.get(..len.into())?, data.get(len.into()..)?);
+    let chain_code = <[u8; 32]>::try_from(data.get(..32)?).ok()?;
+    Some((
+        secp256k1::PublicKey::from_slice(key).ok()?,
+        String::from_utf8(address.to_vec()).ok()?,
+        ChainCode::from(chain_code),
+    ))
+}
+
+fn invalid_response() -> HWIError {
+    HWIError::Transport(
+        crate::TransportErrorKind::Protocol,
+        "Invalid response of the legacy app".to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, bip32::Xpriv, secp256k1::Secp256k1, transaction, Transaction, TxOut,
+    };
+    use std::{cell::RefCell, error::Error, str::FromStr};
+
+    #[test]
+    fn test_app_protocol() {
+        let version = |minor| Version {
+            major: 2,
+            minor,
+            patch: 3,
+            prerelease: None,
+            flavor: None,
+        };
+        assert_eq!(
+            AppProtocol::from_version(&version(2)),
+            AppProtocol::PolicyV2
+        );
+        assert_eq!(
+            AppProtocol::from_version(&version(0)),
+            AppProtocol::PolicyV1
+        );
+        let version = Version {
+            major: 1,
+            minor: 6,
+            patch: 6,
+            prerelease: None,
+            flavor: None,
+        };
+        assert_eq!(AppProtocol::from_version(&version), AppProtocol::Legacy);
+    }
+
+    #[test]
+    fn test_to_policy_v1() {
+        let key = "[f5acc2fd/48'/1'/0'/2']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP";
+        let wallet = |template: &str| {
+            WalletPolicy::new(
+                "wallet".to_string(),
+                WalletVersion::V2,
+                template.to_string(),
+                vec![
+                    WalletPubKey::from_str(key).unwrap(),
+                    WalletPubKey::from_str(key).unwrap(),
+                ],
+            )
+        };
+        let v1 = to_policy_v1(&wallet(
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@1/<2;3>/*),older(10))))",
+        ))
+        .unwrap();
+        assert_eq!(v1.version, WalletVersion::V1);
+        assert_eq!(
+            v1.descriptor_template,
+            "wsh(or_d(pk(@0),and_v(v:pkh(@1),older(10))))"
+        );
+        assert_eq!(v1.keys[0].to_string(), format!("{}/**", key));
+        assert_eq!(v1.keys[1].to_string(), format!("{}/<2;3>/*", key));
+
+        // A key of the policy v1 has a single derivation suffix.
+        assert!(matches!(
+            to_policy_v1(&wallet("wsh(multi(1,@0/**,@0/<2;3>/*))")),
+            Err(HWIError::UnsupportedInput)
+        ));
+    }
+
+    /// Legacy app of a software key, the commands are recorded.
+    struct Btchip {
+        master: Xpriv,
+        commands: RefCell<Vec<(u8, u8, u8)>>,
+    }
+
+    impl Transport for Btchip {
+        type Error = Box<dyn Error>;
+        fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+            self.commands.borrow_mut().push((cmd.ins, cmd.p1, cmd.p2));
+            let secp = Secp256k1::new();
+            let path = |data: &[u8]| {
+                data[1..]
+                    .chunks(4)
+                    .take(data[0].into())
+                    .map(|c| ChildNumber::from(u32::from_be_bytes([c[0], c[1], c[2], c[3]])))
+                    .collect::<DerivationPath>()
+            };
+            let data = match cmd.ins {
+                INS_GET_WALLET_PUBLIC_KEY => {
+                    let xpriv = self.master.derive_priv(&secp, &path(&cmd.data))?;
+                    let xpub = Xpub::from_priv(&secp, &xpriv);
+                    let mut data = vec![65];
+                    data.extend(xpub.public_key.serialize_uncompressed());
+                    data.extend([4, b'a', b'd', b'd', b'r']);
+                    data.extend(xpub.chain_code.as_bytes());
+                    data
+                }
+                INS_HASH_SIGN => {
+                    let xpriv = self.master.derive_priv(&secp, &path(&cmd.data))?;
+                    let msg = secp256k1::Message::from_digest([1; 32]);
+                    let mut sig = secp
+                        .sign_ecdsa(&msg, &xpriv.private_key)
+                        .serialize_der()
+                        .to_vec();
+                    sig[0] = 0x31;
+                    sig.push(1);
+                    sig
+                }
+                _ => Vec::new(),
+            };
+            Ok((StatusWord::OK, data))
+        }
+    }
+
+    #[test]
+    fn test_legacy_client() {
+        let secp = Secp256k1::new();
+        let device = Btchip {
+            master: Xpriv::new_master(Network::Testnet, &[7; 32]).unwrap(),
+            commands: RefCell::new(Vec::new()),
+        };
+        let client = LegacyClient::new(&device);
+        let fingerprint = device.master.fingerprint(&secp);
+        assert_eq!(client.get_master_fingerprint().unwrap(), fingerprint);
+
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpub = Xpub::from_priv(&secp, &device.master.derive_priv(&secp, &path).unwrap());
+        assert_eq!(
+            client
+                .get_extended_pubkey(&path, Network::Testnet, false)
+                .unwrap(),
+            xpub
+        );
+
+        let key_path = DerivationPath::from_str("m/84'/1'/0'/0/3").unwrap();
+        let key = Xpub::from_priv(&secp, &device.master.derive_priv(&secp, &key_path).unwrap())
+            .public_key;
+        let utxo = TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: p2wpkh(&key).unwrap(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut {
+                value: Amount::from_sat(40_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        })
+        .unwrap();
+        for input in &mut psbt.inputs {
+            input.witness_utxo = Some(utxo.clone());
+        }
+        // Only the second input is of this device.
+        psbt.inputs[1]
+            .bip32_derivation
+            .insert(key, (fingerprint, key_path));
+
+        device.commands.borrow_mut().clear();
+        let mut signed = Vec::new();
+        let sigs = client
+            .sign_psbt(&psbt, fingerprint, &mut |i, _| signed.push(i))
+            .unwrap();
+        assert_eq!(signed, vec![1]);
+        assert!(matches!(sigs[..], [(1, InputSignature::Ecdsa(k, _))] if k.inner == key));
+        let commands = device.commands.borrow();
+        assert_eq!(commands[0], (INS_HASH_INPUT_START, 0x00, 0x02));
+        assert!(commands.contains(&(INS_HASH_INPUT_FINALIZE_FULL, 0x80, 0x00)));
+        assert!(commands.contains(&(INS_HASH_INPUT_START, 0x00, 0x80)));
+        assert_eq!(commands.last(), Some(&(INS_HASH_SIGN, 0x00, 0x00)));
+    }
+}
@@ -0,0 +1,2550 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::default::Default;
+use std::error::Error;
+use std::ffi::CString;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    consensus::encode::{deserialize_partial, VarInt},
+    psbt::Psbt,
+    Address, Network,
+};
+use ledger_bitcoin_client::psbt::PartialSignature;
+
+use ledger_apdu::APDUAnswer;
+use ledger_transport_hidapi::{LedgerHIDError, TransportNativeHID};
+
+use ledger_bitcoin_client::{
+    apdu::{APDUCommand, StatusWord},
+    client::BitcoinClient,
+    error::BitcoinClientError,
+    wallet::Version as WalletVersion,
+    WalletPolicy, WalletPubKey,
+};
+
+use crate::{
+    context::open_error,
+    layer::{Layer, LayeredTransport},
+    parse_version, policy, utils, AddressScript, Capabilities, Context, DeviceDetails, DeviceKind,
+    DeviceModel, Error as HWIError, InputSignature, SignResult, TransportErrorKind, Version,
+    WalletRegistration, HWI,
+};
+
+pub mod legacy;
+
+pub use hidapi::{DeviceInfo, HidApi};
+pub use ledger_bitcoin_client::client::Transport;
+pub use legacy::AppProtocol;
+
+/// Minimum version of the Bitcoin app using the default wallet policies without registration.
+pub const MIN_VERSION_DEFAULT_WALLET: Version = Version {
+    major: 2,
+    minor: 0,
+    patch: 0,
+    prerelease: None,
+    flavor: None,
+};
+
+/// Flavor of the Bitcoin app. The Bitcoin Test app derives the keys from the same seed,
+/// but encodes the xpubs as tpubs and its default wallets use the coin type 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppFlavor {
+    Bitcoin,
+    BitcoinTest,
+}
+
+impl AppFlavor {
+    /// None for the dashboard and the other apps.
+    pub fn from_app_name(name: &str) -> Option<Self> {
+        if name.starts_with("Bitcoin Test") {
+            Some(AppFlavor::BitcoinTest)
+        } else if name.starts_with(BITCOIN_APP_NAME) {
+            Some(AppFlavor::Bitcoin)
+        } else {
+            None
+        }
+    }
+
+    /// Flavor of the app of the network.
+    pub fn from_network(network: Network) -> Self {
+        match network {
+            Network::Bitcoin => AppFlavor::Bitcoin,
+            _ => AppFlavor::BitcoinTest,
+        }
+    }
+
+    /// Name of the app on the Ledger store.
+    pub fn app_name(&self) -> &'static str {
+        match self {
+            AppFlavor::Bitcoin => BITCOIN_APP_NAME,
+            AppFlavor::BitcoinTest => "Bitcoin Test",
+        }
+    }
+
+    /// Network of the xpubs and addresses of the app, Testnet for all the test networks.
+    pub fn network(&self) -> Network {
+        match self {
+            AppFlavor::Bitcoin => Network::Bitcoin,
+            AppFlavor::BitcoinTest => Network::Testnet,
+        }
+    }
+
+    /// BIP44 coin type of the default wallets of the app.
+    pub fn coin_type(&self) -> u32 {
+        match self {
+            AppFlavor::Bitcoin => 0,
+            AppFlavor::BitcoinTest => 1,
+        }
+    }
+}
+
+/// Bitcoin app running on the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppInfo {
+    pub name: String,
+    pub version: Version,
+    pub flavor: AppFlavor,
+}
+
+/// Single key templates of the default wallets with their BIP44 purpose.
+const DEFAULT_WALLET_TEMPLATES: [(&str, u32); 4] = [
+    ("pkh(@0/**)", 44),
+    ("sh(wpkh(@0/**))", 49),
+    ("wpkh(@0/**)", 84),
+    ("tr(@0/**)", 86),
+];
+
+/// Highest account index of a default wallet.
+const DEFAULT_WALLET_MAX_ACCOUNT: u32 = 100;
+
+/// Highest address index derived by the app, hardened indexes are rejected.
+pub const MAX_ADDRESS_INDEX: u32 = 0x7FFF_FFFF;
+
+/// Highest address index of a default wallet used without registration,
+/// the app does not derive addresses beyond the recommended BIP44 range.
+pub const DEFAULT_WALLET_MAX_ADDRESS_INDEX: u32 = 50_000;
+
+/// Checks the address index is within the bounds enforced by the app,
+/// which otherwise rejects the request with an opaque status word.
+pub fn check_address_index(index: u32, registered: bool) -> Result<(), HWIError> {
+    let max = if registered {
+        MAX_ADDRESS_INDEX
+    } else {
+        DEFAULT_WALLET_MAX_ADDRESS_INDEX
+    };
+    if index > max {
+        Err(HWIError::IndexOutOfRange { index, max })
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns true if the wallet is a default wallet of the Ledger Bitcoin app:
+/// an unnamed single key BIP44/49/84/86 policy with the account key of the device.
+pub fn is_default_wallet(wallet: &WalletPolicy, fingerprint: Fingerprint) -> bool {
+    if !wallet.name.is_empty() || wallet.keys.len() != 1 {
+        return false;
+    }
+    let template = wallet.descriptor_template.replace("/<0;1>/*", "/**");
+    let purpose = match DEFAULT_WALLET_TEMPLATES
+        .iter()
+        .find(|(t, _)| *t == template)
+    {
+        Some((_, purpose)) => *purpose,
+        None => return false,
+    };
+    match &wallet.keys[0].source {
+        Some((fg, path)) if *fg == fingerprint => match path.as_ref() {
+            [p, ChildNumber::Hardened { index: coin }, ChildNumber::Hardened { index: account }] => {
+                *p == ChildNumber::Hardened { index: purpose }
+                    && *coin <= 1
+                    && *account <= DEFAULT_WALLET_MAX_ACCOUNT
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl TryFrom<&policy::WalletPolicy> for WalletPolicy {
+    type Error = HWIError;
+
+    fn try_from(policy: &policy::WalletPolicy) -> Result<Self, HWIError> {
+        policy.check_internal_key()?;
+        let keys = policy
+            .keys
+            .iter()
+            .map(|key| WalletPubKey::from_str(&key.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| HWIError::UnsupportedInput)?;
+        Ok(WalletPolicy::new(
+            policy.name.clone(),
+            WalletVersion::V2,
+            policy.template.clone(),
+            keys,
+        ))
+    }
+}
+
+/// Wallet policy of a descriptor, see policy::WalletPolicy::from_descriptor.
+fn wallet_policy(name: &str, policy: &str) -> Result<WalletPolicy, HWIError> {
+    WalletPolicy::try_from(&policy::WalletPolicy::from_descriptor(name, policy)?)
+}
+
+/// Id of the wallet policy the registration hmac is bound to, for an app supporting
+/// policies v2. Storing it with the hmac detects a policy serialized differently
+/// by another version of this crate, the stored hmac would not be accepted for it.
+pub fn wallet_id(name: &str, policy: &str) -> Result<[u8; 32], HWIError> {
+    Ok(wallet_policy(name, policy)?.id())
+}
+
+#[derive(Default)]
+struct CommandOptions {
+    wallet: Option<(WalletPolicy, Option<[u8; 32]>)>,
+    display_xpub: bool,
+    allow_unusual_paths: bool,
+    network: Option<Network>,
+    /// Name of a fork of the Bitcoin app.
+    app_name: Option<String>,
+    /// Protocol of the app, selected on first use.
+    protocol: OnceLock<AppProtocol>,
+}
+
+pub struct Ledger<T: Transport> {
+    client: BitcoinClient<T>,
+    /// Handle sharing the connection of the client transport.
+    transport: T,
+    options: CommandOptions,
+    kind: DeviceKind,
+    /// Model told by the USB product id, None for the simulator and the bridge.
+    model: Option<DeviceModel>,
+}
+
+/// Transport able to close and reopen its connection with the device.
+pub trait Reconnect {
+    fn reconnect(&self) -> Result<(), HWIError>;
+}
+
+impl<T: Transport> Ledger<T> {
+    /// Minimum version of the Bitcoin app registering miniscript policies.
+    pub const MIN_VERSION_MINISCRIPT: Version = Version::new(2, 1, 0);
+    /// Minimum version of the Bitcoin app registering miniscript in taproot leaves.
+    pub const MIN_VERSION_TAPMINISCRIPT: Version = Version::new(2, 2, 0);
+
+    /// Version of the app required by the fragments of the descriptor template,
+    /// None for the single key and multisig descriptors.
+    pub fn required_version(template: &str) -> Option<Version> {
+        const DESCRIPTORS: [&str; 7] = ["sh", "wsh", "pkh", "wpkh", "tr", "multi", "sortedmulti"];
+        let taproot = template.starts_with("tr(");
+        template
+            .match_indices('(')
+            .map(|(i, _)| {
+                let start = template[..i]
+                    .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != ':')
+                    .map_or(0, |j| j + 1);
+                &template[start..i]
+            })
+            .filter(|fragment| {
+                if taproot {
+                    *fragment != "tr"
+                } else {
+                    !DESCRIPTORS.contains(fragment)
+                }
+            })
+            .map(|_| {
+                if taproot {
+                    Self::MIN_VERSION_TAPMINISCRIPT
+                } else {
+                    Self::MIN_VERSION_MINISCRIPT
+                }
+            })
+            .next()
+    }
+
+    pub fn display_xpub(mut self, display: bool) -> Result<Self, HWIError> {
+        self.options.display_xpub = display;
+        Ok(self)
+    }
+
+    /// Requests the xpubs of the paths the app does not export silently, see
+    /// utils::is_usual_path, and of the coin type of another network: the app displays
+    /// them with a warning for the user to approve. They are otherwise rejected.
+    pub fn allow_unusual_paths(mut self, allow: bool) -> Self {
+        self.options.allow_unusual_paths = allow;
+        self
+    }
+
+    /// The network is otherwise the one of the installed app.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.options.network = Some(network);
+        self
+    }
+
+    /// Expects the app with the given name, a fork of the Bitcoin app, which has
+    /// the flavor of the configured network.
+    pub fn with_app_name(mut self, name: impl Into<String>) -> Self {
+        self.options.app_name = Some(name.into());
+        self
+    }
+
+    /// Flavor of the app with the given name, None for the dashboard and the other apps.
+    fn app_flavor(&self, name: &str) -> Option<AppFlavor> {
+        if self.options.app_name.as_deref() == Some(name) {
+            return Some(AppFlavor::from_network(
+                self.options.network.unwrap_or(Network::Bitcoin),
+            ));
+        }
+        AppFlavor::from_app_name(name)
+    }
+
+    pub fn with_wallet(
+        mut self,
+        name: impl Into<String>,
+        policy: &str,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Self, HWIError> {
+        self.set_wallet(name, policy, hmac)?;
+        Ok(self)
+    }
+
+    /// Replaces the wallet of a connected device, see with_wallet.
+    pub fn set_wallet(
+        &mut self,
+        name: impl Into<String>,
+        policy: &str,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<(), HWIError> {
+        let name: String = name.into();
+        let wallet = wallet_policy(&name, policy)?;
+        self.options.wallet = Some((wallet, hmac));
+        Ok(())
+    }
+}
+
+impl<T: Transport + Reconnect + Sync + Send> Ledger<T>
+where
+    T::Error: 'static,
+{
+    /// Returns true if the app accepts the wallet without registration.
+    pub fn accepts_unregistered(&self, wallet: &WalletPolicy) -> Result<bool, HWIError> {
+        if self.get_version()? < MIN_VERSION_DEFAULT_WALLET {
+            return Ok(false);
+        }
+        Ok(is_default_wallet(wallet, self.get_master_fingerprint()?))
+    }
+
+    /// Address of the default wallet with the given BIP44 purpose, displayed if display is true.
+    fn default_wallet_address(
+        &self,
+        path: &DerivationPath,
+        purpose: u32,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        let children = utils::bip44_path_child_numbers(path.clone(), purpose)?;
+        let (hardened_children, normal_children) = children.split_at(3);
+        let index: u32 = normal_children[1].into();
+        check_address_index(index, false)?;
+        let path = DerivationPath::from(hardened_children);
+        let template = DEFAULT_WALLET_TEMPLATES
+            .iter()
+            .find(|(_, p)| *p == purpose)
+            .map(|(template, _)| template.to_string())
+            .ok_or(HWIError::UnsupportedInput)?;
+        let fg = self.get_master_fingerprint()?;
+        let xpub = self.get_extended_pubkey(&path)?;
+        let key = WalletPubKey::from_str(&format!(
+            "[{}{}]{}",
+            fg,
+            path.to_string().trim_start_matches('m'),
+            xpub
+        ))
+        .map_err(|_| HWIError::UnsupportedInput)?;
+        let wallet = WalletPolicy::new("".into(), WalletVersion::V2, template, vec![key]);
+        let v1 = self.app_wallet(&wallet)?;
+
+        self.client
+            .get_wallet_address(
+                v1.as_ref().unwrap_or(&wallet),
+                None,
+                normal_children[0] == ChildNumber::from_normal_idx(1).unwrap(),
+                index,
+                display,
+            )
+            .map_err(|e| self.client_error(e))
+    }
+
+    /// Name of the app running on the device, the dashboard answers BOLOS.
+    pub fn running_app(&self) -> Result<String, HWIError> {
+        let (name, _, _) = self.client.get_version()?;
+        Ok(name)
+    }
+
+    /// Name, version and flavor of the running Bitcoin app.
+    pub fn probe(&self) -> Result<AppInfo, HWIError> {
+        let (name, version, _) = self.client.get_version()?;
+        let flavor = match self.app_flavor(&name) {
+            Some(flavor) => flavor,
+            None => return Err(HWIError::AppNotOpen { running: name }),
+        };
+        Ok(AppInfo {
+            version: parse_version(&version)?,
+            name,
+            flavor,
+        })
+    }
+
+    /// Checks the running app is the one set with with_app_name, and that it is
+    /// the Bitcoin app of the configured network.
+    pub fn check_app(&self) -> Result<AppInfo, HWIError> {
+        let info = match self.probe() {
+            Ok(info) => info,
+            Err(HWIError::AppNotOpen { running }) => {
+                return Err(HWIError::WrongApp {
+                    expected: self.expected_app_name(),
+                    found: Some(running),
+                    code: None,
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let wrong_name = matches!(&self.options.app_name, Some(name) if *name != info.name);
+        let wrong_network = matches!(self.options.network,
+            Some(network) if AppFlavor::from_network(network) != info.flavor);
+        if wrong_name || wrong_network {
+            return Err(HWIError::WrongApp {
+                expected: self.expected_app_name(),
+                found: Some(info.name),
+                code: None,
+            });
+        }
+        Ok(info)
+    }
+
+    fn expected_app_name(&self) -> String {
+        match (&self.options.app_name, self.options.network) {
+            (Some(name), _) => name.clone(),
+            (None, network) => AppFlavor::from_network(network.unwrap_or(Network::Bitcoin))
+                .app_name()
+                .to_string(),
+        }
+    }
+
+    /// Protocol of the Bitcoin app, selected from its version on first use.
+    pub fn app_protocol(&self) -> Result<AppProtocol, HWIError> {
+        if let Some(protocol) = self.options.protocol.get() {
+            return Ok(*protocol);
+        }
+        let version = self.app_version()?;
+        Ok(*self
+            .options
+            .protocol
+            .get_or_init(|| AppProtocol::from_version(&version)))
+    }
+
+    fn app_version(&self) -> Result<Version, HWIError> {
+        match self.get_version() {
+            Ok(version) => Ok(version),
+            // The oldest apps do not answer the version command of the OS.
+            Err(e) => self.legacy().get_firmware_version().map_err(|_| e),
+        }
+    }
+
+    fn legacy(&self) -> legacy::LegacyClient<'_, T> {
+        legacy::LegacyClient::new(&self.transport)
+    }
+
+    /// The apps 2.0.x only know the wallet policies v1.
+    fn app_wallet(&self, wallet: &WalletPolicy) -> Result<Option<WalletPolicy>, HWIError> {
+        match self.app_protocol()? {
+            AppProtocol::Legacy => Err(HWIError::UnimplementedMethod),
+            AppProtocol::PolicyV1 => legacy::to_policy_v1(wallet).map(Some),
+            AppProtocol::PolicyV2 => Ok(None),
+        }
+    }
+
+    /// Network of the xpubs of the legacy app, which does not know it.
+    fn legacy_network(&self) -> Network {
+        match self.options.network {
+            Some(network) => network,
+            None => self
+                .running_app()
+                .ok()
+                .and_then(|name| self.app_flavor(&name))
+                .map_or(Network::Bitcoin, |flavor| flavor.network()),
+        }
+    }
+
+    /// Single key address of the legacy app, displayed if display is true.
+    fn legacy_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        let (path, format) = match script {
+            AddressScript::P2PKH(path) => (path, legacy::AddressFormat::Legacy),
+            AddressScript::P2SHWPKH(path) => (path, legacy::AddressFormat::NestedSegwit),
+            AddressScript::P2WPKH(path) => (path, legacy::AddressFormat::NativeSegwit),
+            _ => return Err(HWIError::UnimplementedMethod),
+        };
+        let (_, address, _) = self.legacy().get_wallet_public_key(path, display, format)?;
+        Address::from_str(&address).map_err(|e| HWIError::Device(e.to_string()))
+    }
+
+    /// Signs the single key segwit v0 inputs with the legacy app.
+    fn sign_legacy(
+        &self,
+        psbt: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, HWIError> {
+        let fingerprint = self.get_master_fingerprint()?;
+        let unsigned = psbt.clone();
+        let sigs = self.legacy().sign_psbt(psbt, fingerprint, on_signature)?;
+        for (i, sig) in sigs {
+            let input = psbt.inputs.get_mut(i).ok_or(HWIError::DeviceDidNotSign)?;
+            sig.insert(input);
+        }
+        Ok(SignResult::new(&unsigned, psbt))
+    }
+
+    /// Converts the client error, the running app is queried if the status word
+    /// reports that the Bitcoin app is not opened.
+    fn client_error(&self, e: BitcoinClientError<T::Error>) -> HWIError {
+        match HWIError::from(e) {
+            e @ HWIError::WrongApp { .. } => match self.running_app() {
+                Ok(running) if self.app_flavor(&running).is_none() => {
+                    HWIError::AppNotOpen { running }
+                }
+                _ => e,
+            },
+            e => e,
+        }
+    }
+
+    /// Signs the psbt with the client, which may wrap the transport of the device.
+    fn sign_psbt_with<C: Transport<Error = T::Error>>(
+        &self,
+        client: &BitcoinClient<C>,
+        psbt: &mut Psbt,
+    ) -> Result<SignResult, HWIError> {
+        // Ledger cannot sign without policy.
+        let (policy, hmac) = self
+            .options
+            .wallet
+            .as_ref()
+            .ok_or(HWIError::UnimplementedMethod)?;
+        let hmac = self.wallet_hmac(policy, hmac.as_ref())?;
+        let v1 = self.app_wallet(policy)?;
+        let unsigned = psbt.clone();
+        // The app streams every field it reads with one exchange per 255 bytes, the
+        // previous transactions of the taproot inputs make most of the exchanges of
+        // large psbts, see utils::sanitize_for.
+        let mut sanitized = unsigned.clone();
+        utils::sanitize_for(self.kind, &mut sanitized);
+        let sigs = client
+            .sign_psbt(&sanitized, v1.as_ref().unwrap_or(policy), hmac)
+            .map_err(|e| self.client_error(e))?;
+        for (i, sig) in sigs {
+            let input = psbt.inputs.get_mut(i).ok_or(HWIError::DeviceDidNotSign)?;
+            InputSignature::from(sig).insert(input);
+        }
+        Ok(SignResult::new(&unsigned, psbt))
+    }
+
+    /// Returns the hmac to use with the wallet, the app fails late with an opaque
+    /// status word if a wallet that requires registration is used without it.
+    fn wallet_hmac<'a>(
+        &self,
+        wallet: &WalletPolicy,
+        hmac: Option<&'a [u8; 32]>,
+    ) -> Result<Option<&'a [u8; 32]>, HWIError> {
+        if hmac.is_some() || self.accepts_unregistered(wallet)? {
+            Ok(hmac)
+        } else {
+            Err(HWIError::WalletNotRegistered)
+        }
+    }
+}
+
+/// TODO: remove
+impl<T: Transport> std::fmt::Debug for Ledger<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ledger").finish()
+    }
+}
+
+impl<T: 'static + Transport + Reconnect + Sync + Send> From<Ledger<T>> for Box<dyn HWI + Send> {
+    fn from(s: Ledger<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+impl<T: 'static + Transport + Reconnect + Sync + Send> From<Ledger<T>>
+    for Arc<dyn HWI + Sync + Send>
+{
+    fn from(s: Ledger<T>) -> Arc<dyn HWI + Sync + Send> {
+        Arc::new(s)
+    }
+}
+
+impl<T: Transport + Reconnect + Sync + Send> HWI for Ledger<T>
+where
+    T::Error: 'static,
+{
+    fn device_kind(&self) -> DeviceKind {
+        self.kind
+    }
+
+    fn device_model(&self) -> Option<DeviceModel> {
+        self.model
+    }
+
+    fn get_version(&self) -> Result<super::Version, HWIError> {
+        let (name, version, _) = self.client.get_version()?;
+        // The dashboard and the other apps answer with their own name.
+        if self.app_flavor(&name).is_none() {
+            return Err(HWIError::AppNotOpen { running: name });
+        }
+        parse_version(&version)
+    }
+
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
+        if self.app_protocol()? == AppProtocol::Legacy {
+            return self.legacy().get_master_fingerprint();
+        }
+        self.client
+            .get_master_fingerprint()
+            .map_err(|e| self.client_error(e))
+    }
+
+    /// The unusual paths are displayed with allow_unusual_paths.
+    fn displays_xpub(&self, path: &DerivationPath) -> bool {
+        self.options.display_xpub
+            || (self.options.allow_unusual_paths && !utils::is_usual_path(path))
+    }
+
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
+        let display = if self.options.allow_unusual_paths {
+            self.options.display_xpub || !utils::is_usual_path(path)
+        } else {
+            if let Some(network) = self.options.network {
+                utils::check_coin_type(path, network)?;
+            }
+            if !self.options.display_xpub && !utils::is_usual_path(path) {
+                return Err(HWIError::InvalidParameter(
+                    "path",
+                    format!(
+                        "the app does not export the xpub of the unusual path {} without \
+                         allow_unusual_paths",
+                        path
+                    ),
+                ));
+            }
+            self.options.display_xpub
+        };
+        if self.app_protocol()? == AppProtocol::Legacy {
+            return self
+                .legacy()
+                .get_extended_pubkey(path, self.legacy_network(), display);
+        }
+        self.client
+            .get_extended_pubkey(path, display)
+            .map_err(|e| self.client_error(e))
+    }
+
+    fn display_address(
+        &self,
+        script: &AddressScript,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        self.get_address(script, true)
+    }
+
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
+        let address = match script {
+            _ if self.app_protocol()? == AppProtocol::Legacy => {
+                self.legacy_address(script, display)?
+            }
+            AddressScript::P2PKH(path) => self.default_wallet_address(path, 44, display)?,
+            AddressScript::P2SHWPKH(path) => self.default_wallet_address(path, 49, display)?,
+            AddressScript::P2WPKH(path) => self.default_wallet_address(path, 84, display)?,
+            AddressScript::P2TR(path) => self.default_wallet_address(path, 86, display)?,
+            AddressScript::Miniscript { index, change } => {
+                let (policy, hmac) = &self
+                    .options
+                    .wallet
+                    .as_ref()
+                    .ok_or(HWIError::MissingPolicy)?;
+                let hmac = self.wallet_hmac(policy, hmac.as_ref())?;
+                check_address_index(*index, hmac.is_some())?;
+                let v1 = self.app_wallet(policy)?;
+                self.client
+                    .get_wallet_address(
+                        v1.as_ref().unwrap_or(policy),
+                        hmac,
+                        *change,
+                        *index,
+                        display,
+                    )
+                    .map_err(|e| self.client_error(e))?
+            }
+        };
+        match self.options.network {
+            Some(network) => utils::check_address_network(address, network),
+            None => Ok(address),
+        }
+    }
+
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, HWIError> {
+        utils::validate_wallet_name(self.kind, name)?;
+        let descriptor = policy::WalletPolicy::from_descriptor(name, policy)?;
+        descriptor.check_for_device(self.get_master_fingerprint()?)?;
+        let wallet = WalletPolicy::try_from(&descriptor)?;
+        if let Some(required) = Self::required_version(&wallet.descriptor_template) {
+            self.app_version()?.require(&required)?;
+        }
+        let v1 = self.app_wallet(&wallet)?;
+        let (id, hmac) = self
+            .client
+            .register_wallet(v1.as_ref().unwrap_or(&wallet))
+            .map_err(|e| self.client_error(e))?;
+        Ok(WalletRegistration {
+            id: Some(id),
+            hmac: Some(hmac),
+            ..Default::default()
+        })
+    }
+
+    /// The device does not store registered policies, the hmac returned at registration
+    /// is the only proof of it. The hmac loaded with the wallet options is checked
+    /// by silently requesting the first receive address of the policy.
+    /// Default wallets accepted by the app without registration are reported as registered.
+    fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, HWIError> {
+        let wallet = wallet_policy(name, policy)?;
+        let hmac = match &self.options.wallet {
+            Some((loaded, Some(hmac)))
+                if wallet.name == loaded.name
+                    && wallet.descriptor_template == loaded.descriptor_template
+                    && wallet.keys == loaded.keys =>
+            {
+                Some(hmac)
+            }
+            _ => None,
+        };
+        let hmac = match hmac {
+            Some(hmac) => hmac,
+            None => return self.accepts_unregistered(&wallet),
+        };
+        let v1 = self.app_wallet(&wallet)?;
+        match self.client.get_wallet_address(
+            v1.as_ref().unwrap_or(&wallet),
+            Some(hmac),
+            false,
+            0,
+            false,
+        ) {
+            Ok(_) => Ok(true),
+            Err(BitcoinClientError::Device {
+                status: StatusWord::SignatureFail,
+                ..
+            }) => Ok(false),
+            Err(e) => Err(self.client_error(e)),
+        }
+    }
+
+    fn sign_tx(&self, psbt: &mut Psbt) -> Result<SignResult, HWIError> {
+        if self.app_protocol()? == AppProtocol::Legacy {
+            utils::check_op_return(&self.capabilities(), psbt)?;
+            return self.sign_legacy(psbt, &mut |_, _| {});
+        }
+        self.sign_psbt_with(&self.client, psbt)
+    }
+
+    /// The device yields each signature once the input is signed.
+    fn sign_tx_progressive(
+        &self,
+        psbt: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, HWIError> {
+        if self.app_protocol()? == AppProtocol::Legacy {
+            utils::check_op_return(&self.capabilities(), psbt)?;
+            return self.sign_legacy(psbt, on_signature);
+        }
+        let client = BitcoinClient::new(SignatureYield {
+            transport: &self.transport,
+            on_signature: RefCell::new(on_signature),
+        });
+        self.sign_psbt_with(&client, psbt)
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<(), HWIError> {
+        self.options.network = Some(network);
+        Ok(())
+    }
+
+    fn load_wallet(
+        &mut self,
+        name: &str,
+        policy: &str,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<(), HWIError> {
+        self.set_wallet(name, policy, hmac)
+    }
+
+    /// The wallet and the options are kept, the app must still be opened.
+    fn reset_connection(&self) -> Result<(), HWIError> {
+        self.transport.reconnect()
+    }
+
+    /// The version of the Bitcoin app is queried, if the app is not open
+    /// the capabilities are reported without version.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(self.kind, self.get_version().ok().as_ref())
+    }
+}
+
+impl<T: Transport + Clone> Ledger<T> {
+    /// Wraps the transport with the hooks of the layer.
+    pub fn with_layer<L>(self, layer: L) -> Ledger<LayeredTransport<T, L>>
+    where
+        L: Layer<APDUCommand, (StatusWord, Vec<u8>)>,
+    {
+        let transport = LayeredTransport::new(self.transport, layer);
+        Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            transport,
+            options: self.options,
+            kind: self.kind,
+            model: self.model,
+        }
+    }
+}
+
+impl Ledger<TransportHID> {
+    pub fn enumerate(api: &HidApi) -> impl Iterator<Item = &DeviceInfo> {
+        TransportNativeHID::list_ledgers(api)
+    }
+
+    /// Connected Ledger devices, listed with the HID api of the context.
+    pub fn enumerate_with(ctx: &Context) -> Result<Vec<DeviceInfo>, HWIError> {
+        let api = ctx.hid_api()?;
+        Ok(Self::enumerate(&api).cloned().collect())
+    }
+
+    /// Details of the connected Ledger devices, the path can be given to the
+    /// HID transport of the connect options.
+    pub fn enumerate_details(ctx: &Context) -> Result<Vec<DeviceDetails>, HWIError> {
+        let api = ctx.hid_api()?;
+        Ok(Self::enumerate(&api)
+            .map(|info| DeviceDetails::from_hid(DeviceKind::Ledger, model(info.product_id()), info))
+            .collect())
+    }
+
+    pub fn connect(ctx: &Context, device: &DeviceInfo) -> Result<Self, HWIError> {
+        let hid = TransportNativeHID::open_device(&*ctx.hid_api()?, device)
+            .map_err(|_| open_error(device.path()))?;
+        let transport = TransportHID::new(ctx.clone(), hid, device);
+        Ok(Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            model: transport.model(),
+            transport,
+            options: CommandOptions::default(),
+            kind: DeviceKind::Ledger,
+        })
+    }
+
+    /// Details of the connected Ledger devices, listed with the global context.
+    /// Unlike enumerate, the results do not borrow the HID api.
+    pub fn list() -> Result<Vec<DeviceDetails>, HWIError> {
+        let ctx = Context::global()?;
+        // Refreshes the devices of the long lived context.
+        ctx.hid_devices()?;
+        Self::enumerate_details(&ctx)
+    }
+
+    /// Connects a device returned by list with the global context.
+    pub fn open(details: &DeviceDetails) -> Result<Self, HWIError> {
+        let path = details.path.as_deref().ok_or(HWIError::DeviceNotFound)?;
+        Self::connect_path(&Context::global()?, path)
+    }
+
+    /// Connects the Ledger at the given HID path, see DeviceDetails::path.
+    /// Each instance has its own connection and wallet options, several Ledgers
+    /// can be used at the same time.
+    pub fn connect_path(ctx: &Context, path: &str) -> Result<Self, HWIError> {
+        let info = Self::enumerate_with(ctx)?
+            .into_iter()
+            .find(|info| info.path().to_str() == Ok(path))
+            .ok_or(HWIError::DeviceNotFound)?;
+        Self::connect(ctx, &info)
+    }
+
+    /// Connects the Ledger devices one by one as the iterator is consumed, skipping
+    /// the ones failing to open, see utils::find_device_by_fingerprint.
+    pub fn connect_all(ctx: &Context) -> Result<impl Iterator<Item = Self> + '_, HWIError> {
+        Ok(Self::enumerate_with(ctx)?
+            .into_iter()
+            .filter_map(move |info| Self::connect(ctx, &info).ok()))
+    }
+
+    /// Connects the first Ledger found, see connect_path to choose among several.
+    pub fn try_connect_hid(ctx: &Context) -> Result<Self, HWIError> {
+        let transport = TransportHID::open(ctx)?;
+        Ok(Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            model: transport.model(),
+            transport,
+            options: CommandOptions::default(),
+            kind: DeviceKind::Ledger,
+        })
+    }
+}
+
+/// Declarative options of a Ledger connection, see connect_with_options.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ConnectOptions {
+    pub transport: ConnectTransport,
+    pub network: Option<Network>,
+    /// Reopen the device when the connection is lost, see Ledger::with_auto_reconnect.
+    pub auto_reconnect: bool,
+    /// Time in milliseconds to wait for an answer of the socket transports, the USB
+    /// transport has its own timeout.
+    pub timeout_ms: Option<u64>,
+    /// Name of the expected app, a fork of the Bitcoin app, see Ledger::with_app_name.
+    pub app_name: Option<String>,
+    /// CLA of the commands of the app, see Ledger::with_cla.
+    pub cla: Option<u8>,
+    /// Fail to connect if the running app is not the expected one, see Ledger::check_app.
+    pub check_app: bool,
+    /// Display the xpubs on the device when they are requested, see Ledger::display_xpub.
+    pub display_xpub: bool,
+    /// See Ledger::allow_unusual_paths.
+    pub allow_unusual_paths: bool,
+}
+
+/// Transport of a Ledger connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum ConnectTransport {
+    /// Device plugged in with USB, the first Ledger found if no HID path is given.
+    Hid { path: Option<String> },
+    /// Speculos simulator, at TransportTcp::ADDRESS if no address is given.
+    Simulator { address: Option<SocketAddr> },
+    /// Device of a bridge agent, see TransportBridge.
+    Bridge { address: String },
+}
+
+impl Default for ConnectTransport {
+    fn default() -> Self {
+        Self::Hid { path: None }
+    }
+}
+
+/// Connects the Ledger described by the options.
+pub fn connect_with_options(
+    ctx: &Context,
+    options: &ConnectOptions,
+) -> Result<Box<dyn HWI + Send>, HWIError> {
+    let timeout = options.timeout_ms.map(Duration::from_millis);
+    match &options.transport {
+        ConnectTransport::Hid { path: None } => configure(Ledger::try_connect_hid(ctx)?, options),
+        ConnectTransport::Hid { path: Some(path) } => {
+            configure(Ledger::<TransportHID>::connect_path(ctx, path)?, options)
+        }
+        ConnectTransport::Simulator { address } => {
+            let transport =
+                TransportTcp::connect(address.unwrap_or(TransportTcp::ADDRESS), timeout)
+                    .map_err(|_| HWIError::DeviceNotFound)?;
+            configure(LedgerSimulator::with_transport(transport), options)
+        }
+        ConnectTransport::Bridge { address } => {
+            let transport = TransportBridge::connect_timeout(address.as_str(), timeout)?;
+            configure(LedgerBridge::with_bridge(transport), options)
+        }
+    }
+}
+
+fn configure<T>(
+    device: Ledger<T>,
+    options: &ConnectOptions,
+) -> Result<Box<dyn HWI + Send>, HWIError>
+where
+    T: 'static + Transport<Error = Box<dyn Error>> + Reconnect + Clone + Sync + Send,
+{
+    let device = match options.network {
+        Some(network) => device.with_network(network),
+        None => device,
+    }
+    .display_xpub(options.display_xpub)?
+    .allow_unusual_paths(options.allow_unusual_paths);
+    let device = match &options.app_name {
+        Some(name) => device.with_app_name(name.clone()),
+        None => device,
+    };
+    match options.cla {
+        Some(cla) => finish(device.with_cla(cla), options),
+        None => finish(device, options),
+    }
+}
+
+fn finish<T>(device: Ledger<T>, options: &ConnectOptions) -> Result<Box<dyn HWI + Send>, HWIError>
+where
+    T: 'static + Transport<Error = Box<dyn Error>> + Reconnect + Clone + Sync + Send,
+{
+    if options.check_app {
+        device.check_app()?;
+    }
+    Ok(if options.auto_reconnect {
+        device.with_auto_reconnect().into()
+    } else {
+        device.into()
+    })
+}
+
+/// Model of the Ledger with the USB product id, either the legacy id of the
+/// bootloader or the model id in the high byte followed by the interfaces.
+pub fn model(product_id: u16) -> Option<DeviceModel> {
+    let model_id = if product_id > 0xff {
+        product_id >> 8
+    } else {
+        product_id << 4
+    };
+    match model_id {
+        0x10 => Some(DeviceModel::LedgerNanoS),
+        0x40 => Some(DeviceModel::LedgerNanoX),
+        0x50 => Some(DeviceModel::LedgerNanoSPlus),
+        0x60 => Some(DeviceModel::LedgerStax),
+        0x70 => Some(DeviceModel::LedgerFlex),
+        _ => None,
+    }
+}
+
+/// Transport with the Ledger device, clones share the connection.
+#[derive(Clone)]
+pub struct TransportHID {
+    /// Context owning the HID api used to reopen the device.
+    context: Context,
+    device: Arc<Mutex<Option<TransportNativeHID>>>,
+    /// Path of the device to reopen.
+    path: CString,
+    /// Master fingerprint of the device, to find it again if it re-enumerated with
+    /// another path. The serial numbers do not tell the Ledgers apart, they all report "0001".
+    fingerprint: Arc<Mutex<Option<Fingerprint>>>,
+    product_id: u16,
+}
+
+impl TransportHID {
+    /// Opens the first Ledger found, for the bridge agent.
+    /// The transport keeps reconnecting to this device and not to another Ledger.
+    pub fn open(ctx: &Context) -> Result<Self, HWIError> {
+        let api = ctx.hid_api()?;
+        let info = TransportNativeHID::list_ledgers(&api)
+            .next()
+            .ok_or(HWIError::DeviceNotFound)?
+            .clone();
+        let hid =
+            TransportNativeHID::open_device(&api, &info).map_err(|_| open_error(info.path()))?;
+        drop(api);
+        Ok(Self::new(ctx.clone(), hid, &info))
+    }
+
+    fn new(context: Context, device: TransportNativeHID, info: &DeviceInfo) -> Self {
+        Self {
+            context,
+            fingerprint: Arc::new(Mutex::new(master_fingerprint(&device))),
+            device: Arc::new(Mutex::new(Some(device))),
+            path: info.path().to_owned(),
+            product_id: info.product_id(),
+        }
+    }
+
+    /// Model of the device, see model.
+    pub fn model(&self) -> Option<DeviceModel> {
+        model(self.product_id)
+    }
+}
+
+impl Reconnect for TransportHID {
+    fn reconnect(&self) -> Result<(), HWIError> {
+        let mut device = self
+            .device
+            .lock()
+            .map_err(|_| HWIError::Unexpected("Failed to unlock"))?;
+        // Close the device before opening it again.
+        *device = None;
+        let mut api = self.context.hid_api()?;
+        api.refresh_devices()
+            .map_err(|e| HWIError::Transport(TransportErrorKind::Io, e.to_string()))?;
+        let mut fingerprint = self
+            .fingerprint
+            .lock()
+            .map_err(|_| HWIError::Unexpected("Failed to unlock"))?;
+        // The device is tried first at its path, then at the paths of the other Ledgers.
+        let mut infos: Vec<&DeviceInfo> = TransportNativeHID::list_ledgers(&api).collect();
+        infos.sort_by_key(|info| info.path() != self.path.as_c_str());
+        let mut error = HWIError::DeviceNotFound;
+        for info in infos {
+            let same_path = info.path() == self.path.as_c_str();
+            let hid = match TransportNativeHID::open_device(&api, info) {
+                Ok(hid) => hid,
+                Err(_) => {
+                    if same_path {
+                        error = open_error(info.path());
+                    }
+                    continue;
+                }
+            };
+            let found = master_fingerprint(&hid);
+            if is_same_device(*fingerprint, found, same_path) {
+                *fingerprint = fingerprint.or(found);
+                *device = Some(hid);
+                return Ok(());
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Master fingerprint told by the Bitcoin app, None if the app is not open.
+fn master_fingerprint(hid: &TransportNativeHID) -> Option<Fingerprint> {
+    let answer = hid
+        .exchange(&ledger_apdu::APDUCommand {
+            cla: BITCOIN_APP_CLA,
+            ins: ledger_bitcoin_client::apdu::BitcoinCommandCode::GetMasterFingerprint as u8,
+            p1: 0,
+            p2: 0,
+            data: Vec::new(),
+        })
+        .ok()?;
+    if answer.retcode() != StatusWord::OK as u16 {
+        return None;
+    }
+    let fingerprint: [u8; 4] = TryFrom::try_from(answer.data()).ok()?;
+    Some(Fingerprint::from(fingerprint))
+}
+
+/// A reopened device is the one of the transport if it tells the recorded fingerprint.
+/// Without fingerprint to compare, only the device at the same path is.
+fn is_same_device(
+    recorded: Option<Fingerprint>,
+    found: Option<Fingerprint>,
+    same_path: bool,
+) -> bool {
+    match (recorded, found) {
+        (Some(recorded), Some(found)) => recorded == found,
+        _ => same_path,
+    }
+}
+
+impl Transport for TransportHID {
+    type Error = Box<dyn Error>;
+    fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        let device = self.device.lock().map_err(|_| "Failed to unlock")?;
+        let hid = device.as_ref().ok_or_else(connection_lost)?;
+        check_command(cmd)?;
+        trace_command(cmd);
+        let answer = hid.exchange(&ledger_apdu::APDUCommand {
+            ins: cmd.ins,
+            cla: cmd.cla,
+            p1: cmd.p1,
+            p2: cmd.p2,
+            data: cmd.data.clone(),
+        })?;
+        trace_answer(answer.retcode(), answer.data());
+        Ok((status_word(answer.retcode())?, answer.data().to_vec()))
+    }
+}
+
+pub type LedgerSimulator = Ledger<TransportTcp>;
+
+impl LedgerSimulator {
+    /// Connects with a blocking socket, like the exchanges of the sync client.
+    pub fn try_connect() -> Result<Self, HWIError> {
+        let transport = TransportTcp::new().map_err(|_| HWIError::DeviceNotFound)?;
+        Ok(Self::with_transport(transport))
+    }
+
+    fn with_transport(transport: TransportTcp) -> Self {
+        Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            transport,
+            options: CommandOptions::default(),
+            kind: DeviceKind::LedgerSimulator,
+            model: None,
+        }
+    }
+}
+
+/// Transport to communicate with the Ledger Speculos simulator, clones share the connection.
+#[derive(Clone)]
+pub struct TransportTcp {
+    /// None once an exchange failed, until reconnected.
+    connection: Arc<Mutex<Option<TcpStream>>>,
+    address: SocketAddr,
+    timeout: Option<Duration>,
+}
+
+impl TransportTcp {
+    /// Default address of the simulator APDU server.
+    pub const ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9999);
+
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::connect(Self::ADDRESS, None)
+    }
+
+    /// Connects to the simulator at the address, the answers are awaited for the timeout
+    /// if one is given.
+    pub fn connect(address: SocketAddr, timeout: Option<Duration>) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            connection: Arc::new(Mutex::new(Some(connect_tcp(address, timeout)?))),
+            address,
+            timeout,
+        })
+    }
+}
+
+fn connect_tcp(address: SocketAddr, timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(timeout)?;
+    Ok(stream)
+}
+
+impl Reconnect for TransportTcp {
+    fn reconnect(&self) -> Result<(), HWIError> {
+        let mut stream = self
+            .connection
+            .lock()
+            .map_err(|_| HWIError::Unexpected("Failed to unlock"))?;
+        // The simulator serves one client at a time.
+        if let Some(stream) = stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        *stream =
+            Some(connect_tcp(self.address, self.timeout).map_err(|_| HWIError::DeviceNotFound)?);
+        Ok(())
+    }
+}
+
+impl Transport for TransportTcp {
+    type Error = Box<dyn Error>;
+    fn exchange(&self, command: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        let mut connection = self.connection.lock().map_err(|_| "Failed to unlock")?;
+        check_command(command)?;
+        let stream = connection.as_mut().ok_or_else(connection_lost)?;
+        stream.set_read_timeout(read_timeout(command, self.timeout))?;
+        trace_command(command);
+        let answer = match exchange_framed(stream, command) {
+            Ok(answer) => answer,
+            Err(e) => {
+                // The late answer of the device would be read as the answer of the
+                // next command.
+                let _ = stream.shutdown(Shutdown::Both);
+                *connection = None;
+                return Err(e);
+            }
+        };
+        trace_answer(answer.retcode(), answer.data());
+        Ok((status_word(answer.retcode())?, answer.data().to_vec()))
+    }
+}
+
+fn connection_lost() -> Box<dyn Error> {
+    Box::new(std::io::Error::new(
+        std::io::ErrorKind::NotConnected,
+        "Connection lost, the transport must reconnect",
+    ))
+}
+
+/// Read timeout of the command: the commands answered once the user confirmed on the
+/// device are awaited without timeout, a slow user is not a lost connection.
+fn read_timeout(command: &APDUCommand, timeout: Option<Duration>) -> Option<Duration> {
+    if waits_for_user(command) {
+        None
+    } else {
+        timeout
+    }
+}
+
+fn waits_for_user(command: &APDUCommand) -> bool {
+    let display = command.data.first() == Some(&1);
+    match command.cla {
+        // Answers to the interruptions of a command of the Bitcoin app.
+        0xF8 => true,
+        // Dashboard and legacy app: open app, get wallet public key with display,
+        // finalize inputs, sign and sign message.
+        0xE0 => match command.ins {
+            0xD8 | 0x4A | 0x48 | 0x4E => true,
+            0x40 => command.p1 == 1,
+            _ => false,
+        },
+        0xB0 => false,
+        // Bitcoin app, or a fork with its own CLA: get extended pubkey and get wallet
+        // address with display, register wallet, sign psbt and sign message.
+        _ => match command.ins {
+            0x00 | 0x03 => display,
+            0x02 | 0x04 | 0x10 => true,
+            _ => false,
+        },
+    }
+}
+
+/// Sends the command and reads the answer, both prefixed with their big endian u32 length.
+/// The socket may deliver the frames in several chunks.
+fn exchange_framed<S: Read + Write + ?Sized>(
+    stream: &mut S,
+    command: &APDUCommand,
+) -> Result<APDUAnswer<Vec<u8>>, Box<dyn Error>> {
+    let command_bytes = command.encode();
+
+    let mut req = vec![0u8; command_bytes.len() + 4];
+    req[..4].copy_from_slice(&(command_bytes.len() as u32).to_be_bytes());
+    req[4..].copy_from_slice(&command_bytes);
+    stream.write_all(&req)?;
+
+    let mut buff = [0u8; 4];
+    stream.read_exact(&mut buff)?;
+    // The length comes from the peer, it is checked before allocating.
+    let len = u32::from_be_bytes(buff) as usize;
+    if len > MAX_APDU_DATA_LEN + 2 {
+        return Err(format!("Answer of {} bytes is too long", len).into());
+    }
+
+    let mut resp = vec![0u8; len + 2];
+    stream.read_exact(&mut resp)?;
+    APDUAnswer::from_answer(resp).map_err(|_| "Invalid Answer".into())
+}
+
+/// Transport reopening the device and sending the command again, once, when the
+/// connection is lost. The handle of a device is dead once it re-enumerated, which it
+/// frequently does after an unlock or an app switch. Only the first APDU of a command
+/// is sent again, the device loses the state of the command with the connection.
+#[derive(Clone, Debug)]
+pub struct ReconnectingTransport<T>(T);
+
+impl<T> ReconnectingTransport<T> {
+    pub fn new(transport: T) -> Self {
+        Self(transport)
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Transport for ReconnectingTransport<T>
+where
+    T: Transport<Error = Box<dyn Error>> + Reconnect,
+{
+    type Error = Box<dyn Error>;
+    fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        match self.0.exchange(cmd) {
+            Err(e) if is_first_apdu(cmd) && is_disconnect(e.as_ref()) => {
+                debug_exchange!(error = %e, "ledger exchange failed, reconnecting");
+                self.0.reconnect().map_err(|_| e)?;
+                self.0.exchange(cmd)
+            }
+            res => res,
+        }
+    }
+}
+
+/// The exchange failed because the connection with the device is lost, and not
+/// because of the command or of the answer of the device.
+fn is_disconnect(e: &(dyn Error + 'static)) -> bool {
+    use std::io::ErrorKind;
+    let kind = match e.downcast_ref::<LedgerHIDError>() {
+        Some(LedgerHIDError::Hid(_)) => return true,
+        Some(LedgerHIDError::Io(e)) => e.kind(),
+        _ => match e.downcast_ref::<std::io::Error>() {
+            Some(e) => e.kind(),
+            None => return false,
+        },
+    };
+    matches!(
+        kind,
+        ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+/// The APDU starts a command, and does not continue one.
+fn is_first_apdu(command: &APDUCommand) -> bool {
+    match command.cla {
+        // Answers to the interruptions of a command of the Bitcoin app.
+        0xF8 => false,
+        // Legacy app: get wallet public key and get firmware version, a signature spans
+        // several commands. The dashboard re-enumerates once it opened the app.
+        0xE0 => matches!(command.ins, 0x40 | 0xC4),
+        _ => true,
+    }
+}
+
+impl<T: Reconnect> Reconnect for ReconnectingTransport<T> {
+    fn reconnect(&self) -> Result<(), HWIError> {
+        self.0.reconnect()
+    }
+}
+
+impl<T> Ledger<T>
+where
+    T: Transport<Error = Box<dyn Error>> + Reconnect + Clone,
+{
+    /// Reopens the device and retries the exchange once when the connection is lost.
+    pub fn with_auto_reconnect(self) -> Ledger<ReconnectingTransport<T>> {
+        let transport = ReconnectingTransport::new(self.transport);
+        Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            transport,
+            options: self.options,
+            kind: self.kind,
+            model: self.model,
+        }
+    }
+}
+
+/// CLA of the commands of the Bitcoin app.
+pub const BITCOIN_APP_CLA: u8 = 0xE1;
+
+/// Transport sending the commands of the Bitcoin app with the CLA of a fork of the app,
+/// the commands of the OS are sent unchanged.
+#[derive(Clone, Debug)]
+pub struct ClaTransport<T> {
+    transport: T,
+    cla: u8,
+}
+
+impl<T> ClaTransport<T> {
+    pub fn new(transport: T, cla: u8) -> Self {
+        Self { transport, cla }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> Transport for ClaTransport<T> {
+    type Error = T::Error;
+    fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        if cmd.cla != BITCOIN_APP_CLA {
+            return self.transport.exchange(cmd);
+        }
+        self.transport.exchange(&APDUCommand {
+            cla: self.cla,
+            ..cmd.clone()
+        })
+    }
+}
+
+impl<T: Reconnect> Reconnect for ClaTransport<T> {
+    fn reconnect(&self) -> Result<(), HWIError> {
+        self.transport.reconnect()
+    }
+}
+
+impl<T: Transport + Clone> Ledger<T> {
+    /// Sends the commands of the Bitcoin app with the CLA of a fork of the app.
+    pub fn with_cla(self, cla: u8) -> Ledger<ClaTransport<T>> {
+        let transport = ClaTransport::new(self.transport, cla);
+        Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            transport,
+            options: self.options,
+            kind: self.kind,
+            model: self.model,
+        }
+    }
+}
+
+/// Stream to a bridge agent: a socket, or the stdout and stdin of a ssh session joined
+/// with Duplex.
+pub trait BridgeStream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> BridgeStream for S {}
+
+/// Reader and writer used as a single stream.
+#[derive(Debug)]
+pub struct Duplex<R, W> {
+    pub reader: R,
+    pub writer: W,
+}
+
+impl<R: Read, W> Read for Duplex<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<R, W: Write> Write for Duplex<R, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub type LedgerBridge = Ledger<TransportBridge>;
+
+impl LedgerBridge {
+    /// Ledger plugged into the machine of the bridge agent, see serve_bridge.
+    pub fn with_bridge(transport: TransportBridge) -> Self {
+        Ledger {
+            client: BitcoinClient::new(transport.clone()),
+            transport,
+            options: CommandOptions::default(),
+            kind: DeviceKind::Ledger,
+            model: None,
+        }
+    }
+}
+
+/// Transport forwarding the commands to a bridge agent running on the machine the
+/// device is plugged into, with the length prefixed framing of the simulator.
+/// Clones share the connection.
+#[derive(Clone)]
+pub struct TransportBridge {
+    /// None once an exchange failed.
+    connection: Arc<Mutex<Option<Box<dyn BridgeStream>>>>,
+    /// Handle of the socket of the connection, to set its read timeout and shut it down.
+    socket: Arc<Option<TcpStream>>,
+    timeout: Option<Duration>,
+}
+
+impl TransportBridge {
+    pub fn new<S: BridgeStream + 'static>(stream: S) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(Some(Box::new(stream)))),
+            socket: Arc::new(None),
+            timeout: None,
+        }
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> Result<Self, HWIError> {
+        Self::connect_timeout(address, None)
+    }
+
+    /// Connects to the agent, the answers are awaited for the timeout if one is given.
+    pub fn connect_timeout<A: ToSocketAddrs>(
+        address: A,
+        timeout: Option<Duration>,
+    ) -> Result<Self, HWIError> {
+        let stream = TcpStream::connect(address).map_err(|_| HWIError::DeviceNotFound)?;
+        let socket = stream
+            .try_clone()
+            .map_err(|e| HWIError::Transport(TransportErrorKind::Io, e.to_string()))?;
+        Ok(Self {
+            socket: Arc::new(Some(socket)),
+            timeout,
+            ..Self::new(stream)
+        })
+    }
+}
+
+/// The agent owns the connection with the device.
+impl Reconnect for TransportBridge {
+    fn reconnect(&self) -> Result<(), HWIError> {
+        Err(HWIError::UnimplementedMethod)
+    }
+}
+
+impl Transport for TransportBridge {
+    type Error = Box<dyn Error>;
+    fn exchange(&self, command: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        let mut connection = self.connection.lock().map_err(|_| "Failed to unlock")?;
+        check_command(command)?;
+        let stream = connection.as_mut().ok_or_else(connection_lost)?;
+        if let Some(socket) = &*self.socket {
+            socket.set_read_timeout(read_timeout(command, self.timeout))?;
+        }
+        trace_command(command);
+        let answer = match exchange_framed(&mut **stream, command) {
+            Ok(answer) => answer,
+            Err(e) => {
+                if let Some(socket) = &*self.socket {
+                    let _ = socket.shutdown(Shutdown::Both);
+                }
+                *connection = None;
+                return Err(e);
+            }
+        };
+        trace_answer(answer.retcode(), answer.data());
+        Ok((status_word(answer.retcode())?, answer.data().to_vec()))
+    }
+}
+
+/// Bridge agent: forwards the commands read from the stream to the device
+/// and writes back the answers, until the stream is closed.
+pub fn serve_bridge<S, T>(stream: &mut S, transport: &T) -> Result<(), Box<dyn Error>>
+where
+    S: Read + Write,
+    T: Transport<Error = Box<dyn Error>>,
+{
+    loop {
+        let mut buff = [0u8; 4];
+        match stream.read_exact(&mut buff) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            res => res?,
+        }
+        // The length comes from the peer, it is checked before allocating.
+        let len = u32::from_be_bytes(buff) as usize;
+        if len > 5 + MAX_APDU_DATA_LEN {
+            return Err(format!("Command of {} bytes is too long", len).into());
+        }
+        let mut req = vec![0u8; len];
+        stream.read_exact(&mut req)?;
+        let command = match req.as_slice() {
+            [cla, ins, p1, p2, len, data @ ..] if *len as usize == data.len() => APDUCommand {
+                cla: *cla,
+                ins: *ins,
+                p1: *p1,
+                p2: *p2,
+                data: data.to_vec(),
+            },
+            _ => return Err("Invalid Command".into()),
+        };
+        let (retcode, data) = match transport.exchange(&command) {
+            Ok((status, data)) => (status as u16, data),
+            // Status words unknown to the client, like the locked device one,
+            // are left to the host.
+            Err(e) => match e.downcast_ref::<UnknownStatusWord>() {
+                Some(UnknownStatusWord(code)) => (*code, Vec::new()),
+                None => return Err(e),
+            },
+        };
+        let mut resp = Vec::with_capacity(data.len() + 6);
+        resp.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        resp.extend_from_slice(&data);
+        resp.extend_from_slice(&retcode.to_be_bytes());
+        stream.write_all(&resp)?;
+        stream.flush()?;
+    }
+}
+
+impl<T, L> Transport for LayeredTransport<T, L>
+where
+    T: Transport,
+    L: Layer<APDUCommand, (StatusWord, Vec<u8>)>,
+{
+    type Error = T::Error;
+    fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.layer.pre(cmd);
+            let res = self.transport.exchange(cmd);
+            self.layer
+                .post(cmd, res.as_ref().map_err(|e| e as &dyn std::fmt::Debug));
+            match res {
+                Err(_) if self.layer.retry(cmd, attempt) => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T: Reconnect, L> Reconnect for LayeredTransport<T, L> {
+    fn reconnect(&self) -> Result<(), HWIError> {
+        self.transport.reconnect()
+    }
+}
+
+/// Transport calling back with the signatures the device yields during sign_psbt.
+struct SignatureYield<'a, T> {
+    transport: &'a T,
+    on_signature: RefCell<&'a mut dyn FnMut(usize, InputSignature)>,
+}
+
+/// Client command code of the data yielded by the device.
+const CCMD_YIELD: u8 = 0x10;
+
+impl<T: Transport> Transport for SignatureYield<'_, T> {
+    type Error = T::Error;
+    fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+        let (sw, data) = self.transport.exchange(cmd)?;
+        if sw == StatusWord::InterruptedExecution && data.first() == Some(&CCMD_YIELD) {
+            if let Some((i, sig)) = yielded_signature(&data[1..]) {
+                (self.on_signature.borrow_mut())(i, sig);
+            }
+        }
+        Ok((sw, data))
+    }
+}
+
+/// Parses the input index and the signature yielded by sign_psbt,
+/// invalid data is left to the client that reports it once the command ends.
+fn yielded_signature(data: &[u8]) -> Option<(usize, InputSignature)> {
+    let (index, i): (VarInt, usize) = deserialize_partial(data).ok()?;
+    let sig = PartialSignature::from_slice(&data[i..]).ok()?;
+    Some((index.0 as usize, sig.into()))
+}
+
+impl From<PartialSignature> for InputSignature {
+    fn from(sig: PartialSignature) -> Self {
+        match sig {
+            PartialSignature::Sig(key, sig) => InputSignature::Ecdsa(key, sig),
+            PartialSignature::TapScriptSig(key, Some(leaf_hash), sig) => {
+                InputSignature::TapScript(key, leaf_hash, sig)
+            }
+            PartialSignature::TapScriptSig(_, None, sig) => InputSignature::TapKey(sig),
+        }
+    }
+}
+
+/// Status word unknown to the client, the transports return it as an error
+/// so that the device state it reports is not lost.
+#[derive(Debug)]
+pub struct UnknownStatusWord(pub u16);
+
+impl std::fmt::Display for UnknownStatusWord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown status word {:#06x}", self.0)
+    }
+}
+
+impl Error for UnknownStatusWord {}
+
+fn status_word(retcode: u16) -> Result<StatusWord, Box<dyn Error>> {
+    StatusWord::try_from(retcode).map_err(|_| UnknownStatusWord(retcode).into())
+}
+
+/// Maximum length of the data of a command, its length is encoded in a single byte.
+/// Larger payloads are split by the client into commands of the protocol of the app.
+pub const MAX_APDU_DATA_LEN: usize = 255;
+
+/// Fails before sending a command whose data length would be truncated by the encoding.
+fn check_command(cmd: &APDUCommand) -> Result<(), Box<dyn Error>> {
+    if cmd.data.len() > MAX_APDU_DATA_LEN {
+        return Err(format!(
+            "Command data of {} bytes exceeds the {} bytes of an APDU",
+            cmd.data.len(),
+            MAX_APDU_DATA_LEN
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The payload may hold a psbt or a wallet policy, only its length is logged.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn trace_command(cmd: &APDUCommand) {
+    debug_exchange!(
+        cla = cmd.cla,
+        ins = cmd.ins,
+        p1 = cmd.p1,
+        p2 = cmd.p2,
+        len = cmd.data.len(),
+        "ledger command"
+    );
+}
+
+/// The data may hold signatures or xpubs, only its length is logged.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn trace_answer(retcode: u16, data: &[u8]) {
+    debug_exchange!(retcode, len = data.len(), "ledger response");
+}
+
+/// Prefix of the Bitcoin and Bitcoin Test app names.
+const BITCOIN_APP_NAME: &str = "Bitcoin";
+
+/// Asks the dashboard to open the Bitcoin app, or the Bitcoin Test app for the test networks.
+/// The user confirms on the device, the transport must then be reopened.
+pub fn open_bitcoin_app<T: Transport>(transport: &T, network: Network) -> Result<(), HWIError>
+where
+    T::Error: 'static,
+{
+    let name = if network == Network::Bitcoin {
+        "Bitcoin"
+    } else {
+        "Bitcoin Test"
+    };
+    let (status, _) = transport
+        .exchange(&APDUCommand {
+            cla: 0xE0,
+            ins: 0xD8,
+            p1: 0x00,
+            p2: 0x00,
+            data: name.as_bytes().to_vec(),
+        })
+        .map_err(|e| HWIError::from(BitcoinClientError::<T::Error>::Transport(e)))?;
+    match status {
+        StatusWord::OK => Ok(()),
+        status => Err(error_from_status_word(status as u16)),
+    }
+}
+
+/// Status word of the OS when the device is locked.
+const SW_DEVICE_LOCKED: u16 = 0x5515;
+
+/// Status words of the OS or of another app when the Bitcoin app is not opened.
+const SW_APP_NOT_OPENED: [u16; 4] = [0x6E00, 0x6E01, 0x6D01, 0x6511];
+
+fn error_from_status_word(code: u16) -> HWIError {
+    if code == SW_DEVICE_LOCKED {
+        HWIError::DeviceLocked {
+            code: Some(code.into()),
+        }
+    } else if code == StatusWord::Deny as u16 {
+        HWIError::UserRefused
+    } else if SW_APP_NOT_OPENED.contains(&code) {
+        HWIError::WrongApp {
+            expected: BITCOIN_APP_NAME.to_string(),
+            found: None,
+            code: Some(code.into()),
+        }
+    } else {
+        HWIError::Device(format!("Status word {:#06x}", code))
+    }
+}
+
+impl<T: core::fmt::Debug + 'static> From<BitcoinClientError<T>> for HWIError {
+    fn from(e: BitcoinClientError<T>) -> HWIError {
+        match e {
+            BitcoinClientError::Device { status, .. } if status != StatusWord::Unknown => {
+                error_from_status_word(status as u16)
+            }
+            BitcoinClientError::Transport(e) => {
+                match (&e as &dyn Any)
+                    .downcast_ref::<Box<dyn Error>>()
+                    .and_then(|e| e.downcast_ref::<UnknownStatusWord>())
+                {
+                    Some(UnknownStatusWord(code)) => error_from_status_word(*code),
+                    None => HWIError::Transport(TransportErrorKind::Io, format!("{:?}", e)),
+                }
+            }
+            BitcoinClientError::UnexpectedResult { .. }
+            | BitcoinClientError::InvalidResponse(_) => {
+                HWIError::Transport(TransportErrorKind::Protocol, format!("{:#?}", e))
+            }
+            e => HWIError::Device(format!("{:#?}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_app_flavor() {
+        assert_eq!(
+            AppFlavor::from_app_name("Bitcoin"),
+            Some(AppFlavor::Bitcoin)
+        );
+        assert_eq!(
+            AppFlavor::from_app_name("Bitcoin Test"),
+            Some(AppFlavor::BitcoinTest)
+        );
+        assert_eq!(
+            AppFlavor::from_app_name("Bitcoin Test Legacy"),
+            Some(AppFlavor::BitcoinTest)
+        );
+        assert_eq!(AppFlavor::from_app_name("BOLOS"), None);
+        assert_eq!(AppFlavor::BitcoinTest.coin_type(), 1);
+    }
+
+    #[test]
+    fn test_is_default_wallet() {
+        let fg = Fingerprint::from_str("f5acc2fd").unwrap();
+        let key = "[f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP";
+        let wallet = |name: &str, template: &str| {
+            WalletPolicy::new(
+                name.to_string(),
+                WalletVersion::V2,
+                template.to_string(),
+                vec![WalletPubKey::from_str(key).unwrap()],
+            )
+        };
+        assert!(is_default_wallet(&wallet("", "sh(wpkh(@0/**))"), fg));
+        assert!(is_default_wallet(&wallet("", "sh(wpkh(@0/<0;1>/*))"), fg));
+        // A named policy must be registered.
+        assert!(!is_default_wallet(&wallet("Liana", "sh(wpkh(@0/**))"), fg));
+        // Purpose of the key path does not match the script type.
+        assert!(!is_default_wallet(&wallet("", "wpkh(@0/**)"), fg));
+        // Key of another device.
+        assert!(!is_default_wallet(
+            &wallet("", "sh(wpkh(@0/**))"),
+            Fingerprint::from_str("b0822927").unwrap()
+        ));
+    }
+
+    /// Socket delivering the answer one byte at a time.
+    struct Chunked {
+        sent: Vec<u8>,
+        answer: std::collections::VecDeque<u8>,
+    }
+
+    impl Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.answer.pop_front(), buf.first_mut()) {
+                (Some(byte), Some(b)) => {
+                    *b = byte;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    impl Write for Chunked {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_exchange_framed() {
+        let mut stream = Chunked {
+            sent: Vec::new(),
+            answer: vec![0, 0, 0, 2, 0xab, 0xcd, 0x90, 0x00].into(),
+        };
+        let command = APDUCommand {
+            cla: 0xe1,
+            ins: 0x00,
+            p1: 0x00,
+            p2: 0x00,
+            data: vec![0x01],
+        };
+        let answer = exchange_framed(&mut stream, &command).unwrap();
+        assert_eq!(answer.data(), [0xab, 0xcd]);
+        assert_eq!(answer.retcode(), 0x9000);
+        assert_eq!(
+            stream.sent,
+            [0, 0, 0, 6, 0xe1, 0x00, 0x00, 0x00, 0x01, 0x01]
+        );
+
+        // Connection closed in the middle of the answer.
+        let mut stream = Chunked {
+            sent: Vec::new(),
+            answer: vec![0, 0, 0, 2, 0xab].into(),
+        };
+        assert!(exchange_framed(&mut stream, &command).is_err());
+
+        // Length prefix beyond any APDU answer.
+        let mut stream = Chunked {
+            sent: Vec::new(),
+            answer: vec![0xff, 0xff, 0xff, 0xff, 0x90, 0x00].into(),
+        };
+        assert_eq!(
+            exchange_framed(&mut stream, &command)
+                .unwrap_err()
+                .to_string(),
+            "Answer of 4294967295 bytes is too long"
+        );
+    }
+
+    /// Device echoing the data of the command, locked for the instruction 0xff.
+    struct Echo;
+
+    impl Transport for Echo {
+        type Error = Box<dyn Error>;
+        fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+            if cmd.ins == 0xff {
+                return Err(UnknownStatusWord(SW_DEVICE_LOCKED).into());
+            }
+            Ok((StatusWord::OK, cmd.data.clone()))
+        }
+    }
+
+    #[test]
+    fn test_tcp_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // Simulator answering every command late.
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || loop {
+                    let mut len = [0u8; 4];
+                    if stream.read_exact(&mut len).is_err() {
+                        return;
+                    }
+                    let mut command = vec![0u8; u32::from_be_bytes(len) as usize];
+                    stream.read_exact(&mut command).unwrap();
+                    std::thread::sleep(Duration::from_millis(300));
+                    let _ = stream.write_all(&[0, 0, 0, 1, command[1], 0x90, 0x00]);
+                });
+            }
+        });
+
+        let transport = TransportTcp::connect(address, Some(Duration::from_millis(100))).unwrap();
+        let command = |ins| APDUCommand {
+            cla: BITCOIN_APP_CLA,
+            ins,
+            p1: 0x00,
+            p2: 0x01,
+            data: Vec::new(),
+        };
+        // GET_MASTER_FINGERPRINT times out and the connection is closed, the late
+        // answer is not read as the answer of the next command.
+        assert!(transport.exchange(&command(0x05)).is_err());
+        let err = transport.exchange(&command(0x05)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+            Some(std::io::ErrorKind::NotConnected)
+        );
+
+        // SIGN_PSBT waits for the user without timeout.
+        transport.reconnect().unwrap();
+        let (_, data) = transport.exchange(&command(0x04)).unwrap();
+        assert_eq!(data, [0x04]);
+    }
+
+    #[test]
+    fn test_bridge() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let agent = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            serve_bridge(&mut stream, &Echo).unwrap();
+        });
+
+        let transport = TransportBridge::connect(address).unwrap();
+        let command = |ins, data| APDUCommand {
+            cla: 0xe1,
+            ins,
+            p1: 0x00,
+            p2: 0x00,
+            data,
+        };
+        let (status, data) = transport.exchange(&command(0x01, vec![1, 2, 3])).unwrap();
+        assert_eq!(status, StatusWord::OK);
+        assert_eq!(data, [1, 2, 3]);
+
+        let err = transport.exchange(&command(0xff, Vec::new())).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<UnknownStatusWord>(),
+            Some(UnknownStatusWord(SW_DEVICE_LOCKED))
+        ));
+
+        // Not sent, its length would not fit the encoding.
+        assert!(transport
+            .exchange(&command(0x01, vec![0; MAX_APDU_DATA_LEN + 1]))
+            .is_err());
+        let (_, data) = transport
+            .exchange(&command(0x01, vec![0; MAX_APDU_DATA_LEN]))
+            .unwrap();
+        assert_eq!(data.len(), MAX_APDU_DATA_LEN);
+
+        // The agent stops once the host closes the connection.
+        drop(transport);
+        agent.join().unwrap();
+
+        // A frame longer than any command is rejected before it is read.
+        let mut stream = Duplex {
+            reader: &u32::MAX.to_be_bytes()[..],
+            writer: Vec::new(),
+        };
+        assert!(serve_bridge(&mut stream, &Echo).is_err());
+    }
+
+    /// Transport dead until reconnected.
+    #[derive(Default)]
+    struct Dead {
+        alive: Mutex<bool>,
+        reconnects: Mutex<u32>,
+    }
+
+    impl Transport for Dead {
+        type Error = Box<dyn Error>;
+        fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+            if !*self.alive.lock().unwrap() {
+                return Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into());
+            }
+            Echo.exchange(cmd)
+        }
+    }
+
+    impl Reconnect for Dead {
+        fn reconnect(&self) -> Result<(), HWIError> {
+            *self.reconnects.lock().unwrap() += 1;
+            *self.alive.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_is_same_device() {
+        let fg = Fingerprint::from_str("f5acc2fd").unwrap();
+        let other = Fingerprint::from_str("b0822927").unwrap();
+        assert!(is_same_device(Some(fg), Some(fg), false));
+        assert!(!is_same_device(Some(fg), Some(other), true));
+        // The app is not open, the fingerprint cannot be compared.
+        assert!(is_same_device(Some(fg), None, true));
+        assert!(!is_same_device(Some(fg), None, false));
+        assert!(is_same_device(None, Some(fg), true));
+        assert!(!is_same_device(None, Some(fg), false));
+    }
+
+    #[test]
+    fn test_reconnecting_transport() {
+        let command = |ins| APDUCommand {
+            cla: 0xe1,
+            ins,
+            p1: 0x00,
+            p2: 0x00,
+            data: vec![1],
+        };
+        let transport = ReconnectingTransport::new(Dead::default());
+        assert_eq!(
+            transport.exchange(&command(0x01)).unwrap(),
+            (StatusWord::OK, vec![1])
+        );
+        assert_eq!(*transport.inner().reconnects.lock().unwrap(), 1);
+
+        // The device answered, there is nothing to recover.
+        assert!(transport.exchange(&command(0xff)).is_err());
+        assert_eq!(*transport.inner().reconnects.lock().unwrap(), 1);
+
+        // The connection is lost in the middle of a command.
+        *transport.inner().alive.lock().unwrap() = false;
+        let continuation = APDUCommand {
+            cla: 0xf8,
+            ..command(0x01)
+        };
+        assert!(transport.exchange(&continuation).is_err());
+        let legacy_sign = APDUCommand {
+            cla: 0xe0,
+            ..command(0x48)
+        };
+        assert!(transport.exchange(&legacy_sign).is_err());
+        assert_eq!(*transport.inner().reconnects.lock().unwrap(), 1);
+
+        // The next command reconnects.
+        let legacy_xpub = APDUCommand {
+            cla: 0xe0,
+            ..command(0x40)
+        };
+        assert!(transport.exchange(&legacy_xpub).is_ok());
+        assert_eq!(*transport.inner().reconnects.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_configure() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = ConnectOptions {
+            transport: ConnectTransport::Simulator {
+                address: Some(address),
+            },
+            network: Some(Network::Testnet),
+            auto_reconnect: true,
+            timeout_ms: Some(1000),
+            cla: Some(0xE8),
+            ..Default::default()
+        };
+        let transport = TransportTcp::connect(address, Some(Duration::from_millis(1000))).unwrap();
+        let device = configure(LedgerSimulator::with_transport(transport), &options).unwrap();
+        assert_eq!(device.device_kind(), DeviceKind::LedgerSimulator);
+
+        let transport = TransportBridge::connect_timeout(address, None).unwrap();
+        let device = configure(LedgerBridge::with_bridge(transport), &options).unwrap();
+        assert_eq!(device.device_kind(), DeviceKind::Ledger);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_connect_options_serde() {
+        let options: ConnectOptions = serde_json::from_str(
+            r#"{"transport": {"kind": "bridge", "address": "laptop:9999"}, "auto_reconnect": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            ConnectOptions {
+                transport: ConnectTransport::Bridge {
+                    address: "laptop:9999".to_string()
+                },
+                auto_reconnect: true,
+                ..Default::default()
+            }
+        );
+        let options: ConnectOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options.transport, ConnectTransport::Hid { path: None });
+    }
+
+    #[test]
+    fn test_model() {
+        assert_eq!(model(0x0001), Some(DeviceModel::LedgerNanoS));
+        assert_eq!(model(0x1011), Some(DeviceModel::LedgerNanoS));
+        assert_eq!(model(0x4015), Some(DeviceModel::LedgerNanoX));
+        assert_eq!(model(0x0005), Some(DeviceModel::LedgerNanoSPlus));
+        assert_eq!(model(0x5011), Some(DeviceModel::LedgerNanoSPlus));
+        assert_eq!(model(0x6011), Some(DeviceModel::LedgerStax));
+        assert_eq!(model(0x7011), Some(DeviceModel::LedgerFlex));
+        assert_eq!(model(0x0000), None);
+        assert!(DeviceModel::LedgerNanoS.is_low_memory());
+        assert!(!DeviceModel::LedgerNanoSPlus.is_low_memory());
+        assert!(DeviceModel::LedgerFlex.has_touchscreen());
+        assert!(!DeviceModel::LedgerNanoX.has_touchscreen());
+    }
+
+    #[test]
+    fn test_yielded_signature() {
+        use bitcoin::hashes::hex::FromHex;
+        let key = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let mut data = vec![0x02, 0x20];
+        data.extend(Vec::<u8>::from_hex(key).unwrap());
+        data.extend([0x01; 64]);
+        match yielded_signature(&data) {
+            Some((2, InputSignature::TapKey(sig))) => assert_eq!(sig.to_vec(), vec![0x01; 64]),
+            res => panic!("unexpected {:?}", res),
+        }
+        // Truncated data is left to the client.
+        assert!(yielded_signature(&data[..1]).is_none());
+        assert!(yielded_signature(&[]).is_none());
+    }
+
+    #[test]
+    fn test_error_from_status_word() {
+        assert!(matches!(
+            HWIError::from(BitcoinClientError::<Box<dyn Error>>::Transport(
+                UnknownStatusWord(0x5515).into()
+            )),
+            HWIError::DeviceLocked { code: Some(0x5515) }
+        ));
+        assert!(matches!(
+            HWIError::from(BitcoinClientError::<Box<dyn Error>>::Device {
+                command: 0,
+                status: StatusWord::ClaNotSupported
+            }),
+            HWIError::WrongApp { found: None, .. }
+        ));
+        assert!(matches!(
+            HWIError::from(BitcoinClientError::<Box<dyn Error>>::Device {
+                command: 0,
+                status: StatusWord::Deny
+            }),
+            HWIError::UserRefused
+        ));
+        assert!(matches!(
+            HWIError::from(BitcoinClientError::<Box<dyn Error>>::Transport(
+                "Invalid Length".into()
+            )),
+            HWIError::Transport(TransportErrorKind::Io, _)
+        ));
+    }
+
+    #[test]
+    fn test_check_address_index() {
+        assert!(check_address_index(DEFAULT_WALLET_MAX_ADDRESS_INDEX, false).is_ok());
+        assert!(matches!(
+            check_address_index(DEFAULT_WALLET_MAX_ADDRESS_INDEX + 1, false),
+            Err(HWIError::IndexOutOfRange {
+                max: DEFAULT_WALLET_MAX_ADDRESS_INDEX,
+                ..
+            })
+        ));
+        assert!(check_address_index(DEFAULT_WALLET_MAX_ADDRESS_INDEX + 1, true).is_ok());
+        assert!(check_address_index(MAX_ADDRESS_INDEX + 1, true).is_err());
+    }
+
+    /// App answering the version command with its name and version, recording the CLA
+    /// of the other commands.
+    struct App {
+        name: &'static str,
+        version: &'static str,
+        cla: Mutex<Option<u8>>,
+    }
+
+    impl Transport for App {
+        type Error = Box<dyn Error>;
+        fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+            if cmd.cla == 0xB0 && cmd.ins == 0x01 {
+                let mut data = vec![1, self.name.len() as u8];
+                data.extend_from_slice(self.name.as_bytes());
+                data.push(self.version.len() as u8);
+                data.extend_from_slice(self.version.as_bytes());
+                data.extend_from_slice(&[1, 0]);
+                return Ok((StatusWord::OK, data));
+            }
+            *self.cla.lock().unwrap() = Some(cmd.cla);
+            Ok((StatusWord::OK, vec![0xf5, 0xac, 0xc2, 0xfd]))
+        }
+    }
+
+    impl Reconnect for &App {
+        fn reconnect(&self) -> Result<(), HWIError> {
+            Ok(())
+        }
+    }
+
+    impl Transport for &App {
+        type Error = Box<dyn Error>;
+        fn exchange(&self, cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+            (*self).exchange(cmd)
+        }
+    }
+
+    fn ledger(app: &App) -> Ledger<&App> {
+        Ledger {
+            client: BitcoinClient::new(app),
+            transport: app,
+            options: CommandOptions::default(),
+            kind: DeviceKind::Ledger,
+            model: None,
+        }
+    }
+
+    #[test]
+    fn test_required_version() {
+        let required = Ledger::<&App>::required_version;
+        assert_eq!(required("wsh(sortedmulti(2,@0/**,@1/**))"), None);
+        assert_eq!(required("tr(@0/**)"), None);
+        assert_eq!(
+            required("wsh(or_d(pk(@0/**),and_v(v:pkh(@1/**),older(100))))"),
+            Some(Ledger::<&App>::MIN_VERSION_MINISCRIPT)
+        );
+        assert_eq!(
+            required("tr(@0/**,multi_a(1,@1/**,@2/**))"),
+            Some(Ledger::<&App>::MIN_VERSION_TAPMINISCRIPT)
+        );
+    }
+
+    #[test]
+    fn test_check_app() {
+        let app = |name| App {
+            name,
+            version: "2.1.0",
+            cla: Mutex::new(None),
+        };
+        let test = app("Bitcoin Test");
+        assert_eq!(
+            ledger(&test)
+                .with_network(Network::Signet)
+                .check_app()
+                .unwrap()
+                .flavor,
+            AppFlavor::BitcoinTest
+        );
+        assert!(matches!(
+            ledger(&test).with_network(Network::Bitcoin).check_app(),
+            Err(HWIError::WrongApp { expected, found: Some(found), .. })
+                if expected == "Bitcoin" && found == "Bitcoin Test"
+        ));
+        assert!(matches!(
+            ledger(&app("BOLOS")).check_app(),
+            Err(HWIError::WrongApp { found: Some(found), .. }) if found == "BOLOS"
+        ));
+
+        let fork = app("Bitcoin Fork");
+        assert!(matches!(
+            ledger(&fork).with_app_name("Other Fork").check_app(),
+            Err(HWIError::WrongApp { expected, .. }) if expected == "Other Fork"
+        ));
+        let device = ledger(&fork)
+            .with_network(Network::Testnet)
+            .with_app_name("Bitcoin Fork")
+            .with_cla(0xE8);
+        assert_eq!(device.check_app().unwrap().flavor, AppFlavor::BitcoinTest);
+        assert!(device.get_master_fingerprint().is_ok());
+        assert_eq!(*fork.cla.lock().unwrap(), Some(0xE8));
+    }
+
+    #[test]
+    fn test_accepts_unregistered() {
+        let key = "[f5acc2fd/84'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP";
+        let wallet = WalletPolicy::new(
+            String::new(),
+            WalletVersion::V2,
+            "wpkh(@0/**)".to_string(),
+            vec![WalletPubKey::from_str(key).unwrap()],
+        );
+        let accepts = |version| {
+            let app = App {
+                name: "Bitcoin Test",
+                version,
+                cla: Mutex::new(None),
+            };
+            ledger(&app).accepts_unregistered(&wallet).unwrap()
+        };
+        assert!(accepts("2.0.0"));
+        assert!(accepts("2.1.3"));
+        assert!(accepts("2.2.0"));
+        assert!(!accepts("1.6.5"));
+    }
+
+    #[test]
+    fn test_layered_transport() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        /// Transport failing the first exchange.
+        struct Flaky(AtomicU32);
+        impl Transport for Flaky {
+            type Error = &'static str;
+            fn exchange(&self, _cmd: &APDUCommand) -> Result<(StatusWord, Vec<u8>), Self::Error> {
+                if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err("disconnected")
+                } else {
+                    Ok((StatusWord::OK, Vec::new()))
+                }
+            }
+        }
+
+        #[derive(Default)]
+        struct Count {
+            sent: AtomicU32,
+            failed: AtomicU32,
+        }
+        impl Layer<APDUCommand, (StatusWord, Vec<u8>)> for Count {
+            fn pre(&self, _req: &APDUCommand) {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+            }
+            fn post(
+                &self,
+                _req: &APDUCommand,
+                res: Result<&(StatusWord, Vec<u8>), &dyn std::fmt::Debug>,
+            ) {
+                if res.is_err() {
+                    self.failed.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            fn retry(&self, _req: &APDUCommand, attempt: u32) -> bool {
+                attempt < 2
+            }
+        }
+
+        let cmd = APDUCommand {
+            cla: 0xE1,
+            ins: 0x00,
+            p1: 0x00,
+            p2: 0x00,
+            data: Vec::new(),
+        };
+        let transport = LayeredTransport::new(Flaky(AtomicU32::new(0)), Count::default());
+        assert!(transport.exchange(&cmd).is_ok());
+        assert_eq!(transport.layer().sent.load(Ordering::SeqCst), 2);
+        assert_eq!(transport.layer().failed.load(Ordering::SeqCst), 1);
+    }
+
+    /// Ids of registered policies: a change of the serialization of the policies
+    /// invalidates the hmacs stored by the users.
+    #[test]
+    fn test_wallet_id_stability() {
+        use bitcoin::hex::DisplayHex;
+        let policies = [
+            ("", "wpkh([f5acc2fd/84'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**)",
+                "d77c499af8571f6b13d109a6acf53462f09fd08b93280708a2b622633868bd3e",
+            ),
+            ("Liana", "wsh(or_d(pk([f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**),and_v(v:pkh(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/**),older(100))))",
+                "9bfd57d89a88d26c9e9d69671a77bcb3155ea9be88f13da5ae299cf77cc9600c",
+            ),
+            ("Vault", "wsh(or_d(multi(2,[b0822927/48'/1'/0'/2']tpubDEvZxV86Br8Knbm9tWcr5Hvmg5cYTYsg92vinqH6Bie6U8ix8CsoN9W11NQygdqVwmHUJpsHXxNsi5gXn36g4xNfLWkMqPuFhRZAmMQ7jjQ/<0;1>/*,[7fc39c07/48'/1'/0'/2']tpubDEvjgXtrUuH3Qtkapny9aE8gN847xiXsf9MDM5XueGf9nrvStqAuBSva3ajGyTvtp8Ti55FvVXsgYSXuS1tQkBeopFuodx2hRUDmQbvKxbZ/<0;1>/*),and_v(v:thresh(2,pkh([b0822927/48'/1'/0'/2']tpubDEvZxV86Br8Knbm9tWcr5Hvmg5cYTYsg92vinqH6Bie6U8ix8CsoN9W11NQygdqVwmHUJpsHXxNsi5gXn36g4xNfLWkMqPuFhRZAmMQ7jjQ/<2;3>/*),a:pkh([7fc39c07/48'/1'/0'/2']tpubDEvjgXtrUuH3Qtkapny9aE8gN847xiXsf9MDM5XueGf9nrvStqAuBSva3ajGyTvtp8Ti55FvVXsgYSXuS1tQkBeopFuodx2hRUDmQbvKxbZ/<2;3>/*),a:pkh([1a1ffd98/48'/1'/0'/2']tpubDFZqzTvGijYb13BC73CkS1er8DrP5YdzMhziN3kWCKUFaW51Yj6ggvf99YpdrkTJy4RT85mxQMHXDiFAKRxzf6BykQgT4pRRBNPshSJJcKo/<0;1>/*)),older(300))))#wp0w3hlw",
+                "a187cbd60c2345867b7b53407553ddf45d806aadf80473d3347ac5a153c36569",
+            ),
+        ];
+        for (name, policy, id) in policies {
+            assert_eq!(wallet_id(name, policy).unwrap().to_lower_hex_string(), id);
+        }
+        // The checksum is not part of the policy.
+        let (_, policy, id) = policies[2];
+        let policy = policy.rsplit_once('#').unwrap().0;
+        assert_eq!(
+            wallet_id("Vault", policy).unwrap().to_lower_hex_string(),
+            id
+        );
+    }
+
+    #[test]
+    fn test_load_wallet() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = TransportTcp::connect(
+            listener.local_addr().unwrap(),
+            Some(Duration::from_millis(1000)),
+        )
+        .unwrap();
+        let policy = "wsh(or_d(pk([f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**),and_v(v:pkh(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/**),older(100))))";
+        let mut device = LedgerSimulator::with_transport(transport);
+        device.load_wallet("Liana", policy, Some([1; 32])).unwrap();
+        let (wallet, hmac) = device.options.wallet.as_ref().unwrap();
+        assert_eq!(wallet.name, "Liana");
+        assert_eq!(*hmac, Some([1; 32]));
+
+        // The wallet is kept if the new one is invalid.
+        let invalid = format!("{}#00000000", policy);
+        assert!(device.load_wallet("Vault", &invalid, None).is_err());
+        assert_eq!(device.options.wallet.as_ref().unwrap().0.name, "Liana");
+
+        let mut device: Box<dyn HWI + Send> = Box::new(device);
+        device.load_wallet("Vault", policy, None).unwrap();
+    }
+
+    /// Differential test of the policy extraction: the descriptor rebuilt by the
+    /// ledger client from the extracted template and keys must match the original one.
+    #[test]
+    fn test_extract_keys_and_template_differential() {
+        let origin_keys = [
+            "[b0822927/48'/1'/0'/2']tpubDEvZxV86Br8Knbm9tWcr5Hvmg5cYTYsg92vinqH6Bie6U8ix8CsoN9W11NQygdqVwmHUJpsHXxNsi5gXn36g4xNfLWkMqPuFhRZAmMQ7jjQ",
+            "[7fc39c07/48'/1'/0'/2']tpubDEvjgXtrUuH3Qtkapny9aE8gN847xiXsf9MDM5XueGf9nrvStqAuBSva3ajGyTvtp8Ti55FvVXsgYSXuS1tQkBeopFuodx2hRUDmQbvKxbZ",
+            "[1a1ffd98/48'/1'/0'/2']tpubDFZqzTvGijYb13BC73CkS1er8DrP5YdzMhziN3kWCKUFaW51Yj6ggvf99YpdrkTJy4RT85mxQMHXDiFAKRxzf6BykQgT4pRRBNPshSJJcKo",
+        ];
+        // The last key without origin, also used with origin by the other key sets.
+        let bare_key = origin_keys[2].split_once(']').unwrap().1;
+        let key_sets = [
+            [origin_keys[0], origin_keys[1], origin_keys[2]],
+            [origin_keys[2], origin_keys[0], origin_keys[1]],
+            [bare_key, origin_keys[2], origin_keys[0]],
+            [origin_keys[0], bare_key, origin_keys[2]],
+        ];
+        let shapes = [
+            "pkh(K0)",
+            "wpkh(K0)",
+            "sh(wpkh(K0))",
+            "tr(K0)",
+            "wsh(multi(2,K0,K1,K2))",
+            "wsh(sortedmulti(1,K0,K1))",
+            "sh(wsh(sortedmulti(2,K0,K1,K2)))",
+            "wsh(or_d(pk(K0),and_v(v:pkh(K1),older(52560))))",
+            "wsh(or_d(multi(2,K0,K1),and_v(v:pk(K2),after(840000))))",
+            "tr(K0,{pk(K1),pk(K2)})",
+            "tr(K0,multi_a(1,K1,K2))",
+        ];
+        let suffixes = ["/**", "/<0;1>/*"];
+
+        for shape in shapes {
+            for keys in &key_sets {
+                for suffix in suffixes {
+                    let mut descriptor = shape.to_string();
+                    for (i, key) in keys.iter().enumerate() {
+                        descriptor =
+                            descriptor.replace(&format!("K{}", i), &format!("{}{}", key, suffix));
+                    }
+                    let (template, keys) =
+                        utils::extract_keys_and_template::<WalletPubKey>(&descriptor).unwrap();
+                    assert!(!template.contains("pub"), "{}: {}", descriptor, template);
+                    assert!(!template.contains(']'), "{}: {}", descriptor, template);
+                    for i in 0..keys.len() {
+                        assert!(template.contains(&format!("@{}/", i)), "{}", template);
+                    }
+
+                    let policy =
+                        WalletPolicy::new("test".to_string(), WalletVersion::V2, template, keys);
+                    let receive = descriptor.replace(suffix, "/0/*");
+                    assert_eq!(policy.get_descriptor(false).unwrap(), receive);
+                }
+            }
+        }
+    }
+}
@@ -1,24 +1,82 @@
+/// Logs an exchange with a device at debug level if the tracing feature is enabled.
+/// Payloads must be redacted: they may hold keys, signatures or wallet details.
+#[allow(unused_macros)]
+macro_rules! debug_exchange {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+// Declared first, the wrappers implement HWI with its macro.
+#[macro_use]
+mod delegate;
+
+pub mod audit;
 pub mod bip389;
+pub mod bip85;
 #[cfg(feature = "bitbox")]
 pub mod bitbox;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
 #[cfg(feature = "coldcard")]
 pub mod coldcard;
+pub mod connect;
+pub mod context;
+#[cfg(feature = "regex")]
+pub mod export;
+#[cfg(feature = "test-harness")]
+pub mod harness;
+pub mod interactive;
 #[cfg(feature = "jade")]
 pub mod jade;
+pub mod layer;
 #[cfg(feature = "ledger")]
 pub mod ledger;
+pub mod limits;
+#[cfg(feature = "liquid")]
+pub mod liquid;
+pub mod lock;
+pub mod management;
+#[cfg(test)]
+mod mock;
+#[cfg(feature = "regex")]
+pub mod policy;
+#[cfg(feature = "regex")]
+pub mod psbt;
+pub mod readonly;
+pub mod scan;
+#[cfg(feature = "serde")]
+pub mod schema;
+pub mod signature;
+#[cfg(feature = "signer")]
+pub mod signer;
+pub mod slip19;
 #[cfg(feature = "specter")]
 pub mod specter;
+pub mod stats;
+#[cfg(feature = "serde")]
+pub mod transcript;
+pub mod ur;
 pub mod utils;
 
 use bitcoin::{
+    address::NetworkUnchecked,
     bip32::{DerivationPath, Fingerprint, Xpub},
     psbt::Psbt,
+    secp256k1::{PublicKey, SecretKey},
+    Address, Network,
 };
 
-use std::{cmp::Ordering, fmt::Debug, str::FromStr};
+#[cfg(feature = "regex")]
+use std::str::FromStr;
+use std::{cmp::Ordering, fmt::Debug, sync::Arc};
+
+pub use context::{init, Context};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Error {
     ParsingPolicy(bip389::ParseError),
     MissingPolicy,
@@ -29,10 +87,55 @@ pub enum Error {
     DeviceDisconnected,
     DeviceNotFound,
     DeviceDidNotSign,
+    DeviceLocked {
+        code: Option<i32>,
+    },
+    WrongApp {
+        expected: String,
+        found: Option<String>,
+        code: Option<i32>,
+    },
+    UnsupportedFirmware {
+        required: Version,
+        /// Boxed to keep the errors small.
+        found: Option<Box<Version>>,
+    },
+    AppNotOpen {
+        running: String,
+    },
+    Transport(TransportErrorKind, String),
+    /// Device error that is not one of the conditions above.
     Device(String),
     Unexpected(&'static str),
     UserRefused,
     NetworkMismatch,
+    UnsupportedNetwork(bitcoin::Network),
+    WalletNotRegistered,
+    ReadOnly,
+    IndexOutOfRange {
+        index: u32,
+        max: u32,
+    },
+    AddressMismatch {
+        index: u32,
+    },
+    /// The device did not answer before the deadline, see interactive::Interactive.
+    Timeout,
+    /// The host stopped waiting for the device.
+    Cancelled,
+    /// PSBT exceeding a limit checked by the host, see limits::PsbtLimits.
+    PsbtLimit {
+        limit: limits::PsbtLimit,
+        value: usize,
+        max: usize,
+    },
+    /// Policy the device cannot sign for, see policy::WalletPolicy::check_for_device.
+    PolicyMismatch(String),
+    /// The OS refused to open the device, on Linux the udev rules of the device are
+    /// missing, see utils::udev_rules.
+    PermissionDenied {
+        path: String,
+    },
 }
 
 impl std::fmt::Display for Error {
@@ -46,11 +149,223 @@ impl std::fmt::Display for Error {
             Error::DeviceDisconnected => write!(f, "Device disconnected"),
             Error::DeviceNotFound => write!(f, "Device not found"),
             Error::DeviceDidNotSign => write!(f, "Device did not sign"),
+            Error::DeviceLocked { .. } => write!(f, "Device is locked"),
+            Error::WrongApp {
+                expected,
+                found: Some(found),
+                ..
+            } => write!(f, "Wrong app {} opened, expected {}", found, expected),
+            Error::WrongApp { expected, .. } => write!(f, "App {} is not opened", expected),
+            Error::AppNotOpen { running } => {
+                write!(f, "Bitcoin app is not open, running app is {}", running)
+            }
+            Error::UnsupportedFirmware {
+                required,
+                found: Some(found),
+            } => write!(
+                f,
+                "Firmware version {} is required, the device runs {}",
+                required, found
+            ),
+            Error::UnsupportedFirmware { required, .. } => {
+                write!(f, "Firmware version {} is required", required)
+            }
+            Error::Transport(kind, e) => write!(f, "{} error: {}", kind, e),
             Error::Device(e) => write!(f, "{}", e),
             Error::InvalidParameter(param, e) => write!(f, "Invalid parameter {}: {}", param, e),
             Error::Unexpected(e) => write!(f, "{}", e),
             Error::UserRefused => write!(f, "User refused operation"),
             Error::NetworkMismatch => write!(f, "Device network is different"),
+            Error::UnsupportedNetwork(network) => {
+                write!(f, "Network {} is not supported by the device", network)
+            }
+            Error::WalletNotRegistered => write!(f, "Wallet is not registered on the device"),
+            Error::ReadOnly => write!(f, "Operation not permitted in read-only mode"),
+            Error::IndexOutOfRange { index, max } => {
+                write!(f, "Index {} is out of range, maximum is {}", index, max)
+            }
+            Error::AddressMismatch { index } => {
+                write!(f, "Device address at index {} does not match", index)
+            }
+            Error::Timeout => write!(f, "Device did not answer in time"),
+            Error::Cancelled => write!(f, "Operation cancelled"),
+            Error::PsbtLimit { limit, value, max } => {
+                write!(f, "PSBT {} {} exceeds the maximum {}", limit, value, max)
+            }
+            Error::PolicyMismatch(e) => write!(f, "Policy mismatch: {}", e),
+            Error::PermissionDenied { path } => {
+                write!(f, "Permission denied to open the device {}", path)
+            }
+        }
+    }
+}
+
+/// Stable code of an error, for the applications mapping the errors to localized
+/// messages. The codes of the existing errors never change, new errors get new codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+#[repr(u16)]
+pub enum ErrorCode {
+    ParsingPolicy = 1,
+    MissingPolicy = 2,
+    UnsupportedVersion = 3,
+    UnsupportedInput = 4,
+    InvalidParameter = 5,
+    UnimplementedMethod = 6,
+    DeviceDisconnected = 7,
+    DeviceNotFound = 8,
+    DeviceDidNotSign = 9,
+    DeviceLocked = 10,
+    WrongApp = 11,
+    UnsupportedFirmware = 12,
+    AppNotOpen = 13,
+    Transport = 14,
+    Device = 15,
+    Unexpected = 16,
+    UserRefused = 17,
+    NetworkMismatch = 18,
+    UnsupportedNetwork = 19,
+    WalletNotRegistered = 20,
+    ReadOnly = 21,
+    IndexOutOfRange = 22,
+    AddressMismatch = 23,
+    Timeout = 24,
+    Cancelled = 25,
+    PsbtLimit = 26,
+    PolicyMismatch = 27,
+    PermissionDenied = 28,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 28] = [
+        ErrorCode::ParsingPolicy,
+        ErrorCode::MissingPolicy,
+        ErrorCode::UnsupportedVersion,
+        ErrorCode::UnsupportedInput,
+        ErrorCode::InvalidParameter,
+        ErrorCode::UnimplementedMethod,
+        ErrorCode::DeviceDisconnected,
+        ErrorCode::DeviceNotFound,
+        ErrorCode::DeviceDidNotSign,
+        ErrorCode::DeviceLocked,
+        ErrorCode::WrongApp,
+        ErrorCode::UnsupportedFirmware,
+        ErrorCode::AppNotOpen,
+        ErrorCode::Transport,
+        ErrorCode::Device,
+        ErrorCode::Unexpected,
+        ErrorCode::UserRefused,
+        ErrorCode::NetworkMismatch,
+        ErrorCode::UnsupportedNetwork,
+        ErrorCode::WalletNotRegistered,
+        ErrorCode::ReadOnly,
+        ErrorCode::IndexOutOfRange,
+        ErrorCode::AddressMismatch,
+        ErrorCode::Timeout,
+        ErrorCode::Cancelled,
+        ErrorCode::PsbtLimit,
+        ErrorCode::PolicyMismatch,
+        ErrorCode::PermissionDenied,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::ParsingPolicy => "parsing_policy",
+            ErrorCode::MissingPolicy => "missing_policy",
+            ErrorCode::UnsupportedVersion => "unsupported_version",
+            ErrorCode::UnsupportedInput => "unsupported_input",
+            ErrorCode::InvalidParameter => "invalid_parameter",
+            ErrorCode::UnimplementedMethod => "unimplemented_method",
+            ErrorCode::DeviceDisconnected => "device_disconnected",
+            ErrorCode::DeviceNotFound => "device_not_found",
+            ErrorCode::DeviceDidNotSign => "device_did_not_sign",
+            ErrorCode::DeviceLocked => "device_locked",
+            ErrorCode::WrongApp => "wrong_app",
+            ErrorCode::UnsupportedFirmware => "unsupported_firmware",
+            ErrorCode::AppNotOpen => "app_not_open",
+            ErrorCode::Transport => "transport",
+            ErrorCode::Device => "device",
+            ErrorCode::Unexpected => "unexpected",
+            ErrorCode::UserRefused => "user_refused",
+            ErrorCode::NetworkMismatch => "network_mismatch",
+            ErrorCode::UnsupportedNetwork => "unsupported_network",
+            ErrorCode::WalletNotRegistered => "wallet_not_registered",
+            ErrorCode::ReadOnly => "read_only",
+            ErrorCode::IndexOutOfRange => "index_out_of_range",
+            ErrorCode::AddressMismatch => "address_mismatch",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::PsbtLimit => "psbt_limit",
+            ErrorCode::PolicyMismatch => "policy_mismatch",
+            ErrorCode::PermissionDenied => "permission_denied",
+        }
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        *self as u16
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::ParsingPolicy(..) => ErrorCode::ParsingPolicy,
+            Error::MissingPolicy => ErrorCode::MissingPolicy,
+            Error::UnsupportedVersion => ErrorCode::UnsupportedVersion,
+            Error::UnsupportedInput => ErrorCode::UnsupportedInput,
+            Error::InvalidParameter(..) => ErrorCode::InvalidParameter,
+            Error::UnimplementedMethod => ErrorCode::UnimplementedMethod,
+            Error::DeviceDisconnected => ErrorCode::DeviceDisconnected,
+            Error::DeviceNotFound => ErrorCode::DeviceNotFound,
+            Error::DeviceDidNotSign => ErrorCode::DeviceDidNotSign,
+            Error::DeviceLocked { .. } => ErrorCode::DeviceLocked,
+            Error::WrongApp { .. } => ErrorCode::WrongApp,
+            Error::UnsupportedFirmware { .. } => ErrorCode::UnsupportedFirmware,
+            Error::AppNotOpen { .. } => ErrorCode::AppNotOpen,
+            Error::Transport(..) => ErrorCode::Transport,
+            Error::Device(..) => ErrorCode::Device,
+            Error::Unexpected(..) => ErrorCode::Unexpected,
+            Error::UserRefused => ErrorCode::UserRefused,
+            Error::NetworkMismatch => ErrorCode::NetworkMismatch,
+            Error::UnsupportedNetwork(..) => ErrorCode::UnsupportedNetwork,
+            Error::WalletNotRegistered => ErrorCode::WalletNotRegistered,
+            Error::ReadOnly => ErrorCode::ReadOnly,
+            Error::IndexOutOfRange { .. } => ErrorCode::IndexOutOfRange,
+            Error::AddressMismatch { .. } => ErrorCode::AddressMismatch,
+            Error::Timeout => ErrorCode::Timeout,
+            Error::Cancelled => ErrorCode::Cancelled,
+            Error::PsbtLimit { .. } => ErrorCode::PsbtLimit,
+            Error::PolicyMismatch(..) => ErrorCode::PolicyMismatch,
+            Error::PermissionDenied { .. } => ErrorCode::PermissionDenied,
+        }
+    }
+}
+
+/// Kind of failure of the communication with the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransportErrorKind {
+    /// Failed to read from or write to the device.
+    Io,
+    /// The device answered with a malformed or unexpected message.
+    Protocol,
+}
+
+impl std::fmt::Display for TransportErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io => write!(f, "Transport"),
+            Self::Protocol => write!(f, "Protocol"),
         }
     }
 }
@@ -67,49 +382,545 @@ impl std::error::Error for Error {}
 pub trait HWI: Debug {
     /// Return the device kind
     fn device_kind(&self) -> DeviceKind;
+    /// Model of the connected device, None if the backend does not tell it.
+    fn device_model(&self) -> Option<DeviceModel> {
+        None
+    }
     /// Application version or OS version.
     fn get_version(&self) -> Result<Version, Error>;
     /// Get master fingerprint.
     fn get_master_fingerprint(&self) -> Result<Fingerprint, Error>;
     /// Get the xpub with the given derivation path.
     fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, Error>;
+    /// Get the xpubs with the given derivation paths, in the same order.
+    fn get_extended_pubkeys(&self, paths: &[DerivationPath]) -> Result<Vec<Xpub>, Error> {
+        paths
+            .iter()
+            .map(|path| self.get_extended_pubkey(path))
+            .collect()
+    }
+    /// True if the device shows the xpub of the path to the user when it is requested,
+    /// the xpub must then be requested each time and not served from a cache.
+    fn displays_xpub(&self, _path: &DerivationPath) -> bool {
+        false
+    }
     /// Register a new wallet policy.
-    fn register_wallet(&self, name: &str, policy: &str) -> Result<Option<[u8; 32]>, Error>;
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, Error>;
     /// Returns true if the wallet is registered on the device.
     fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, Error>;
-    /// Display address on the device screen.
-    fn display_address(&self, script: &AddressScript) -> Result<(), Error>;
+    /// Display address on the device screen and return it.
+    /// The address computed by the device is not checked against the network.
+    fn display_address(&self, script: &AddressScript) -> Result<Address<NetworkUnchecked>, Error>;
+    /// Get the address computed by the device, displayed like display_address only if
+    /// display is true. The devices that always display the address only support true.
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        if display {
+            self.display_address(script)
+        } else {
+            Err(Error::UnimplementedMethod)
+        }
+    }
     /// Sign a partially signed bitcoin transaction (PSBT).
-    fn sign_tx(&self, tx: &mut Psbt) -> Result<(), Error>;
+    fn sign_tx(&self, tx: &mut Psbt) -> Result<SignResult, Error>;
+    /// Sign the PSBT and call `on_signature` with each input signature, as soon as the
+    /// device returns it if the protocol allows it, so that partial progress can be persisted.
+    /// By default the signatures are reported once the PSBT is signed.
+    fn sign_tx_progressive(
+        &self,
+        tx: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, Error> {
+        let unsigned = tx.clone();
+        let res = self.sign_tx(tx)?;
+        for (i, sig) in utils::new_signatures(&unsigned, tx) {
+            on_signature(i, sig);
+        }
+        Ok(res)
+    }
+    /// Sign the PSBTs one after the other in the session of the device, with the wallet
+    /// loaded once. Stops at the first failure, the PSBTs before it are signed.
+    fn sign_txs(&self, txs: &mut [Psbt]) -> Result<Vec<SignResult>, Error> {
+        txs.iter_mut().map(|tx| self.sign_tx(tx)).collect()
+    }
+    /// Sign a copy of the PSBT and return it, the given PSBT is left untouched.
+    fn signed_copy(&self, tx: &Psbt) -> Result<Psbt, Error> {
+        let mut signed = tx.clone();
+        self.sign_tx(&mut signed)?;
+        Ok(signed)
+    }
+    /// Derive the BIP85 entropy of the application at the given index.
+    /// Only the software signer implements it: the hardware backends return
+    /// [`Error::UnimplementedMethod`], their APIs do not expose BIP85 to the host.
+    fn derive_bip85(
+        &self,
+        _application: &bip85::Application,
+        _index: u32,
+    ) -> Result<Vec<u8>, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// SLIP-0019 proof of ownership of the output spent by the input, committing to the
+    /// commitment data of the coinjoin coordinator. Unimplemented by default, the devices
+    /// of the crate do not implement SLIP-0019.
+    fn prove_ownership(
+        &self,
+        _input: &bitcoin::psbt::Input,
+        _commitment_data: &[u8],
+    ) -> Result<slip19::OwnershipProof, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Export the BIP352 silent payments keys of the account, so that the wallet scans
+    /// for the payments without the device. Unimplemented by default, no device
+    /// firmware exposes them yet.
+    fn get_silent_payment_keys(&self, _account: u32) -> Result<SilentPaymentKeys, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// User-visible name of the device, None if the user did not set one.
+    fn get_label(&self) -> Result<Option<String>, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Wallets registered on the device with their descriptors, for the recovery of the
+    /// wallets. Unimplemented by default, few devices list their registered wallets.
+    fn list_registered_wallets(&self) -> Result<Vec<RegisteredWallet>, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Vendor genuine check of the device, see Attestation. Unimplemented by default,
+    /// the checks need a vendor service or a certificate chain the backends do not expose.
+    fn verify_attestation(&self) -> Result<Attestation, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Output descriptor of the single key account of the network, with the account xpub
+    /// of the device, see utils::account_descriptor.
+    fn get_descriptor(
+        &self,
+        script: ScriptType,
+        network: Network,
+        account: u32,
+    ) -> Result<String, Error> {
+        let path = utils::account_path(script, network, account)?;
+        let xpub = self.get_extended_pubkey(&path)?;
+        Ok(utils::account_descriptor(
+            script,
+            self.get_master_fingerprint()?,
+            &path,
+            &xpub,
+        ))
+    }
+    /// Set the user-visible name of the device, confirmed by the user on the device.
+    fn set_label(&self, _label: &str) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Set the network used to select the coin of the device, check the coin type
+    /// of the derivation paths and check the network of the displayed addresses.
+    fn set_network(&mut self, _network: Network) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Set the wallet policy used to display the addresses and sign the transactions,
+    /// with the hmac returned by its registration if the device requires one,
+    /// see WalletRegistration::hmac.
+    fn load_wallet(
+        &mut self,
+        _name: &str,
+        _policy: &str,
+        _hmac: Option<[u8; 32]>,
+    ) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Close and reopen the connection with the device, keeping the configured
+    /// wallet and network, to recover from a stuck transport.
+    fn reset_connection(&self) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Features supported by the device, the default implementation does not know
+    /// the firmware version, see Capabilities::new.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(self.device_kind(), None)
+    }
+}
+
+impl<T: HWI + ?Sized> delegate::Delegate for Box<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        self
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(self)
+    }
+}
+
+delegate_hwi!([T: HWI + ?Sized] Box<T>);
+
+/// Device shared between threads or tasks, the calls of the clones are not serialized,
+/// see lock::LockWatch.
+impl<T: HWI + ?Sized> delegate::Delegate for Arc<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        self
+    }
+
+    /// Only the last clone can change the network or the wallet, the others would be
+    /// left with the previous ones.
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Arc::get_mut(self).ok_or(Error::Unexpected("settings of a shared device"))
+    }
+}
+
+delegate_hwi!([T: HWI + ?Sized] Arc<T>);
+
+/// BIP352 keys of a silent payments account, derived from m/352'/coin_type'/account'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentKeys {
+    /// Private key of m/352'/coin_type'/account'/1'/0, the payments cannot be spent with it.
+    pub scan_key: SecretKey,
+    /// Public key of m/352'/coin_type'/account'/0'/0.
+    pub spend_key: PublicKey,
+}
+
+/// Wallet registered on the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegisteredWallet {
+    pub name: String,
+    /// Output descriptor of the wallet, with its keys.
+    pub descriptor: String,
+}
+
+/// Result of the genuine check of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Attestation {
+    /// The device proved it was made by its vendor.
+    Genuine,
+    /// The device failed the check, with the reason given by the check.
+    NotGenuine(String),
+    /// The device cannot prove it is genuine to the host, the user compares the
+    /// number of its tamper-evident bag with the number shown by the device.
+    BagNumber(String),
+}
+
+/// Outcome of a wallet policy registration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WalletRegistration {
+    /// Policy id computed by the device, if the device exposes one.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex"))]
+    pub id: Option<[u8; 32]>,
+    /// Proof of registration the device requires to later use the policy.
+    #[cfg_attr(feature = "serde", serde(with = "serde_hex"))]
+    pub hmac: Option<[u8; 32]>,
+    /// Account xpubs of the device used by the policy,
+    /// see utils::register_wallet_with_xpubs.
+    pub xpubs: Vec<(DerivationPath, Xpub)>,
+    /// The policy was already registered, the device did not show it to the user.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub already_registered: bool,
+}
+
+/// Hex representation of the 32 bytes identifiers.
+#[cfg(feature = "serde")]
+mod serde_hex {
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hex::DisplayHex;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<[u8; 32]>, s: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => s.serialize_some(&bytes.to_lower_hex_string()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<[u8; 32]>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|s| <[u8; 32]>::from_hex(&s).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// Outcome of a transaction signature.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignResult {
+    /// Warnings of the transaction review the user confirmed by signing,
+    /// see utils::review_warnings.
+    pub warnings: Vec<SignWarning>,
+    /// Inputs which received a signature, see utils::signed_inputs.
+    pub signed_inputs: Vec<SignedInput>,
+}
+
+impl SignResult {
+    /// Outcome of the signature of the unsigned psbt, computed from the signed one.
+    pub fn new(unsigned: &Psbt, signed: &Psbt) -> Self {
+        SignResult {
+            warnings: utils::review_warnings(signed),
+            signed_inputs: utils::signed_inputs(unsigned, signed),
+        }
+    }
+}
+
+/// Input which received a signature and the key which signed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedInput {
+    pub input: usize,
+    pub key: SigningKey,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SigningKey {
+    Ecdsa(bitcoin::PublicKey),
+    /// Key of a taproot script path signature,
+    /// or internal key of a taproot key path signature.
+    Schnorr(bitcoin::key::XOnlyPublicKey),
+}
+
+/// Signature of a PSBT input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputSignature {
+    Ecdsa(bitcoin::PublicKey, bitcoin::ecdsa::Signature),
+    TapScript(
+        bitcoin::key::XOnlyPublicKey,
+        bitcoin::taproot::TapLeafHash,
+        bitcoin::taproot::Signature,
+    ),
+    TapKey(bitcoin::taproot::Signature),
+}
+
+impl InputSignature {
+    /// Adds the signature to the PSBT input.
+    pub fn insert(self, input: &mut bitcoin::psbt::Input) {
+        match self {
+            Self::Ecdsa(key, sig) => {
+                input.partial_sigs.insert(key, sig);
+            }
+            Self::TapScript(key, leaf_hash, sig) => {
+                input.tap_script_sigs.insert((key, leaf_hash), sig);
+            }
+            Self::TapKey(sig) => input.tap_key_sig = Some(sig),
+        }
+    }
+}
+
+/// Unusual transaction field the devices warn about during the review.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SignWarning {
+    /// The transaction cannot be mined before the locktime.
+    LockTime(bitcoin::absolute::LockTime),
+    /// The input cannot be mined before the relative locktime of its sequence.
+    RelativeLockTime {
+        input: usize,
+        sequence: bitcoin::Sequence,
+    },
+    /// The input is signed with a sighash type other than the default one.
+    SighashType {
+        input: usize,
+        sighash_type: bitcoin::psbt::PsbtSighashType,
+    },
+    /// The key of a single key input is not on the BIP44 path of its script type,
+    /// see utils::MAX_STANDARD_ADDRESS_INDEX.
+    UnusualPath { input: usize, path: DerivationPath },
+    /// The change output is not on the BIP44 change path of its script type, the devices
+    /// display it as an external output.
+    UnusualChange { output: usize, path: DerivationPath },
+    /// The fee is above utils::HIGH_FEE_PERCENT of the spent amount.
+    HighFee {
+        fee: bitcoin::Amount,
+        spent: bitcoin::Amount,
+    },
+}
+
+/// Single signature script types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScriptType {
+    P2PKH,
+    P2SHWPKH,
+    P2WPKH,
+    P2TR,
+}
+
+/// Features supported by a device with this crate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// Single signature scripts of which the device displays the addresses.
+    pub address_scripts: Vec<ScriptType>,
+    /// Registration of miniscript wallet policies.
+    pub miniscript: bool,
+    /// Signing of taproot inputs.
+    pub taproot: bool,
+    /// Signing of messages with the keys of the device.
+    pub sign_message: bool,
+    /// Maximum length of a registered wallet name, None if the device has no limit.
+    pub max_wallet_name_len: Option<usize>,
+    /// Signing of transactions with OP_RETURN outputs, see utils::check_op_return.
+    pub op_return: bool,
+}
+
+impl Capabilities {
+    /// Capabilities of the device kind with the given firmware or app version.
+    /// Without version, the features requiring a minimum version are reported unsupported.
+    pub fn new(kind: DeviceKind, version: Option<&Version>) -> Self {
+        let at_least = |major, minor, patch| {
+            version.is_some_and(|v| v.is_at_least(&Version::new(major, minor, patch)))
+        };
+        match kind {
+            DeviceKind::BitBox02 => {
+                let mut address_scripts = vec![ScriptType::P2SHWPKH, ScriptType::P2WPKH];
+                if at_least(9, 10, 0) {
+                    address_scripts.push(ScriptType::P2TR);
+                }
+                Capabilities {
+                    address_scripts,
+                    miniscript: at_least(9, 15, 0),
+                    taproot: at_least(9, 10, 0),
+                    sign_message: at_least(9, 5, 0),
+                    max_wallet_name_len: Some(30),
+                    // The output type is unknown to bitbox-api.
+                    op_return: false,
+                }
+            }
+            DeviceKind::Coldcard => Capabilities {
+                address_scripts: vec![ScriptType::P2PKH, ScriptType::P2SHWPKH, ScriptType::P2WPKH],
+                miniscript: at_least(6, 0, 0),
+                taproot: at_least(6, 0, 0),
+                sign_message: true,
+                max_wallet_name_len: Some(40),
+                op_return: true,
+            },
+            DeviceKind::Jade | DeviceKind::JadeSimulator => Capabilities {
+                address_scripts: vec![ScriptType::P2PKH, ScriptType::P2SHWPKH, ScriptType::P2WPKH],
+                miniscript: true,
+                taproot: false,
+                sign_message: true,
+                max_wallet_name_len: Some(16),
+                op_return: true,
+            },
+            DeviceKind::Ledger | DeviceKind::LedgerSimulator => Capabilities {
+                // The legacy apps display the single key addresses except taproot ones,
+                // the apps 2.0.0 and later use the default wallet policies.
+                address_scripts: if at_least(2, 0, 0) {
+                    vec![
+                        ScriptType::P2PKH,
+                        ScriptType::P2SHWPKH,
+                        ScriptType::P2WPKH,
+                        ScriptType::P2TR,
+                    ]
+                } else {
+                    vec![ScriptType::P2PKH, ScriptType::P2SHWPKH, ScriptType::P2WPKH]
+                },
+                miniscript: at_least(2, 1, 0),
+                taproot: at_least(2, 0, 0),
+                sign_message: at_least(2, 1, 0),
+                max_wallet_name_len: Some(64),
+                op_return: at_least(2, 0, 0),
+            },
+            DeviceKind::Specter | DeviceKind::SpecterSimulator => Capabilities {
+                address_scripts: Vec::new(),
+                miniscript: true,
+                taproot: false,
+                sign_message: true,
+                max_wallet_name_len: None,
+                op_return: true,
+            },
+            DeviceKind::HotSigner => Capabilities {
+                address_scripts: vec![
+                    ScriptType::P2PKH,
+                    ScriptType::P2SHWPKH,
+                    ScriptType::P2WPKH,
+                    ScriptType::P2TR,
+                ],
+                miniscript: false,
+                taproot: true,
+                sign_message: false,
+                max_wallet_name_len: None,
+                op_return: true,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AddressScript {
+    /// Must be a bip44 path.
+    P2PKH(DerivationPath),
+    /// Must be a bip49 path.
+    P2SHWPKH(DerivationPath),
+    /// Must be a bip84 path.
+    P2WPKH(DerivationPath),
     /// Must be a bip86 path.
     P2TR(DerivationPath),
     /// Miniscript requires the policy be loaded into the device.
     Miniscript { index: u32, change: bool },
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Version {
     pub major: u32,
     pub minor: u32,
     pub patch: u32,
     pub prerelease: Option<String>,
+    /// Edition of the firmware: the X and QX suffixes of the Coldcard Mk4 and Q,
+    /// or the build metadata of the version. It is ignored by the comparisons and the
+    /// equality.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub flavor: Option<String>,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+            prerelease: None,
+            flavor: None,
+        }
+    }
+
+    /// False if the prereleases of the same version cannot be compared.
+    pub fn is_at_least(&self, required: &Version) -> bool {
+        self >= required
+    }
+
+    /// Fails with UnsupportedFirmware if the version is older than the required one.
+    pub fn require(&self, required: &Version) -> Result<(), Error> {
+        if self.is_at_least(required) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFirmware {
+                required: required.clone(),
+                found: Some(Box::new(self.clone())),
+            })
+        }
+    }
 }
 
 #[cfg(feature = "regex")]
 pub fn parse_version(s: &str) -> Result<Version, Error> {
     // Regex from https://semver.org/ with patch group marked as optional
     let re = regex::Regex::new(r"^(0|[1-9]\d*)\.(0|[1-9]\d*)(?:\.(0|[1-9]\d*))?(?:-((?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*)(?:\.(?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*))*))?(?:\+([0-9a-zA-Z-]+(?:\.[0-9a-zA-Z-]+)*))?$").unwrap();
-    if let Some(captures) = re.captures(
-        s.trim_start_matches('v')
-            // Coldcard Q does not follow semver format
-            .trim_end_matches("QX")
-            // Coldcard mk4 does not follow semver format
-            .trim_end_matches('X'),
-    ) {
+    let s = s.trim_start_matches('v');
+    // Coldcard Mk4 and Q do not follow semver format
+    let (s, edition) = ["QX", "X"]
+        .iter()
+        .find_map(|edition| {
+            s.strip_suffix(edition)
+                .filter(|s| s.ends_with(|c: char| c.is_ascii_digit()))
+                .map(|s| (s, Some(*edition)))
+        })
+        .unwrap_or((s, None));
+    if let Some(captures) = re.captures(s) {
         let major = if let Some(s) = captures.get(1) {
             u32::from_str(s.as_str()).map_err(|_| Error::UnsupportedVersion)?
         } else {
@@ -130,12 +941,30 @@ pub fn parse_version(s: &str) -> Result<Version, Error> {
             minor,
             patch,
             prerelease: captures.get(4).map(|s| s.as_str().to_string()),
+            flavor: edition
+                .or_else(|| captures.get(5).map(|s| s.as_str()))
+                .map(str::to_string),
         })
     } else {
         Err(Error::UnsupportedVersion)
     }
 }
 
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        (self.major, self.minor, self.patch, &self.prerelease)
+            == (other.major, other.minor, other.patch, &other.prerelease)
+    }
+}
+
+impl Eq for Version {}
+
+impl std::hash::Hash for Version {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.major, self.minor, self.patch, &self.prerelease).hash(state);
+    }
+}
+
 impl PartialOrd for Version {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match self.major.cmp(&other.major) {
@@ -143,6 +972,7 @@ impl PartialOrd for Version {
                 Ordering::Equal => match self.patch.cmp(&other.patch) {
                     Ordering::Equal => {
                         match (&self.prerelease, &other.prerelease) {
+                            (Some(a), Some(b)) if a == b => Some(Ordering::Equal),
                             // Cannot compare versions at this point.
                             (Some(_), Some(_)) => None,
                             (Some(_), None) => Some(Ordering::Greater),
@@ -161,22 +991,24 @@ impl PartialOrd for Version {
 
 impl std::fmt::Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
         if let Some(prerelease) = &self.prerelease {
-            write!(
-                f,
-                "{}.{}.{}-{}",
-                self.major, self.minor, self.patch, prerelease
-            )
-        } else {
-            write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+            write!(f, "-{}", prerelease)?;
+        }
+        if let Some(flavor) = &self.flavor {
+            write!(f, "+{}", flavor)?;
         }
+        Ok(())
     }
 }
 
 /// DeviceType is the result of the following process:
 /// If it is talking like a Duck© hardware wallet it is a Duck© hardware wallet.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum DeviceKind {
+    #[cfg_attr(feature = "serde", serde(rename = "bitbox02"))]
     BitBox02,
     Coldcard,
     Specter,
@@ -184,6 +1016,9 @@ pub enum DeviceKind {
     Ledger,
     LedgerSimulator,
     Jade,
+    JadeSimulator,
+    /// Software signer, see signer::HotSigner.
+    HotSigner,
 }
 
 impl std::fmt::Display for DeviceKind {
@@ -196,22 +1031,249 @@ impl std::fmt::Display for DeviceKind {
             DeviceKind::Ledger => write!(f, "ledger"),
             DeviceKind::LedgerSimulator => write!(f, "ledger-simulator"),
             DeviceKind::Jade => write!(f, "jade"),
+            DeviceKind::JadeSimulator => write!(f, "jade-simulator"),
+            DeviceKind::HotSigner => write!(f, "hot-signer"),
         }
     }
 }
 
+/// Parses the names displayed by DeviceKind, which are also its serde names.
 impl std::str::FromStr for DeviceKind {
-    type Err = ();
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "bitbox02" => Ok(DeviceKind::BitBox02),
-            "specter" => Ok(DeviceKind::Specter),
-            "specter-simulator" => Ok(DeviceKind::SpecterSimulator),
-            "ledger" => Ok(DeviceKind::Ledger),
-            "ledger-simulator" => Ok(DeviceKind::LedgerSimulator),
-            "jade" => Ok(DeviceKind::Jade),
-            _ => Err(()),
+        DeviceKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| Error::InvalidParameter("kind", format!("unknown device {}", s)))
+    }
+}
+
+/// Vendor information of a device kind, to help users fix the device state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceMetadata {
+    pub vendor: &'static str,
+    pub support_url: &'static str,
+    /// How to update the firmware of the device.
+    pub firmware_update: &'static str,
+    /// Minimum firmware version recommended for the features of this crate,
+    /// for Ledger it is the version of the Bitcoin app.
+    pub min_firmware: Version,
+}
+
+impl DeviceKind {
+    pub const ALL: [DeviceKind; 9] = [
+        DeviceKind::BitBox02,
+        DeviceKind::Coldcard,
+        DeviceKind::Specter,
+        DeviceKind::SpecterSimulator,
+        DeviceKind::Ledger,
+        DeviceKind::LedgerSimulator,
+        DeviceKind::Jade,
+        DeviceKind::JadeSimulator,
+        DeviceKind::HotSigner,
+    ];
+
+    pub fn metadata(&self) -> DeviceMetadata {
+        match self {
+            DeviceKind::BitBox02 => DeviceMetadata {
+                vendor: "Shift Crypto",
+                support_url: "https://bitbox.swiss/support/",
+                firmware_update: "Update the firmware with the BitBoxApp.",
+                min_firmware: Version::new(9, 15, 0),
+            },
+            DeviceKind::Coldcard => DeviceMetadata {
+                vendor: "Coinkite",
+                support_url: "https://coldcard.com/docs/",
+                firmware_update: "Download the firmware from https://coldcard.com/downloads \
+                    and install it from a microSD card.",
+                min_firmware: Version::new(6, 0, 0),
+            },
+            DeviceKind::Specter | DeviceKind::SpecterSimulator => DeviceMetadata {
+                vendor: "Crypto Advance",
+                support_url: "https://github.com/cryptoadvance/specter-diy",
+                firmware_update: "Flash the firmware from \
+                    https://github.com/cryptoadvance/specter-diy/releases.",
+                min_firmware: Version::new(1, 9, 0),
+            },
+            DeviceKind::Ledger | DeviceKind::LedgerSimulator => DeviceMetadata {
+                vendor: "Ledger",
+                support_url: "https://support.ledger.com/",
+                firmware_update: "Update the firmware and the Bitcoin app with Ledger Live.",
+                min_firmware: Version::new(2, 1, 0),
+            },
+            DeviceKind::Jade | DeviceKind::JadeSimulator => DeviceMetadata {
+                vendor: "Blockstream",
+                support_url: "https://help.blockstream.com/",
+                firmware_update: "Update the firmware with the Blockstream Green app.",
+                min_firmware: Version::new(1, 0, 0),
+            },
+            DeviceKind::HotSigner => DeviceMetadata {
+                vendor: "bp-hwi",
+                support_url: "https://github.com/wizardsardine/async-hwi",
+                firmware_update: "Update the bp-hwi crate.",
+                min_firmware: Version::new(0, 11, 0),
+            },
+        }
+    }
+
+    /// Guidance for the user to recover from the error reported by a device of this kind,
+    /// None if the error does not come from the device state.
+    pub fn recovery_hint(&self, error: &Error) -> Option<String> {
+        let metadata = self.metadata();
+        match error {
+            Error::UnsupportedFirmware { required, .. } => Some(format!(
+                "Firmware version {} is required. {}",
+                required, metadata.firmware_update
+            )),
+            Error::UnsupportedVersion => Some(format!(
+                "Firmware version {} is recommended. {}",
+                metadata.min_firmware, metadata.firmware_update
+            )),
+            Error::WrongApp { expected, .. } => {
+                Some(format!("Open the {} app on the {} device.", expected, self))
+            }
+            Error::AppNotOpen { .. } => {
+                Some(format!("Open the Bitcoin app on the {} device.", self))
+            }
+            Error::DeviceLocked { .. } => Some(format!("Unlock the {} device.", self)),
+            Error::DeviceNotFound | Error::DeviceDisconnected | Error::Transport(..) => {
+                Some(format!(
+                    "Check the connection of the {} device, see {}",
+                    self, metadata.support_url
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Model of a device, when the backend tells it from the enumeration data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DeviceModel {
+    LedgerNanoS,
+    LedgerNanoSPlus,
+    LedgerNanoX,
+    LedgerStax,
+    LedgerFlex,
+    #[cfg_attr(feature = "serde", serde(rename = "bitbox02_multi"))]
+    BitBox02Multi,
+    #[cfg_attr(feature = "serde", serde(rename = "bitbox02_btc_only"))]
+    BitBox02BtcOnly,
+}
+
+impl std::fmt::Display for DeviceModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceModel::LedgerNanoS => write!(f, "Ledger Nano S"),
+            DeviceModel::LedgerNanoSPlus => write!(f, "Ledger Nano S Plus"),
+            DeviceModel::LedgerNanoX => write!(f, "Ledger Nano X"),
+            DeviceModel::LedgerStax => write!(f, "Ledger Stax"),
+            DeviceModel::LedgerFlex => write!(f, "Ledger Flex"),
+            DeviceModel::BitBox02Multi => write!(f, "BitBox02 Multi"),
+            DeviceModel::BitBox02BtcOnly => write!(f, "BitBox02 Bitcoin-only"),
+        }
+    }
+}
+
+/// Parses the names displayed by DeviceModel and its identifiers, see DeviceModel::as_str.
+impl std::str::FromStr for DeviceModel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DeviceModel::ALL
+            .iter()
+            .copied()
+            .find(|model| model.as_str() == s || model.to_string() == s)
+            .ok_or_else(|| Error::InvalidParameter("model", format!("unknown model {}", s)))
+    }
+}
+
+impl DeviceModel {
+    pub const ALL: [DeviceModel; 7] = [
+        DeviceModel::LedgerNanoS,
+        DeviceModel::LedgerNanoSPlus,
+        DeviceModel::LedgerNanoX,
+        DeviceModel::LedgerStax,
+        DeviceModel::LedgerFlex,
+        DeviceModel::BitBox02Multi,
+        DeviceModel::BitBox02BtcOnly,
+    ];
+
+    /// Identifier of the model for the configuration files, which is also its serde name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceModel::LedgerNanoS => "ledger_nano_s",
+            DeviceModel::LedgerNanoSPlus => "ledger_nano_s_plus",
+            DeviceModel::LedgerNanoX => "ledger_nano_x",
+            DeviceModel::LedgerStax => "ledger_stax",
+            DeviceModel::LedgerFlex => "ledger_flex",
+            DeviceModel::BitBox02Multi => "bitbox02_multi",
+            DeviceModel::BitBox02BtcOnly => "bitbox02_btc_only",
+        }
+    }
+
+    /// The Nano S has too little memory for large miniscript policies, the app may fail
+    /// to register or to sign with them.
+    pub fn is_low_memory(&self) -> bool {
+        *self == DeviceModel::LedgerNanoS
+    }
+
+    /// The Stax and the Flex have a touchscreen, the other models have buttons.
+    pub fn has_touchscreen(&self) -> bool {
+        matches!(self, DeviceModel::LedgerStax | DeviceModel::LedgerFlex)
+    }
+}
+
+/// Device found by the enumeration of a backend, before connecting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceDetails {
+    pub kind: DeviceKind,
+    pub model: Option<DeviceModel>,
+    pub serial: Option<String>,
+    /// HID path or serial port of the device.
+    pub path: Option<String>,
+    /// Product name reported by the device.
+    pub product: Option<String>,
+    /// Name set by the user, only known once the device is connected, see with_user_label.
+    pub user_label: Option<String>,
+}
+
+impl DeviceDetails {
+    #[cfg(feature = "hidapi")]
+    pub fn from_hid(
+        kind: DeviceKind,
+        model: Option<DeviceModel>,
+        info: &hidapi::DeviceInfo,
+    ) -> Self {
+        Self {
+            kind,
+            model,
+            serial: info.serial_number().map(str::to_string),
+            path: Some(info.path().to_string_lossy().into_owned()),
+            product: info.product_string().map(str::to_string),
+            user_label: None,
+        }
+    }
+
+    /// Sets user_label to the label of the connected device, if it exposes one.
+    pub fn with_user_label<T: HWI + ?Sized>(mut self, device: &T) -> Self {
+        self.user_label = device.get_label().ok().flatten();
+        self
+    }
+
+    /// Label to show to the user: the name set by the user, else the model,
+    /// else the product name, else the kind.
+    pub fn label(&self) -> String {
+        match (&self.user_label, &self.model, &self.product) {
+            (Some(label), _, _) => label.clone(),
+            (None, Some(model), _) => model.to_string(),
+            (None, None, Some(product)) => product.clone(),
+            (None, None, None) => self.kind.to_string(),
         }
     }
 }
@@ -219,6 +1281,33 @@ impl std::str::FromStr for DeviceKind {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_error_code() {
+        let mut numbers: Vec<u16> = ErrorCode::ALL.iter().map(ErrorCode::as_u16).collect();
+        let mut names: Vec<&str> = ErrorCode::ALL.iter().map(ErrorCode::as_str).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(numbers.len(), ErrorCode::ALL.len());
+        assert_eq!(names.len(), ErrorCode::ALL.len());
+
+        // Stable values.
+        assert_eq!(ErrorCode::ParsingPolicy.as_u16(), 1);
+        assert_eq!(ErrorCode::UserRefused.as_u16(), 17);
+        assert_eq!(Error::UserRefused.code().as_str(), "user_refused");
+        assert_eq!(
+            Error::DeviceLocked { code: None }.code(),
+            ErrorCode::DeviceLocked
+        );
+        assert_eq!(
+            Error::PolicyMismatch(String::new()).code().to_string(),
+            "policy_mismatch"
+        );
+    }
 
     #[cfg(feature = "regex")]
     #[test]
@@ -231,6 +1320,7 @@ mod tests {
                     minor: 1,
                     patch: 0,
                     prerelease: None,
+                    flavor: None,
                 },
             ),
             (
@@ -240,6 +1330,7 @@ mod tests {
                     minor: 0,
                     patch: 0,
                     prerelease: None,
+                    flavor: None,
                 },
             ),
             (
@@ -249,6 +1340,7 @@ mod tests {
                     minor: 0,
                     patch: 0,
                     prerelease: Some("rc2".to_string()),
+                    flavor: None,
                 },
             ),
             (
@@ -258,6 +1350,7 @@ mod tests {
                     minor: 1,
                     patch: 0,
                     prerelease: Some("ALPHA".to_string()),
+                    flavor: None,
                 },
             ),
             (
@@ -267,6 +1360,7 @@ mod tests {
                     minor: 2,
                     patch: 1,
                     prerelease: None,
+                    flavor: Some("X".to_string()),
                 },
             ),
             (
@@ -276,11 +1370,24 @@ mod tests {
                     minor: 3,
                     patch: 3,
                     prerelease: None,
+                    flavor: Some("QX".to_string()),
+                },
+            ),
+            (
+                "1.0.1-beta+build.5",
+                Version {
+                    major: 1,
+                    minor: 0,
+                    patch: 1,
+                    prerelease: Some("beta".to_string()),
+                    flavor: Some("build.5".to_string()),
                 },
             ),
         ];
         for (s, v) in test_cases {
-            assert_eq!(v, parse_version(s).unwrap());
+            let parsed = parse_version(s).unwrap();
+            assert_eq!(v, parsed);
+            assert_eq!(v.flavor, parsed.flavor);
         }
     }
 
@@ -307,4 +1414,138 @@ mod tests {
         let v2 = parse_version("v2.0-rc1weirderstuff").unwrap();
         assert!(v1.partial_cmp(&v2).is_none());
     }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_version_require() {
+        let version = parse_version("6.2.1X").unwrap();
+        assert_eq!(version.to_string(), "6.2.1+X");
+        assert_eq!(parse_version(&version.to_string()).unwrap(), version);
+        assert!(version.is_at_least(&Version::new(6, 2, 1)));
+        assert!(version.require(&Version::new(6, 0, 0)).is_ok());
+        assert!(matches!(
+            version.require(&Version::new(6, 3, 0)),
+            Err(Error::UnsupportedFirmware { required, found: Some(found) })
+                if required == Version::new(6, 3, 0) && *found == version
+        ));
+        assert!(!parse_version("6.3.0-rc1")
+            .unwrap()
+            .is_at_least(&parse_version("6.3.0-rc2").unwrap()));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_version_flavor() {
+        use std::collections::HashSet;
+
+        let x = parse_version("6.2.1X").unwrap();
+        let qx = parse_version("6.2.1QX").unwrap();
+        assert_eq!(x, qx);
+        assert_eq!(x.partial_cmp(&qx), Some(Ordering::Equal));
+        assert_eq!(x, Version::new(6, 2, 1));
+        assert_eq!(HashSet::from([x, qx]).len(), 1);
+
+        let rc = parse_version("6.3.0-rc1").unwrap();
+        assert_eq!(rc.partial_cmp(&rc.clone()), Some(Ordering::Equal));
+    }
+
+    #[test]
+    fn test_device_names() {
+        for kind in DeviceKind::ALL {
+            assert_eq!(DeviceKind::from_str(&kind.to_string()).unwrap(), kind);
+        }
+        assert_eq!(
+            DeviceKind::from_str("coldcard").unwrap(),
+            DeviceKind::Coldcard
+        );
+        assert!(DeviceKind::from_str("trezor").is_err());
+
+        for model in DeviceModel::ALL {
+            assert_eq!(DeviceModel::from_str(&model.to_string()).unwrap(), model);
+            assert_eq!(DeviceModel::from_str(model.as_str()).unwrap(), model);
+        }
+        assert!(DeviceModel::from_str("ledger").is_err());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let version = Version {
+            major: 9,
+            minor: 15,
+            patch: 0,
+            prerelease: None,
+            flavor: None,
+        };
+        let bitbox = Capabilities::new(DeviceKind::BitBox02, Some(&version));
+        assert!(bitbox.miniscript && bitbox.taproot);
+        assert!(bitbox.address_scripts.contains(&ScriptType::P2TR));
+
+        let bitbox = Capabilities::new(DeviceKind::BitBox02, None);
+        assert!(!bitbox.miniscript && !bitbox.taproot);
+        assert_eq!(
+            bitbox.address_scripts,
+            vec![ScriptType::P2SHWPKH, ScriptType::P2WPKH]
+        );
+
+        let version = Version {
+            major: 2,
+            minor: 1,
+            patch: 3,
+            prerelease: None,
+            flavor: None,
+        };
+        let ledger = Capabilities::new(DeviceKind::Ledger, Some(&version));
+        assert!(ledger.miniscript);
+        assert_eq!(
+            ledger.address_scripts,
+            vec![
+                ScriptType::P2PKH,
+                ScriptType::P2SHWPKH,
+                ScriptType::P2WPKH,
+                ScriptType::P2TR
+            ]
+        );
+
+        let ledger = Capabilities::new(DeviceKind::Ledger, None);
+        assert_eq!(
+            ledger.address_scripts,
+            vec![ScriptType::P2PKH, ScriptType::P2SHWPKH, ScriptType::P2WPKH]
+        );
+    }
+
+    #[test]
+    fn test_recovery_hint() {
+        let hint = DeviceKind::Ledger.recovery_hint(&Error::AppNotOpen {
+            running: "BOLOS".to_string(),
+        });
+        assert_eq!(
+            hint.as_deref(),
+            Some("Open the Bitcoin app on the ledger device.")
+        );
+        assert!(DeviceKind::Jade
+            .recovery_hint(&Error::UnsupportedVersion)
+            .unwrap()
+            .contains("1.0.0"));
+        assert!(DeviceKind::Coldcard
+            .recovery_hint(&Error::UserRefused)
+            .is_none());
+    }
+
+    #[test]
+    fn test_device_details_label() {
+        let details = DeviceDetails {
+            kind: DeviceKind::Ledger,
+            model: Some(DeviceModel::LedgerNanoX),
+            serial: None,
+            path: None,
+            product: Some("Nano X".to_string()),
+            user_label: None,
+        };
+        assert_eq!(details.label(), DeviceModel::LedgerNanoX.to_string());
+        let details = DeviceDetails {
+            user_label: Some("Backup".to_string()),
+            ..details
+        };
+        assert_eq!(details.label(), "Backup");
+    }
 }
@@ -0,0 +1,228 @@
+use bitcoin::psbt::Psbt;
+
+use crate::{Error, InputSignature, SignResult, HWI};
+
+/// Limit of the PSBTs submitted to the devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PsbtLimit {
+    Inputs,
+    Outputs,
+    /// Size in bytes of the serialized PSBT.
+    Size,
+    /// Size in bytes of the largest redeem, witness or tapscript of the PSBT.
+    ScriptSize,
+}
+
+impl std::fmt::Display for PsbtLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PsbtLimit::Inputs => write!(f, "number of inputs"),
+            PsbtLimit::Outputs => write!(f, "number of outputs"),
+            PsbtLimit::Size => write!(f, "size"),
+            PsbtLimit::ScriptSize => write!(f, "script size"),
+        }
+    }
+}
+
+/// Maximum PSBT accepted by the host before submitting it to the device.
+/// The defaults fit the devices with the smallest memory and are far above
+/// the PSBTs of the usual wallets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PsbtLimits {
+    pub max_inputs: usize,
+    pub max_outputs: usize,
+    pub max_size: usize,
+    pub max_script_size: usize,
+}
+
+impl Default for PsbtLimits {
+    fn default() -> Self {
+        Self {
+            max_inputs: 256,
+            max_outputs: 256,
+            max_size: 512 * 1024,
+            // Maximum size of a P2WSH witness script by standardness.
+            max_script_size: 3600,
+        }
+    }
+}
+
+impl PsbtLimits {
+    /// Returns Error::PsbtLimit with the first limit the PSBT exceeds.
+    pub fn check(&self, psbt: &Psbt) -> Result<(), Error> {
+        let check = |limit, value, max| {
+            if value > max {
+                Err(Error::PsbtLimit { limit, value, max })
+            } else {
+                Ok(())
+            }
+        };
+        check(PsbtLimit::Inputs, psbt.inputs.len(), self.max_inputs)?;
+        check(PsbtLimit::Outputs, psbt.outputs.len(), self.max_outputs)?;
+        check(
+            PsbtLimit::ScriptSize,
+            max_script_size(psbt),
+            self.max_script_size,
+        )?;
+        check(PsbtLimit::Size, psbt.serialize().len(), self.max_size)
+    }
+}
+
+fn max_script_size(psbt: &Psbt) -> usize {
+    let inputs = psbt.inputs.iter().flat_map(|input| {
+        input
+            .redeem_script
+            .iter()
+            .chain(input.witness_script.iter())
+            .chain(input.tap_scripts.values().map(|(script, _)| script))
+            .map(|script| script.len())
+    });
+    let outputs = psbt.outputs.iter().flat_map(|output| {
+        output
+            .redeem_script
+            .iter()
+            .chain(output.witness_script.iter())
+            .map(|script| script.len())
+    });
+    inputs.chain(outputs).max().unwrap_or(0)
+}
+
+/// Limited wraps a device and checks the PSBTs against the limits
+/// before submitting them to the device.
+#[derive(Debug)]
+pub struct Limited<T> {
+    device: T,
+    limits: PsbtLimits,
+}
+
+impl<T: HWI> Limited<T> {
+    pub fn new(device: T, limits: PsbtLimits) -> Self {
+        Self { device, limits }
+    }
+
+    pub fn limits(&self) -> &PsbtLimits {
+        &self.limits
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+}
+
+impl<T: HWI> crate::delegate::Delegate for Limited<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.device
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(&mut self.device)
+    }
+
+    fn sign_tx(&self, tx: &mut Psbt) -> Result<SignResult, Error> {
+        self.limits.check(tx)?;
+        self.device.sign_tx(tx)
+    }
+
+    fn sign_tx_progressive(
+        &self,
+        tx: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, Error> {
+        self.limits.check(tx)?;
+        self.device.sign_tx_progressive(tx, on_signature)
+    }
+
+    /// All the psbts are checked before the first is signed.
+    fn sign_txs(&self, txs: &mut [Psbt]) -> Result<Vec<SignResult>, Error> {
+        for tx in txs.iter() {
+            self.limits.check(tx)?;
+        }
+        self.device.sign_txs(txs)
+    }
+}
+
+delegate_hwi!([T: HWI] Limited<T>);
+
+impl<T: 'static + HWI + Send> From<Limited<T>> for Box<dyn HWI + Send> {
+    fn from(s: Limited<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{absolute::LockTime, transaction, Amount, ScriptBuf, Transaction, TxIn, TxOut};
+
+    fn psbt(inputs: usize, outputs: usize) -> Psbt {
+        Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(); inputs],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(1000),
+                    script_pubkey: ScriptBuf::new(),
+                };
+                outputs
+            ],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let limits = PsbtLimits {
+            max_inputs: 2,
+            max_outputs: 2,
+            max_size: 1000,
+            max_script_size: 10,
+        };
+        assert!(limits.check(&psbt(2, 2)).is_ok());
+        assert!(matches!(
+            limits.check(&psbt(3, 1)),
+            Err(Error::PsbtLimit {
+                limit: PsbtLimit::Inputs,
+                value: 3,
+                max: 2
+            })
+        ));
+        assert!(matches!(
+            limits.check(&psbt(1, 3)),
+            Err(Error::PsbtLimit {
+                limit: PsbtLimit::Outputs,
+                ..
+            })
+        ));
+
+        let mut p = psbt(1, 1);
+        p.inputs[0].witness_script = Some(ScriptBuf::from_bytes(vec![0x51; 11]));
+        assert!(matches!(
+            limits.check(&p),
+            Err(Error::PsbtLimit {
+                limit: PsbtLimit::ScriptSize,
+                value: 11,
+                max: 10
+            })
+        ));
+
+        p.inputs[0].witness_script = None;
+        p.unknown.insert(
+            bitcoin::psbt::raw::Key {
+                type_value: 0xf0,
+                key: vec![],
+            },
+            vec![0; 1000],
+        );
+        assert!(matches!(
+            limits.check(&p),
+            Err(Error::PsbtLimit {
+                limit: PsbtLimit::Size,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+//! Liquid blinding keys derived as in SLIP-77, the signing of the PSETs requires
+//! the elements types and is not supported yet.
+
+use bitcoin::{
+    hashes::{hmac, sha256, sha512, Hash, HashEngine},
+    secp256k1::{PublicKey, Secp256k1, SecretKey, Signing},
+    Script,
+};
+
+/// Master blinding key of a wallet, from which the blinding key of each script is derived.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterBlindingKey([u8; 32]);
+
+impl MasterBlindingKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// SLIP-21 node of the label "SLIP-0077" of the BIP39 seed.
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(b"Symmetric key seed");
+        engine.input(seed);
+        let root = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(&root[..32]);
+        engine.input(b"\x00SLIP-0077");
+        let node = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+
+        let mut key = [0; 32];
+        key.copy_from_slice(&node[32..]);
+        Self(key)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Blinding private key of the confidential addresses of the script.
+    pub fn blinding_private_key(&self, script_pubkey: &Script) -> SecretKey {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&self.0);
+        engine.input(script_pubkey.as_bytes());
+        SecretKey::from_slice(&hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array())
+            .expect("negligible probability of an invalid key")
+    }
+
+    pub fn blinding_public_key<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        script_pubkey: &Script,
+    ) -> PublicKey {
+        PublicKey::from_secret_key(secp, &self.blinding_private_key(script_pubkey))
+    }
+}
+
+/// The key is not printed.
+impl std::fmt::Debug for MasterBlindingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("MasterBlindingKey").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{hashes::hex::FromHex, ScriptBuf};
+
+    #[test]
+    fn test_slip77() {
+        // Seed of the mnemonic "all all all all all all all all all all all all".
+        let seed = Vec::<u8>::from_hex("c76c4ac4f4e4a00d6b274d5c39c700bb4a7ddc04fbc6f78e85ca75007b5b495f74a9043eeb77bdd53aa6fc3a0e31462270316fa04b8c19114c8798706cd02ac8").unwrap();
+        let key = MasterBlindingKey::from_seed(&seed);
+        assert_eq!(
+            key.to_bytes(),
+            <[u8; 32]>::from_hex(
+                "6c2de18eabeff3f7822bc724ad482bef0557f3e1c1e1c75b7a393a5ced4de616"
+            )
+            .unwrap()
+        );
+        let script =
+            ScriptBuf::from_hex("76a914a579388225827d9f2fe9014add644487808c695d88ac").unwrap();
+        assert_eq!(
+            key.blinding_private_key(&script).secret_bytes(),
+            <[u8; 32]>::from_hex(
+                "4e6e94df28448c7bb159271fe546da464ea863b3887d2eec6afd841184b70592"
+            )
+            .unwrap()
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::Mutex;
+
+use crate::{stats::Operation, Error, HWI};
+
+/// Change of the lock state of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockEvent {
+    DeviceLocked,
+    DeviceUnlocked,
+}
+
+/// LockWatch wraps a device and calls back when the results of the device calls
+/// show that the device was locked or unlocked, so that the UI can prompt the
+/// user before the next operation. LockWatch::poll checks the state with a
+/// request that does not need user interaction.
+pub struct LockWatch<T> {
+    device: T,
+    locked: Mutex<Option<bool>>,
+    on_event: Box<dyn Fn(LockEvent) + Send + Sync>,
+}
+
+impl<T: HWI> LockWatch<T> {
+    /// on_event is called each time the observed state changes,
+    /// including the first observation.
+    pub fn new<F>(device: T, on_event: F) -> Self
+    where
+        F: Fn(LockEvent) + Send + Sync + 'static,
+    {
+        Self {
+            device,
+            locked: Mutex::new(None),
+            on_event: Box::new(on_event),
+        }
+    }
+
+    /// Last observed state, None if no call told it yet.
+    pub fn is_locked(&self) -> Option<bool> {
+        *self.locked.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Requests the master fingerprint and returns true if the device is locked.
+    pub fn poll(&self) -> Result<bool, Error> {
+        match self.observe(self.device.get_master_fingerprint()) {
+            Ok(_) => Ok(false),
+            Err(Error::DeviceLocked { .. }) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    fn observe<R>(&self, res: Result<R, Error>) -> Result<R, Error> {
+        match &res {
+            Err(Error::DeviceLocked { .. }) => self.update(true),
+            // The device answered a request that requires it to be unlocked.
+            Ok(_)
+            | Err(Error::UserRefused)
+            | Err(Error::WrongApp { .. })
+            | Err(Error::AppNotOpen { .. }) => self.update(false),
+            Err(_) => {}
+        }
+        res
+    }
+
+    fn update(&self, locked: bool) {
+        let mut state = self.locked.lock().unwrap_or_else(|e| e.into_inner());
+        if *state != Some(locked) {
+            *state = Some(locked);
+            drop(state);
+            (self.on_event)(if locked {
+                LockEvent::DeviceLocked
+            } else {
+                LockEvent::DeviceUnlocked
+            });
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for LockWatch<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LockWatch")
+            .field("device", &self.device)
+            .field("locked", &self.locked)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: HWI> crate::delegate::Delegate for LockWatch<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.device
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(&mut self.device)
+    }
+
+    /// The local settings are not observed, they do not reach the device.
+    fn call<R>(
+        &self,
+        _operation: Operation,
+        call: impl FnOnce(&T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.observe(call(&self.device))
+    }
+}
+
+delegate_hwi!([T: HWI] LockWatch<T>);
+
+impl<T: 'static + HWI + Send> From<LockWatch<T>> for Box<dyn HWI + Send> {
+    fn from(s: LockWatch<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockDevice, DeviceKind};
+    use bitcoin::{bip32::Fingerprint, psbt::Psbt, Network};
+    use std::sync::Arc;
+
+    fn device() -> MockDevice {
+        MockDevice {
+            fingerprint: Ok(Fingerprint::default()),
+            sign: Err(Error::UserRefused),
+            ..MockDevice::new(DeviceKind::Ledger)
+        }
+    }
+
+    #[test]
+    fn test_lock_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let watch = LockWatch::new(device(), move |e| recorded.lock().unwrap().push(e));
+        assert_eq!(watch.is_locked(), None);
+
+        // Errors unrelated to the lock state are ignored.
+        assert!(watch.get_version().is_err());
+        assert_eq!(watch.is_locked(), None);
+
+        watch
+            .device
+            .fail_with(Some(Error::DeviceLocked { code: None }));
+        assert!(watch.poll().unwrap());
+        assert!(watch.poll().unwrap());
+        watch.device.fail_with(None);
+        assert!(!watch.poll().unwrap());
+        // The user refusal tells that the device is still unlocked.
+        assert!(watch
+            .sign_tx(&mut Psbt::from_unsigned_tx(empty_tx()).unwrap())
+            .is_err());
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![LockEvent::DeviceLocked, LockEvent::DeviceUnlocked]
+        );
+        assert_eq!(watch.is_locked(), Some(false));
+    }
+
+    #[test]
+    fn test_local_settings() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let mut watch = LockWatch::new(device(), move |e| recorded.lock().unwrap().push(e));
+        watch
+            .device
+            .fail_with(Some(Error::DeviceLocked { code: None }));
+        assert!(watch.poll().unwrap());
+
+        watch.set_network(Network::Testnet).unwrap();
+        assert_eq!(watch.device.network, Some(Network::Testnet));
+        assert_eq!(watch.is_locked(), Some(true));
+        assert_eq!(*events.lock().unwrap(), vec![LockEvent::DeviceLocked]);
+    }
+
+    fn empty_tx() -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+}
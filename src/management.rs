@@ -0,0 +1,58 @@
+//! Onboarding and management operations of the devices exposing them over USB,
+//! so that the wallets guide the first setup without the vendor software.
+
+use crate::{Error, HWI};
+
+/// Options of a new seed, the device asks the user for what is not given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetupOptions {
+    /// Name displayed by the device.
+    pub name: Option<String>,
+    /// Number of words of the mnemonic, 12 or 24 for most devices.
+    pub words: Option<usize>,
+}
+
+/// Extension of HWI implemented by the devices with management operations.
+/// Every operation is confirmed by the user on the device, and the mnemonic
+/// never reaches the host. Unimplemented by default.
+pub trait Management: HWI {
+    /// Erases the seed and the settings of the device.
+    fn wipe_device(&self) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Generates a new seed on an uninitialized device, and starts its backup.
+    fn setup_device(&self, _options: &SetupOptions) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Restores a seed from a mnemonic entered on the device.
+    fn restore_from_mnemonic(&self, _options: &SetupOptions) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    /// Lets the user check the backup against the seed of the device.
+    fn check_backup(&self) -> Result<(), Error> {
+        Err(Error::UnimplementedMethod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockDevice, DeviceKind};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_management() {
+        let mock = MockDevice::new(DeviceKind::BitBox02);
+        let device: &dyn Management = &mock;
+        assert!(device.wipe_device().is_ok());
+        assert!(mock.wiped.load(Ordering::SeqCst));
+        assert!(matches!(
+            device.setup_device(&SetupOptions::default()),
+            Err(Error::UnimplementedMethod)
+        ));
+        assert!(matches!(
+            device.check_backup(),
+            Err(Error::UnimplementedMethod)
+        ));
+    }
+}
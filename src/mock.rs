@@ -0,0 +1,132 @@
+//! Configurable device shared by the tests of the wrappers.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{DerivationPath, Fingerprint, Xpriv, Xpub},
+    psbt::Psbt,
+    secp256k1::Secp256k1,
+    Address, Network,
+};
+
+use crate::{
+    management::Management, AddressScript, DeviceKind, Error, SignResult, Version,
+    WalletRegistration, HWI,
+};
+
+/// Device answering with the configured results.
+///
+/// Every method is unimplemented unless its result is set. With a seed, the fingerprint and the
+/// xpubs are derived from it.
+#[derive(Debug)]
+pub(crate) struct MockDevice {
+    pub kind: DeviceKind,
+    pub version: Result<Version, Error>,
+    pub seed: Option<Xpriv>,
+    pub fingerprint: Result<Fingerprint, Error>,
+    pub register: Result<WalletRegistration, Error>,
+    pub address: Result<Address<NetworkUnchecked>, Error>,
+    pub sign: Result<SignResult, Error>,
+    /// Time the user takes to check the address.
+    pub delay: Duration,
+    /// Error returned instead of the fingerprint, for a locked or disconnected device.
+    pub failure: Mutex<Option<Error>>,
+    /// Paths of the derived xpubs.
+    pub queried: Mutex<Vec<DerivationPath>>,
+    pub display_xpub: bool,
+    pub wiped: AtomicBool,
+    pub network: Option<Network>,
+}
+
+impl MockDevice {
+    pub fn new(kind: DeviceKind) -> Self {
+        MockDevice {
+            kind,
+            version: Err(Error::UnimplementedMethod),
+            seed: None,
+            fingerprint: Err(Error::UnimplementedMethod),
+            register: Err(Error::UnimplementedMethod),
+            address: Err(Error::UnimplementedMethod),
+            sign: Err(Error::UnimplementedMethod),
+            delay: Duration::ZERO,
+            failure: Mutex::new(None),
+            queried: Mutex::new(Vec::new()),
+            display_xpub: false,
+            wiped: AtomicBool::new(false),
+            network: None,
+        }
+    }
+
+    pub fn with_seed(kind: DeviceKind, seed: Xpriv) -> Self {
+        MockDevice {
+            seed: Some(seed),
+            ..MockDevice::new(kind)
+        }
+    }
+
+    pub fn fail_with(&self, failure: Option<Error>) {
+        *self.failure.lock().unwrap() = failure;
+    }
+}
+
+impl HWI for MockDevice {
+    fn device_kind(&self) -> DeviceKind {
+        self.kind
+    }
+    fn get_version(&self) -> Result<Version, Error> {
+        self.version.clone()
+    }
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, Error> {
+        if let Some(e) = self.failure.lock().unwrap().clone() {
+            return Err(e);
+        }
+        match &self.seed {
+            Some(seed) => Ok(seed.fingerprint(&Secp256k1::new())),
+            None => self.fingerprint.clone(),
+        }
+    }
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+        let seed = self.seed.as_ref().ok_or(Error::UnimplementedMethod)?;
+        self.queried.lock().unwrap().push(path.clone());
+        let secp = Secp256k1::new();
+        let xpriv = seed
+            .derive_priv(&secp, path)
+            .map_err(|e| Error::Device(e.to_string()))?;
+        Ok(Xpub::from_priv(&secp, &xpriv))
+    }
+    fn displays_xpub(&self, _path: &DerivationPath) -> bool {
+        self.display_xpub
+    }
+    fn register_wallet(&self, _name: &str, _policy: &str) -> Result<WalletRegistration, Error> {
+        self.register.clone()
+    }
+    fn is_wallet_registered(&self, _name: &str, _policy: &str) -> Result<bool, Error> {
+        Err(Error::UnimplementedMethod)
+    }
+    fn display_address(&self, _script: &AddressScript) -> Result<Address<NetworkUnchecked>, Error> {
+        thread::sleep(self.delay);
+        self.address.clone()
+    }
+    fn sign_tx(&self, _tx: &mut Psbt) -> Result<SignResult, Error> {
+        self.sign.clone()
+    }
+    fn set_network(&mut self, network: Network) -> Result<(), Error> {
+        self.network = Some(network);
+        Ok(())
+    }
+}
+
+impl Management for MockDevice {
+    fn wipe_device(&self) -> Result<(), Error> {
+        self.wiped.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
@@ -0,0 +1,785 @@
+use std::{ops::Range, str::FromStr};
+
+use bitcoin::{
+    bip32::{ChainCode, ChildNumber, Fingerprint, KeySource, Xpub},
+    hashes::{sha256, Hash},
+    secp256k1::{Parity, PublicKey, XOnlyPublicKey},
+    Network,
+};
+
+use crate::{utils, Error};
+
+/// Point H of BIP-341 with no known discrete logarithm, the x-only internal key of the
+/// taproot policies without key path.
+pub const NUMS_POINT: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Internal key of a taproot policy that cannot sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnspendableKey {
+    /// Key @i, an xpub of NUMS_POINT without origin: the derived keys are H + tG with a
+    /// public t, provably unspendable. The devices take it like any key without origin.
+    Xpub(usize),
+    /// NUMS_POINT itself, the same for all the addresses. Wallet policies only have
+    /// derived keys, the devices registering them reject it.
+    Constant,
+}
+
+/// Key of a wallet policy, with its origin if the descriptor has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyKey {
+    pub origin: Option<KeySource>,
+    pub xpub: Xpub,
+}
+
+impl PolicyKey {
+    /// Unspendable key to use as internal key of a policy of the keys: an xpub of
+    /// NUMS_POINT whose chain code is the hash of the keys, so that the wallets do not
+    /// share their internal keys.
+    pub fn unspendable(keys: &[PolicyKey]) -> Self {
+        let mut data = Vec::with_capacity(33 * keys.len());
+        for key in keys {
+            data.extend_from_slice(&key.xpub.public_key.serialize());
+        }
+        let nums = XOnlyPublicKey::from_str(NUMS_POINT).expect("valid point");
+        Self {
+            origin: None,
+            xpub: Xpub {
+                network: keys
+                    .first()
+                    .map_or(Network::Bitcoin, |key| key.xpub.network),
+                depth: 0,
+                parent_fingerprint: Fingerprint::default(),
+                child_number: ChildNumber::Normal { index: 0 },
+                public_key: PublicKey::from_x_only_public_key(nums, Parity::Even),
+                chain_code: ChainCode::from(sha256::Hash::hash(&data).to_byte_array()),
+            },
+        }
+    }
+
+    /// True for an xpub of NUMS_POINT without origin.
+    pub fn is_unspendable(&self) -> bool {
+        self.origin.is_none()
+            && self.xpub.public_key.x_only_public_key().0.to_string() == NUMS_POINT
+    }
+}
+
+impl FromStr for PolicyKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (origin, xpub) = utils::parse_key_with_origin(s)?;
+        Ok(PolicyKey { origin, xpub })
+    }
+}
+
+impl std::fmt::Display for PolicyKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some((fg, path)) = &self.origin {
+            write!(f, "[{}{}]", fg, path.to_string().trim_start_matches('m'))?;
+        }
+        write!(f, "{}", self.xpub)
+    }
+}
+
+/// BIP388 wallet policy: a descriptor template where the keys are replaced by
+/// their index @i in the ordered list of keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletPolicy {
+    pub name: String,
+    pub template: String,
+    pub keys: Vec<PolicyKey>,
+}
+
+impl WalletPolicy {
+    pub fn new(name: impl Into<String>, template: impl Into<String>, keys: Vec<PolicyKey>) -> Self {
+        Self {
+            name: name.into(),
+            template: template.into(),
+            keys,
+        }
+    }
+
+    /// Parses a descriptor, the keys are numbered in order of first appearance
+    /// and the checksum, if any, is verified.
+    pub fn from_descriptor(name: impl Into<String>, descriptor: &str) -> Result<Self, Error> {
+        let (template, keys) = utils::extract_keys_and_template::<String>(descriptor)?;
+        let keys = keys
+            .iter()
+            .map(|key| PolicyKey::from_str(key))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(name, template, keys))
+    }
+
+    /// Descriptor of the policy, without checksum.
+    /// Fails if the template uses a key index out of the keys.
+    pub fn to_descriptor(&self) -> Result<String, Error> {
+        let mut descriptor = String::with_capacity(self.template.len());
+        let mut end = 0;
+        for (range, index) in placeholders(&self.template) {
+            let key = index.and_then(|i| self.keys.get(i)).ok_or_else(|| {
+                Error::InvalidParameter(
+                    "policy",
+                    format!("no key {} in the policy", &self.template[range.clone()]),
+                )
+            })?;
+            descriptor.push_str(&self.template[end..range.start]);
+            descriptor.push_str(&key.to_string());
+            end = range.end;
+        }
+        descriptor.push_str(&self.template[end..]);
+        Ok(descriptor)
+    }
+
+    /// Checks the device of the fingerprint can sign for the policy: one of the keys
+    /// has its origin, and the template uses every key with the derivations /** or
+    /// /<M;N>/*, the indexes of a key being all distinct.
+    pub fn check_for_device(&self, fingerprint: Fingerprint) -> Result<(), Error> {
+        self.tap_leaves()?;
+        if !self
+            .keys
+            .iter()
+            .any(|key| matches!(key.origin, Some((fg, _)) if fg == fingerprint))
+        {
+            return Err(Error::PolicyMismatch(format!(
+                "no key of the device {} in the policy",
+                fingerprint
+            )));
+        }
+        let mut used = vec![Vec::new(); self.keys.len()];
+        for (range, index) in placeholders(&self.template) {
+            let placeholder = &self.template[range.clone()];
+            let derivations = index.and_then(|i| used.get_mut(i)).ok_or_else(|| {
+                Error::PolicyMismatch(format!("no key {} in the policy", placeholder))
+            })?;
+            let derivation = self.template[range.end..]
+                .split([',', ')'])
+                .next()
+                .unwrap_or_default();
+            let (receive, change) = parse_multipath(derivation).ok_or_else(|| {
+                Error::PolicyMismatch(format!(
+                    "key {} has the derivation {}, expected /** or /<M;N>/*",
+                    placeholder, derivation
+                ))
+            })?;
+            if receive == change || derivations.contains(&receive) || derivations.contains(&change)
+            {
+                return Err(Error::PolicyMismatch(format!(
+                    "key {} is used twice with the index {} of {}",
+                    placeholder,
+                    if derivations.contains(&receive) {
+                        receive
+                    } else {
+                        change
+                    },
+                    derivation
+                )));
+            }
+            derivations.extend([receive, change]);
+        }
+        match used.iter().position(Vec::is_empty) {
+            Some(i) => Err(Error::PolicyMismatch(format!(
+                "key @{} is not used by the template",
+                i
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Script templates of the leaves of a taproot policy, in the order of the tree.
+    /// Empty for a policy without script path. Fails if the tree is not made of
+    /// branches {A,B}.
+    pub fn tap_leaves(&self) -> Result<Vec<&str>, Error> {
+        let mut leaves = Vec::new();
+        let tr = self
+            .template
+            .strip_prefix("tr(")
+            .and_then(|s| s.strip_suffix(')'));
+        if let Some((_, tree)) = tr.and_then(split_branch) {
+            collect_leaves(tree, &mut leaves)?;
+        }
+        Ok(leaves)
+    }
+
+    /// Unspendable internal key of a taproot policy, None if the policy has a key path.
+    pub fn unspendable_internal_key(&self) -> Option<UnspendableKey> {
+        let tr = self.template.strip_prefix("tr(")?.strip_suffix(')')?;
+        let internal = split_branch(tr).map_or(tr, |(internal, _)| internal);
+        if internal == NUMS_POINT || internal.strip_prefix("02") == Some(NUMS_POINT) {
+            return Some(UnspendableKey::Constant);
+        }
+        let index: usize = internal
+            .strip_prefix('@')?
+            .split('/')
+            .next()?
+            .parse()
+            .ok()?;
+        if self.keys.get(index)?.is_unspendable() {
+            Some(UnspendableKey::Xpub(index))
+        } else {
+            None
+        }
+    }
+
+    /// Fails if the devices cannot register the internal key of the policy.
+    pub fn check_internal_key(&self) -> Result<(), Error> {
+        if self.unspendable_internal_key() == Some(UnspendableKey::Constant) {
+            return Err(Error::InvalidParameter(
+                "policy",
+                "the internal key H is not derived, use the unspendable xpub of PolicyKey::unspendable"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Splits at the first comma outside of the fragments and branches.
+fn split_branch(s: &str) -> Option<(&str, &str)> {
+    let mut depth = 0i32;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => return Some((&s[..i], &s[i + 1..])),
+            _ => {}
+        }
+        if depth < 0 {
+            return None;
+        }
+    }
+    None
+}
+
+fn collect_leaves<'a>(tree: &'a str, leaves: &mut Vec<&'a str>) -> Result<(), Error> {
+    let invalid = || Error::InvalidParameter("policy", format!("invalid taproot tree {}", tree));
+    match tree.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+        Some(branch) => {
+            let (left, right) = split_branch(branch).ok_or_else(invalid)?;
+            collect_leaves(left, leaves)?;
+            collect_leaves(right, leaves)
+        }
+        None if tree.is_empty() || tree.contains(['{', '}']) => Err(invalid()),
+        None => {
+            leaves.push(tree);
+            Ok(())
+        }
+    }
+}
+
+/// Key of the policy built by PolicyBuilder, with the multipath derivation of its use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRef {
+    index: usize,
+    receive: u32,
+    change: u32,
+}
+
+impl KeyRef {
+    /// Derivation /<receive;change>/* instead of the default /**.
+    pub fn with_multipath(self, receive: u32, change: u32) -> Self {
+        Self {
+            receive,
+            change,
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for KeyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if (self.receive, self.change) == (0, 1) {
+            write!(f, "@{}/**", self.index)
+        } else {
+            write!(f, "@{}/<{};{}>/*", self.index, self.receive, self.change)
+        }
+    }
+}
+
+/// Miniscript fragments of the policies, the wrappers required by the
+/// miniscript types are added when the template is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Miniscript {
+    Pk(KeyRef),
+    Pkh(KeyRef),
+    /// multi, or multi_a in a taproot leaf.
+    Multi(usize, Vec<KeyRef>),
+    /// sortedmulti, or sortedmulti_a in a taproot leaf.
+    SortedMulti(usize, Vec<KeyRef>),
+    Older(u32),
+    After(u32),
+    AndV(Box<Miniscript>, Box<Miniscript>),
+    OrD(Box<Miniscript>, Box<Miniscript>),
+    Thresh(usize, Vec<Miniscript>),
+}
+
+impl Miniscript {
+    pub fn and_v(left: Miniscript, right: Miniscript) -> Self {
+        Miniscript::AndV(Box::new(left), Box::new(right))
+    }
+
+    pub fn or_d(left: Miniscript, right: Miniscript) -> Self {
+        Miniscript::OrD(Box::new(left), Box::new(right))
+    }
+
+    fn write(&self, out: &mut String, tap: bool) {
+        let keys = |keys: &[KeyRef]| {
+            keys.iter()
+                .map(|k| k.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let suffix = if tap { "_a" } else { "" };
+        match self {
+            Miniscript::Pk(k) => out.push_str(&format!("pk({})", k)),
+            Miniscript::Pkh(k) => out.push_str(&format!("pkh({})", k)),
+            Miniscript::Multi(t, k) => out.push_str(&format!("multi{}({},{})", suffix, t, keys(k))),
+            Miniscript::SortedMulti(t, k) => {
+                out.push_str(&format!("sortedmulti{}({},{})", suffix, t, keys(k)))
+            }
+            Miniscript::Older(n) => out.push_str(&format!("older({})", n)),
+            Miniscript::After(n) => out.push_str(&format!("after({})", n)),
+            Miniscript::AndV(left, right) => {
+                out.push_str("and_v(v:");
+                left.write(out, tap);
+                out.push(',');
+                right.write(out, tap);
+                out.push(')');
+            }
+            Miniscript::OrD(left, right) => {
+                out.push_str("or_d(");
+                left.write(out, tap);
+                out.push(',');
+                right.write(out, tap);
+                out.push(')');
+            }
+            Miniscript::Thresh(t, subs) => {
+                out.push_str(&format!("thresh({}", t));
+                for (i, sub) in subs.iter().enumerate() {
+                    out.push_str(if i == 0 { "," } else { ",a:" });
+                    sub.write(out, tap);
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// Taproot script tree of the policies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapTree {
+    Leaf(Miniscript),
+    Branch(Box<TapTree>, Box<TapTree>),
+}
+
+impl TapTree {
+    fn write(&self, out: &mut String) {
+        match self {
+            TapTree::Leaf(ms) => ms.write(out, true),
+            TapTree::Branch(left, right) => {
+                out.push('{');
+                left.write(out);
+                out.push(',');
+                right.write(out);
+                out.push('}');
+            }
+        }
+    }
+}
+
+/// Script of the policies built by PolicyBuilder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyScript {
+    Wsh(Miniscript),
+    ShWsh(Miniscript),
+    Tr(KeyRef, Option<TapTree>),
+}
+
+/// Builds a wallet policy from its keys and a typed script, instead of formatting
+/// the descriptor template by hand.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyBuilder {
+    keys: Vec<PolicyKey>,
+}
+
+impl PolicyBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reference to the key in the template, a key added again gets the same index.
+    pub fn key(&mut self, key: PolicyKey) -> KeyRef {
+        let index = match self.keys.iter().position(|k| *k == key) {
+            Some(index) => index,
+            None => {
+                self.keys.push(key);
+                self.keys.len() - 1
+            }
+        };
+        KeyRef {
+            index,
+            receive: 0,
+            change: 1,
+        }
+    }
+
+    pub fn build(self, name: impl Into<String>, script: &PolicyScript) -> WalletPolicy {
+        let mut template = String::new();
+        match script {
+            PolicyScript::Wsh(ms) => {
+                template.push_str("wsh(");
+                ms.write(&mut template, false);
+                template.push(')');
+            }
+            PolicyScript::ShWsh(ms) => {
+                template.push_str("sh(wsh(");
+                ms.write(&mut template, false);
+                template.push_str("))");
+            }
+            PolicyScript::Tr(key, tree) => {
+                template.push_str(&format!("tr({}", key));
+                if let Some(tree) = tree {
+                    template.push(',');
+                    tree.write(&mut template);
+                }
+                template.push(')');
+            }
+        }
+        WalletPolicy::new(name, template, self.keys)
+    }
+}
+
+/// Receive and change indexes of the derivation /** or /<M;N>/* of a key placeholder.
+fn parse_multipath(derivation: &str) -> Option<(u32, u32)> {
+    if derivation == "/**" {
+        return Some((0, 1));
+    }
+    let (receive, change) = derivation
+        .strip_prefix("/<")?
+        .strip_suffix(">/*")?
+        .split_once(';')?;
+    let index = |s: &str| {
+        s.parse::<u32>()
+            .ok()
+            .filter(|i| ChildNumber::from_normal_idx(*i).is_ok())
+    };
+    Some((index(receive)?, index(change)?))
+}
+
+/// Positions of the key placeholders @i in the template, with their index.
+fn placeholders(template: &str) -> Vec<(Range<usize>, Option<usize>)> {
+    let mut placeholders = Vec::new();
+    let bytes = template.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'@' {
+            let start = i;
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+            placeholders.push((start..i, template[start + 1..i].parse().ok()));
+        } else {
+            i += 1;
+        }
+    }
+    placeholders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wallet_policy() {
+        let descriptor = "wsh(or_d(pk([f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**),and_v(v:pkh(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/**),older(100))))";
+        let policy = WalletPolicy::from_descriptor("Liana", descriptor).unwrap();
+        assert_eq!(
+            policy.template,
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@1/**),older(100))))"
+        );
+        assert!(policy.keys[0].origin.is_some());
+        assert!(policy.keys[1].origin.is_none());
+        assert_eq!(policy.to_descriptor().unwrap(), descriptor);
+
+        // Indexes of more than one digit.
+        let keys = vec![policy.keys[1].clone(); 11];
+        let policy = WalletPolicy::new("", "wsh(multi(1,@10/**,@1/**))", keys);
+        assert_eq!(policy.to_descriptor().unwrap().matches("tpub").count(), 2);
+
+        let policy = WalletPolicy::new("", "wsh(multi(1,@0/**,@2/**))", policy.keys[..2].to_vec());
+        assert!(policy.to_descriptor().is_err());
+    }
+
+    #[test]
+    fn test_policy_builder() {
+        let primary = PolicyKey::from_str("[f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP").unwrap();
+        let recovery = PolicyKey::from_str("tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S").unwrap();
+
+        let mut builder = PolicyBuilder::new();
+        let a = builder.key(primary.clone());
+        let b = builder.key(recovery.clone());
+        let policy = builder.build(
+            "Liana",
+            &PolicyScript::Wsh(Miniscript::or_d(
+                Miniscript::Pk(a),
+                Miniscript::and_v(Miniscript::Pkh(b), Miniscript::Older(100)),
+            )),
+        );
+        assert_eq!(
+            policy,
+            WalletPolicy::from_descriptor("Liana", "wsh(or_d(pk([f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**),and_v(v:pkh(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/**),older(100))))").unwrap()
+        );
+
+        let mut builder = PolicyBuilder::new();
+        let a = builder.key(primary.clone());
+        let b = builder.key(recovery);
+        let a2 = builder.key(primary).with_multipath(2, 3);
+        let policy = builder.build(
+            "",
+            &PolicyScript::Tr(
+                a,
+                Some(TapTree::Branch(
+                    Box::new(TapTree::Leaf(Miniscript::Multi(1, vec![a2, b]))),
+                    Box::new(TapTree::Leaf(Miniscript::Thresh(
+                        2,
+                        vec![Miniscript::Pkh(b), Miniscript::Older(10)],
+                    ))),
+                )),
+            ),
+        );
+        assert_eq!(
+            policy.template,
+            "tr(@0/**,{multi_a(1,@0/<2;3>/*,@1/**),thresh(2,pkh(@1/**),a:older(10))})"
+        );
+        assert_eq!(policy.keys.len(), 2);
+    }
+
+    #[test]
+    fn test_check_for_device() {
+        let key = |origin: &str| {
+            PolicyKey::from_str(&format!("{}tpubDEvZxV86Br8Knbm9tWcr5Hvmg5cYTYsg92vinqH6Bie6U8ix8CsoN9W11NQygdqVwmHUJpsHXxNsi5gXn36g4xNfLWkMqPuFhRZAmMQ7jjQ", origin)).unwrap()
+        };
+        let keys = vec![key("[b0822927/48'/1'/0'/2']"), key("")];
+        let fg = Fingerprint::from_str("b0822927").unwrap();
+        let check =
+            |template: &str, fg| WalletPolicy::new("", template, keys.clone()).check_for_device(fg);
+
+        assert!(check("wsh(multi(1,@0/**,@1/**))", fg).is_ok());
+        // The same key with distinct derivations.
+        assert!(check(
+            "wsh(or_d(multi(1,@0/<0;1>/*,@1/**),and_v(v:pk(@0/<2;3>/*),older(10))))",
+            fg
+        )
+        .is_ok());
+        assert!(matches!(
+            check(
+                "wsh(multi(1,@0/**,@1/**))",
+                Fingerprint::from_str("7fc39c07").unwrap()
+            ),
+            Err(Error::PolicyMismatch(_))
+        ));
+        assert!(matches!(
+            check("wsh(pk(@0/**))", fg),
+            Err(Error::PolicyMismatch(_))
+        ));
+        assert!(matches!(
+            check("wsh(multi(1,@0/**,@1/**,@0/**))", fg),
+            Err(Error::PolicyMismatch(_))
+        ));
+        assert!(matches!(
+            check("wsh(multi(1,@0/**,@1/**,@2/**))", fg),
+            Err(Error::PolicyMismatch(_))
+        ));
+        // The same indexes written differently, or shared by two derivations.
+        for template in [
+            "wsh(or_d(pk(@0/**),and_v(v:pk(@0/<0;1>/*),pk(@1/**))))",
+            "wsh(or_d(pk(@0/<0;1>/*),and_v(v:pk(@0/<1;2>/*),pk(@1/**))))",
+            "wsh(multi(1,@0/<2;2>/*,@1/**))",
+            "wsh(multi(1,@0/<0;1>,@1/**))",
+            "wsh(multi(1,@0/<0';1'>/*,@1/**))",
+            "wsh(multi(1,@0/0/*,@1/**))",
+        ] {
+            assert!(matches!(check(template, fg), Err(Error::PolicyMismatch(_))));
+        }
+        assert!(check("tr(@0/**,{pk(@1/**),pk(@0/<2;3>/*)})", fg).is_ok());
+        assert!(matches!(
+            check("tr(@0/**,{pk(@1/**)},{pk(@0/<2;3>/*)})", fg),
+            Err(Error::InvalidParameter(..))
+        ));
+    }
+
+    #[test]
+    fn test_tap_leaves() {
+        let descriptor = "tr([f5acc2fd/86'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**,{and_v(v:pk(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/**),older(100)),{multi_a(1,[f5acc2fd/86'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/<2;3>/*,tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/<2;3>/*),pk(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/<4;5>/*)}})";
+        let policy = WalletPolicy::from_descriptor("", descriptor).unwrap();
+        assert_eq!(policy.keys.len(), 2);
+        assert_eq!(
+            policy.tap_leaves().unwrap(),
+            vec![
+                "and_v(v:pk(@1/**),older(100))",
+                "multi_a(1,@0/<2;3>/*,@1/<2;3>/*)",
+                "pk(@1/<4;5>/*)"
+            ]
+        );
+        assert_eq!(policy.to_descriptor().unwrap(), descriptor);
+        policy
+            .check_for_device(Fingerprint::from_str("f5acc2fd").unwrap())
+            .unwrap();
+
+        let leaves = |template| {
+            WalletPolicy::new("", template, Vec::new())
+                .tap_leaves()
+                .map(|leaves| leaves.join(" "))
+        };
+        assert_eq!(leaves("tr(@0/**)").unwrap(), "");
+        assert_eq!(leaves("wsh(pk(@0/**))").unwrap(), "");
+        assert_eq!(leaves("tr(@0/**,pk(@1/**))").unwrap(), "pk(@1/**)");
+        assert!(leaves("tr(@0/**,{pk(@1/**)})").is_err());
+        assert!(leaves("tr(@0/**,{pk(@1/**),})").is_err());
+    }
+
+    #[test]
+    fn test_unspendable_internal_key() {
+        let keys: Vec<PolicyKey> = [
+            "[f5acc2fd/86'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP",
+            "tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S",
+        ]
+        .iter()
+        .map(|key| PolicyKey::from_str(key).unwrap())
+        .collect();
+        let unspendable = PolicyKey::unspendable(&keys);
+        assert!(unspendable.is_unspendable());
+        assert!(!keys[0].is_unspendable() && !keys[1].is_unspendable());
+        assert_eq!(unspendable.xpub.network, Network::Testnet);
+        assert_eq!(unspendable, PolicyKey::unspendable(&keys));
+        assert_ne!(unspendable.xpub, PolicyKey::unspendable(&keys[..1]).xpub);
+        assert_eq!(
+            PolicyKey::from_str(&unspendable.to_string()).unwrap(),
+            unspendable
+        );
+
+        let descriptor = format!(
+            "tr({}/**,{{pk({}/**),pk({}/**)}})",
+            unspendable, keys[0], keys[1]
+        );
+        let policy = WalletPolicy::from_descriptor("", &descriptor).unwrap();
+        assert_eq!(policy.template, "tr(@0/**,{pk(@1/**),pk(@2/**)})");
+        assert_eq!(
+            policy.unspendable_internal_key(),
+            Some(UnspendableKey::Xpub(0))
+        );
+        policy.check_internal_key().unwrap();
+        policy
+            .check_for_device(Fingerprint::from_str("f5acc2fd").unwrap())
+            .unwrap();
+
+        for internal in [NUMS_POINT.to_string(), format!("02{}", NUMS_POINT)] {
+            let descriptor = format!("tr({},pk({}/**))", internal, keys[0]);
+            let policy = WalletPolicy::from_descriptor("", &descriptor).unwrap();
+            assert_eq!(
+                policy.unspendable_internal_key(),
+                Some(UnspendableKey::Constant)
+            );
+            assert!(policy.check_internal_key().is_err());
+        }
+
+        let policy = WalletPolicy::new(
+            "",
+            "tr(@0/**,pk(@1/**))",
+            vec![keys[1].clone(), keys[0].clone()],
+        );
+        assert_eq!(policy.unspendable_internal_key(), None);
+        let policy = WalletPolicy::new("", "wsh(pk(@0/**))", vec![unspendable]);
+        assert_eq!(policy.unspendable_internal_key(), None);
+    }
+
+    /// Deterministic xorshift generator, the malformed descriptors are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Mutates the descriptor: removes, repeats or inserts characters at random,
+    /// including the delimiters and multi-byte characters.
+    fn mutate(rng: &mut Rng, descriptor: &str) -> String {
+        const INSERTED: [&str; 14] = [
+            "(", ")", "{", "}", "[", "]", "/", "<", ";", ",", "@", "#", "'", "é",
+        ];
+        let mut chars: Vec<String> = descriptor.chars().map(String::from).collect();
+        for _ in 0..=rng.below(4) {
+            let i = rng.below(chars.len() + 1);
+            match rng.below(4) {
+                0 if i < chars.len() => {
+                    let end = (i + rng.below(8)).min(chars.len());
+                    chars.drain(i..end);
+                }
+                1 if i < chars.len() => {
+                    let end = (i + rng.below(16)).min(chars.len());
+                    let repeated = chars[i..end].to_vec();
+                    chars.splice(i..i, repeated);
+                }
+                2 => chars.truncate(i),
+                _ => chars.insert(i, INSERTED[rng.below(INSERTED.len())].to_string()),
+            }
+        }
+        chars.concat()
+    }
+
+    /// Malformed descriptors, as sent by an untrusted coordinator, fail with an error
+    /// and never panic.
+    #[test]
+    fn test_malformed_descriptors() {
+        let key = "[f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP";
+        let other = "tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S";
+        let seeds = [
+            format!(
+                "wsh(or_d(pk({}/**),and_v(v:pkh({}/<0;1>/*),older(100))))",
+                key, other
+            ),
+            format!(
+                "tr({}/**,{{pk({}/**),and_v(v:pk({}/**),older(6))}})",
+                other, key, other
+            ),
+            format!("sh(wsh(sortedmulti(2,{}/0/*,{}/1/*)))", key, other),
+        ];
+        let fingerprint = Fingerprint::from_str("f5acc2fd").unwrap();
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for i in 0..400 {
+            let seed = &seeds[i % seeds.len()];
+            let descriptor = mutate(&mut rng, seed);
+            let descriptor = match rng.below(3) {
+                0 => format!(
+                    "{}#{}",
+                    descriptor,
+                    utils::descriptor_checksum(&descriptor).unwrap_or_default()
+                ),
+                _ => descriptor,
+            };
+            let _ = utils::extract_keys_and_template::<String>(&descriptor);
+            #[cfg(feature = "bitbox")]
+            {
+                let _ = crate::bitbox::extract_script_config_policy(&descriptor);
+                let _ = crate::bitbox::extract_first_appended_derivation_with_some_wildcard(
+                    0,
+                    &descriptor,
+                );
+            }
+            if let Ok(policy) = WalletPolicy::from_descriptor("fuzz", &descriptor) {
+                let _ = policy.to_descriptor();
+                let _ = policy.tap_leaves();
+                let _ = policy.check_for_device(fingerprint);
+            }
+            let _ = WalletPolicy::new("fuzz", descriptor.clone(), Vec::new()).tap_leaves();
+            for part in descriptor.split(['(', ')', ',']) {
+                let _ = PolicyKey::from_str(part);
+                let _ = crate::bip389::parse_xkey_deriv(part);
+            }
+        }
+    }
+}
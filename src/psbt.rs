@@ -0,0 +1,754 @@
+use std::collections::BTreeMap;
+
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource},
+    hashes::Hash,
+    opcodes::all::OP_CHECKMULTISIG,
+    psbt::{Output, Psbt},
+    script::Builder,
+    secp256k1::{self, Secp256k1},
+    PubkeyHash, PublicKey, ScriptBuf, WPubkeyHash, XOnlyPublicKey,
+};
+
+use crate::{policy::WalletPolicy, utils, Error};
+
+/// Wallet of the device signing the psbt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletPolicyInfo {
+    pub fingerprint: Fingerprint,
+    /// Origin paths of the device keys of the policy, None for a single key wallet.
+    pub key_paths: Option<Vec<DerivationPath>>,
+}
+
+impl WalletPolicyInfo {
+    pub fn single_key(fingerprint: Fingerprint) -> Self {
+        Self {
+            fingerprint,
+            key_paths: None,
+        }
+    }
+
+    /// Fails if the policy has no key with the origin of the device.
+    pub fn with_policy(fingerprint: Fingerprint, policy: &str) -> Result<Self, Error> {
+        let (_, keys) = utils::extract_keys_and_template::<String>(policy)?;
+        let mut key_paths = Vec::new();
+        for key in keys {
+            if let (Some((fg, path)), _) = utils::parse_key_with_origin(&key)? {
+                if fg == fingerprint {
+                    key_paths.push(path);
+                }
+            }
+        }
+        if key_paths.is_empty() {
+            return Err(Error::InvalidParameter(
+                "policy",
+                format!("no key of the device {} in the policy", fingerprint),
+            ));
+        }
+        Ok(Self {
+            fingerprint,
+            key_paths: Some(key_paths),
+        })
+    }
+}
+
+/// Input the device would fail to sign, or sign without being able to verify it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreflightError {
+    /// The input has neither witness_utxo nor non_witness_utxo.
+    MissingUtxo { input: usize },
+    /// The non_witness_utxo is not the transaction spent by the input,
+    /// or its output differs from the witness_utxo.
+    UtxoMismatch { input: usize },
+    /// No key derivation of the input has the fingerprint of the device.
+    NoDeviceKey { input: usize },
+    /// The device key of the input is not derived from a key of the policy.
+    KeyNotInPolicy { input: usize, path: DerivationPath },
+}
+
+impl std::fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PreflightError::MissingUtxo { input } => write!(
+                f,
+                "Input {} has no previous output, add its witness_utxo or non_witness_utxo",
+                input
+            ),
+            PreflightError::UtxoMismatch { input } => write!(
+                f,
+                "Previous transaction of input {} does not match its outpoint or witness_utxo",
+                input
+            ),
+            PreflightError::NoDeviceKey { input } => write!(
+                f,
+                "Input {} has no key derivation of the device, update the psbt with the wallet descriptor",
+                input
+            ),
+            PreflightError::KeyNotInPolicy { input, path } => write!(
+                f,
+                "Key {} of input {} is not a key of the registered policy",
+                path, input
+            ),
+        }
+    }
+}
+
+/// Checks the psbt has what the device requires to sign all its inputs, the devices
+/// otherwise reject it with an error rarely pointing at the faulty input.
+pub fn validate_for_signing(
+    psbt: &Psbt,
+    wallet: &WalletPolicyInfo,
+) -> Result<(), Vec<PreflightError>> {
+    let mut errors = Vec::new();
+    for (i, (txin, input)) in psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(psbt.inputs.iter())
+        .enumerate()
+    {
+        match (&input.witness_utxo, &input.non_witness_utxo) {
+            (None, None) => errors.push(PreflightError::MissingUtxo { input: i }),
+            (utxo, Some(tx)) => {
+                let spent = tx.output.get(txin.previous_output.vout as usize);
+                if tx.txid() != txin.previous_output.txid
+                    || spent.is_none()
+                    || utxo.as_ref().is_some_and(|utxo| Some(utxo) != spent)
+                {
+                    errors.push(PreflightError::UtxoMismatch { input: i });
+                }
+            }
+            (Some(_), None) => {}
+        }
+
+        let paths: Vec<&DerivationPath> = input
+            .bip32_derivation
+            .values()
+            .chain(input.tap_key_origins.values().map(|(_, origin)| origin))
+            .filter(|(fg, _)| *fg == wallet.fingerprint)
+            .map(|(_, path)| path)
+            .collect();
+        if paths.is_empty() {
+            errors.push(PreflightError::NoDeviceKey { input: i });
+        }
+        if let Some(key_paths) = &wallet.key_paths {
+            for path in paths {
+                if !key_paths.iter().any(|origin| is_derived_from(path, origin)) {
+                    errors.push(PreflightError::KeyNotInPolicy {
+                        input: i,
+                        path: path.clone(),
+                    });
+                }
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Merges the signatures of the copies of the psbt signed by each device into the first
+/// one: partial_sigs, tap_script_sigs and tap_key_sig. Fails if a copy is not a psbt of the
+/// same unsigned transaction.
+pub fn combine(signed_parts: impl IntoIterator<Item = Psbt>) -> Result<Psbt, Error> {
+    let mut parts = signed_parts.into_iter();
+    let mut combined = parts
+        .next()
+        .ok_or_else(|| Error::InvalidParameter("psbt", "no psbt to combine".to_string()))?;
+    for (i, part) in parts.enumerate() {
+        if part.unsigned_tx != combined.unsigned_tx || part.inputs.len() != combined.inputs.len() {
+            return Err(Error::InvalidParameter(
+                "psbt",
+                format!("psbt {} is not a copy of the same transaction", i + 1),
+            ));
+        }
+        utils::merge_signatures(&mut combined, &part);
+    }
+    Ok(combined)
+}
+
+/// Output of the psbt paying to an address of the wallet policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalletOutput {
+    pub output: usize,
+    /// True for the change addresses, false for the receive addresses.
+    pub change: bool,
+    pub index: u32,
+}
+
+/// Fills the outputs paying to the first `lookahead` receive and change addresses of the
+/// policy with the key derivations and the redeem or witness script the devices need to
+/// recognize them as change, instead of displaying them as payments, and returns the
+/// outputs of the wallet.
+/// The outputs with a key derivation of the policy are checked to pay to the address of
+/// the derivation, the devices would otherwise show a payment to a third party as change.
+/// Only the single key, multi and sortedmulti templates are supported.
+pub fn annotate_wallet_outputs(
+    psbt: &mut Psbt,
+    policy: &WalletPolicy,
+    lookahead: u32,
+) -> Result<Vec<WalletOutput>, Error> {
+    let template = Template::parse(&policy.template)?;
+    let secp = Secp256k1::verification_only();
+    let mut addresses = BTreeMap::new();
+    for index in 0..lookahead {
+        for change in [false, true] {
+            let address = template.derive(&secp, policy, change, index)?;
+            addresses.insert(address.script_pubkey.clone(), (change, index, address));
+        }
+    }
+
+    let mut outputs = Vec::new();
+    for (i, (txout, output)) in psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(psbt.outputs.iter_mut())
+        .enumerate()
+    {
+        let (change, index, address) = match template.derivation(policy, output)? {
+            Some((change, index)) => {
+                let address = template.derive(&secp, policy, change, index)?;
+                if address.script_pubkey != txout.script_pubkey {
+                    return Err(Error::PolicyMismatch(format!(
+                        "output {} has the key derivations of the {} address {} of the policy but does not pay to it",
+                        i,
+                        if change { "change" } else { "receive" },
+                        index
+                    )));
+                }
+                (change, index, address)
+            }
+            None => match addresses.get(&txout.script_pubkey) {
+                Some((change, index, address)) => (*change, *index, address.clone()),
+                None => continue,
+            },
+        };
+        address.annotate(output);
+        outputs.push(WalletOutput {
+            output: i,
+            change,
+            index,
+        });
+    }
+    Ok(outputs)
+}
+
+/// Script type of a template supported by annotate_wallet_outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Pkh,
+    Wpkh,
+    ShWpkh,
+    Tr,
+    Sh,
+    Wsh,
+    ShWsh,
+}
+
+/// Key placeholder @i/<receive;change>/* of a template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TemplateKey {
+    index: usize,
+    receive: u32,
+    change: u32,
+}
+
+impl TemplateKey {
+    fn parse(key: &str) -> Option<Self> {
+        let (index, derivation) = key.strip_prefix('@')?.split_once('/')?;
+        let (receive, change) = if derivation == "**" {
+            (0, 1)
+        } else {
+            let (receive, change) = derivation
+                .strip_prefix('<')?
+                .strip_suffix(">/*")?
+                .split_once(';')?;
+            (receive.parse().ok()?, change.parse().ok()?)
+        };
+        Some(Self {
+            index: index.parse().ok()?,
+            receive,
+            change,
+        })
+    }
+}
+
+/// Single key or multisig template, the threshold of a single key template is 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    shape: Shape,
+    threshold: usize,
+    sorted: bool,
+    keys: Vec<TemplateKey>,
+}
+
+/// Scripts of an address of the policy with the origins of its keys.
+#[derive(Debug, Clone)]
+struct WalletAddress {
+    script_pubkey: ScriptBuf,
+    redeem_script: Option<ScriptBuf>,
+    witness_script: Option<ScriptBuf>,
+    keys: Vec<(secp256k1::PublicKey, KeySource)>,
+    taproot: bool,
+}
+
+impl Template {
+    fn parse(template: &str) -> Result<Self, Error> {
+        let unsupported = || {
+            Error::InvalidParameter(
+                "policy",
+                format!("the outputs of {} cannot be derived", template),
+            )
+        };
+        let (shape, inner) = [
+            ("sh(wsh(", "))", Shape::ShWsh),
+            ("sh(wpkh(", "))", Shape::ShWpkh),
+            ("sh(", ")", Shape::Sh),
+            ("wsh(", ")", Shape::Wsh),
+            ("wpkh(", ")", Shape::Wpkh),
+            ("pkh(", ")", Shape::Pkh),
+            ("tr(", ")", Shape::Tr),
+        ]
+        .iter()
+        .find_map(|(prefix, suffix, shape)| {
+            let inner = template.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some((*shape, inner))
+        })
+        .ok_or_else(unsupported)?;
+        match shape {
+            Shape::Pkh | Shape::Wpkh | Shape::ShWpkh | Shape::Tr => Ok(Self {
+                shape,
+                threshold: 1,
+                sorted: false,
+                keys: vec![TemplateKey::parse(inner).ok_or_else(unsupported)?],
+            }),
+            Shape::Sh | Shape::Wsh | Shape::ShWsh => {
+                let (sorted, args) = match inner.strip_prefix("sortedmulti(") {
+                    Some(args) => (true, args),
+                    None => (false, inner.strip_prefix("multi(").ok_or_else(unsupported)?),
+                };
+                let mut args = args.strip_suffix(')').ok_or_else(unsupported)?.split(',');
+                let threshold = args
+                    .next()
+                    .and_then(|threshold| threshold.parse().ok())
+                    .ok_or_else(unsupported)?;
+                let keys = args
+                    .map(TemplateKey::parse)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(unsupported)?;
+                if threshold == 0 || threshold > keys.len() {
+                    return Err(unsupported());
+                }
+                Ok(Self {
+                    shape,
+                    threshold,
+                    sorted,
+                    keys,
+                })
+            }
+        }
+    }
+
+    /// Change and address index of the policy address the key derivations of the
+    /// output belong to, if any.
+    fn derivation(
+        &self,
+        policy: &WalletPolicy,
+        output: &Output,
+    ) -> Result<Option<(bool, u32)>, Error> {
+        let sources = output
+            .bip32_derivation
+            .values()
+            .chain(output.tap_key_origins.values().map(|(_, source)| source));
+        for (fg, path) in sources {
+            for key in &self.keys {
+                let origin = policy
+                    .keys
+                    .get(key.index)
+                    .and_then(|key| key.origin.as_ref());
+                let (origin_fg, origin_path) = match origin {
+                    Some(origin) => origin,
+                    None => continue,
+                };
+                if fg != origin_fg || !is_derived_from(path, origin_path) {
+                    continue;
+                }
+                let (branch, index) = match path[origin_path.len()..] {
+                    [ChildNumber::Normal { index: branch }, ChildNumber::Normal { index }] => {
+                        (branch, index)
+                    }
+                    _ => continue,
+                };
+                if branch == key.change {
+                    return Ok(Some((true, index)));
+                } else if branch == key.receive {
+                    return Ok(Some((false, index)));
+                }
+                return Err(Error::PolicyMismatch(format!(
+                    "key derivation {} is not a receive or change path of the policy",
+                    path
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    fn derive<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        policy: &WalletPolicy,
+        change: bool,
+        index: u32,
+    ) -> Result<WalletAddress, Error> {
+        let invalid = |e: bitcoin::bip32::Error| Error::InvalidParameter("policy", e.to_string());
+        let mut keys = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            let policy_key = policy.keys.get(key.index).ok_or_else(|| {
+                Error::InvalidParameter("policy", format!("no key @{} in the policy", key.index))
+            })?;
+            let children = [
+                ChildNumber::from_normal_idx(if change { key.change } else { key.receive })
+                    .map_err(invalid)?,
+                ChildNumber::from_normal_idx(index).map_err(invalid)?,
+            ];
+            let xpub = policy_key
+                .xpub
+                .derive_pub(secp, &children)
+                .map_err(invalid)?;
+            let (fg, path) = policy_key
+                .origin
+                .clone()
+                .unwrap_or_else(|| (policy_key.xpub.fingerprint(), DerivationPath::master()));
+            keys.push((xpub.public_key, (fg, path.extend(children))));
+        }
+
+        let mut address = WalletAddress {
+            script_pubkey: ScriptBuf::new(),
+            redeem_script: None,
+            witness_script: None,
+            keys,
+            taproot: self.shape == Shape::Tr,
+        };
+        let first = address.keys[0].0;
+        let p2wpkh = || ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&first.serialize()));
+        address.script_pubkey = match self.shape {
+            Shape::Pkh => ScriptBuf::new_p2pkh(&PubkeyHash::hash(&first.serialize())),
+            Shape::Wpkh => p2wpkh(),
+            Shape::ShWpkh => {
+                let redeem_script = p2wpkh();
+                let script_pubkey = ScriptBuf::new_p2sh(&redeem_script.script_hash());
+                address.redeem_script = Some(redeem_script);
+                script_pubkey
+            }
+            Shape::Tr => ScriptBuf::new_p2tr(secp, XOnlyPublicKey::from(first), None),
+            Shape::Sh | Shape::Wsh | Shape::ShWsh => {
+                let mut pubkeys: Vec<PublicKey> = address
+                    .keys
+                    .iter()
+                    .map(|(key, _)| PublicKey::new(*key))
+                    .collect();
+                if self.sorted {
+                    pubkeys.sort_by_key(|key| key.to_bytes());
+                }
+                let mut builder = Builder::new().push_int(self.threshold as i64);
+                for key in &pubkeys {
+                    builder = builder.push_key(key);
+                }
+                let multi = builder
+                    .push_int(pubkeys.len() as i64)
+                    .push_opcode(OP_CHECKMULTISIG)
+                    .into_script();
+                match self.shape {
+                    Shape::Sh => {
+                        let script_pubkey = ScriptBuf::new_p2sh(&multi.script_hash());
+                        address.redeem_script = Some(multi);
+                        script_pubkey
+                    }
+                    Shape::Wsh => {
+                        let script_pubkey = ScriptBuf::new_p2wsh(&multi.wscript_hash());
+                        address.witness_script = Some(multi);
+                        script_pubkey
+                    }
+                    _ => {
+                        let redeem_script = ScriptBuf::new_p2wsh(&multi.wscript_hash());
+                        let script_pubkey = ScriptBuf::new_p2sh(&redeem_script.script_hash());
+                        address.redeem_script = Some(redeem_script);
+                        address.witness_script = Some(multi);
+                        script_pubkey
+                    }
+                }
+            }
+        };
+        Ok(address)
+    }
+}
+
+impl WalletAddress {
+    fn annotate(&self, output: &mut Output) {
+        if self.redeem_script.is_some() {
+            output.redeem_script = self.redeem_script.clone();
+        }
+        if self.witness_script.is_some() {
+            output.witness_script = self.witness_script.clone();
+        }
+        for (key, source) in &self.keys {
+            if self.taproot {
+                let key = XOnlyPublicKey::from(*key);
+                output.tap_internal_key = Some(key);
+                output
+                    .tap_key_origins
+                    .insert(key, (Vec::new(), source.clone()));
+            } else {
+                output.bip32_derivation.insert(*key, source.clone());
+            }
+        }
+    }
+}
+
+/// Policy keys are derived with a change and an address index.
+fn is_derived_from(path: &DerivationPath, origin: &DerivationPath) -> bool {
+    path.len() == origin.len() + 2 && path[..origin.len()] == origin[..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, bip32::Xpub, transaction, Amount, OutPoint, ScriptBuf, Transaction,
+        TxIn, TxOut,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_validate_for_signing() {
+        let xpub = "tpubDEvZxV86Br8Knbm9tWcr5Hvmg5cYTYsg92vinqH6Bie6U8ix8CsoN9W11NQygdqVwmHUJpsHXxNsi5gXn36g4xNfLWkMqPuFhRZAmMQ7jjQ";
+        let fg = Fingerprint::from_str("b0822927").unwrap();
+        let policy = format!(
+            "wsh(or_d(pk([b0822927/48'/1'/0'/2']{}/<0;1>/*),and_v(v:pkh([7fc39c07/48'/1'/0'/2']tpubDEvjgXtrUuH3Qtkapny9aE8gN847xiXsf9MDM5XueGf9nrvStqAuBSva3ajGyTvtp8Ti55FvVXsgYSXuS1tQkBeopFuodx2hRUDmQbvKxbZ/<0;1>/*),older(100))))",
+            xpub
+        );
+        let wallet = WalletPolicyInfo::with_policy(fg, &policy).unwrap();
+        assert!(
+            WalletPolicyInfo::with_policy(Fingerprint::from_str("1a1ffd98").unwrap(), &policy)
+                .is_err()
+        );
+
+        let previous = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let txin = |vout| TxIn {
+            previous_output: OutPoint {
+                txid: previous.txid(),
+                vout,
+            },
+            ..Default::default()
+        };
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![txin(0), txin(1), txin(0)],
+            output: Vec::new(),
+        })
+        .unwrap();
+        let key = Xpub::from_str(xpub).unwrap().public_key;
+        let path = |s| DerivationPath::from_str(s).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(previous.clone());
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(key, (fg, path("m/48'/1'/0'/2'/0/3")));
+        // Output 1 does not exist.
+        psbt.inputs[1].non_witness_utxo = Some(previous.clone());
+        psbt.inputs[1]
+            .bip32_derivation
+            .insert(key, (fg, path("m/84'/1'/0'/0/3")));
+        assert_eq!(
+            validate_for_signing(&psbt, &wallet).unwrap_err(),
+            vec![
+                PreflightError::UtxoMismatch { input: 1 },
+                PreflightError::KeyNotInPolicy {
+                    input: 1,
+                    path: path("m/84'/1'/0'/0/3")
+                },
+                PreflightError::MissingUtxo { input: 2 },
+                PreflightError::NoDeviceKey { input: 2 },
+            ]
+        );
+
+        // Single key wallets do not check the key paths.
+        assert_eq!(
+            validate_for_signing(&psbt, &WalletPolicyInfo::single_key(fg)).unwrap_err()[1],
+            PreflightError::MissingUtxo { input: 2 }
+        );
+
+        psbt.unsigned_tx.input.truncate(1);
+        psbt.inputs.truncate(1);
+        assert!(validate_for_signing(&psbt, &wallet).is_ok());
+    }
+
+    #[test]
+    fn test_combine() {
+        use bitcoin::{absolute::LockTime, ecdsa, secp256k1, transaction, Transaction, TxIn};
+
+        let secp = secp256k1::Secp256k1::new();
+        let psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: Vec::new(),
+        })
+        .unwrap();
+        let sign = |seed| {
+            let key = secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap();
+            let msg = secp256k1::Message::from_digest([1; 32]);
+            let mut part = psbt.clone();
+            part.inputs[0].partial_sigs.insert(
+                bitcoin::PublicKey::new(key.public_key(&secp)),
+                ecdsa::Signature::sighash_all(secp.sign_ecdsa(&msg, &key)),
+            );
+            part
+        };
+
+        let combined = combine(vec![sign(1), sign(2), sign(1)]).unwrap();
+        assert_eq!(combined.inputs[0].partial_sigs.len(), 2);
+        assert_eq!(combined.unsigned_tx, psbt.unsigned_tx);
+
+        let mut other = sign(3);
+        other.unsigned_tx.lock_time = LockTime::from_height(1).unwrap();
+        assert!(combine(vec![sign(1), other]).is_err());
+        assert!(combine(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_annotate_wallet_outputs() {
+        use bitcoin::Address;
+
+        // BIP84 test vectors.
+        let policy = WalletPolicy::from_descriptor(
+            "",
+            "wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)",
+        )
+        .unwrap();
+        let script = |address| {
+            Address::from_str(address)
+                .unwrap()
+                .assume_checked()
+                .script_pubkey()
+        };
+        let output = |script_pubkey| TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey,
+        };
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                output(script("bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el")),
+                output(script("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu")),
+                output(ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros())),
+            ],
+        })
+        .unwrap();
+
+        let expected = vec![
+            WalletOutput {
+                output: 0,
+                change: true,
+                index: 0,
+            },
+            WalletOutput {
+                output: 1,
+                change: false,
+                index: 0,
+            },
+        ];
+        assert_eq!(
+            annotate_wallet_outputs(&mut psbt, &policy, 5).unwrap(),
+            expected
+        );
+        assert_eq!(
+            psbt.outputs[0]
+                .bip32_derivation
+                .values()
+                .collect::<Vec<_>>(),
+            vec![&(
+                Fingerprint::from_str("73c5da0a").unwrap(),
+                DerivationPath::from_str("m/84'/0'/0'/1/0").unwrap()
+            )]
+        );
+        assert!(psbt.outputs[2].bip32_derivation.is_empty());
+        // The annotated outputs are recognized beyond the lookahead.
+        assert_eq!(
+            annotate_wallet_outputs(&mut psbt, &policy, 0).unwrap(),
+            expected
+        );
+
+        // A payment with the derivations of the change.
+        psbt.outputs[2] = psbt.outputs[0].clone();
+        assert!(matches!(
+            annotate_wallet_outputs(&mut psbt, &policy, 5),
+            Err(Error::PolicyMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_template() {
+        assert_eq!(
+            Template::parse("sh(wsh(sortedmulti(2,@0/**,@1/<2;3>/*)))").unwrap(),
+            Template {
+                shape: Shape::ShWsh,
+                threshold: 2,
+                sorted: true,
+                keys: vec![
+                    TemplateKey {
+                        index: 0,
+                        receive: 0,
+                        change: 1
+                    },
+                    TemplateKey {
+                        index: 1,
+                        receive: 2,
+                        change: 3
+                    },
+                ],
+            }
+        );
+        assert!(Template::parse("wsh(multi(3,@0/**,@1/**))").is_err());
+        assert!(Template::parse("wsh(or_d(pk(@0/**),and_v(v:pkh(@1/**),older(100))))").is_err());
+        assert!(Template::parse("tr(@0/**,pk(@1/**))").is_err());
+
+        let policy = WalletPolicy::from_descriptor(
+            "",
+            "wsh(sortedmulti(1,[73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/**,tpubDEvZxV86Br8Knbm9tWcr5Hvmg5cYTYsg92vinqH6Bie6U8ix8CsoN9W11NQygdqVwmHUJpsHXxNsi5gXn36g4xNfLWkMqPuFhRZAmMQ7jjQ/**))",
+        )
+        .unwrap();
+        let address = Template::parse(&policy.template)
+            .unwrap()
+            .derive(&Secp256k1::verification_only(), &policy, true, 3)
+            .unwrap();
+        let witness_script = address.witness_script.unwrap();
+        assert_eq!(
+            address.script_pubkey,
+            ScriptBuf::new_p2wsh(&witness_script.wscript_hash())
+        );
+        assert_eq!(
+            address.keys[0].1 .1,
+            DerivationPath::from_str("m/84'/0'/0'/1/3").unwrap()
+        );
+        let keys: Vec<_> = witness_script
+            .instructions()
+            .filter_map(|i| i.ok()?.push_bytes().map(|key| key.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(keys.len(), 2);
+        assert!(keys[0] < keys[1]);
+    }
+}
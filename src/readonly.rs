@@ -0,0 +1,98 @@
+use bitcoin::psbt::{self, Psbt};
+
+use crate::{
+    bip85, slip19, Capabilities, Error, InputSignature, SignResult, SilentPaymentKeys,
+    WalletRegistration, HWI,
+};
+
+/// ReadOnly wraps a device and permits only the operations that cannot
+/// produce a signature or change the device state.
+/// Signing and registration return Error::ReadOnly.
+#[derive(Debug)]
+pub struct ReadOnly<T>(T);
+
+impl<T: HWI> ReadOnly<T> {
+    pub fn new(device: T) -> Self {
+        Self(device)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: HWI> crate::delegate::Delegate for ReadOnly<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.0
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(&mut self.0)
+    }
+
+    fn register_wallet(&self, _name: &str, _policy: &str) -> Result<WalletRegistration, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn sign_tx(&self, _tx: &mut Psbt) -> Result<SignResult, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn sign_tx_progressive(
+        &self,
+        _tx: &mut Psbt,
+        _on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn sign_txs(&self, _txs: &mut [Psbt]) -> Result<Vec<SignResult>, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Derived entropy is secret material.
+    fn derive_bip85(
+        &self,
+        _application: &bip85::Application,
+        _index: u32,
+    ) -> Result<Vec<u8>, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// The proof is a signature.
+    fn prove_ownership(
+        &self,
+        _input: &psbt::Input,
+        _commitment_data: &[u8],
+    ) -> Result<slip19::OwnershipProof, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// The scan key is secret material.
+    fn get_silent_payment_keys(&self, _account: u32) -> Result<SilentPaymentKeys, Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// The settings of the device are not changed.
+    fn set_label(&self, _label: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly)
+    }
+
+    /// Messages cannot be signed.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            sign_message: false,
+            ..self.0.capabilities()
+        }
+    }
+}
+
+delegate_hwi!([T: HWI] ReadOnly<T>);
+
+impl<T: 'static + HWI + Send> From<ReadOnly<T>> for Box<dyn HWI + Send> {
+    fn from(s: ReadOnly<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
@@ -0,0 +1,443 @@
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Xpub},
+    secp256k1::{Secp256k1, Verification},
+    Address, Network,
+};
+
+use crate::{utils, AddressScript, Error, HWI};
+
+/// Range of addresses derived host-side and displayed on the device by index.
+pub trait AddressRange {
+    /// Address script of the given index to display on the device.
+    fn address_script(&self, index: u32) -> Result<AddressScript, Error>;
+
+    /// Derives the address of the given index.
+    fn address<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, Error>;
+}
+
+/// Single key keychain of an account, derived host-side from the account xpub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keychain {
+    /// Account path: m/purpose'/coin_type'/account'.
+    pub account: DerivationPath,
+    /// Xpub of the account path.
+    pub xpub: Xpub,
+    /// Change keychain instead of the receive keychain.
+    pub change: bool,
+}
+
+impl Keychain {
+    fn purpose(&self) -> Result<u32, Error> {
+        match self.account.as_ref() {
+            [ChildNumber::Hardened { index: purpose }, ChildNumber::Hardened { .. }, ChildNumber::Hardened { .. }] => {
+                Ok(*purpose)
+            }
+            _ => Err(Error::InvalidParameter(
+                "account",
+                "path is not a bip44 account path".to_string(),
+            )),
+        }
+    }
+
+    fn children(&self, index: u32) -> Result<[ChildNumber; 2], Error> {
+        let change = ChildNumber::from(self.change as u32);
+        let index = ChildNumber::from_normal_idx(index)
+            .map_err(|e| Error::InvalidParameter("index", e.to_string()))?;
+        Ok([change, index])
+    }
+}
+
+impl AddressRange for Keychain {
+    fn address_script(&self, index: u32) -> Result<AddressScript, Error> {
+        let path = self.account.extend(self.children(index)?);
+        match self.purpose()? {
+            44 => Ok(AddressScript::P2PKH(path)),
+            49 => Ok(AddressScript::P2SHWPKH(path)),
+            84 => Ok(AddressScript::P2WPKH(path)),
+            86 => Ok(AddressScript::P2TR(path)),
+            purpose => Err(Error::InvalidParameter(
+                "account",
+                format!("purpose {} is not supported", purpose),
+            )),
+        }
+    }
+
+    fn address<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, Error> {
+        let xpub = self
+            .xpub
+            .derive_pub(secp, &self.children(index)?)
+            .map_err(|e| Error::InvalidParameter("xpub", e.to_string()))?;
+        let pk = xpub.to_pub();
+        let address = match self.address_script(index)? {
+            AddressScript::P2PKH(_) => Ok(Address::p2pkh(&pk, network)),
+            AddressScript::P2SHWPKH(_) => Address::p2shwpkh(&pk, network),
+            AddressScript::P2WPKH(_) => Address::p2wpkh(&pk, network),
+            _ => Ok(Address::p2tr(secp, pk.inner.into(), None, network)),
+        };
+        address.map_err(|e| Error::InvalidParameter("xpub", e.to_string()))
+    }
+}
+
+/// Keychain of a descriptor, derived host-side from the keys of the wallet policy and
+/// displayed on the device with the loaded wallet, see HWI::load_wallet. The keys must
+/// have a receive and a change derivation, e.g. @0/**. Only the single key, the multisig
+/// and the taproot key path descriptors are derived host-side.
+#[cfg(feature = "regex")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorKeychain {
+    pub policy: crate::policy::WalletPolicy,
+    /// Change keychain instead of the receive keychain.
+    pub change: bool,
+}
+
+#[cfg(feature = "regex")]
+impl DescriptorKeychain {
+    /// Derives the key of the placeholder @i/<receive;change>/* at the given index.
+    fn key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        placeholder: &str,
+        index: u32,
+    ) -> Result<bitcoin::PublicKey, Error> {
+        let invalid = || Error::InvalidParameter("policy", format!("invalid key {}", placeholder));
+        let (i, derivation) = placeholder
+            .strip_prefix('@')
+            .and_then(|s| s.split_once('/'))
+            .ok_or_else(invalid)?;
+        let key = i
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| self.policy.keys.get(i))
+            .ok_or_else(invalid)?;
+        let (paths, wildcard) = crate::bip389::parse_xkey_deriv(derivation)?;
+        if paths.len() != 2 || wildcard != crate::bip389::Wildcard::Unhardened {
+            return Err(invalid());
+        }
+        let index = ChildNumber::from_normal_idx(index)
+            .map_err(|e| Error::InvalidParameter("index", e.to_string()))?;
+        let path = paths[self.change as usize].extend([index]);
+        key.xpub
+            .derive_pub(secp, &path)
+            .map(|xpub| xpub.to_pub())
+            .map_err(|e| Error::InvalidParameter("policy", e.to_string()))
+    }
+
+    /// Script of a multi or sortedmulti fragment.
+    fn multisig<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        fragment: &str,
+        index: u32,
+    ) -> Result<Option<bitcoin::ScriptBuf>, Error> {
+        let (args, sorted) = match (
+            wrapped(fragment, "multi("),
+            wrapped(fragment, "sortedmulti("),
+        ) {
+            (Some(args), _) => (args, false),
+            (_, Some(args)) => (args, true),
+            _ => return Ok(None),
+        };
+        let mut args = args.split(',');
+        let threshold: i64 = args
+            .next()
+            .and_then(|k| k.parse().ok())
+            .ok_or_else(|| Error::InvalidParameter("policy", format!("invalid {}", fragment)))?;
+        let mut keys = args
+            .map(|key| self.key(secp, key, index))
+            .collect::<Result<Vec<_>, _>>()?;
+        if threshold < 1 || threshold as usize > keys.len() || keys.len() > 20 {
+            return Err(Error::InvalidParameter(
+                "policy",
+                format!("invalid {}", fragment),
+            ));
+        }
+        if sorted {
+            keys.sort_by_key(|key| key.to_bytes());
+        }
+        let mut builder = bitcoin::script::Builder::new().push_int(threshold);
+        for key in &keys {
+            builder = builder.push_key(key);
+        }
+        Ok(Some(
+            builder
+                .push_int(keys.len() as i64)
+                .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+                .into_script(),
+        ))
+    }
+}
+
+#[cfg(feature = "regex")]
+impl AddressRange for DescriptorKeychain {
+    fn address_script(&self, index: u32) -> Result<AddressScript, Error> {
+        Ok(AddressScript::Miniscript {
+            index,
+            change: self.change,
+        })
+    }
+
+    fn address<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, Error> {
+        let template = self.policy.template.as_str();
+        let address = if let Some(key) = wrapped(template, "pkh(") {
+            Ok(Address::p2pkh(&self.key(secp, key, index)?, network))
+        } else if let Some(key) = wrapped(template, "wpkh(") {
+            Address::p2wpkh(&self.key(secp, key, index)?, network)
+        } else if let Some(key) = wrapped(template, "sh(").and_then(|s| wrapped(s, "wpkh(")) {
+            Address::p2shwpkh(&self.key(secp, key, index)?, network)
+        } else if let Some(key) = wrapped(template, "tr(").filter(|s| !s.contains(',')) {
+            let key = self.key(secp, key, index)?;
+            Ok(Address::p2tr(secp, key.inner.into(), None, network))
+        } else if let Some(script) = wrapped(template, "sh(")
+            .and_then(|s| wrapped(s, "wsh("))
+            .map(|s| self.multisig(secp, s, index))
+            .transpose()?
+            .flatten()
+        {
+            Ok(Address::p2shwsh(&script, network))
+        } else if let Some(script) = wrapped(template, "wsh(")
+            .map(|s| self.multisig(secp, s, index))
+            .transpose()?
+            .flatten()
+        {
+            Ok(Address::p2wsh(&script, network))
+        } else if let Some(script) = wrapped(template, "sh(")
+            .map(|s| self.multisig(secp, s, index))
+            .transpose()?
+            .flatten()
+        {
+            Address::p2sh(&script, network)
+        } else {
+            return Err(Error::InvalidParameter(
+                "policy",
+                format!("{} is not derived host-side", template),
+            ));
+        };
+        address.map_err(|e| Error::InvalidParameter("policy", e.to_string()))
+    }
+}
+
+/// Arguments of the fragment `name(args)`.
+#[cfg(feature = "regex")]
+fn wrapped<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.strip_prefix(name)?.strip_suffix(')')
+}
+
+/// Derives the addresses of the keychain until `gap_limit` consecutive addresses
+/// are unused and returns the addresses up to the last used one.
+pub fn scan<K: AddressRange, C: Verification>(
+    secp: &Secp256k1<C>,
+    keychain: &K,
+    network: Network,
+    gap_limit: u32,
+    mut is_used: impl FnMut(&Address) -> bool,
+) -> Result<Vec<Address>, Error> {
+    let mut addresses = Vec::new();
+    let mut used = 0;
+    let mut index = 0;
+    while index < used + gap_limit {
+        let address = keychain.address(secp, index, network)?;
+        index += 1;
+        if is_used(&address) {
+            used = index;
+        }
+        addresses.push(address);
+    }
+    addresses.truncate(used as usize);
+    Ok(addresses)
+}
+
+/// Indexes of a range of `len` addresses to verify on the device:
+/// the first, every `step`th and the last one.
+pub fn sample_indexes(len: u32, step: u32) -> Vec<u32> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let mut indexes: Vec<u32> = (0..len).step_by(step.max(1) as usize).collect();
+    if indexes.last() != Some(&(len - 1)) {
+        indexes.push(len - 1);
+    }
+    indexes
+}
+
+/// Displays the sampled addresses on the device and checks the addresses computed
+/// by the device match the addresses derived host-side. The scripts are compared, the
+/// devices returning the testnet addresses on regtest.
+pub fn verify_sample<T: HWI + ?Sized, K: AddressRange, C: Verification>(
+    device: &T,
+    secp: &Secp256k1<C>,
+    keychain: &K,
+    network: Network,
+    indexes: &[u32],
+) -> Result<(), Error> {
+    for index in indexes {
+        let address = device.display_address(&keychain.address_script(*index)?)?;
+        let address = utils::check_address_network(address, network)?.assume_checked();
+        if address.script_pubkey() != keychain.address(secp, *index, network)?.script_pubkey() {
+            return Err(Error::AddressMismatch { index: *index });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_sample_indexes() {
+        assert_eq!(sample_indexes(0, 50), Vec::<u32>::new());
+        assert_eq!(sample_indexes(1, 50), vec![0]);
+        assert_eq!(sample_indexes(50, 50), vec![0, 49]);
+        assert_eq!(sample_indexes(101, 50), vec![0, 50, 100]);
+        assert_eq!(sample_indexes(120, 50), vec![0, 50, 100, 119]);
+    }
+
+    #[test]
+    fn test_scan() {
+        let secp = Secp256k1::verification_only();
+        // BIP84 test vector account 0.
+        let keychain = Keychain {
+            account: DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+            xpub: Xpub::from_str("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V").unwrap(),
+            change: false,
+        };
+        assert_eq!(
+            keychain.address(&secp, 0, Network::Bitcoin).unwrap(),
+            Address::from_str("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu")
+                .unwrap()
+                .assume_checked()
+        );
+        assert_eq!(
+            keychain.address_script(1).unwrap(),
+            AddressScript::P2WPKH(DerivationPath::from_str("m/84'/0'/0'/0/1").unwrap())
+        );
+
+        let used = keychain.address(&secp, 3, Network::Bitcoin).unwrap();
+        let addresses = scan(&secp, &keychain, Network::Bitcoin, 20, |a| *a == used).unwrap();
+        assert_eq!(addresses.len(), 4);
+        assert!(scan(&secp, &keychain, Network::Bitcoin, 20, |_| false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_descriptor_keychain() {
+        use crate::policy::WalletPolicy;
+
+        let secp = Secp256k1::verification_only();
+        // BIP84 and BIP86 test vectors, account 0.
+        let keychain = |descriptor: &str| DescriptorKeychain {
+            policy: WalletPolicy::from_descriptor("", descriptor).unwrap(),
+            change: false,
+        };
+        let address = |keychain: &DescriptorKeychain| {
+            keychain
+                .address(&secp, 0, Network::Bitcoin)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            address(&keychain("wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/<0;1>/*)")),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            address(&keychain("tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/**)")),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+        assert_eq!(
+            keychain("wpkh([73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V/**)")
+                .address_script(7)
+                .unwrap(),
+            AddressScript::Miniscript {
+                index: 7,
+                change: false
+            }
+        );
+
+        // The keys of sortedmulti are sorted, not the ones of multi.
+        let a = "[f5acc2fd/48'/1'/0'/2']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**";
+        let b = "tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/<2;3>/*";
+        let multisig = |template: &str, first, second| {
+            keychain(&template.replace("$a", first).replace("$b", second))
+        };
+        for template in [
+            "wsh(sortedmulti(1,$a,$b))",
+            "sh(wsh(sortedmulti(1,$a,$b)))",
+            "sh(sortedmulti(1,$a,$b))",
+        ] {
+            assert_eq!(
+                address(&multisig(template, a, b)),
+                address(&multisig(template, b, a))
+            );
+        }
+        assert_ne!(
+            address(&multisig("wsh(multi(1,$a,$b))", a, b)),
+            address(&multisig("wsh(multi(1,$a,$b))", b, a))
+        );
+        assert!(address(&multisig("wsh(multi(1,$a,$b))", a, b)).starts_with("bc1q"));
+        assert!(address(&multisig("sh(wsh(multi(1,$a,$b)))", a, b)).starts_with('3'));
+
+        let change = DescriptorKeychain {
+            change: true,
+            ..multisig("wsh(multi(1,$a,$b))", a, b)
+        };
+        assert_ne!(
+            address(&change),
+            address(&multisig("wsh(multi(1,$a,$b))", a, b))
+        );
+
+        for template in [
+            "wsh(multi(3,$a,$b))",
+            "wsh(or_d(pk($a),and_v(v:pkh($b),older(100))))",
+        ] {
+            assert!(matches!(
+                multisig(template, a, b).address(&secp, 0, Network::Bitcoin),
+                Err(Error::InvalidParameter("policy", _))
+            ));
+        }
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn test_verify_sample_regtest() {
+        use crate::signer::HotSigner;
+
+        let signer = HotSigner::from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "",
+            Network::Testnet,
+        )
+        .unwrap();
+        let account = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let keychain = Keychain {
+            xpub: signer.get_extended_pubkey(&account).unwrap(),
+            account,
+            change: false,
+        };
+        let secp = Secp256k1::verification_only();
+        for network in [Network::Testnet, Network::Regtest] {
+            verify_sample(&signer, &secp, &keychain, network, &[0, 5]).unwrap();
+        }
+        assert!(matches!(
+            verify_sample(&signer, &secp, &keychain, Network::Bitcoin, &[0]),
+            Err(Error::NetworkMismatch)
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+/// JSON Schema (draft 2020-12) of the serde representation of the public types,
+/// for the clients of the crate written in other languages. The types are defined
+/// under `$defs` with the name of the Rust type.
+pub const JSON_SCHEMA: &str = include_str!("schema.json");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audit::{AuditReport, Discrepancy},
+        limits::PsbtLimit,
+        transcript::{
+            DeviceIdentity, Entry, ReviewedOutput, SignedTranscript, TranscriptDocument,
+            Verification,
+        },
+        AddressScript, Attestation, Capabilities, DeviceDetails, DeviceKind, DeviceModel, Error,
+        ErrorCode, RegisteredWallet, SignResult, SignWarning, SignedInput, SigningKey,
+        TransportErrorKind, Version, WalletRegistration,
+    };
+    use bitcoin::hashes::Hash;
+    use serde_json::{json, Value};
+    use std::str::FromStr;
+
+    /// Validates the subset of JSON Schema used by the schema of the crate.
+    fn validate(root: &Value, schema: &Value, value: &Value) -> bool {
+        if let Some(path) = schema.get("$ref").and_then(Value::as_str) {
+            let name = path.trim_start_matches("#/$defs/");
+            return validate(root, &root["$defs"][name], value);
+        }
+        if let Some(variants) = schema.get("oneOf").and_then(Value::as_array) {
+            return variants.iter().filter(|s| validate(root, s, value)).count() == 1;
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return values.contains(value);
+        }
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let type_ok = types.iter().any(|t| match *t {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        });
+        if !type_ok {
+            return false;
+        }
+        if let (Some(props), Some(object)) = (schema.get("properties"), value.as_object()) {
+            let required = schema["required"].as_array().unwrap();
+            return required
+                .iter()
+                .all(|k| object.contains_key(k.as_str().unwrap()))
+                && object
+                    .iter()
+                    .all(|(k, v)| props.get(k).is_some_and(|s| validate(root, s, v)));
+        }
+        if let Some(array) = value.as_array() {
+            if let Some(prefix) = schema.get("prefixItems").and_then(Value::as_array) {
+                return array.len() == prefix.len()
+                    && prefix.iter().zip(array).all(|(s, v)| validate(root, s, v));
+            }
+            if let Some(items) = schema.get("items") {
+                return array.iter().all(|v| validate(root, items, v));
+            }
+        }
+        true
+    }
+
+    fn check<T: serde::Serialize>(name: &str, value: &T) -> Value {
+        let root: Value = serde_json::from_str(JSON_SCHEMA).unwrap();
+        let value = serde_json::to_value(value).unwrap();
+        assert!(
+            validate(&root, &root["$defs"][name], &value),
+            "{} does not match the schema: {}",
+            name,
+            value
+        );
+        value
+    }
+
+    #[test]
+    fn test_schema() {
+        let version = Version {
+            major: 2,
+            minor: 1,
+            patch: 0,
+            prerelease: Some("rc".to_string()),
+            flavor: Some("QX".to_string()),
+        };
+        check("Version", &version);
+        for kind in [
+            DeviceKind::BitBox02,
+            DeviceKind::SpecterSimulator,
+            DeviceKind::LedgerSimulator,
+            DeviceKind::JadeSimulator,
+        ] {
+            assert_eq!(check("DeviceKind", &kind), json!(kind.to_string()));
+        }
+        let root: Value = serde_json::from_str(JSON_SCHEMA).unwrap();
+        assert!(!validate(
+            &root,
+            &root["$defs"]["DeviceKind"],
+            &json!("trezor")
+        ));
+        assert!(!validate(
+            &root,
+            &root["$defs"]["Version"],
+            &json!({"major": 2})
+        ));
+        let details = DeviceDetails {
+            kind: DeviceKind::BitBox02,
+            model: Some(DeviceModel::BitBox02BtcOnly),
+            serial: Some("v9.15.0".to_string()),
+            path: Some("/dev/hidraw0".to_string()),
+            product: Some("BitBox02BTC".to_string()),
+            user_label: Some("Cold storage".to_string()),
+        };
+        assert_eq!(
+            check("DeviceDetails", &details)["model"],
+            json!("bitbox02_btc_only")
+        );
+        check(
+            "DeviceDetails",
+            &DeviceDetails {
+                model: None,
+                serial: None,
+                path: None,
+                product: None,
+                user_label: None,
+                ..details
+            },
+        );
+        check(
+            "Capabilities",
+            &Capabilities::new(DeviceKind::Ledger, Some(&version)),
+        );
+
+        let registration = WalletRegistration {
+            id: None,
+            hmac: Some([0xab; 32]),
+            xpubs: vec![(
+                bitcoin::bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap(),
+                bitcoin::bip32::Xpub::from_str("tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP").unwrap(),
+            )],
+            already_registered: false,
+        };
+        let value = check("WalletRegistration", &registration);
+        assert_eq!(
+            serde_json::from_value::<WalletRegistration>(value).unwrap(),
+            registration
+        );
+
+        check(
+            "RegisteredWallet",
+            &RegisteredWallet {
+                name: "vault".to_string(),
+                descriptor: "wsh(pk([b0822927/48'/1'/0'/2']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/<0;1>/*))".to_string(),
+            },
+        );
+
+        check("Attestation", &Attestation::Genuine);
+        check(
+            "Attestation",
+            &Attestation::BagNumber("XY12345678".to_string()),
+        );
+
+        check(
+            "SignResult",
+            &SignResult {
+                warnings: vec![
+                    SignWarning::LockTime(bitcoin::absolute::LockTime::from_consensus(800_000)),
+                    SignWarning::RelativeLockTime {
+                        input: 0,
+                        sequence: bitcoin::Sequence::from_height(144),
+                    },
+                    SignWarning::SighashType {
+                        input: 1,
+                        sighash_type: bitcoin::EcdsaSighashType::None.into(),
+                    },
+                    SignWarning::UnusualPath {
+                        input: 0,
+                        path: bitcoin::bip32::DerivationPath::from_str("m/84'/0'/0'/0/100000").unwrap(),
+                    },
+                    SignWarning::UnusualChange {
+                        output: 1,
+                        path: bitcoin::bip32::DerivationPath::from_str("m/84'/0'/0'/0/3").unwrap(),
+                    },
+                    SignWarning::HighFee {
+                        fee: bitcoin::Amount::from_sat(20_000),
+                        spent: bitcoin::Amount::from_sat(100_000),
+                    },
+                ],
+                signed_inputs: vec![
+                    SignedInput {
+                        input: 0,
+                        key: SigningKey::Ecdsa(
+                            bitcoin::PublicKey::from_str("0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c").unwrap(),
+                        ),
+                    },
+                    SignedInput {
+                        input: 1,
+                        key: SigningKey::Schnorr(
+                            bitcoin::key::XOnlyPublicKey::from_str("30d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c").unwrap(),
+                        ),
+                    },
+                ],
+            },
+        );
+
+        let fingerprint = bitcoin::bip32::Fingerprint::from_str("f5acc2fd").unwrap();
+        let path = bitcoin::bip32::DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        let xpub = bitcoin::bip32::Xpub::from_str("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V").unwrap();
+        check(
+            "AuditReport",
+            &AuditReport {
+                fingerprint,
+                xpubs: vec![(path.clone(), xpub)],
+                discrepancies: vec![
+                    Discrepancy::Fingerprint {
+                        device: 1,
+                        expected: fingerprint,
+                        found: bitcoin::bip32::Fingerprint::default(),
+                    },
+                    Discrepancy::Xpub {
+                        device: 1,
+                        path,
+                        expected: Box::new(xpub),
+                        found: Box::new(xpub),
+                    },
+                ],
+            },
+        );
+
+        let address =
+            bitcoin::Address::from_str("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu").unwrap();
+        let entry = |verification| Entry {
+            timestamp: 1_700_000_000,
+            verification,
+        };
+        check(
+            "SignedTranscript",
+            &SignedTranscript {
+                document: TranscriptDocument {
+                    device: DeviceIdentity {
+                        kind: DeviceKind::Ledger,
+                        fingerprint: Some(fingerprint),
+                        version: None,
+                    },
+                    started: 1_700_000_000,
+                    entries: vec![
+                        entry(Verification::PolicyShown {
+                            name: "vault".to_string(),
+                            policy: "wsh(pk(@0/**))".to_string(),
+                        }),
+                        entry(Verification::AddressVerified {
+                            address: address.clone(),
+                        }),
+                        entry(Verification::OutputsApproved {
+                            txid: bitcoin::Txid::all_zeros(),
+                            outputs: vec![ReviewedOutput {
+                                script_pubkey: address.clone().assume_checked().script_pubkey(),
+                                address: Some(address),
+                                amount: bitcoin::Amount::from_sat(10_000),
+                            }],
+                            warnings: Vec::new(),
+                        }),
+                    ],
+                },
+                signer: "02".to_string(),
+                signature: "30".to_string(),
+            },
+        );
+
+        for error in [
+            Error::DeviceNotFound,
+            Error::Timeout,
+            Error::InvalidParameter("path", "invalid".to_string()),
+            Error::DeviceLocked { code: Some(0x5515) },
+            Error::WrongApp {
+                expected: "Bitcoin".to_string(),
+                found: None,
+                code: None,
+            },
+            Error::UnsupportedFirmware {
+                required: version.clone(),
+                found: Some(Box::new(version)),
+            },
+            Error::Transport(TransportErrorKind::Io, "broken pipe".to_string()),
+            Error::UnsupportedNetwork(bitcoin::Network::Signet),
+            Error::PsbtLimit {
+                limit: PsbtLimit::Inputs,
+                value: 300,
+                max: 256,
+            },
+            Error::PolicyMismatch("no key of the device".to_string()),
+            Error::PermissionDenied {
+                path: "/dev/hidraw3".to_string(),
+            },
+        ] {
+            check("Error", &error);
+        }
+
+        for kind in DeviceKind::ALL {
+            assert_eq!(check("DeviceKind", &kind), json!(kind.to_string()));
+        }
+        for model in DeviceModel::ALL {
+            assert_eq!(check("DeviceModel", &model), json!(model.as_str()));
+        }
+
+        for code in ErrorCode::ALL {
+            assert_eq!(check("ErrorCode", &code), json!(code.as_str()));
+        }
+
+        for script in [
+            AddressScript::P2TR(
+                bitcoin::bip32::DerivationPath::from_str("m/86'/1'/0'/0/3").unwrap(),
+            ),
+            AddressScript::Miniscript {
+                index: 7,
+                change: true,
+            },
+        ] {
+            let value = check("AddressScript", &script);
+            assert_eq!(
+                serde_json::from_value::<AddressScript>(value).unwrap(),
+                script
+            );
+        }
+    }
+}
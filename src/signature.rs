@@ -0,0 +1,332 @@
+//! Conversions and host side verification of the message signatures: the BIP137
+//! compact signatures of the legacy format and the BIP322 simple signatures.
+
+use bitcoin::{
+    absolute::LockTime,
+    base64::{prelude::BASE64_STANDARD, Engine},
+    consensus::encode,
+    ecdsa,
+    hashes::{sha256, sha256d, Hash, HashEngine},
+    key::XOnlyPublicKey,
+    opcodes::all::OP_RETURN,
+    secp256k1::{
+        ecdsa::{RecoverableSignature, RecoveryId},
+        Message, Secp256k1,
+    },
+    sighash::{Prevouts, SighashCache},
+    sign_message::signed_msg_hash,
+    transaction, Amount, OutPoint, PublicKey, Script, ScriptBuf, Sequence, Transaction, TxIn,
+    TxOut, Witness,
+};
+
+use crate::{Error, ScriptType};
+
+/// Recoverable signature of a message, with the header of BIP137 telling the script of
+/// the signing key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactSignature {
+    pub signature: RecoverableSignature,
+    /// P2TR is not a script of BIP137.
+    pub script: ScriptType,
+    /// Only P2PKH keys may be uncompressed.
+    pub compressed: bool,
+}
+
+impl CompactSignature {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        let (header, compact) = match bytes {
+            [header, compact @ ..] if compact.len() == 64 => (*header, compact),
+            _ => return Err(invalid(format!("{} bytes instead of 65", bytes.len()))),
+        };
+        let (script, compressed) = match header {
+            27..=30 => (ScriptType::P2PKH, false),
+            31..=34 => (ScriptType::P2PKH, true),
+            35..=38 => (ScriptType::P2SHWPKH, true),
+            39..=42 => (ScriptType::P2WPKH, true),
+            _ => return Err(invalid(format!("unknown header {}", header))),
+        };
+        let recid = RecoveryId::from_i32(((header - 27) & 0x03) as i32)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(Self {
+            signature: RecoverableSignature::from_compact(compact, recid)
+                .map_err(|e| invalid(e.to_string()))?,
+            script,
+            compressed,
+        })
+    }
+
+    pub fn serialize(&self) -> [u8; 65] {
+        let (recid, compact) = self.signature.serialize_compact();
+        let offset = match (self.script, self.compressed) {
+            (ScriptType::P2PKH, false) => 27,
+            (ScriptType::P2SHWPKH, _) => 35,
+            (ScriptType::P2WPKH, _) => 39,
+            _ => 31,
+        };
+        let mut bytes = [0; 65];
+        bytes[0] = offset + recid.to_i32() as u8;
+        bytes[1..].copy_from_slice(&compact);
+        bytes
+    }
+
+    /// Legacy format of the signatures shown by the wallets.
+    pub fn from_base64(s: &str) -> Result<Self, Error> {
+        Self::from_slice(
+            &BASE64_STANDARD
+                .decode(s)
+                .map_err(|e| invalid(e.to_string()))?,
+        )
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(self.serialize())
+    }
+
+    /// Key of the signature, recovered with the hash of the message.
+    pub fn recover_pubkey(&self, message: &str) -> Result<PublicKey, Error> {
+        let msg = Message::from_digest(signed_msg_hash(message).to_byte_array());
+        let key = Secp256k1::verification_only()
+            .recover_ecdsa(&msg, &self.signature)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(PublicKey {
+            inner: key,
+            compressed: self.compressed,
+        })
+    }
+
+    /// Script of the signing key with the script type of the header.
+    pub fn script_pubkey(&self, message: &str) -> Result<ScriptBuf, Error> {
+        let key = self.recover_pubkey(message)?;
+        let wpkh = || {
+            key.wpubkey_hash()
+                .map(|hash| ScriptBuf::new_p2wpkh(&hash))
+                .ok_or_else(|| invalid("uncompressed segwit key".to_string()))
+        };
+        match self.script {
+            ScriptType::P2PKH => Ok(ScriptBuf::new_p2pkh(&key.pubkey_hash())),
+            ScriptType::P2SHWPKH => Ok(ScriptBuf::new_p2sh(&wpkh()?.script_hash())),
+            ScriptType::P2WPKH => wpkh(),
+            ScriptType::P2TR => Err(invalid("taproot compact signature".to_string())),
+        }
+    }
+}
+
+impl From<CompactSignature> for ecdsa::Signature {
+    fn from(sig: CompactSignature) -> Self {
+        ecdsa::Signature::sighash_all(sig.signature.to_standard())
+    }
+}
+
+/// Hash of the message signed by the BIP322 signatures.
+pub fn bip322_message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(b"BIP0322-signed-message");
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Virtual transaction spending the output of the script, committing to the message.
+pub fn bip322_to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    let script_sig = bitcoin::script::Builder::new()
+        .push_int(0)
+        .push_slice(bip322_message_hash(message).to_byte_array())
+        .into_script();
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.to_owned(),
+        }],
+    }
+}
+
+/// Virtual transaction signed by the BIP322 signature, its witness is the signature.
+pub fn bip322_to_sign(to_spend: &Transaction, witness: Witness) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(vec![OP_RETURN.to_u8()]),
+        }],
+    }
+}
+
+/// Witness of a BIP322 simple signature, encoded in base64.
+pub fn witness_from_base64(s: &str) -> Result<Witness, Error> {
+    let bytes = BASE64_STANDARD
+        .decode(s)
+        .map_err(|e| invalid(e.to_string()))?;
+    encode::deserialize(&bytes).map_err(|e| invalid(e.to_string()))
+}
+
+pub fn witness_to_base64(witness: &Witness) -> String {
+    BASE64_STANDARD.encode(encode::serialize(witness))
+}
+
+/// Checks the base64 signature of the message, either a BIP137 signature or a BIP322
+/// simple signature of a P2WPKH or P2TR key path spend.
+/// Returns false if the signature is valid but not of the script.
+pub fn verify(script_pubkey: &Script, message: &str, signature: &str) -> Result<bool, Error> {
+    let bytes = BASE64_STANDARD
+        .decode(signature)
+        .map_err(|e| invalid(e.to_string()))?;
+    if bytes.len() == 65 {
+        if let Ok(sig) = CompactSignature::from_slice(&bytes) {
+            return Ok(sig.script_pubkey(message)? == *script_pubkey);
+        }
+    }
+    let witness: Witness = encode::deserialize(&bytes).map_err(|e| invalid(e.to_string()))?;
+    verify_bip322(script_pubkey, message, witness)
+}
+
+fn verify_bip322(script_pubkey: &Script, message: &str, witness: Witness) -> Result<bool, Error> {
+    let secp = Secp256k1::verification_only();
+    let to_spend = bip322_to_spend(script_pubkey, message);
+    let to_sign = bip322_to_sign(&to_spend, witness.clone());
+    let mut cache = SighashCache::new(&to_sign);
+    if script_pubkey.is_p2wpkh() {
+        let (sig, key) = match (witness.nth(0), witness.nth(1), witness.len()) {
+            (Some(sig), Some(key), 2) => (
+                ecdsa::Signature::from_slice(sig).map_err(|e| invalid(e.to_string()))?,
+                PublicKey::from_slice(key).map_err(|e| invalid(e.to_string()))?,
+            ),
+            _ => return Err(invalid("p2wpkh witness of 2 items expected".to_string())),
+        };
+        let wpkh = key
+            .wpubkey_hash()
+            .ok_or_else(|| invalid("uncompressed segwit key".to_string()))?;
+        if ScriptBuf::new_p2wpkh(&wpkh) != *script_pubkey {
+            return Ok(false);
+        }
+        let sighash = cache
+            .p2wpkh_signature_hash(0, script_pubkey, Amount::ZERO, sig.hash_ty)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(secp
+            .verify_ecdsa(
+                &Message::from_digest(sighash.to_byte_array()),
+                &sig.sig,
+                &key.inner,
+            )
+            .is_ok())
+    } else if script_pubkey.is_p2tr() {
+        let sig = match (witness.nth(0), witness.len()) {
+            (Some(sig), 1) => {
+                bitcoin::taproot::Signature::from_slice(sig).map_err(|e| invalid(e.to_string()))?
+            }
+            _ => return Err(invalid("taproot key path witness expected".to_string())),
+        };
+        let key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+            .map_err(|e| invalid(e.to_string()))?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&to_spend.output), sig.hash_ty)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(secp
+            .verify_schnorr(
+                &sig.sig,
+                &Message::from_digest(sighash.to_byte_array()),
+                &key,
+            )
+            .is_ok())
+    } else {
+        Err(invalid(
+            "BIP322 verification of p2wpkh and p2tr scripts only".to_string(),
+        ))
+    }
+}
+
+/// Hash of the message signed by the BIP137 signatures.
+pub fn message_hash(message: &str) -> sha256d::Hash {
+    signed_msg_hash(message)
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidParameter("signature", reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{secp256k1::SecretKey, Address};
+    use std::str::FromStr;
+
+    fn script(address: &str) -> ScriptBuf {
+        Address::from_str(address)
+            .unwrap()
+            .assume_checked()
+            .script_pubkey()
+    }
+
+    #[test]
+    fn test_compact_signature() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let pubkey = PublicKey::new(key.public_key(&secp));
+        let msg = Message::from_digest(message_hash("hello").to_byte_array());
+        let sig = CompactSignature {
+            signature: secp.sign_ecdsa_recoverable(&msg, &key),
+            script: ScriptType::P2WPKH,
+            compressed: true,
+        };
+        let encoded = sig.to_base64();
+        assert_eq!(CompactSignature::from_base64(&encoded).unwrap(), sig);
+        assert_eq!(sig.recover_pubkey("hello").unwrap(), pubkey);
+
+        let wpkh = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().unwrap());
+        assert!(verify(&wpkh, "hello", &encoded).unwrap());
+        assert!(!verify(&wpkh, "other", &encoded).unwrap());
+        // The header tells a p2wpkh key.
+        let pkh = ScriptBuf::new_p2pkh(&pubkey.pubkey_hash());
+        assert!(!verify(&pkh, "hello", &encoded).unwrap());
+        let legacy = CompactSignature {
+            script: ScriptType::P2PKH,
+            ..sig
+        };
+        assert!((31..=34).contains(&legacy.serialize()[0]));
+        assert!(verify(&pkh, "hello", &legacy.to_base64()).unwrap());
+    }
+
+    #[test]
+    fn test_verify_bip322() {
+        // Test vectors of BIP322.
+        assert_eq!(
+            bip322_message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        let wpkh = script("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+        assert!(verify(
+            &wpkh,
+            "",
+            "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        )
+        .unwrap());
+        let hello = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify(&wpkh, "Hello World", hello).unwrap());
+        assert!(!verify(&wpkh, "", hello).unwrap());
+        let witness = witness_from_base64(hello).unwrap();
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness_to_base64(&witness), hello);
+
+        assert!(verify(
+            &script("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3"),
+            "Hello World",
+            "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ=="
+        )
+        .unwrap());
+    }
+}
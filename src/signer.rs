@@ -0,0 +1,684 @@
+use std::sync::Mutex;
+
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub},
+    ecdsa,
+    hashes::{hmac, sha256, sha512, Hash, HashEngine},
+    key::{Keypair, TapTweak},
+    psbt::{self, Psbt},
+    secp256k1::{All, Message, Secp256k1},
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot, Address, Network, ScriptBuf, TxOut, Witness,
+};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    bip85,
+    slip19::{self, OwnershipProof},
+    AddressScript, Capabilities, DeviceKind, Error, SignResult, SilentPaymentKeys, Version,
+    WalletRegistration, HWI,
+};
+
+/// Number of words of a BIP39 mnemonic.
+const MNEMONIC_WORDS: [usize; 5] = [12, 15, 18, 21, 24];
+
+/// BIP39 english wordlist, one word per line in alphabetical order.
+const WORDLIST: &str = include_str!("bip39_english.txt");
+
+/// Iterations of PBKDF2 deriving the BIP39 seed.
+const PBKDF2_ROUNDS: u32 = 2048;
+
+/// Signer holding its keys in memory, with the same interface as the hardware devices.
+/// The registered wallets are only kept for is_wallet_registered, and display_address
+/// returns the single key addresses without displaying them.
+pub struct HotSigner {
+    master: Xpriv,
+    secp: Secp256k1<All>,
+    wallets: Mutex<Vec<(String, String)>>,
+    /// SLIP-0019 ownership key, derived from the seed.
+    ownership_key: Option<[u8; 32]>,
+}
+
+impl HotSigner {
+    /// The signer has no seed to derive the SLIP-0019 ownership key,
+    /// see HotSigner::from_seed.
+    pub fn from_xpriv(master: Xpriv) -> Self {
+        Self {
+            master,
+            secp: Secp256k1::new(),
+            wallets: Mutex::new(Vec::new()),
+            ownership_key: None,
+        }
+    }
+
+    pub fn from_seed(seed: &[u8], network: Network) -> Result<Self, Error> {
+        let master = Xpriv::new_master(network, seed)
+            .map_err(|e| Error::InvalidParameter("seed", e.to_string()))?;
+        Ok(Self {
+            ownership_key: Some(slip19::ownership_key(seed)),
+            ..Self::from_xpriv(master)
+        })
+    }
+
+    /// The mnemonic must be made of words of the BIP39 english wordlist
+    /// with a valid checksum, see check_mnemonic.
+    pub fn from_mnemonic(
+        mnemonic: &str,
+        passphrase: &str,
+        network: Network,
+    ) -> Result<Self, Error> {
+        check_mnemonic(mnemonic)?;
+        Self::from_seed(&mnemonic_to_seed(mnemonic, passphrase)?, network)
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        self.master.fingerprint(&self.secp)
+    }
+
+    fn derive(&self, path: &DerivationPath) -> Result<Xpriv, Error> {
+        self.master
+            .derive_priv(&self.secp, path)
+            .map_err(|e| Error::InvalidParameter("path", e.to_string()))
+    }
+
+    /// Signs the taproot inputs with the keys of tap_key_origins, the psbt module
+    /// of rust-bitcoin only signs the ecdsa ones.
+    fn sign_taproot(&self, psbt: &mut Psbt) -> Result<(), Error> {
+        let fingerprint = self.fingerprint();
+        let prevouts: Option<Vec<TxOut>> =
+            psbt.inputs.iter().map(|i| i.witness_utxo.clone()).collect();
+        let tx = psbt.unsigned_tx.clone();
+        let mut cache = SighashCache::new(&tx);
+        for (i, input) in psbt.inputs.iter_mut().enumerate() {
+            let hash_ty = match input.sighash_type {
+                Some(t) => t
+                    .taproot_hash_ty()
+                    .map_err(|e| Error::InvalidParameter("sighash_type", e.to_string()))?,
+                None => TapSighashType::Default,
+            };
+            for (key, (leaves, (fg, path))) in input.tap_key_origins.clone() {
+                if fg != fingerprint {
+                    continue;
+                }
+                let keypair =
+                    Keypair::from_secret_key(&self.secp, &self.derive(&path)?.private_key);
+                if keypair.x_only_public_key().0 != key {
+                    continue;
+                }
+                let prevouts = Prevouts::All(prevouts.as_deref().ok_or(Error::UnsupportedInput)?);
+                let sighash_error =
+                    |e: bitcoin::sighash::Error| Error::InvalidParameter("psbt", e.to_string());
+                if leaves.is_empty() && input.tap_internal_key == Some(key) {
+                    let sighash = cache
+                        .taproot_key_spend_signature_hash(i, &prevouts, hash_ty)
+                        .map_err(sighash_error)?;
+                    let tweaked = keypair.tap_tweak(&self.secp, input.tap_merkle_root);
+                    let sig = self.secp.sign_schnorr_no_aux_rand(
+                        &Message::from_digest(sighash.to_byte_array()),
+                        &tweaked.to_inner(),
+                    );
+                    input.tap_key_sig = Some(taproot::Signature { sig, hash_ty });
+                }
+                for leaf in leaves {
+                    let sighash = cache
+                        .taproot_script_spend_signature_hash(i, &prevouts, leaf, hash_ty)
+                        .map_err(sighash_error)?;
+                    let sig = self.secp.sign_schnorr_no_aux_rand(
+                        &Message::from_digest(sighash.to_byte_array()),
+                        &keypair,
+                    );
+                    input
+                        .tap_script_sigs
+                        .insert((key, leaf), taproot::Signature { sig, hash_ty });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for HotSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HotSigner")
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Words of the mnemonic, NFKD normalized and lowercased.
+fn mnemonic_words(mnemonic: &str) -> Result<Vec<String>, Error> {
+    let words: Vec<String> = mnemonic
+        .split_whitespace()
+        .map(|w| w.nfkd().collect::<String>().to_lowercase())
+        .collect();
+    if !MNEMONIC_WORDS.contains(&words.len()) {
+        return Err(Error::InvalidParameter(
+            "mnemonic",
+            format!("{} words mnemonic is not supported", words.len()),
+        ));
+    }
+    Ok(words)
+}
+
+/// Checks that the words are in the BIP39 english wordlist and that the
+/// checksum matches the entropy they encode.
+pub fn check_mnemonic(mnemonic: &str) -> Result<(), Error> {
+    let wordlist: Vec<&str> = WORDLIST.lines().collect();
+    let mut bits = Vec::new();
+    for word in mnemonic_words(mnemonic)? {
+        let index = wordlist
+            .binary_search(&word.as_str())
+            .map_err(|_| Error::InvalidParameter("mnemonic", format!("unknown word {}", word)))?;
+        bits.extend((0..11).rev().map(|i| (index >> i) & 1 == 1));
+    }
+    // One bit of checksum for every 32 bits of entropy.
+    let (entropy_bits, checksum_bits) = bits.split_at(bits.len() * 32 / 33);
+    let entropy: Vec<u8> = entropy_bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | *bit as u8))
+        .collect();
+    let hash = sha256::Hash::hash(&entropy).to_byte_array();
+    let valid = checksum_bits
+        .iter()
+        .enumerate()
+        .all(|(i, bit)| (hash[i / 8] >> (7 - i % 8)) & 1 == *bit as u8);
+    if !valid {
+        return Err(Error::InvalidParameter(
+            "mnemonic",
+            "invalid checksum".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// BIP39 seed of the mnemonic, both the words and the passphrase are NFKD normalized.
+/// The words and the checksum are not verified, see check_mnemonic.
+pub fn mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> Result<[u8; 64], Error> {
+    let words = mnemonic_words(mnemonic)?;
+    let password = words.join(" ");
+    let salt = format!("mnemonic{}", passphrase.nfkd().collect::<String>());
+
+    // PBKDF2-HMAC-SHA512 with a single block of 64 bytes.
+    let prf = |data: &[u8]| {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(password.as_bytes());
+        engine.input(data);
+        hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array()
+    };
+    let mut block = salt.into_bytes();
+    block.extend(1u32.to_be_bytes());
+    let mut u = prf(&block);
+    let mut seed = u;
+    for _ in 1..PBKDF2_ROUNDS {
+        u = prf(&u);
+        seed.iter_mut().zip(u.iter()).for_each(|(s, u)| *s ^= u);
+    }
+    Ok(seed)
+}
+
+impl HWI for HotSigner {
+    fn device_kind(&self) -> DeviceKind {
+        DeviceKind::HotSigner
+    }
+
+    /// Version of the crate.
+    fn get_version(&self) -> Result<Version, Error> {
+        let number = |s: &str| s.parse().map_err(|_| Error::UnsupportedVersion);
+        Ok(Version {
+            major: number(env!("CARGO_PKG_VERSION_MAJOR"))?,
+            minor: number(env!("CARGO_PKG_VERSION_MINOR"))?,
+            patch: number(env!("CARGO_PKG_VERSION_PATCH"))?,
+            prerelease: None,
+            flavor: None,
+        })
+    }
+
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, Error> {
+        Ok(self.fingerprint())
+    }
+
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, Error> {
+        Ok(Xpub::from_priv(&self.secp, &self.derive(path)?))
+    }
+
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, Error> {
+        self.wallets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((name.to_string(), policy.to_string()));
+        Ok(WalletRegistration::default())
+    }
+
+    fn is_wallet_registered(&self, name: &str, policy: &str) -> Result<bool, Error> {
+        Ok(self
+            .wallets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .any(|(n, p)| n == name && p == policy))
+    }
+
+    /// Miniscript addresses are not derived.
+    fn display_address(&self, script: &AddressScript) -> Result<Address<NetworkUnchecked>, Error> {
+        let network = self.master.network;
+        let address_error =
+            |e: bitcoin::address::Error| Error::InvalidParameter("path", e.to_string());
+        let address = match script {
+            AddressScript::P2PKH(path) => Address::p2pkh(
+                &self.derive(path)?.to_priv().public_key(&self.secp),
+                network,
+            ),
+            AddressScript::P2SHWPKH(path) => Address::p2shwpkh(
+                &self.derive(path)?.to_priv().public_key(&self.secp),
+                network,
+            )
+            .map_err(address_error)?,
+            AddressScript::P2WPKH(path) => Address::p2wpkh(
+                &self.derive(path)?.to_priv().public_key(&self.secp),
+                network,
+            )
+            .map_err(address_error)?,
+            AddressScript::P2TR(path) => {
+                let keypair = self.derive(path)?.to_keypair(&self.secp);
+                Address::p2tr(&self.secp, keypair.x_only_public_key().0, None, network)
+            }
+            AddressScript::Miniscript { .. } => return Err(Error::UnimplementedMethod),
+        };
+        Ok(address.as_unchecked().clone())
+    }
+
+    /// The signer has no screen, the address is derived the same way.
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        _display: bool,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        self.display_address(script)
+    }
+
+    fn sign_tx(&self, psbt: &mut Psbt) -> Result<SignResult, Error> {
+        let unsigned = psbt.clone();
+        psbt.sign(&self.master, &self.secp).map_err(|(_, errors)| {
+            let (i, e) = errors.iter().next().expect("errors are not empty");
+            Error::InvalidParameter("psbt", format!("input {}: {}", i, e))
+        })?;
+        self.sign_taproot(psbt)?;
+        Ok(SignResult::new(&unsigned, psbt))
+    }
+
+    fn derive_bip85(&self, application: &bip85::Application, index: u32) -> Result<Vec<u8>, Error> {
+        bip85::derive_entropy(&self.secp, &self.master, application, index)
+    }
+
+    /// Proves the ownership of the P2WPKH, P2SH-P2WPKH and P2TR key path outputs with
+    /// the key of the input derivations, the proofs are not confirmed by the user.
+    fn prove_ownership(
+        &self,
+        input: &psbt::Input,
+        commitment_data: &[u8],
+    ) -> Result<OwnershipProof, Error> {
+        let ownership_key = self.ownership_key.ok_or(Error::UnimplementedMethod)?;
+        let script_pubkey = &input
+            .witness_utxo
+            .as_ref()
+            .ok_or(Error::UnsupportedInput)?
+            .script_pubkey;
+        let fingerprint = self.fingerprint();
+        let mut proof = OwnershipProof {
+            user_confirmed: false,
+            ownership_ids: vec![slip19::ownership_id(&ownership_key, script_pubkey)],
+            script_sig: ScriptBuf::new(),
+            witness: Witness::new(),
+        };
+        let msg = Message::from_digest(proof.sighash(script_pubkey, commitment_data));
+        if script_pubkey.is_p2tr() {
+            let path = input
+                .tap_key_origins
+                .iter()
+                .find(|(key, (leaves, (fg, _)))| {
+                    leaves.is_empty() && *fg == fingerprint && input.tap_internal_key == Some(**key)
+                })
+                .map(|(_, (_, (_, path)))| path)
+                .ok_or(Error::UnsupportedInput)?;
+            let keypair = Keypair::from_secret_key(&self.secp, &self.derive(path)?.private_key);
+            let tweaked = keypair.tap_tweak(&self.secp, input.tap_merkle_root);
+            let sig = self
+                .secp
+                .sign_schnorr_no_aux_rand(&msg, &tweaked.to_inner());
+            proof.witness.push(sig.as_ref());
+        } else {
+            let path = input
+                .bip32_derivation
+                .values()
+                .find(|(fg, _)| *fg == fingerprint)
+                .map(|(_, path)| path)
+                .ok_or(Error::UnsupportedInput)?;
+            let key = self.derive(path)?.private_key;
+            let pubkey = bitcoin::PublicKey::new(key.public_key(&self.secp));
+            if script_pubkey.is_p2sh() {
+                let wpkh = pubkey.wpubkey_hash().ok_or(Error::UnsupportedInput)?;
+                proof.script_sig = slip19::p2sh_p2wpkh_script_sig(&ScriptBuf::new_p2wpkh(&wpkh));
+            }
+            let sig = ecdsa::Signature::sighash_all(self.secp.sign_ecdsa(&msg, &key));
+            proof.witness.push(sig.serialize());
+            proof.witness.push(pubkey.to_bytes());
+        }
+        // Fails if the key does not match the script pubkey.
+        proof.verify(&self.secp, script_pubkey, commitment_data)?;
+        Ok(proof)
+    }
+
+    fn get_silent_payment_keys(&self, account: u32) -> Result<SilentPaymentKeys, Error> {
+        let coin_type = if self.master.network == Network::Bitcoin {
+            0
+        } else {
+            1
+        };
+        let derive = |branch| -> Result<Xpriv, Error> {
+            let path: Vec<ChildNumber> = [352, coin_type, account, branch]
+                .iter()
+                .map(|i| ChildNumber::from_hardened_idx(*i))
+                .chain(std::iter::once(ChildNumber::from_normal_idx(0)))
+                .collect::<Result<_, _>>()
+                .map_err(|e| Error::InvalidParameter("account", e.to_string()))?;
+            self.master
+                .derive_priv(&self.secp, &path)
+                .map_err(|e| Error::Device(e.to_string()))
+        };
+        Ok(SilentPaymentKeys {
+            scan_key: derive(1)?.private_key,
+            spend_key: derive(0)?.private_key.public_key(&self.secp),
+        })
+    }
+
+    /// The xpubs and the addresses are encoded for the network.
+    fn set_network(&mut self, network: Network) -> Result<(), Error> {
+        self.master.network = network;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(DeviceKind::HotSigner, self.get_version().ok().as_ref())
+    }
+}
+
+impl From<HotSigner> for Box<dyn HWI + Send> {
+    fn from(s: HotSigner) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+impl From<HotSigner> for std::sync::Arc<dyn HWI + Sync + Send> {
+    fn from(s: HotSigner) -> std::sync::Arc<dyn HWI + Sync + Send> {
+        std::sync::Arc::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ScriptType, SignedInput, SigningKey};
+    use bitcoin::{
+        absolute::LockTime, bip32::ChildNumber, hashes::hex::FromHex, transaction, Amount,
+        ScriptBuf, Transaction, TxIn,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_check_mnemonic() {
+        // BIP39 test vectors.
+        for mnemonic in [
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon agent",
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+            "ABANDON  abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon About",
+        ] {
+            check_mnemonic(mnemonic).unwrap();
+        }
+        assert!(matches!(
+            check_mnemonic("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon"),
+            Err(Error::InvalidParameter("mnemonic", _))
+        ));
+        assert!(matches!(
+            check_mnemonic("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abot"),
+            Err(Error::InvalidParameter("mnemonic", _))
+        ));
+        assert!(HotSigner::from_mnemonic(
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo",
+            "",
+            Network::Bitcoin
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_mnemonic_to_seed() {
+        // BIP39 test vector with the passphrase TREZOR.
+        let seed = mnemonic_to_seed(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(
+            seed.to_vec(),
+            Vec::<u8>::from_hex("c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04").unwrap()
+        );
+        assert!(mnemonic_to_seed("abandon about", "").is_err());
+
+        let signer = HotSigner::from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        // BIP84 test vector.
+        assert_eq!(
+            signer
+                .display_address(&AddressScript::P2WPKH(
+                    DerivationPath::from_str("m/84'/0'/0'/0/0").unwrap()
+                ))
+                .unwrap()
+                .assume_checked()
+                .to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+
+        let keys = signer.get_silent_payment_keys(0).unwrap();
+        let xpub = |path| {
+            signer
+                .get_extended_pubkey(&DerivationPath::from_str(path).unwrap())
+                .unwrap()
+                .public_key
+        };
+        assert_eq!(
+            keys.scan_key.public_key(&Secp256k1::new()),
+            xpub("m/352'/0'/0'/1'/0")
+        );
+        assert_eq!(keys.spend_key, xpub("m/352'/0'/0'/0'/0"));
+    }
+
+    #[test]
+    fn test_shared_device() {
+        let mut device: std::sync::Arc<dyn HWI + Sync + Send> =
+            HotSigner::from_xpriv(Xpriv::new_master(Network::Regtest, &[1; 32]).unwrap()).into();
+        let fingerprint = device.get_master_fingerprint().unwrap();
+        let shared = device.clone();
+        let other = std::thread::spawn(move || shared.get_master_fingerprint().unwrap());
+        assert_eq!(other.join().unwrap(), fingerprint);
+
+        let shared = device.clone();
+        assert!(device.set_network(Network::Testnet).is_err());
+        drop(shared);
+        assert!(device.set_network(Network::Testnet).is_ok());
+    }
+
+    #[test]
+    fn test_get_descriptor() {
+        let signer = HotSigner::from_xpriv(Xpriv::new_master(Network::Regtest, &[1; 32]).unwrap());
+        let descriptor = signer
+            .get_descriptor(ScriptType::P2WPKH, Network::Testnet, 2)
+            .unwrap();
+        let xpub = signer
+            .get_extended_pubkey(&DerivationPath::from_str("m/84'/1'/2'").unwrap())
+            .unwrap();
+        assert!(descriptor.starts_with(&format!(
+            "wpkh([{}/84'/1'/2']{}/<0;1>/*)#",
+            signer.get_master_fingerprint().unwrap(),
+            xpub
+        )));
+        // The checksum is verified.
+        #[cfg(feature = "regex")]
+        assert!(crate::policy::WalletPolicy::from_descriptor("", &descriptor).is_ok());
+        assert!(signer
+            .get_descriptor(ScriptType::P2TR, Network::Bitcoin, 0)
+            .unwrap()
+            .starts_with("tr(["));
+    }
+
+    #[test]
+    fn test_prove_ownership() {
+        use bitcoin::hex::DisplayHex;
+
+        // Test vector of SLIP-0019.
+        let signer = HotSigner::from_mnemonic(
+            "all all all all all all all all all all all all",
+            "",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        let path = DerivationPath::from_str("m/84'/0'/0'/1/0").unwrap();
+        let key = signer.derive(&path).unwrap().private_key;
+        let pubkey = bitcoin::PublicKey::new(key.public_key(&signer.secp));
+        let mut input = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+            }),
+            ..Default::default()
+        };
+        input
+            .bip32_derivation
+            .insert(pubkey.inner, (signer.fingerprint(), path));
+        let proof = signer.prove_ownership(&input, b"").unwrap();
+        assert_eq!(
+            proof.serialize().to_lower_hex_string(),
+            "534c00190001a122407efc198211c81af4450f40b235d54775efd934d16b9e31c6ce9bad57070002483045022100c0dc28bb563fc5fea76cacff75dba9cb4122412faae01937cdebccfb065f9a7002202e980bfbd8a434a7fc4cd2ca49da476ce98ca097437f8159b1a386b41fcdfac50121032ef68318c8f6aaa0adec0199c69901f0db7d3485eb38d9ad235221dc3d61154b"
+        );
+
+        let path = DerivationPath::from_str("m/86'/0'/0'/1/0").unwrap();
+        let keypair =
+            Keypair::from_secret_key(&signer.secp, &signer.derive(&path).unwrap().private_key);
+        let (internal_key, _) = keypair.x_only_public_key();
+        let script_pubkey = ScriptBuf::new_p2tr(&signer.secp, internal_key, None);
+        let mut input = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: script_pubkey.clone(),
+            }),
+            tap_internal_key: Some(internal_key),
+            ..Default::default()
+        };
+        input
+            .tap_key_origins
+            .insert(internal_key, (Vec::new(), (signer.fingerprint(), path)));
+        let proof = signer.prove_ownership(&input, b"round 1").unwrap();
+        assert!(proof
+            .verify(&signer.secp, &script_pubkey, b"round 1")
+            .is_ok());
+        assert!(proof
+            .verify(&signer.secp, &script_pubkey, b"round 2")
+            .is_err());
+
+        // Without the seed, the ownership key is unknown.
+        let signer = HotSigner::from_xpriv(signer.master);
+        assert!(matches!(
+            signer.prove_ownership(&input, b""),
+            Err(Error::UnimplementedMethod)
+        ));
+    }
+
+    #[test]
+    fn test_sign_tx() {
+        let signer = HotSigner::from_xpriv(Xpriv::new_master(Network::Regtest, &[1; 32]).unwrap());
+        let fingerprint = signer.get_master_fingerprint().unwrap();
+        let secp = Secp256k1::new();
+        let path = |change| {
+            DerivationPath::from_str("m/86'/1'/0'")
+                .unwrap()
+                .extend([ChildNumber::from(change), ChildNumber::from(0)])
+        };
+        let wpkh = signer.derive(&path(0)).unwrap().to_priv().public_key(&secp);
+        let tr = signer
+            .derive(&path(1))
+            .unwrap()
+            .to_keypair(&secp)
+            .x_only_public_key()
+            .0;
+        let utxo = |script_pubkey| TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey,
+        };
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![utxo(ScriptBuf::new())],
+        })
+        .unwrap();
+        psbt.inputs[0].witness_utxo =
+            Some(utxo(ScriptBuf::new_p2wpkh(&wpkh.wpubkey_hash().unwrap())));
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(wpkh.inner, (fingerprint, path(0)));
+        psbt.inputs[1].witness_utxo = Some(utxo(ScriptBuf::new_p2tr(&secp, tr, None)));
+        psbt.inputs[1].tap_internal_key = Some(tr);
+        psbt.inputs[1]
+            .tap_key_origins
+            .insert(tr, (Vec::new(), (fingerprint, path(1))));
+
+        let mut batch = vec![psbt.clone(), psbt.clone()];
+        assert_eq!(signer.sign_txs(&mut batch).unwrap().len(), 2);
+        assert!(batch
+            .iter()
+            .all(|signed| signed.inputs[0].partial_sigs.contains_key(&wpkh)));
+
+        let signed = signer.signed_copy(&psbt).unwrap();
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        assert!(signed.inputs[0].partial_sigs.contains_key(&wpkh));
+
+        let result = signer.sign_tx(&mut psbt).unwrap();
+        assert_eq!(
+            result.signed_inputs,
+            vec![
+                SignedInput {
+                    input: 0,
+                    key: SigningKey::Ecdsa(wpkh)
+                },
+                SignedInput {
+                    input: 1,
+                    key: SigningKey::Schnorr(tr)
+                },
+            ]
+        );
+        assert!(psbt.inputs[0].partial_sigs.contains_key(&wpkh));
+        let sig = psbt.inputs[1].tap_key_sig.unwrap();
+        let prevouts: Vec<TxOut> = psbt
+            .inputs
+            .iter()
+            .map(|i| i.witness_utxo.clone().unwrap())
+            .collect();
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .taproot_key_spend_signature_hash(1, &Prevouts::All(&prevouts), sig.hash_ty)
+            .unwrap();
+        let output_key = tr.tap_tweak(&secp, None).0.to_inner();
+        assert!(secp
+            .verify_schnorr(
+                &sig.sig,
+                &Message::from_digest(sighash.to_byte_array()),
+                &output_key
+            )
+            .is_ok());
+    }
+}
@@ -0,0 +1,210 @@
+use std::convert::{TryFrom, TryInto};
+
+use bitcoin::{
+    consensus::encode::{deserialize_partial, serialize, VarInt},
+    ecdsa,
+    hashes::{hmac, sha256, sha512, Hash, HashEngine},
+    key::XOnlyPublicKey,
+    secp256k1::{schnorr, Message, Secp256k1, Verification},
+    PublicKey, Script, ScriptBuf, Witness,
+};
+
+use crate::Error;
+
+/// See https://github.com/satoshilabs/slips/blob/master/slip-0019.md
+pub const VERSION_MAGIC: [u8; 4] = *b"SL\x00\x19";
+
+/// Flag of the proofs confirmed by the user on the device.
+const FLAG_USER_CONFIRMED: u8 = 0x01;
+
+/// SLIP-0019 proof of ownership of an output, with the BIP322 signature of its
+/// script pubkey and the commitment data of the coordinator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnershipProof {
+    /// The user confirmed the proof on the device.
+    pub user_confirmed: bool,
+    /// Ownership identifiers of the output, see ownership_id.
+    pub ownership_ids: Vec<[u8; 32]>,
+    pub script_sig: ScriptBuf,
+    pub witness: Witness,
+}
+
+impl OwnershipProof {
+    /// Proof body: the version, the flags and the ownership identifiers.
+    fn body(&self) -> Vec<u8> {
+        let mut body = VERSION_MAGIC.to_vec();
+        body.push(if self.user_confirmed {
+            FLAG_USER_CONFIRMED
+        } else {
+            0
+        });
+        body.extend(serialize(&VarInt(self.ownership_ids.len() as u64)));
+        for id in &self.ownership_ids {
+            body.extend(id);
+        }
+        body
+    }
+
+    /// Hash signed by the proof, committing to the body, the script pubkey of the
+    /// output and the commitment data.
+    pub fn sighash(&self, script_pubkey: &Script, commitment_data: &[u8]) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.body());
+        engine.input(&serialize(script_pubkey));
+        engine.input(&serialize(&commitment_data.to_vec()));
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut proof = self.body();
+        proof.extend(serialize(&self.script_sig));
+        proof.extend(serialize(&self.witness));
+        proof
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidParameter("proof", reason.to_string());
+        if bytes.len() < 5 || bytes[..4] != VERSION_MAGIC {
+            return Err(invalid("not a SLIP-0019 proof"));
+        }
+        let user_confirmed = match bytes[4] {
+            0 => false,
+            FLAG_USER_CONFIRMED => true,
+            _ => return Err(invalid("unknown flags")),
+        };
+        let mut rest = &bytes[5..];
+        let (VarInt(count), len) =
+            deserialize_partial::<VarInt>(rest).map_err(|e| invalid(&e.to_string()))?;
+        rest = &rest[len..];
+        let ids_len = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(32))
+            .filter(|ids_len| *ids_len <= rest.len())
+            .ok_or_else(|| invalid("truncated ownership identifiers"))?;
+        let ownership_ids = rest[..ids_len]
+            .chunks(32)
+            .map(|id| id.try_into().expect("chunks of 32 bytes"))
+            .collect();
+        rest = &rest[ids_len..];
+        let (script_sig, len) =
+            deserialize_partial::<ScriptBuf>(rest).map_err(|e| invalid(&e.to_string()))?;
+        rest = &rest[len..];
+        let (witness, len) =
+            deserialize_partial::<Witness>(rest).map_err(|e| invalid(&e.to_string()))?;
+        if len != rest.len() {
+            return Err(invalid("trailing data"));
+        }
+        Ok(Self {
+            user_confirmed,
+            ownership_ids,
+            script_sig,
+            witness,
+        })
+    }
+
+    /// Verifies the signature of the proof for the P2WPKH, P2SH-P2WPKH and P2TR outputs,
+    /// the other scripts are not supported.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        script_pubkey: &Script,
+        commitment_data: &[u8],
+    ) -> Result<(), Error> {
+        let msg = Message::from_digest(self.sighash(script_pubkey, commitment_data));
+        let invalid = || Error::InvalidParameter("proof", "invalid signature".to_string());
+        if script_pubkey.is_p2tr() {
+            let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+                .map_err(|_| invalid())?;
+            let sig = match self.witness.iter().collect::<Vec<_>>()[..] {
+                [sig] => schnorr::Signature::from_slice(sig).map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            };
+            return secp
+                .verify_schnorr(&sig, &msg, &output_key)
+                .map_err(|_| invalid());
+        }
+        let (sig, pubkey) = match self.witness.iter().collect::<Vec<_>>()[..] {
+            [sig, pubkey] => (
+                ecdsa::Signature::from_slice(sig).map_err(|_| invalid())?,
+                PublicKey::from_slice(pubkey).map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+        let wpkh = pubkey.wpubkey_hash().ok_or_else(invalid)?;
+        let expected = ScriptBuf::new_p2wpkh(&wpkh);
+        let matches = if script_pubkey.is_p2sh() {
+            let redeem_script = p2sh_p2wpkh_script_sig(&expected);
+            self.script_sig == redeem_script
+                && *script_pubkey == ScriptBuf::new_p2sh(&expected.script_hash())
+        } else {
+            self.script_sig.is_empty() && *script_pubkey == expected
+        };
+        if !matches {
+            return Err(invalid());
+        }
+        secp.verify_ecdsa(&msg, &sig.sig, &pubkey.inner)
+            .map_err(|_| invalid())
+    }
+}
+
+/// Script sig pushing the P2WPKH redeem script of a P2SH-P2WPKH output.
+pub fn p2sh_p2wpkh_script_sig(redeem_script: &Script) -> ScriptBuf {
+    let push: &bitcoin::script::PushBytes = redeem_script
+        .as_bytes()
+        .try_into()
+        .expect("P2WPKH script is 22 bytes");
+    bitcoin::script::Builder::new()
+        .push_slice(push)
+        .into_script()
+}
+
+/// SLIP-0021 key of the ownership identifiers, derived from the BIP39 seed.
+pub fn ownership_key(seed: &[u8]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha512::Hash>::new(b"Symmetric key seed");
+    engine.input(seed);
+    let mut node = hmac::Hmac::from_engine(engine).to_byte_array();
+    for label in [&b"SLIP-0019"[..], b"Ownership identification key"] {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(&node[..32]);
+        engine.input(&[0]);
+        engine.input(label);
+        node = hmac::Hmac::from_engine(engine).to_byte_array();
+    }
+    node[32..].try_into().expect("64 bytes node")
+}
+
+/// Identifier of the output, to recognize the outputs of the wallet in the proofs.
+pub fn ownership_id(key: &[u8; 32], script_pubkey: &Script) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(script_pubkey.as_bytes());
+    hmac::Hmac::from_engine(engine).to_byte_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::FromHex;
+
+    #[test]
+    fn test_ownership_proof() {
+        // Test vector of SLIP-0019.
+        let bytes = Vec::<u8>::from_hex("534c00190001a122407efc198211c81af4450f40b235d54775efd934d16b9e31c6ce9bad57070002483045022100c0dc28bb563fc5fea76cacff75dba9cb4122412faae01937cdebccfb065f9a7002202e980bfbd8a434a7fc4cd2ca49da476ce98ca097437f8159b1a386b41fcdfac50121032ef68318c8f6aaa0adec0199c69901f0db7d3485eb38d9ad235221dc3d61154b").unwrap();
+        let script_pubkey =
+            ScriptBuf::from_hex("0014b2f771c370ccf219cd3059cda92bdf7f00cf2103").unwrap();
+        let secp = Secp256k1::verification_only();
+
+        let proof = OwnershipProof::deserialize(&bytes).unwrap();
+        assert!(!proof.user_confirmed);
+        assert_eq!(proof.ownership_ids.len(), 1);
+        assert_eq!(proof.serialize(), bytes);
+        assert!(proof.verify(&secp, &script_pubkey, b"").is_ok());
+        assert!(proof.verify(&secp, &script_pubkey, b"data").is_err());
+
+        let mut confirmed = proof.clone();
+        confirmed.user_confirmed = true;
+        assert!(confirmed.verify(&secp, &script_pubkey, b"").is_err());
+
+        assert!(OwnershipProof::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        assert!(OwnershipProof::deserialize(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(OwnershipProof::deserialize(&bytes[..40]).is_err());
+    }
+}
@@ -1,24 +1,26 @@
 use std::fmt::Debug;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use bitcoin::{
+    address::NetworkUnchecked,
     bip32::{DerivationPath, Fingerprint, Xpub},
     psbt::Psbt,
-    taproot,
+    taproot, Address,
 };
 
-use serialport::{available_ports, SerialPortType};
-use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt},
-    net::TcpStream,
-    sync::Mutex,
-};
-use tokio_serial::SerialPortBuilderExt;
-pub use tokio_serial::SerialStream;
+use serialport::{available_ports, SerialPort, SerialPortType};
+
+/// Serial port of the device.
+pub type SerialStream = Box<dyn SerialPort>;
 
-use super::{AddressScript, DeviceKind, Error as HWIError, HWI};
-use async_trait::async_trait;
+use super::{
+    layer::{Layer, LayeredTransport},
+    AddressScript, DeviceKind, Error as HWIError, SignResult, WalletRegistration, HWI,
+};
 
 #[derive(Debug)]
 pub struct Specter<T> {
@@ -27,19 +29,25 @@ pub struct Specter<T> {
 }
 
 impl<T: Transport> Specter<T> {
-    pub async fn fingerprint(&self) -> Result<Fingerprint, SpecterError> {
+    /// Wraps the transport with the hooks of the layer.
+    pub fn with_layer<L: Layer<str, String>>(self, layer: L) -> Specter<LayeredTransport<T, L>> {
+        Specter {
+            transport: LayeredTransport::new(self.transport, layer),
+            kind: self.kind,
+        }
+    }
+
+    pub fn fingerprint(&self) -> Result<Fingerprint, SpecterError> {
         self.transport
             .request("\r\n\r\nfingerprint\r\n")
-            .await
             .and_then(|resp| {
                 Fingerprint::from_str(&resp).map_err(|e| SpecterError::Device(e.to_string()))
             })
     }
 
-    pub async fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, SpecterError> {
+    pub fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, SpecterError> {
         self.transport
             .request(&format!("\r\n\r\nxpub {}\r\n", path))
-            .await
             .and_then(|resp| Xpub::from_str(&resp).map_err(|e| SpecterError::Device(e.to_string())))
     }
 
@@ -48,7 +56,7 @@ impl<T: Transport> Specter<T> {
     /// See: https://github.com/cryptoadvance/specter-diy/blob/master/docs/descriptors.md#default-derivations
     /// If at least one of the xpubs has a wildcard derivation the descriptor will not be changed.
     /// /** is an equivalent of /{0,1}/*.
-    pub async fn add_wallet(&self, name: &str, policy: &str) -> Result<(), SpecterError> {
+    pub fn add_wallet(&self, name: &str, policy: &str) -> Result<(), SpecterError> {
         self.transport
             .request(&format!(
                 "\r\n\r\naddwallet {}&{}\r\n",
@@ -60,7 +68,6 @@ impl<T: Transport> Specter<T> {
                     .replace(';', ",")
                     .replace('>', "}")
             ))
-            .await
             .and_then(|resp| {
                 if resp.is_empty() || resp == "success" {
                     Ok(())
@@ -72,10 +79,9 @@ impl<T: Transport> Specter<T> {
             })
     }
 
-    pub async fn sign(&self, psbt: &Psbt) -> Result<Psbt, SpecterError> {
+    pub fn sign(&self, psbt: &Psbt) -> Result<Psbt, SpecterError> {
         self.transport
             .request(&format!("\r\n\r\nsign {}\r\n", psbt))
-            .await
             .and_then(|resp| {
                 if resp == "error: User cancelled" {
                     Err(SpecterError::UserCancelled)
@@ -86,43 +92,42 @@ impl<T: Transport> Specter<T> {
     }
 }
 
-#[async_trait]
 impl<T: Transport + Sync + Send> HWI for Specter<T> {
     fn device_kind(&self) -> DeviceKind {
         self.kind
     }
 
-    async fn get_version(&self) -> Result<super::Version, HWIError> {
+    fn get_version(&self) -> Result<super::Version, HWIError> {
         Err(HWIError::UnimplementedMethod)
     }
 
-    async fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
-        Ok(self.fingerprint().await?)
+    fn get_master_fingerprint(&self) -> Result<Fingerprint, HWIError> {
+        Ok(self.fingerprint()?)
     }
 
-    async fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
-        Ok(self.get_extended_pubkey(path).await?)
+    fn get_extended_pubkey(&self, path: &DerivationPath) -> Result<Xpub, HWIError> {
+        Ok(self.get_extended_pubkey(path)?)
     }
 
-    async fn display_address(&self, _script: &AddressScript) -> Result<(), HWIError> {
+    fn display_address(
+        &self,
+        _script: &AddressScript,
+    ) -> Result<Address<NetworkUnchecked>, HWIError> {
         Err(HWIError::UnimplementedMethod)
     }
 
-    async fn register_wallet(
-        &self,
-        name: &str,
-        policy: &str,
-    ) -> Result<Option<[u8; 32]>, HWIError> {
-        self.add_wallet(name, policy).await?;
-        Ok(None)
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, HWIError> {
+        self.add_wallet(name, policy)?;
+        Ok(WalletRegistration::default())
     }
 
-    async fn is_wallet_registered(&self, _name: &str, _policy: &str) -> Result<bool, HWIError> {
+    fn is_wallet_registered(&self, _name: &str, _policy: &str) -> Result<bool, HWIError> {
         Err(HWIError::UnimplementedMethod)
     }
 
-    async fn sign_tx(&self, psbt: &mut Psbt) -> Result<(), HWIError> {
-        let mut new_psbt = self.sign(psbt).await?;
+    fn sign_tx(&self, psbt: &mut Psbt) -> Result<SignResult, HWIError> {
+        let unsigned = psbt.clone();
+        let mut new_psbt = self.sign(psbt)?;
         // Psbt returned by specter wallet has all unnecessary fields removed,
         // only global transaction and partial signatures for all inputs remain in it.
         // In order to have the full Psbt, the partial_sigs are extracted and appended
@@ -161,7 +166,7 @@ impl<T: Transport + Sync + Send> HWI for Specter<T> {
             return Err(SpecterError::DeviceDidNotSign.into());
         }
 
-        Ok(())
+        Ok(SignResult::new(&unsigned, psbt))
     }
 }
 
@@ -171,20 +176,24 @@ impl<T: 'static + Transport + Sync + Send> From<Specter<T>> for Box<dyn HWI + Se
     }
 }
 
-async fn exchange<T: Unpin + AsyncRead + AsyncWrite>(
+fn exchange<T: Read + Write + ?Sized>(
     transport: &mut T,
     req: &str,
 ) -> Result<String, SpecterError> {
+    // The arguments may hold a psbt or a descriptor, only the command is logged.
+    debug_exchange!(
+        command = req.split_whitespace().next().unwrap_or_default(),
+        "specter request"
+    );
     transport
         .write_all(req.as_bytes())
-        .await
         .map_err(|e| SpecterError::Device(e.to_string()))?;
 
-    let reader = tokio::io::BufReader::new(transport);
+    let reader = BufReader::new(transport);
     let mut lines = reader.lines();
     if let Some(line) = lines
-        .next_line()
-        .await
+        .next()
+        .transpose()
         .map_err(|e| SpecterError::Device(e.to_string()))?
     {
         if line != "ACK" {
@@ -195,34 +204,48 @@ async fn exchange<T: Unpin + AsyncRead + AsyncWrite>(
     }
 
     if let Some(line) = lines
-        .next_line()
-        .await
+        .next()
+        .transpose()
         .map_err(|e| SpecterError::Device(e.to_string()))?
     {
+        debug_exchange!(len = line.len(), "specter response");
         return Ok(line);
     }
     Err(SpecterError::Device("Unexpected".to_string()))
 }
 
-#[async_trait]
 pub trait Transport: Debug {
-    async fn request(&self, req: &str) -> Result<String, SpecterError>;
+    fn request(&self, req: &str) -> Result<String, SpecterError>;
+}
+
+impl<T: Transport + Sync + Send, L: Layer<str, String>> Transport for LayeredTransport<T, L> {
+    fn request(&self, req: &str) -> Result<String, SpecterError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.layer.pre(req);
+            let res = self.transport.request(req);
+            self.layer
+                .post(req, res.as_ref().map_err(|e| e as &dyn Debug));
+            match res {
+                Err(_) if self.layer.retry(req, attempt) => continue,
+                res => return res,
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct TcpTransport;
 pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8789";
 
-#[async_trait]
 impl Transport for TcpTransport {
-    async fn request(&self, req: &str) -> Result<String, SpecterError> {
-        let mut transport = TcpStream::connect(DEFAULT_ADDRESS)
-            .await
-            .map_err(|e| SpecterError::Device(e.to_string()))?;
-        let res = exchange(&mut transport, req).await;
+    fn request(&self, req: &str) -> Result<String, SpecterError> {
+        let mut transport =
+            TcpStream::connect(DEFAULT_ADDRESS).map_err(|e| SpecterError::Device(e.to_string()))?;
+        let res = exchange(&mut transport, req);
         transport
-            .shutdown()
-            .await
+            .shutdown(Shutdown::Both)
             .map_err(|e| SpecterError::Device(e.to_string()))?;
         res
     }
@@ -231,12 +254,12 @@ impl Transport for TcpTransport {
 pub type SpecterSimulator = Specter<TcpTransport>;
 
 impl SpecterSimulator {
-    pub async fn try_connect() -> Result<Self, HWIError> {
+    pub fn try_connect() -> Result<Self, HWIError> {
         let s = SpecterSimulator {
             transport: TcpTransport {},
             kind: DeviceKind::SpecterSimulator,
         };
-        let _ = s.get_master_fingerprint().await?;
+        let _ = s.get_master_fingerprint()?;
         Ok(s)
     }
 }
@@ -249,11 +272,11 @@ impl Specter<SerialTransport> {
             kind: DeviceKind::Specter,
         })
     }
-    pub async fn enumerate() -> Result<Vec<Self>, SpecterError> {
+    pub fn enumerate() -> Result<Vec<Self>, SpecterError> {
         let mut res = Vec::new();
         for port_name in SerialTransport::enumerate_potential_ports()? {
             let specter = Specter::<SerialTransport>::new(port_name)?;
-            if specter.get_master_fingerprint().await.is_ok() {
+            if specter.get_master_fingerprint().is_ok() {
                 res.push(specter);
             }
         }
@@ -261,6 +284,38 @@ impl Specter<SerialTransport> {
     }
 }
 
+/// Declarative options of a Specter connection, see connect_with_options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum ConnectOptions {
+    /// Device plugged in with USB, the first Specter answering if no port is given.
+    Serial { port: Option<String> },
+    /// Simulator listening on the port of TcpTransport.
+    Simulator,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self::Serial { port: None }
+    }
+}
+
+/// Connects the Specter described by the options.
+pub fn connect_with_options(options: &ConnectOptions) -> Result<Box<dyn HWI + Send>, HWIError> {
+    match options {
+        ConnectOptions::Serial { port: Some(port) } => {
+            Ok(Specter::<SerialTransport>::new(port.clone())?.into())
+        }
+        ConnectOptions::Serial { port: None } => Specter::<SerialTransport>::enumerate()?
+            .into_iter()
+            .next()
+            .map(|device| device.into())
+            .ok_or(HWIError::DeviceNotFound),
+        ConnectOptions::Simulator => Ok(SpecterSimulator::try_connect()?.into()),
+    }
+}
+
 #[derive(Debug)]
 pub struct SerialTransport {
     stream: Arc<Mutex<SerialStream>>,
@@ -271,8 +326,10 @@ impl SerialTransport {
     pub const SPECTER_PID: u16 = 38914;
 
     pub fn new(port_name: String) -> Result<Self, SpecterError> {
-        let stream = tokio_serial::new(port_name, 9600)
-            .open_native_async()
+        // The device answers once the user confirmed the request.
+        let stream = serialport::new(port_name, 9600)
+            .timeout(Duration::MAX)
+            .open()
             .map_err(|e| SpecterError::Device(e.to_string()))?;
         Ok(Self {
             stream: Arc::new(Mutex::new(stream)),
@@ -304,15 +361,17 @@ impl SerialTransport {
     }
 }
 
-async fn exchange_serial(transport: &mut SerialStream, req: &str) -> Result<String, SpecterError> {
-    exchange(transport, req).await
+fn exchange_serial(transport: &mut SerialStream, req: &str) -> Result<String, SpecterError> {
+    exchange(transport, req)
 }
 
-#[async_trait]
 impl Transport for SerialTransport {
-    async fn request(&self, req: &str) -> Result<String, SpecterError> {
-        let mut transport = self.stream.lock().await;
-        exchange_serial(&mut transport, req).await
+    fn request(&self, req: &str) -> Result<String, SpecterError> {
+        let mut transport = self
+            .stream
+            .lock()
+            .map_err(|_| SpecterError::Device("Failed to unlock".to_string()))?;
+        exchange_serial(&mut transport, req)
     }
 }
 
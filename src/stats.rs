@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bitcoin::Network;
+
+use crate::{DeviceKind, Error, Version, HWI};
+
+/// Device operation reported to the hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    GetVersion,
+    GetMasterFingerprint,
+    GetExtendedPubkey,
+    RegisterWallet,
+    IsWalletRegistered,
+    DisplayAddress,
+    GetAddress,
+    SignTx,
+    DeriveBip85,
+    ProveOwnership,
+    GetSilentPaymentKeys,
+    GetLabel,
+    SetLabel,
+    ListRegisteredWallets,
+    VerifyAttestation,
+    GetDescriptor,
+    SetNetwork,
+    LoadWallet,
+    ResetConnection,
+}
+
+/// Outcome of an operation, the errors are not detailed to keep the report anonymous.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Success,
+    UserRefused,
+    Failure,
+}
+
+impl<T> From<&Result<T, Error>> for Outcome {
+    fn from(res: &Result<T, Error>) -> Self {
+        match res {
+            Ok(_) => Outcome::Success,
+            Err(Error::UserRefused) => Outcome::UserRefused,
+            Err(_) => Outcome::Failure,
+        }
+    }
+}
+
+/// Report of a device operation, it holds no key, address, policy or transaction data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub kind: DeviceKind,
+    /// Firmware or app version, known once get_version succeeded, see Stats::with_version.
+    pub version: Option<Version>,
+    pub operation: Operation,
+    pub outcome: Outcome,
+    /// Duration of the call, including the time the user took to confirm on the device.
+    pub duration: Duration,
+}
+
+/// Receiver of the reports implemented by the host, which decides where to send them.
+/// The crate itself does not send anything over the network.
+pub trait StatsHook: Send + Sync {
+    fn report(&self, report: &Report);
+}
+
+impl<F: Fn(&Report) + Send + Sync> StatsHook for F {
+    fn report(&self, report: &Report) {
+        self(report)
+    }
+}
+
+/// Device and operation of the aggregated reports.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MetricsKey {
+    pub kind: DeviceKind,
+    pub version: Option<Version>,
+    pub operation: Operation,
+}
+
+/// Aggregated reports of an operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationMetrics {
+    pub calls: u64,
+    pub refusals: u64,
+    pub failures: u64,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+}
+
+impl OperationMetrics {
+    pub fn mean_duration(&self) -> Option<Duration> {
+        u32::try_from(self.calls)
+            .ok()
+            .filter(|calls| *calls > 0)
+            .map(|calls| self.total_duration / calls)
+    }
+}
+
+/// Hook aggregating the reports in memory by device kind, version and operation,
+/// clones share the metrics: one is given to Stats, the host reads the other.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<HashMap<MetricsKey, OperationMetrics>>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> HashMap<MetricsKey, OperationMetrics> {
+        self.0.lock().map(|m| m.clone()).unwrap_or_default()
+    }
+
+    /// Returns the metrics and restarts from zero, to export them periodically.
+    pub fn take(&self) -> HashMap<MetricsKey, OperationMetrics> {
+        self.0
+            .lock()
+            .map(|mut m| std::mem::take(&mut *m))
+            .unwrap_or_default()
+    }
+}
+
+impl StatsHook for Metrics {
+    fn report(&self, report: &Report) {
+        if let Ok(mut metrics) = self.0.lock() {
+            let entry = metrics
+                .entry(MetricsKey {
+                    kind: report.kind,
+                    version: report.version.clone(),
+                    operation: report.operation,
+                })
+                .or_default();
+            entry.calls += 1;
+            match report.outcome {
+                Outcome::Success => {}
+                Outcome::UserRefused => entry.refusals += 1,
+                Outcome::Failure => entry.failures += 1,
+            }
+            entry.total_duration += report.duration;
+            entry.max_duration = entry.max_duration.max(report.duration);
+        }
+    }
+}
+
+/// Hook logging the reports with tracing, an audit trail of the device interactions.
+/// The failures are logged as warnings.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingHook;
+
+#[cfg(feature = "tracing")]
+impl StatsHook for TracingHook {
+    fn report(&self, report: &Report) {
+        let duration_ms = report.duration.as_millis() as u64;
+        match report.outcome {
+            Outcome::Failure => tracing::warn!(
+                kind = %report.kind,
+                operation = ?report.operation,
+                duration_ms,
+                "device operation failed"
+            ),
+            outcome => tracing::info!(
+                kind = %report.kind,
+                operation = ?report.operation,
+                ?outcome,
+                duration_ms,
+                "device operation"
+            ),
+        }
+    }
+}
+
+/// Device logging each operation with its duration and outcome, see TracingHook.
+#[cfg(feature = "tracing")]
+pub type LoggingHWI<T> = Stats<T>;
+
+/// Stats wraps a device and reports the outcome of each operation to the hook.
+pub struct Stats<T> {
+    device: T,
+    hook: Box<dyn StatsHook>,
+    version: Mutex<Option<Version>>,
+}
+
+impl<T: HWI> Stats<T> {
+    pub fn new<H: StatsHook + 'static>(device: T, hook: H) -> Self {
+        Self {
+            device,
+            hook: Box::new(hook),
+            version: Mutex::new(None),
+        }
+    }
+
+    /// Version reported before the first get_version call.
+    pub fn with_version(self, version: Version) -> Self {
+        Self {
+            version: Mutex::new(Some(version)),
+            ..self
+        }
+    }
+
+    /// Logs the operations with tracing.
+    #[cfg(feature = "tracing")]
+    pub fn logging(device: T) -> LoggingHWI<T> {
+        Self::new(device, TracingHook)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    fn measure<R>(
+        &self,
+        operation: Operation,
+        call: impl FnOnce() -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let start = Instant::now();
+        let res = call();
+        self.report(operation, &res, start);
+        res
+    }
+
+    fn report<R>(&self, operation: Operation, res: &Result<R, Error>, start: Instant) {
+        self.hook.report(&Report {
+            kind: self.device.device_kind(),
+            version: self.version(),
+            operation,
+            outcome: Outcome::from(res),
+            duration: start.elapsed(),
+        });
+    }
+
+    fn version(&self) -> Option<Version> {
+        self.version.lock().ok().and_then(|v| v.clone())
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Stats<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stats")
+            .field("device", &self.device)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: HWI> crate::delegate::Delegate for Stats<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.device
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(&mut self.device)
+    }
+
+    /// get_extended_pubkeys and sign_txs are reported once for all the paths and psbts.
+    fn call<R>(
+        &self,
+        operation: Operation,
+        call: impl FnOnce(&T) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.measure(operation, || call(&self.device))
+    }
+
+    fn get_version(&self) -> Result<Version, Error> {
+        self.measure(Operation::GetVersion, || {
+            let version = self.device.get_version();
+            if let (Ok(version), Ok(mut cached)) = (&version, self.version.lock()) {
+                *cached = Some(version.clone());
+            }
+            version
+        })
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<(), Error> {
+        let start = Instant::now();
+        let res = self.device.set_network(network);
+        self.report(Operation::SetNetwork, &res, start);
+        res
+    }
+
+    fn load_wallet(
+        &mut self,
+        name: &str,
+        policy: &str,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        let res = self.device.load_wallet(name, policy, hmac);
+        self.report(Operation::LoadWallet, &res, start);
+        res
+    }
+
+    fn reset_connection(&self) -> Result<(), Error> {
+        self.measure(Operation::ResetConnection, || {
+            self.device.reset_connection()
+        })
+    }
+}
+
+delegate_hwi!([T: HWI] Stats<T>);
+
+impl<T: 'static + HWI + Send> From<Stats<T>> for Box<dyn HWI + Send> {
+    fn from(s: Stats<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockDevice;
+    use bitcoin::bip32::Fingerprint;
+    use std::sync::{Arc, Mutex};
+
+    fn device() -> MockDevice {
+        MockDevice {
+            version: Err(Error::DeviceNotFound),
+            fingerprint: Ok(Fingerprint::default()),
+            register: Err(Error::UserRefused),
+            ..MockDevice::new(DeviceKind::Coldcard)
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        let device = Stats::new(device(), move |r: &Report| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((r.kind, r.operation, r.outcome))
+        });
+        assert!(device.get_master_fingerprint().is_ok());
+        assert!(device.get_version().is_err());
+        assert!(device.register_wallet("vault", "").is_err());
+        // Not a device operation.
+        device.capabilities();
+
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![
+                (
+                    DeviceKind::Coldcard,
+                    Operation::GetMasterFingerprint,
+                    Outcome::Success
+                ),
+                (
+                    DeviceKind::Coldcard,
+                    Operation::GetVersion,
+                    Outcome::Failure
+                ),
+                (
+                    DeviceKind::Coldcard,
+                    Operation::RegisterWallet,
+                    Outcome::UserRefused
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        let version = Version::new(5, 4, 0);
+        let device = Stats::new(device(), metrics.clone()).with_version(version.clone());
+        assert!(device.get_master_fingerprint().is_ok());
+        assert!(device.get_master_fingerprint().is_ok());
+        assert!(device.register_wallet("vault", "").is_err());
+        assert!(device.get_version().is_err());
+
+        let key = |operation| MetricsKey {
+            kind: DeviceKind::Coldcard,
+            version: Some(version.clone()),
+            operation,
+        };
+        let snapshot = metrics.take();
+        assert_eq!(snapshot.len(), 3);
+        let fingerprint = snapshot[&key(Operation::GetMasterFingerprint)];
+        assert_eq!((fingerprint.calls, fingerprint.failures), (2, 0));
+        assert!(fingerprint.mean_duration().unwrap() <= fingerprint.max_duration);
+        assert_eq!(snapshot[&key(Operation::RegisterWallet)].refusals, 1);
+        assert_eq!(snapshot[&key(Operation::GetVersion)].failures, 1);
+        assert!(metrics.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,464 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::Fingerprint,
+    hashes::{sha256, Hash},
+    hex::{DisplayHex, FromHex},
+    psbt::Psbt,
+    secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey},
+    Address, Amount, Network, ScriptBuf, Txid,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    utils, AddressScript, DeviceKind, Error, InputSignature, SignResult, SignWarning, Version,
+    WalletRegistration, HWI,
+};
+
+/// Output of a signed transaction as reviewed on the device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewedOutput {
+    /// None if the network is unknown or the script has no address.
+    pub address: Option<Address<NetworkUnchecked>>,
+    pub script_pubkey: ScriptBuf,
+    pub amount: Amount,
+}
+
+/// Step of the session verified by the user on the device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Verification {
+    /// The wallet policy was shown and registered, not recorded for the policies
+    /// the device had already registered.
+    PolicyShown { name: String, policy: String },
+    /// The address was displayed for verification.
+    AddressVerified { address: Address<NetworkUnchecked> },
+    /// The outputs were approved and the inputs signed.
+    OutputsApproved {
+        txid: Txid,
+        outputs: Vec<ReviewedOutput>,
+        warnings: Vec<SignWarning>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub verification: Verification,
+}
+
+/// Identity of the device, the fields it failed to answer are None.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub kind: DeviceKind,
+    pub fingerprint: Option<Fingerprint>,
+    pub version: Option<Version>,
+}
+
+/// Transcript of a session, see Transcript::document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptDocument {
+    pub device: DeviceIdentity,
+    /// Seconds since the unix epoch.
+    pub started: u64,
+    pub entries: Vec<Entry>,
+}
+
+/// Transcript signed by the host for audit archives.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTranscript {
+    pub document: TranscriptDocument,
+    /// Identifier of the host key, see HostSigner::key_id.
+    pub signer: String,
+    pub signature: String,
+}
+
+impl SignedTranscript {
+    /// Checks the signature of a transcript signed with a secp256k1 SecretKey.
+    pub fn verify_ecdsa(&self) -> bool {
+        let (key, sig) = match (
+            PublicKey::from_slice(&Vec::<u8>::from_hex(&self.signer).unwrap_or_default()),
+            ecdsa::Signature::from_der(&Vec::<u8>::from_hex(&self.signature).unwrap_or_default()),
+        ) {
+            (Ok(key), Ok(sig)) => (key, sig),
+            _ => return false,
+        };
+        match serde_json::to_vec(&self.document) {
+            Ok(document) => Secp256k1::verification_only()
+                .verify_ecdsa(&digest(&document), &sig, &key)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Key of the host signing the transcripts, it may be held by an HSM.
+pub trait HostSigner {
+    /// Identifier of the key, written in the signed transcript.
+    fn key_id(&self) -> String;
+    /// Signature of the JSON serialization of the document.
+    fn sign(&self, document: &[u8]) -> Result<String, Error>;
+}
+
+/// ECDSA signature of the sha256 of the document, the key id is the public key in hex.
+impl HostSigner for SecretKey {
+    fn key_id(&self) -> String {
+        self.public_key(&Secp256k1::signing_only())
+            .serialize()
+            .to_lower_hex_string()
+    }
+
+    fn sign(&self, document: &[u8]) -> Result<String, Error> {
+        let sig = Secp256k1::signing_only().sign_ecdsa(&digest(document), self);
+        Ok(sig.serialize_der().to_lower_hex_string())
+    }
+}
+
+fn digest(document: &[u8]) -> Message {
+    Message::from_digest(sha256::Hash::hash(document).to_byte_array())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Transcript wraps a device and records the steps the user verified on it:
+/// policies shown for registration, displayed addresses and approved transactions.
+/// Failed or refused calls are not recorded.
+#[derive(Debug)]
+pub struct Transcript<T> {
+    device: T,
+    network: Option<Network>,
+    started: u64,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl<T: HWI> Transcript<T> {
+    pub fn new(device: T) -> Self {
+        Self {
+            device,
+            network: None,
+            started: now(),
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The network gives the addresses of the approved outputs.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Document of the session, the device is asked for its identity.
+    pub fn document(&self) -> TranscriptDocument {
+        TranscriptDocument {
+            device: DeviceIdentity {
+                kind: self.device.device_kind(),
+                fingerprint: self.device.get_master_fingerprint().ok(),
+                version: self.device.get_version().ok(),
+            },
+            started: self.started,
+            entries: self.entries(),
+        }
+    }
+
+    /// Signs the document of the session with the host key.
+    pub fn export(&self, signer: &dyn HostSigner) -> Result<SignedTranscript, Error> {
+        let document = self.document();
+        let bytes = serde_json::to_vec(&document)
+            .map_err(|e| Error::InvalidParameter("document", e.to_string()))?;
+        Ok(SignedTranscript {
+            signature: signer.sign(&bytes)?,
+            signer: signer.key_id(),
+            document,
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.device
+    }
+
+    fn record(&self, verification: Verification) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Entry {
+                timestamp: now(),
+                verification,
+            });
+    }
+
+    fn record_signature(&self, tx: &Psbt, result: &SignResult) {
+        let outputs = tx
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|output| ReviewedOutput {
+                address: self.network.and_then(|network| {
+                    Address::from_script(&output.script_pubkey, network)
+                        .ok()
+                        .map(|address| address.as_unchecked().clone())
+                }),
+                script_pubkey: output.script_pubkey.clone(),
+                amount: output.value,
+            })
+            .collect();
+        self.record(Verification::OutputsApproved {
+            txid: tx.unsigned_tx.txid(),
+            outputs,
+            warnings: result.warnings.clone(),
+        });
+    }
+}
+
+impl<T: HWI> crate::delegate::Delegate for Transcript<T> {
+    type Inner = T;
+
+    fn inner(&self) -> &T {
+        &self.device
+    }
+
+    fn inner_mut(&mut self) -> Result<&mut T, Error> {
+        Ok(&mut self.device)
+    }
+
+    fn register_wallet(&self, name: &str, policy: &str) -> Result<WalletRegistration, Error> {
+        let registration = self.device.register_wallet(name, policy)?;
+        if !registration.already_registered {
+            self.record(Verification::PolicyShown {
+                name: name.to_string(),
+                policy: policy.to_string(),
+            });
+        }
+        Ok(registration)
+    }
+
+    fn display_address(&self, script: &AddressScript) -> Result<Address<NetworkUnchecked>, Error> {
+        let address = self.device.display_address(script)?;
+        self.record(Verification::AddressVerified {
+            address: address.clone(),
+        });
+        Ok(address)
+    }
+
+    fn get_address(
+        &self,
+        script: &AddressScript,
+        display: bool,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        if display {
+            return HWI::display_address(self, script);
+        }
+        self.device.get_address(script, false)
+    }
+
+    fn sign_tx(&self, tx: &mut Psbt) -> Result<SignResult, Error> {
+        let result = self.device.sign_tx(tx);
+        if let Ok(result) = &result {
+            self.record_signature(tx, result);
+        }
+        result
+    }
+
+    fn sign_tx_progressive(
+        &self,
+        tx: &mut Psbt,
+        on_signature: &mut dyn FnMut(usize, InputSignature),
+    ) -> Result<SignResult, Error> {
+        let result = self.device.sign_tx_progressive(tx, on_signature);
+        if let Ok(result) = &result {
+            self.record_signature(tx, result);
+        }
+        result
+    }
+
+    /// The batch goes to the device at once, each approved psbt is recorded. On a
+    /// failure, the psbts signed before it are still recorded.
+    fn sign_txs(&self, txs: &mut [Psbt]) -> Result<Vec<SignResult>, Error> {
+        let unsigned = txs.to_vec();
+        match self.device.sign_txs(txs) {
+            Ok(results) => {
+                for (tx, result) in txs.iter().zip(&results) {
+                    self.record_signature(tx, result);
+                }
+                Ok(results)
+            }
+            Err(e) => {
+                for (unsigned, tx) in unsigned.iter().zip(txs.iter()) {
+                    if !utils::new_signatures(unsigned, tx).is_empty() {
+                        self.record_signature(tx, &SignResult::new(unsigned, tx));
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<(), Error> {
+        self.device.set_network(network)?;
+        self.network = Some(network);
+        Ok(())
+    }
+}
+
+delegate_hwi!([T: HWI] Transcript<T>);
+
+impl<T: 'static + HWI + Send> From<Transcript<T>> for Box<dyn HWI + Send> {
+    fn from(s: Transcript<T>) -> Box<dyn HWI + Send> {
+        Box::new(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockDevice;
+    use bitcoin::{
+        absolute::LockTime, bip32::DerivationPath, transaction, Transaction, TxIn, TxOut,
+    };
+    use std::str::FromStr;
+
+    /// Device approving everything.
+    fn approving() -> MockDevice {
+        MockDevice {
+            version: Err(Error::DeviceLocked { code: None }),
+            fingerprint: Ok(Fingerprint::from_str("f5acc2fd").unwrap()),
+            register: Err(Error::UserRefused),
+            address: Ok(Address::from_str("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu").unwrap()),
+            sign: Ok(SignResult::default()),
+            ..MockDevice::new(DeviceKind::Coldcard)
+        }
+    }
+
+    #[test]
+    fn test_transcript() {
+        let device = Transcript::new(approving()).with_network(Network::Bitcoin);
+        let address = device
+            .display_address(&AddressScript::P2WPKH(
+                DerivationPath::from_str("m/84'/0'/0'/0/0").unwrap(),
+            ))
+            .unwrap();
+        // Refused steps are not recorded.
+        assert!(device.register_wallet("vault", "wsh(pk(@0/**))").is_err());
+        // Nor are the addresses not displayed.
+        assert!(matches!(
+            device.get_address(
+                &AddressScript::P2WPKH(DerivationPath::from_str("m/84'/0'/0'/0/1").unwrap()),
+                false
+            ),
+            Err(Error::UnimplementedMethod)
+        ));
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: address.clone().assume_checked().script_pubkey(),
+            }],
+        })
+        .unwrap();
+        device.sign_tx(&mut psbt).unwrap();
+
+        let entries = device.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].verification,
+            Verification::AddressVerified {
+                address: address.clone()
+            }
+        );
+        match &entries[1].verification {
+            Verification::OutputsApproved { txid, outputs, .. } => {
+                assert_eq!(*txid, psbt.unsigned_tx.txid());
+                assert_eq!(outputs[0].address, Some(address));
+            }
+            v => panic!("unexpected {:?}", v),
+        }
+
+        let key = SecretKey::from_slice(&[3; 32]).unwrap();
+        let mut signed = device.export(&key).unwrap();
+        assert_eq!(signed.document.device.version, None);
+        assert!(signed.verify_ecdsa());
+        let json = serde_json::to_string(&signed).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SignedTranscript>(&json).unwrap(),
+            signed
+        );
+
+        signed.document.entries.pop();
+        assert!(!signed.verify_ecdsa());
+    }
+
+    #[test]
+    fn test_transcript_registration() {
+        let device = Transcript::new(MockDevice {
+            register: Ok(WalletRegistration {
+                already_registered: true,
+                ..Default::default()
+            }),
+            ..approving()
+        });
+        device.register_wallet("vault", "wsh(pk(@0/**))").unwrap();
+        assert!(device.entries().is_empty());
+
+        let device = Transcript::new(MockDevice {
+            register: Ok(WalletRegistration::default()),
+            ..approving()
+        });
+        device.register_wallet("vault", "wsh(pk(@0/**))").unwrap();
+        assert_eq!(
+            device.entries()[0].verification,
+            Verification::PolicyShown {
+                name: "vault".to_string(),
+                policy: "wsh(pk(@0/**))".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_transcript_batch() {
+        let device = Transcript::new(approving());
+        let mut psbts: Vec<Psbt> = (0..2)
+            .map(|i| {
+                Psbt::from_unsigned_tx(Transaction {
+                    version: transaction::Version::TWO,
+                    lock_time: LockTime::from_consensus(i),
+                    input: vec![TxIn::default()],
+                    output: vec![TxOut {
+                        value: Amount::from_sat(10_000),
+                        script_pubkey: ScriptBuf::new(),
+                    }],
+                })
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(device.sign_txs(&mut psbts).unwrap().len(), 2);
+
+        let txids: Vec<Txid> = device
+            .entries()
+            .into_iter()
+            .map(|entry| match entry.verification {
+                Verification::OutputsApproved { txid, .. } => txid,
+                v => panic!("unexpected {:?}", v),
+            })
+            .collect();
+        assert_eq!(
+            txids,
+            vec![psbts[0].unsigned_tx.txid(), psbts[1].unsigned_tx.txid()]
+        );
+    }
+}
@@ -0,0 +1,1217 @@
+//! Uniform Resources (BCR-2020-005) of the registry types crypto-psbt, crypto-hdkey,
+//! crypto-account and bytes, with the multi-part fountain encoding used by the QR codes
+//! of the air-gapped wallets.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::{TryFrom, TryInto};
+use std::str::FromStr;
+
+use bitcoin::{
+    bip32::{ChainCode, ChildNumber, DerivationPath, Fingerprint, KeySource, Xpub},
+    hashes::{sha256, Hash},
+    psbt::Psbt,
+    secp256k1::PublicKey,
+    Network,
+};
+
+use crate::ScriptType;
+
+pub const CRYPTO_PSBT: &str = "crypto-psbt";
+pub const CRYPTO_HDKEY: &str = "crypto-hdkey";
+pub const CRYPTO_ACCOUNT: &str = "crypto-account";
+/// Maximum number of fragments of a multi-part UR accepted by the Decoder, the
+/// mixed parts are sampled among all of them.
+pub const MAX_SEQ_LEN: usize = 10_000;
+
+/// Maximum length of the message of a multi-part UR accepted by the Decoder.
+pub const MAX_MESSAGE_LEN: usize = 4_000_000;
+
+/// Raw bytes, e.g. the multisig configuration files scanned by Keystone and Passport.
+pub const BYTES: &str = "bytes";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The string is not a UR, or a part of a multi-part UR.
+    InvalidUr(&'static str),
+    /// The bytewords or the checksum of the message do not match.
+    Checksum,
+    /// The CBOR is not of the expected registry type.
+    InvalidCbor(&'static str),
+    /// The part belongs to another message than the parts already received.
+    PartMismatch,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidUr(e) => write!(f, "Invalid UR: {}", e),
+            Error::Checksum => write!(f, "Invalid UR checksum"),
+            Error::InvalidCbor(e) => write!(f, "Invalid UR CBOR: {}", e),
+            Error::PartMismatch => write!(f, "UR part of another message"),
+        }
+    }
+}
+
+/// Registry type and CBOR of a UR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ur {
+    pub ur_type: String,
+    pub cbor: Vec<u8>,
+}
+
+impl Ur {
+    pub fn new(ur_type: impl Into<String>, cbor: Vec<u8>) -> Self {
+        Self {
+            ur_type: ur_type.into(),
+            cbor,
+        }
+    }
+
+    pub fn from_psbt(psbt: &Psbt) -> Self {
+        let mut cbor = Vec::new();
+        cbor::bytes(&mut cbor, &psbt.serialize());
+        Self::new(CRYPTO_PSBT, cbor)
+    }
+
+    pub fn to_psbt(&self) -> Result<Psbt, Error> {
+        self.expect_type(CRYPTO_PSBT)?;
+        let mut reader = cbor::Reader::new(&self.cbor);
+        let bytes = reader.bytes()?;
+        reader.end()?;
+        Psbt::deserialize(bytes).map_err(|_| Error::InvalidCbor("invalid psbt"))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut cbor = Vec::new();
+        cbor::bytes(&mut cbor, data);
+        Self::new(BYTES, cbor)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.expect_type(BYTES)?;
+        let mut reader = cbor::Reader::new(&self.cbor);
+        let bytes = reader.bytes()?;
+        reader.end()?;
+        Ok(bytes.to_vec())
+    }
+
+    pub fn from_hdkey(key: &HdKey) -> Self {
+        let mut cbor = Vec::new();
+        key.write(&mut cbor);
+        Self::new(CRYPTO_HDKEY, cbor)
+    }
+
+    pub fn to_hdkey(&self) -> Result<HdKey, Error> {
+        self.expect_type(CRYPTO_HDKEY)?;
+        let mut reader = cbor::Reader::new(&self.cbor);
+        let key = HdKey::read(&mut reader)?;
+        reader.end()?;
+        Ok(key)
+    }
+
+    pub fn from_account(account: &Account) -> Self {
+        let mut cbor = Vec::new();
+        account.write(&mut cbor);
+        Self::new(CRYPTO_ACCOUNT, cbor)
+    }
+
+    pub fn to_account(&self) -> Result<Account, Error> {
+        self.expect_type(CRYPTO_ACCOUNT)?;
+        let mut reader = cbor::Reader::new(&self.cbor);
+        let account = Account::read(&mut reader)?;
+        reader.end()?;
+        Ok(account)
+    }
+
+    /// Encoder of the parts of the UR, each part holds at most max_fragment_len bytes.
+    pub fn encoder(&self, max_fragment_len: usize) -> Encoder {
+        Encoder::new(self, max_fragment_len)
+    }
+
+    fn expect_type(&self, ur_type: &'static str) -> Result<(), Error> {
+        if self.ur_type == ur_type {
+            Ok(())
+        } else {
+            Err(Error::InvalidUr("unexpected registry type"))
+        }
+    }
+}
+
+/// Single-part encoding.
+impl std::fmt::Display for Ur {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ur:{}/{}", self.ur_type, bytewords::encode(&self.cbor))
+    }
+}
+
+/// Single-part decoding, see Decoder for the multi-part URs.
+impl FromStr for Ur {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse(s)? {
+            (ur_type, None, payload) => Ok(Ur::new(ur_type, bytewords::decode(&payload)?)),
+            (_, Some(_), _) => Err(Error::InvalidUr("multi-part UR")),
+        }
+    }
+}
+
+/// Sequence number and length of a multi-part UR.
+type Sequence = (u32, usize);
+
+/// Splits the UR into its type, its sequence and its bytewords. QR codes use upper case.
+fn parse(s: &str) -> Result<(String, Option<Sequence>, String), Error> {
+    let s = s.to_lowercase();
+    let s = s
+        .strip_prefix("ur:")
+        .ok_or(Error::InvalidUr("missing ur: scheme"))?;
+    let components: Vec<&str> = s.split('/').collect();
+    let ur_type = components[0];
+    if ur_type.is_empty()
+        || !ur_type
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(Error::InvalidUr("invalid type"));
+    }
+    match components[1..] {
+        [payload] => Ok((ur_type.to_string(), None, payload.to_string())),
+        [seq, payload] => {
+            let (num, len) = seq
+                .split_once('-')
+                .and_then(|(num, len)| Some((num.parse().ok()?, len.parse().ok()?)))
+                .filter(|(num, len)| *num > 0 && *len > 0)
+                .ok_or(Error::InvalidUr("invalid sequence"))?;
+            Ok((ur_type.to_string(), Some((num, len)), payload.to_string()))
+        }
+        _ => Err(Error::InvalidUr("invalid path")),
+    }
+}
+
+/// Extended public key, with its origin. Without origin the depth and the child
+/// number of the key are not encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HdKey {
+    pub xpub: Xpub,
+    pub origin: Option<KeySource>,
+}
+
+impl HdKey {
+    const TAG: u64 = 303;
+    const TAG_COIN_INFO: u64 = 305;
+    const TAG_KEYPATH: u64 = 304;
+
+    fn write(&self, out: &mut Vec<u8>) {
+        cbor::head(out, cbor::MAP, if self.origin.is_some() { 5 } else { 4 });
+        cbor::head(out, cbor::UINT, 3);
+        cbor::bytes(out, &self.xpub.public_key.serialize());
+        cbor::head(out, cbor::UINT, 4);
+        cbor::bytes(out, self.xpub.chain_code.as_bytes());
+        cbor::head(out, cbor::UINT, 5);
+        cbor::head(out, cbor::TAG, Self::TAG_COIN_INFO);
+        cbor::head(out, cbor::MAP, 1);
+        cbor::head(out, cbor::UINT, 2);
+        cbor::head(
+            out,
+            cbor::UINT,
+            if self.xpub.network == Network::Bitcoin {
+                0
+            } else {
+                1
+            },
+        );
+        if let Some((fingerprint, path)) = &self.origin {
+            cbor::head(out, cbor::UINT, 6);
+            cbor::head(out, cbor::TAG, Self::TAG_KEYPATH);
+            cbor::head(out, cbor::MAP, 3);
+            cbor::head(out, cbor::UINT, 1);
+            cbor::head(out, cbor::ARRAY, 2 * path.len() as u64);
+            for child in path {
+                let (index, hardened) = match *child {
+                    ChildNumber::Normal { index } => (index, false),
+                    ChildNumber::Hardened { index } => (index, true),
+                };
+                cbor::head(out, cbor::UINT, index as u64);
+                cbor::boolean(out, hardened);
+            }
+            cbor::head(out, cbor::UINT, 2);
+            cbor::head(
+                out,
+                cbor::UINT,
+                u32::from_be_bytes(fingerprint.to_bytes()) as u64,
+            );
+            cbor::head(out, cbor::UINT, 3);
+            cbor::head(out, cbor::UINT, path.len() as u64);
+        }
+        cbor::head(out, cbor::UINT, 8);
+        cbor::head(
+            out,
+            cbor::UINT,
+            u32::from_be_bytes(self.xpub.parent_fingerprint.to_bytes()) as u64,
+        );
+    }
+
+    fn read(reader: &mut cbor::Reader) -> Result<Self, Error> {
+        let mut key = None;
+        let mut chain_code = None;
+        let mut network = Network::Bitcoin;
+        let mut origin: Option<(Option<Fingerprint>, DerivationPath)> = None;
+        let mut parent_fingerprint = Fingerprint::default();
+        for _ in 0..reader.map()? {
+            match reader.uint()? {
+                1 | 2 => {
+                    if reader.boolean()? {
+                        return Err(Error::InvalidCbor(
+                            "private or master keys are not supported",
+                        ));
+                    }
+                }
+                3 => {
+                    key = Some(
+                        PublicKey::from_slice(reader.bytes()?)
+                            .map_err(|_| Error::InvalidCbor("invalid key-data"))?,
+                    )
+                }
+                4 => {
+                    chain_code = Some(ChainCode::from(
+                        <[u8; 32]>::try_from(reader.bytes()?)
+                            .map_err(|_| Error::InvalidCbor("invalid chain-code"))?,
+                    ))
+                }
+                5 => {
+                    reader.tag(Self::TAG_COIN_INFO)?;
+                    for _ in 0..reader.map()? {
+                        match reader.uint()? {
+                            2 => {
+                                if reader.uint()? != 0 {
+                                    network = Network::Testnet
+                                }
+                            }
+                            _ => reader.skip()?,
+                        }
+                    }
+                }
+                6 => origin = Some(Self::read_keypath(reader)?),
+                8 => parent_fingerprint = fingerprint(reader.uint()?)?,
+                _ => reader.skip()?,
+            }
+        }
+        let (public_key, chain_code) = match (key, chain_code) {
+            (Some(key), Some(chain_code)) => (key, chain_code),
+            _ => return Err(Error::InvalidCbor("missing key-data or chain-code")),
+        };
+        let (depth, child_number) = match &origin {
+            Some((_, path)) => (
+                u8::try_from(path.len()).map_err(|_| Error::InvalidCbor("invalid depth"))?,
+                path.into_iter()
+                    .last()
+                    .copied()
+                    .unwrap_or(ChildNumber::Normal { index: 0 }),
+            ),
+            None => (0, ChildNumber::Normal { index: 0 }),
+        };
+        Ok(HdKey {
+            xpub: Xpub {
+                network,
+                depth,
+                parent_fingerprint,
+                child_number,
+                public_key,
+                chain_code,
+            },
+            origin: origin.and_then(|(fg, path)| Some((fg?, path))),
+        })
+    }
+
+    fn read_keypath(
+        reader: &mut cbor::Reader,
+    ) -> Result<(Option<Fingerprint>, DerivationPath), Error> {
+        reader.tag(Self::TAG_KEYPATH)?;
+        let mut path = Vec::new();
+        let mut source = None;
+        for _ in 0..reader.map()? {
+            match reader.uint()? {
+                1 => {
+                    let len = reader.array()?;
+                    if len % 2 == 1 {
+                        return Err(Error::InvalidCbor("wildcard components are not supported"));
+                    }
+                    for _ in 0..len / 2 {
+                        let index = u32::try_from(reader.uint()?)
+                            .map_err(|_| Error::InvalidCbor("invalid component"))?;
+                        let child = if reader.boolean()? {
+                            ChildNumber::from_hardened_idx(index)
+                        } else {
+                            ChildNumber::from_normal_idx(index)
+                        };
+                        path.push(child.map_err(|_| Error::InvalidCbor("invalid component"))?);
+                    }
+                }
+                2 => source = Some(fingerprint(reader.uint()?)?),
+                _ => reader.skip()?,
+            }
+        }
+        Ok((source, DerivationPath::from(path)))
+    }
+}
+
+fn fingerprint(value: u64) -> Result<Fingerprint, Error> {
+    u32::try_from(value)
+        .map(|v| Fingerprint::from(v.to_be_bytes()))
+        .map_err(|_| Error::InvalidCbor("invalid fingerprint"))
+}
+
+/// Single key accounts of a wallet, as exported for the air-gapped wallets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub master_fingerprint: Fingerprint,
+    pub outputs: Vec<(ScriptType, HdKey)>,
+}
+
+impl Account {
+    const TAG_OUTPUT: u64 = 308;
+    const TAG_SH: u64 = 400;
+    const TAG_PKH: u64 = 403;
+    const TAG_WPKH: u64 = 404;
+    const TAG_TR: u64 = 409;
+
+    fn script_tags(script: &ScriptType) -> &'static [u64] {
+        match script {
+            ScriptType::P2PKH => &[Self::TAG_PKH],
+            ScriptType::P2SHWPKH => &[Self::TAG_SH, Self::TAG_WPKH],
+            ScriptType::P2WPKH => &[Self::TAG_WPKH],
+            ScriptType::P2TR => &[Self::TAG_TR],
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        cbor::head(out, cbor::MAP, 2);
+        cbor::head(out, cbor::UINT, 1);
+        cbor::head(
+            out,
+            cbor::UINT,
+            u32::from_be_bytes(self.master_fingerprint.to_bytes()) as u64,
+        );
+        cbor::head(out, cbor::UINT, 2);
+        cbor::head(out, cbor::ARRAY, self.outputs.len() as u64);
+        for (script, key) in &self.outputs {
+            cbor::head(out, cbor::TAG, Self::TAG_OUTPUT);
+            for tag in Self::script_tags(script) {
+                cbor::head(out, cbor::TAG, *tag);
+            }
+            cbor::head(out, cbor::TAG, HdKey::TAG);
+            key.write(out);
+        }
+    }
+
+    fn read(reader: &mut cbor::Reader) -> Result<Self, Error> {
+        let mut master_fingerprint = None;
+        let mut outputs = Vec::new();
+        for _ in 0..reader.map()? {
+            match reader.uint()? {
+                1 => master_fingerprint = Some(fingerprint(reader.uint()?)?),
+                2 => {
+                    for _ in 0..reader.array()? {
+                        reader.tag(Self::TAG_OUTPUT)?;
+                        let mut tags = Vec::new();
+                        loop {
+                            let tag = reader.any_tag()?;
+                            if tag == HdKey::TAG {
+                                break;
+                            }
+                            tags.push(tag);
+                        }
+                        let script = [
+                            ScriptType::P2PKH,
+                            ScriptType::P2SHWPKH,
+                            ScriptType::P2WPKH,
+                            ScriptType::P2TR,
+                        ]
+                        .iter()
+                        .find(|s| Self::script_tags(s) == tags.as_slice())
+                        .cloned()
+                        .ok_or(Error::InvalidCbor("unsupported output descriptor"))?;
+                        outputs.push((script, HdKey::read(reader)?));
+                    }
+                }
+                _ => reader.skip()?,
+            }
+        }
+        Ok(Account {
+            master_fingerprint: master_fingerprint
+                .ok_or(Error::InvalidCbor("missing master-fingerprint"))?,
+            outputs,
+        })
+    }
+}
+
+/// Fountain encoder of the parts of a UR, the first seq_len parts are the fragments
+/// of the message and the following ones mix them, so that the decoder recovers the
+/// parts it missed while scanning an animated QR code.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    ur_type: String,
+    message_len: usize,
+    checksum: u32,
+    fragments: Vec<Vec<u8>>,
+    seq_num: u32,
+}
+
+impl Encoder {
+    const MIN_FRAGMENT_LEN: usize = 10;
+
+    fn new(ur: &Ur, max_fragment_len: usize) -> Self {
+        let fragment_len = fragment_len(
+            ur.cbor.len(),
+            Self::MIN_FRAGMENT_LEN.min(max_fragment_len),
+            max_fragment_len,
+        );
+        let mut padded = ur.cbor.clone();
+        padded.resize(
+            ceil_div(padded.len(), fragment_len).max(1) * fragment_len,
+            0,
+        );
+        Self {
+            ur_type: ur.ur_type.clone(),
+            message_len: ur.cbor.len(),
+            checksum: crc32(&ur.cbor),
+            fragments: padded.chunks(fragment_len).map(|f| f.to_vec()).collect(),
+            seq_num: 0,
+        }
+    }
+
+    pub fn seq_len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// A single fragment message is encoded as a single-part UR.
+    pub fn next_part(&mut self) -> String {
+        self.seq_num += 1;
+        let seq_len = self.seq_len();
+        if seq_len == 1 {
+            return format!(
+                "ur:{}/{}",
+                self.ur_type,
+                bytewords::encode(&self.fragments[0])
+            );
+        }
+        let mut data = vec![0; self.fragments[0].len()];
+        for i in choose_fragments(self.seq_num, seq_len, self.checksum) {
+            xor(&mut data, &self.fragments[i]);
+        }
+        let mut part = Vec::new();
+        cbor::head(&mut part, cbor::ARRAY, 5);
+        cbor::head(&mut part, cbor::UINT, self.seq_num as u64);
+        cbor::head(&mut part, cbor::UINT, seq_len as u64);
+        cbor::head(&mut part, cbor::UINT, self.message_len as u64);
+        cbor::head(&mut part, cbor::UINT, self.checksum as u64);
+        cbor::bytes(&mut part, &data);
+        format!(
+            "ur:{}/{}-{}/{}",
+            self.ur_type,
+            self.seq_num,
+            seq_len,
+            bytewords::encode(&part)
+        )
+    }
+}
+
+/// Smallest fragment length of at most max_len splitting the message in equal fragments.
+fn fragment_len(message_len: usize, min_len: usize, max_len: usize) -> usize {
+    let max_count = (message_len / min_len.max(1)).max(1);
+    (1..=max_count)
+        .map(|count| ceil_div(message_len, count).max(1))
+        .find(|len| *len <= max_len)
+        .unwrap_or_else(|| max_len.max(1))
+}
+
+fn ceil_div(a: usize, b: usize) -> usize {
+    let floor = a / b;
+    if floor * b < a {
+        floor + 1
+    } else {
+        floor
+    }
+}
+
+/// Decoder of the parts of a UR, received in any order.
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    ur_type: Option<String>,
+    /// Sequence length, message length and checksum of the parts.
+    params: Option<(usize, usize, u32)>,
+    simple: BTreeMap<usize, Vec<u8>>,
+    mixed: Vec<(BTreeSet<usize>, Vec<u8>)>,
+    result: Option<Ur>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn receive(&mut self, part: &str) -> Result<(), Error> {
+        if self.result.is_some() {
+            return Ok(());
+        }
+        let (ur_type, seq, payload) = parse(part)?;
+        if self.ur_type.as_ref().is_some_and(|t| *t != ur_type) {
+            return Err(Error::PartMismatch);
+        }
+        let bytes = bytewords::decode(&payload)?;
+        let (seq_num, seq_len) = match seq {
+            Some(seq) => seq,
+            None => {
+                if self.params.is_some() {
+                    return Err(Error::PartMismatch);
+                }
+                self.result = Some(Ur::new(ur_type, bytes));
+                return Ok(());
+            }
+        };
+
+        let mut reader = cbor::Reader::new(&bytes);
+        if reader.array()? != 5 {
+            return Err(Error::InvalidCbor("invalid part"));
+        }
+        let num = reader.uint()?;
+        let len = reader.uint()? as usize;
+        let message_len = reader.uint()? as usize;
+        let checksum = u32::try_from(reader.uint()?).map_err(|_| Error::Checksum)?;
+        let data = reader.bytes()?.to_vec();
+        reader.end()?;
+        if num != seq_num as u64 || len != seq_len || data.is_empty() {
+            return Err(Error::InvalidUr("part does not match its sequence"));
+        }
+        match self.params {
+            None => {
+                if seq_len > MAX_SEQ_LEN || message_len > MAX_MESSAGE_LEN {
+                    return Err(Error::InvalidUr("message too long"));
+                }
+                if seq_len > message_len || data.len().saturating_mul(seq_len) < message_len {
+                    return Err(Error::InvalidCbor("invalid part"));
+                }
+                self.params = Some((seq_len, message_len, checksum));
+                self.ur_type = Some(ur_type);
+            }
+            Some(params) => {
+                if params != (seq_len, message_len, checksum)
+                    || self.fragment_len() != Some(data.len())
+                {
+                    return Err(Error::PartMismatch);
+                }
+            }
+        }
+        self.process(choose_fragments(seq_num, seq_len, checksum), data);
+        self.complete()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Fraction of the fragments recovered.
+    pub fn progress(&self) -> f64 {
+        match (&self.result, self.params) {
+            (Some(_), _) => 1.0,
+            (None, Some((seq_len, _, _))) => self.simple.len() as f64 / seq_len as f64,
+            (None, None) => 0.0,
+        }
+    }
+
+    pub fn result(&self) -> Option<&Ur> {
+        self.result.as_ref()
+    }
+
+    fn fragment_len(&self) -> Option<usize> {
+        self.simple
+            .values()
+            .chain(self.mixed.iter().map(|(_, data)| data))
+            .next()
+            .map(|data| data.len())
+    }
+
+    fn process(&mut self, indexes: BTreeSet<usize>, data: Vec<u8>) {
+        let mut queue = vec![(indexes, data)];
+        while let Some((mut indexes, mut data)) = queue.pop() {
+            for i in indexes.clone() {
+                if let Some(fragment) = self.simple.get(&i) {
+                    xor(&mut data, fragment);
+                    indexes.remove(&i);
+                }
+            }
+            for (mixed, fragment) in &self.mixed {
+                if mixed.len() < indexes.len() && mixed.is_subset(&indexes) {
+                    xor(&mut data, fragment);
+                    indexes = &indexes - mixed;
+                }
+            }
+            match indexes.len() {
+                0 => {}
+                1 => {
+                    let i = *indexes.iter().next().expect("one index");
+                    self.simple.insert(i, data);
+                    // The mixed parts with the fragment are reduced again.
+                    let (reducible, mixed) = std::mem::take(&mut self.mixed)
+                        .into_iter()
+                        .partition(|(mixed, _)| mixed.contains(&i));
+                    self.mixed = mixed;
+                    queue.extend(reducible);
+                }
+                _ => {
+                    if !self.mixed.iter().any(|(mixed, _)| *mixed == indexes) {
+                        self.mixed.push((indexes, data));
+                    }
+                }
+            }
+        }
+    }
+
+    fn complete(&mut self) -> Result<(), Error> {
+        let (seq_len, message_len, checksum) = match self.params {
+            Some(params) if self.simple.len() == params.0 => params,
+            _ => return Ok(()),
+        };
+        let mut message: Vec<u8> = (0..seq_len)
+            .flat_map(|i| self.simple[&i].iter().copied())
+            .collect();
+        message.truncate(message_len);
+        if crc32(&message) != checksum {
+            // The parts are inconsistent, the decoding starts over.
+            *self = Self::default();
+            return Err(Error::Checksum);
+        }
+        self.result = Some(Ur::new(self.ur_type.clone().unwrap_or_default(), message));
+        Ok(())
+    }
+}
+
+fn xor(data: &mut [u8], other: &[u8]) {
+    for (a, b) in data.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
+
+/// Indexes of the fragments mixed in the part, the first seq_len parts are the fragments.
+fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if seq_num as usize <= seq_len {
+        return BTreeSet::from([seq_num as usize - 1]);
+    }
+    let mut seed = seq_num.to_be_bytes().to_vec();
+    seed.extend_from_slice(&checksum.to_be_bytes());
+    let mut rng = Xoshiro256::new(&sha256::Hash::hash(&seed).to_byte_array());
+    let weights: Vec<f64> = (1..=seq_len).map(|i| 1.0 / i as f64).collect();
+    let degree = RandomSampler::new(&weights).next(&mut rng) + 1;
+    rng.shuffled((0..seq_len).collect())
+        .into_iter()
+        .take(degree)
+        .collect()
+}
+
+/// Xoshiro256** seeded as in the reference implementation of the fountain encoder.
+struct Xoshiro256([u64; 4]);
+
+impl Xoshiro256 {
+    fn new(seed: &[u8; 32]) -> Self {
+        let mut s = [0; 4];
+        for (i, chunk) in seed.chunks(8).enumerate() {
+            s[i] = u64::from_be_bytes(chunk.try_into().expect("8 bytes"));
+        }
+        Self(s)
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.0;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn next_double(&mut self) -> f64 {
+        self.next() as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    fn next_int(&mut self, low: usize, high: usize) -> usize {
+        (self.next_double() * (high - low + 1) as f64) as usize + low
+    }
+
+    fn shuffled<T>(&mut self, mut items: Vec<T>) -> Vec<T> {
+        let mut result = Vec::with_capacity(items.len());
+        while !items.is_empty() {
+            let i = self.next_int(0, items.len() - 1);
+            result.push(items.remove(i));
+        }
+        result
+    }
+}
+
+/// Walker's alias method, as in the reference implementation.
+struct RandomSampler {
+    probs: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl RandomSampler {
+    fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let sum: f64 = weights.iter().sum();
+        let mut p: Vec<f64> = weights.iter().map(|w| w * n as f64 / sum).collect();
+        let (mut small, mut large) = (Vec::new(), Vec::new());
+        for j in (0..n).rev() {
+            if p[j] < 1.0 {
+                small.push(j);
+            } else {
+                large.push(j);
+            }
+        }
+        let mut probs = vec![0.0; n];
+        let mut aliases = vec![0; n];
+        while !small.is_empty() && !large.is_empty() {
+            let a = small.pop().expect("not empty");
+            let g = large.pop().expect("not empty");
+            probs[a] = p[a];
+            aliases[a] = g;
+            p[g] += p[a] - 1.0;
+            if p[g] < 1.0 {
+                small.push(g);
+            } else {
+                large.push(g);
+            }
+        }
+        for i in large.into_iter().chain(small) {
+            probs[i] = 1.0;
+        }
+        Self { probs, aliases }
+    }
+
+    fn next(&self, rng: &mut Xoshiro256) -> usize {
+        let r1 = rng.next_double();
+        let r2 = rng.next_double();
+        let i = (self.probs.len() as f64 * r1) as usize;
+        if r2 < self.probs[i] {
+            i
+        } else {
+            self.aliases[i]
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Minimal bytewords (BCR-2020-012), the first and last letters of each word.
+mod bytewords {
+    use super::{crc32, Error};
+
+    const WORDS: &str = "\
+        ableacidalsoapexaquaarchatomauntawayaxisbackbaldbarnbeltbetabias\
+        bluebodybragbrewbulbbuzzcalmcashcatschefcityclawcodecolacookcost\
+        cruxcurlcuspcyandarkdatadaysdelidicedietdoordowndrawdropdrumdull\
+        dutyeacheasyechoedgeepicevenexamexiteyesfactfairfernfigsfilmfish\
+        fizzflapflewfluxfoxyfreefrogfuelfundgalagamegeargemsgiftgirlglow\
+        goodgraygrimgurugushgyrohalfhanghardhawkheathelphighhillholyhope\
+        hornhutsicedideaidleinchinkyintoirisironitemjadejazzjoinjoltjowl\
+        judojugsjumpjunkjurykeepkenokeptkeyskickkilnkingkitekiwiknoblamb\
+        lavalazyleaflegsliarlimplionlistlogoloudloveluaulucklungmainmany\
+        mathmazememomenumeowmildmintmissmonknailnavyneednewsnextnoonnote\
+        numbobeyoboeomitonyxopenovalowlspaidpartpeckplaypluspoempoolpose\
+        puffpumapurrquadquizraceramprealredorichroadrockroofrubyruinruns\
+        rustsafesagascarsetssilkskewslotsoapsolosongstubsurfswantacotask\
+        taxitenttiedtimetinytoiltombtoystriptunatwinuglyundouniturgeuser\
+        vastveryvetovialvibeviewvisavoidvowswallwandwarmwaspwavewaxywebs\
+        whatwhenwhizwolfworkyankyawnyellyogayurtzapszerozestzinczonezoom";
+
+    fn minimal(byte: u8) -> [u8; 2] {
+        let word = &WORDS.as_bytes()[byte as usize * 4..byte as usize * 4 + 4];
+        [word[0], word[3]]
+    }
+
+    pub fn encode(data: &[u8]) -> String {
+        let mut out = String::with_capacity((data.len() + 4) * 2);
+        for byte in data.iter().chain(&crc32(data).to_be_bytes()) {
+            out.extend(minimal(*byte).iter().map(|c| *c as char));
+        }
+        out
+    }
+
+    pub fn decode(s: &str) -> Result<Vec<u8>, Error> {
+        if s.len() % 2 == 1 || !s.is_ascii() {
+            return Err(Error::InvalidUr("invalid bytewords"));
+        }
+        let mut data = s
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                (0..=255u8)
+                    .find(|b| minimal(*b) == pair)
+                    .ok_or(Error::InvalidUr("invalid bytewords"))
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        if data.len() < 4 {
+            return Err(Error::InvalidUr("invalid bytewords"));
+        }
+        let checksum = data.split_off(data.len() - 4);
+        if crc32(&data).to_be_bytes()[..] != checksum[..] {
+            return Err(Error::Checksum);
+        }
+        Ok(data)
+    }
+}
+
+/// The subset of CBOR used by the registry types.
+mod cbor {
+    use super::Error;
+    use std::convert::{TryFrom, TryInto};
+
+    pub const UINT: u8 = 0;
+    pub const BYTES: u8 = 2;
+    pub const ARRAY: u8 = 4;
+    pub const MAP: u8 = 5;
+    pub const TAG: u8 = 6;
+    const SIMPLE: u8 = 7;
+
+    pub fn head(out: &mut Vec<u8>, major: u8, value: u64) {
+        let major = major << 5;
+        match value {
+            0..=23 => out.push(major | value as u8),
+            24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+            0x100..=0xffff => {
+                out.push(major | 25);
+                out.extend_from_slice(&(value as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(major | 26);
+                out.extend_from_slice(&(value as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(major | 27);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+
+    pub fn bytes(out: &mut Vec<u8>, data: &[u8]) {
+        head(out, BYTES, data.len() as u64);
+        out.extend_from_slice(data);
+    }
+
+    pub fn boolean(out: &mut Vec<u8>, value: bool) {
+        out.push(SIMPLE << 5 | if value { 21 } else { 20 });
+    }
+
+    pub struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        pub fn new(data: &'a [u8]) -> Self {
+            Self { data }
+        }
+
+        fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+            if self.data.len() < len {
+                return Err(Error::InvalidCbor("unexpected end"));
+            }
+            let (taken, rest) = self.data.split_at(len);
+            self.data = rest;
+            Ok(taken)
+        }
+
+        fn head(&mut self) -> Result<(u8, u64), Error> {
+            let initial = self.take(1)?[0];
+            let value = match initial & 0x1f {
+                v @ 0..=23 => v as u64,
+                24 => self.take(1)?[0] as u64,
+                25 => u16::from_be_bytes(self.take(2)?.try_into().expect("2 bytes")) as u64,
+                26 => u32::from_be_bytes(self.take(4)?.try_into().expect("4 bytes")) as u64,
+                27 => u64::from_be_bytes(self.take(8)?.try_into().expect("8 bytes")),
+                _ => return Err(Error::InvalidCbor("indefinite lengths are not supported")),
+            };
+            Ok((initial >> 5, value))
+        }
+
+        fn expect(&mut self, major: u8) -> Result<u64, Error> {
+            match self.head()? {
+                (m, value) if m == major => Ok(value),
+                _ => Err(Error::InvalidCbor("unexpected type")),
+            }
+        }
+
+        pub fn uint(&mut self) -> Result<u64, Error> {
+            self.expect(UINT)
+        }
+
+        pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+            let len = self.expect(BYTES)?;
+            self.take(usize::try_from(len).map_err(|_| Error::InvalidCbor("unexpected end"))?)
+        }
+
+        pub fn array(&mut self) -> Result<u64, Error> {
+            self.expect(ARRAY)
+        }
+
+        pub fn map(&mut self) -> Result<u64, Error> {
+            self.expect(MAP)
+        }
+
+        pub fn any_tag(&mut self) -> Result<u64, Error> {
+            self.expect(TAG)
+        }
+
+        pub fn tag(&mut self, tag: u64) -> Result<(), Error> {
+            if self.any_tag()? == tag {
+                Ok(())
+            } else {
+                Err(Error::InvalidCbor("unexpected tag"))
+            }
+        }
+
+        pub fn boolean(&mut self) -> Result<bool, Error> {
+            match self.expect(SIMPLE)? {
+                20 => Ok(false),
+                21 => Ok(true),
+                _ => Err(Error::InvalidCbor("unexpected type")),
+            }
+        }
+
+        /// Skips a value of an unknown key.
+        ///
+        /// Iterates over the nested items to bound the stack on untrusted input: each pending
+        /// item takes at least one byte, so their count cannot exceed the remaining data.
+        pub fn skip(&mut self) -> Result<(), Error> {
+            let mut pending: u64 = 1;
+            while pending > 0 {
+                pending -= 1;
+                let (major, value) = self.head()?;
+                let items = match major {
+                    BYTES | 3 => {
+                        self.take(
+                            usize::try_from(value)
+                                .map_err(|_| Error::InvalidCbor("unexpected end"))?,
+                        )?;
+                        0
+                    }
+                    ARRAY => value,
+                    MAP => value
+                        .checked_mul(2)
+                        .ok_or(Error::InvalidCbor("unexpected end"))?,
+                    TAG => 1,
+                    _ => 0,
+                };
+                pending = pending
+                    .checked_add(items)
+                    .filter(|&pending| pending <= self.data.len() as u64)
+                    .ok_or(Error::InvalidCbor("unexpected end"))?;
+            }
+            Ok(())
+        }
+
+        pub fn end(&self) -> Result<(), Error> {
+            if self.data.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::InvalidCbor("trailing bytes"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{absolute::LockTime, transaction, Amount, ScriptBuf, Transaction, TxIn, TxOut};
+
+    #[test]
+    fn test_bytewords() {
+        assert_eq!(crc32(b"Hello, world!"), 0xebe6c6e6);
+        assert_eq!(
+            bytewords::encode(&[0, 1, 2, 128, 255]),
+            "aeadaolazmjendeoti"
+        );
+        assert_eq!(
+            bytewords::decode("aeadaolazmjendeoti").unwrap(),
+            vec![0, 1, 2, 128, 255]
+        );
+        assert_eq!(
+            bytewords::decode("aeadaolazmjendeota"),
+            Err(Error::Checksum)
+        );
+    }
+
+    #[test]
+    fn test_fountain_rng() {
+        let mut rng = Xoshiro256::new(&sha256::Hash::hash(b"Wolf").to_byte_array());
+        let values: Vec<u64> = (0..10).map(|_| rng.next() % 100).collect();
+        assert_eq!(values, vec![42, 81, 85, 8, 82, 84, 76, 73, 70, 88]);
+
+        let mut rng = Xoshiro256::new(&sha256::Hash::hash(b"Wolf").to_byte_array());
+        assert_eq!(
+            rng.shuffled((1..=10).collect()),
+            vec![6, 4, 9, 3, 10, 5, 7, 8, 1, 2]
+        );
+
+        assert_eq!(fragment_len(12345, 1005, 1955), 1764);
+        assert_eq!(fragment_len(12345, 1005, 30000), 12345);
+    }
+
+    #[test]
+    fn test_multi_part() {
+        let message: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let ur = Ur::new("bytes", message);
+        let mut encoder = ur.encoder(100);
+        assert_eq!(encoder.seq_len(), 10);
+
+        // The first parts are lost, the mixed parts recover them.
+        let mut decoder = Decoder::new();
+        for _ in 0..5 {
+            encoder.next_part();
+        }
+        let mut parts = 0;
+        while !decoder.is_complete() {
+            decoder.receive(&encoder.next_part()).unwrap();
+            parts += 1;
+            assert!(parts < 100);
+        }
+        assert_eq!(decoder.result(), Some(&ur));
+
+        let mut decoder = Decoder::new();
+        decoder.receive(&ur.encoder(2000).next_part()).unwrap();
+        assert_eq!(decoder.result(), Some(&ur));
+        let mut part = ur.encoder(100).next_part();
+        part.truncate(part.len() - 2);
+        assert!(Decoder::new().receive(&part).is_err());
+
+        // Parts announcing too many fragments or too long a message.
+        let part = |seq_num: u64, seq_len: u64, message_len: u64| {
+            let mut cbor = Vec::new();
+            cbor::head(&mut cbor, 4, 5);
+            cbor::head(&mut cbor, 0, seq_num);
+            cbor::head(&mut cbor, 0, seq_len);
+            cbor::head(&mut cbor, 0, message_len);
+            cbor::head(&mut cbor, 0, 0);
+            cbor::bytes(&mut cbor, &[0; 10]);
+            format!(
+                "ur:bytes/{}-{}/{}",
+                seq_num,
+                seq_len,
+                bytewords::encode(&cbor)
+            )
+        };
+        for (seq_num, seq_len, message_len) in [
+            (1, 100_000_000, 100_000_000),
+            (100_000_001, 100_000_000, 1_000_000_000),
+            (1, 2, 20_000_000),
+        ] {
+            assert_eq!(
+                Decoder::new().receive(&part(seq_num, seq_len, message_len)),
+                Err(Error::InvalidUr("message too long"))
+            );
+        }
+        assert!(Decoder::new().receive(&part(1, 2, 20)).is_ok());
+    }
+
+    #[test]
+    fn test_registry_types() {
+        let psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        })
+        .unwrap();
+        let ur = Ur::from_psbt(&psbt);
+        assert!(ur.to_string().starts_with("ur:crypto-psbt/"));
+        assert_eq!(
+            Ur::from_str(&ur.to_string().to_uppercase())
+                .unwrap()
+                .to_psbt()
+                .unwrap(),
+            psbt
+        );
+        assert!(ur.to_hdkey().is_err());
+
+        let key = HdKey {
+            xpub: Xpub::from_str("tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP").unwrap(),
+            origin: Some((
+                Fingerprint::from_str("f5acc2fd").unwrap(),
+                DerivationPath::from_str("m/49'/1'/0'").unwrap(),
+            )),
+        };
+        assert_eq!(Ur::from_hdkey(&key).to_hdkey().unwrap(), key);
+
+        let mainnet = HdKey {
+            xpub: Xpub::from_str("xpub6CUGRUonZSQ4TWtTMmzXdrXDtypWKiKrhko4egpiMZbpiaQL2jkwSB1icqYh2cfDfVxdx4df189oLKnC5fSwqPfgyP3hooxujYzAu3fDVmz").unwrap(),
+            origin: Some((
+                Fingerprint::from_str("73c5da0a").unwrap(),
+                DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+            )),
+        };
+        assert_eq!(Ur::from_hdkey(&mainnet).to_hdkey().unwrap(), mainnet);
+
+        // Explicit is-master flag before the other keys.
+        let flagged = |master| {
+            let encoded = Ur::from_hdkey(&mainnet).cbor;
+            let mut cbor = Vec::new();
+            cbor::head(&mut cbor, cbor::MAP, 6);
+            cbor::head(&mut cbor, cbor::UINT, 1);
+            cbor::boolean(&mut cbor, master);
+            cbor.extend_from_slice(&encoded[1..]);
+            Ur::new(CRYPTO_HDKEY, cbor).to_hdkey()
+        };
+        assert_eq!(flagged(false).unwrap(), mainnet);
+        assert!(flagged(true).is_err());
+
+        let account = Account {
+            master_fingerprint: Fingerprint::from_str("f5acc2fd").unwrap(),
+            outputs: vec![(ScriptType::P2SHWPKH, key.clone()), (ScriptType::P2TR, key)],
+        };
+        let ur = Ur::from_account(&account);
+        let mut encoder = ur.encoder(50);
+        let mut decoder = Decoder::new();
+        while !decoder.is_complete() {
+            decoder.receive(&encoder.next_part()).unwrap();
+        }
+        assert_eq!(decoder.result().unwrap().to_account().unwrap(), account);
+    }
+
+    #[test]
+    fn test_cbor_skip() {
+        let skip = |data: &[u8]| cbor::Reader::new(data).skip();
+        let mut huge = Vec::new();
+        cbor::head(&mut huge, cbor::MAP, u64::MAX);
+        assert_eq!(skip(&huge), Err(Error::InvalidCbor("unexpected end")));
+        let mut long = Vec::new();
+        cbor::head(&mut long, cbor::ARRAY, 1000);
+        long.extend_from_slice(&[0; 10]);
+        assert_eq!(skip(&long), Err(Error::InvalidCbor("unexpected end")));
+
+        // Deep nesting does not overflow the stack.
+        let mut nested = vec![0xc0; 2_000_000];
+        nested.push(0);
+        assert_eq!(skip(&nested), Ok(()));
+        let mut nested = vec![0x81; 2_000_000];
+        nested.push(0);
+        assert_eq!(skip(&nested), Ok(()));
+    }
+}
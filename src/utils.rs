@@ -1,12 +1,20 @@
 use std::{cmp::Ordering, collections::BTreeMap, str::FromStr};
 
 use bitcoin::{
-    bip32::{ChildNumber, DerivationPath, KeySource},
+    absolute::LockTime,
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource, Xpub},
+    key::XOnlyPublicKey,
     psbt::Psbt,
     secp256k1::PublicKey,
+    taproot::TapLeafHash,
+    Address, Amount, Network, Script,
 };
 
-use crate::{Error, HWI};
+use crate::{
+    Capabilities, DeviceKind, Error, InputSignature, ScriptType, SignResult, SignWarning,
+    SignedInput, SigningKey, HWI,
+};
 
 pub struct Bip32DerivationFilter<'a> {
     psbt: &'a mut Psbt,
@@ -77,8 +85,8 @@ impl<'a> Bip32DerivationFilter<'a> {
     }
 
     /// Signs the psbt with the HWI interface and puts back the ignored bip32 derivations
-    pub fn sign_psbt<T: HWI>(mut self, device: &T) -> Result<(), Error> {
-        device.sign_tx(self.psbt)?;
+    pub fn sign_psbt<T: HWI>(mut self, device: &T) -> Result<SignResult, Error> {
+        let res = device.sign_tx(self.psbt)?;
 
         for (i, input) in self.psbt.inputs.iter_mut().enumerate() {
             input
@@ -86,10 +94,227 @@ impl<'a> Bip32DerivationFilter<'a> {
                 .append(&mut self.ignored_bip32_derivations[i]);
         }
 
-        Ok(())
+        Ok(res)
+    }
+}
+
+/// Signatures of the signed psbt missing from the unsigned one.
+pub fn new_signatures(unsigned: &Psbt, signed: &Psbt) -> Vec<(usize, InputSignature)> {
+    let mut signatures = Vec::new();
+    for (i, (before, after)) in unsigned.inputs.iter().zip(signed.inputs.iter()).enumerate() {
+        for (key, sig) in &after.partial_sigs {
+            if before.partial_sigs.get(key) != Some(sig) {
+                signatures.push((i, InputSignature::Ecdsa(*key, *sig)));
+            }
+        }
+        for ((key, leaf_hash), sig) in &after.tap_script_sigs {
+            if before.tap_script_sigs.get(&(*key, *leaf_hash)) != Some(sig) {
+                signatures.push((i, InputSignature::TapScript(*key, *leaf_hash, *sig)));
+            }
+        }
+        if let Some(sig) = after.tap_key_sig {
+            if before.tap_key_sig != Some(sig) {
+                signatures.push((i, InputSignature::TapKey(sig)));
+            }
+        }
+    }
+    signatures
+}
+
+/// Inputs of the signed psbt with a signature missing from the unsigned one, and their keys.
+/// Key path signatures are reported with the internal key of the input, or the output
+/// key of the spent script without it.
+pub fn signed_inputs(unsigned: &Psbt, signed: &Psbt) -> Vec<SignedInput> {
+    new_signatures(unsigned, signed)
+        .into_iter()
+        .filter_map(|(i, sig)| {
+            let key = match sig {
+                InputSignature::Ecdsa(key, _) => SigningKey::Ecdsa(key),
+                InputSignature::TapScript(key, _, _) => SigningKey::Schnorr(key),
+                InputSignature::TapKey(_) => {
+                    let input = &signed.inputs[i];
+                    SigningKey::Schnorr(input.tap_internal_key.or_else(|| {
+                        let script = &input.witness_utxo.as_ref()?.script_pubkey;
+                        if !script.is_p2tr() {
+                            return None;
+                        }
+                        XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).ok()
+                    })?)
+                }
+            };
+            Some(SignedInput { input: i, key })
+        })
+        .collect()
+}
+
+/// Fee, in percent of the spent amount, above which the devices warn about the fee.
+pub const HIGH_FEE_PERCENT: u64 = 10;
+
+/// Warnings the devices display during the review of the psbt.
+/// The devices do not report to the host the warnings the user confirmed,
+/// they are computed from the psbt with the rules of the devices.
+pub fn review_warnings(psbt: &Psbt) -> Vec<SignWarning> {
+    let tx = &psbt.unsigned_tx;
+    let mut warnings = Vec::new();
+    if tx.lock_time != LockTime::ZERO && tx.is_lock_time_enabled() {
+        warnings.push(SignWarning::LockTime(tx.lock_time));
+    }
+    for (i, input) in tx.input.iter().enumerate() {
+        if input.sequence.is_relative_lock_time() {
+            warnings.push(SignWarning::RelativeLockTime {
+                input: i,
+                sequence: input.sequence,
+            });
+        }
+    }
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        if let Some(sighash_type) = input.sighash_type {
+            // SIGHASH_DEFAULT of taproot inputs and SIGHASH_ALL.
+            if sighash_type.to_u32() > 1 {
+                warnings.push(SignWarning::SighashType {
+                    input: i,
+                    sighash_type,
+                });
+            }
+        }
+    }
+    for (i, (txin, input)) in tx.input.iter().zip(psbt.inputs.iter()).enumerate() {
+        let script = input
+            .witness_utxo
+            .as_ref()
+            .map(|utxo| &utxo.script_pubkey)
+            .or_else(|| {
+                input.non_witness_utxo.as_ref().and_then(|tx| {
+                    tx.output
+                        .get(txin.previous_output.vout as usize)
+                        .map(|o| &o.script_pubkey)
+                })
+            });
+        let path = single_key_path(&input.bip32_derivation, &input.tap_key_origins);
+        if let (Some(script), Some(path)) = (script, path) {
+            if !is_standard_path(path, script, &[0, 1]) {
+                warnings.push(SignWarning::UnusualPath {
+                    input: i,
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+    for (i, (txout, output)) in tx.output.iter().zip(psbt.outputs.iter()).enumerate() {
+        if let Some(path) = single_key_path(&output.bip32_derivation, &output.tap_key_origins) {
+            if !is_standard_path(path, &txout.script_pubkey, &[1]) {
+                warnings.push(SignWarning::UnusualChange {
+                    output: i,
+                    path: path.clone(),
+                });
+            }
+        }
+    }
+    if let Some(spent) = spent_amount(psbt) {
+        let outputs = tx
+            .output
+            .iter()
+            .try_fold(Amount::ZERO, |total, o| total.checked_add(o.value));
+        if let Some(fee) = outputs.and_then(|outputs| spent.checked_sub(outputs)) {
+            if u128::from(fee.to_sat()) * 100
+                > u128::from(spent.to_sat()) * u128::from(HIGH_FEE_PERCENT)
+            {
+                warnings.push(SignWarning::HighFee { fee, spent });
+            }
+        }
+    }
+    warnings
+}
+
+/// Indexes of the OP_RETURN outputs of the transaction.
+pub fn op_return_outputs(psbt: &Psbt) -> Vec<usize> {
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| output.script_pubkey.is_op_return())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Checks the transaction before it is sent to the device, the devices without
+/// Capabilities::op_return refuse or fail to sign the transactions with OP_RETURN
+/// outputs. The devices which support them display the data to the user.
+pub fn check_op_return(capabilities: &Capabilities, psbt: &Psbt) -> Result<(), Error> {
+    match op_return_outputs(psbt).first() {
+        Some(i) if !capabilities.op_return => Err(Error::InvalidParameter(
+            "psbt",
+            format!(
+                "output {} is an OP_RETURN output, the device does not support them",
+                i
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Highest address index of the standard paths, the BIP44 recommended range.
+pub const MAX_STANDARD_ADDRESS_INDEX: u32 = 50_000;
+
+/// Path of the key of a single key script, None for the scripts with several keys.
+fn single_key_path<'a>(
+    bip32_derivation: &'a BTreeMap<PublicKey, KeySource>,
+    tap_key_origins: &'a BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+) -> Option<&'a DerivationPath> {
+    match (bip32_derivation.len(), tap_key_origins.len()) {
+        (1, 0) => bip32_derivation.values().next().map(|(_, path)| path),
+        (0, 1) => tap_key_origins
+            .values()
+            .find(|(leaves, _)| leaves.is_empty())
+            .map(|(_, (_, path))| path),
+        _ => None,
     }
 }
 
+/// Returns true if the path is purpose'/coin'/account'/change/index with the BIP44 purpose
+/// of the script, one of the given changes and an index in MAX_STANDARD_ADDRESS_INDEX.
+/// The paths of the other scripts are not checked.
+fn is_standard_path(path: &DerivationPath, script: &Script, changes: &[u32]) -> bool {
+    let purpose = if script.is_p2pkh() {
+        44
+    } else if script.is_p2sh() {
+        49
+    } else if script.is_p2wpkh() {
+        84
+    } else if script.is_p2tr() {
+        86
+    } else {
+        return true;
+    };
+    matches!(
+        path.as_ref(),
+        [ChildNumber::Hardened { index: p }, ChildNumber::Hardened { index: coin }, ChildNumber::Hardened { .. }, ChildNumber::Normal { index: change }, ChildNumber::Normal { index }]
+            if *p == purpose && *coin <= 1 && changes.contains(change) && *index <= MAX_STANDARD_ADDRESS_INDEX
+    )
+}
+
+/// Sum of the spent outputs, None if the psbt is missing one of them.
+fn spent_amount(psbt: &Psbt) -> Option<Amount> {
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(psbt.inputs.iter())
+        .map(|(txin, input)| {
+            input
+                .witness_utxo
+                .as_ref()
+                .map(|utxo| utxo.value)
+                .or_else(|| {
+                    input.non_witness_utxo.as_ref().and_then(|tx| {
+                        tx.output
+                            .get(txin.previous_output.vout as usize)
+                            .map(|o| o.value)
+                    })
+                })
+        })
+        .try_fold(Amount::ZERO, |total, value| total.checked_add(value?))
+}
+
 pub fn merge_signatures(psbt: &mut Psbt, signed_psbt: &Psbt) {
     for i in 0..signed_psbt.inputs.len() {
         let psbtin = match psbt.inputs.get_mut(i) {
@@ -112,10 +337,206 @@ pub fn merge_signatures(psbt: &mut Psbt, signed_psbt: &Psbt) {
     }
 }
 
+/// Strips from the PSBT the fields the device does not need before the transfer, most
+/// useful to air-gapped devices for which the PSBT size drives the QR or SD transfer:
+/// - the unknown and proprietary fields, which no device reads.
+/// - the previous transaction of the taproot inputs, their signatures commit to the
+///   amounts of all the inputs.
+/// - the global xpubs, except for Coldcard which imports a multisig wallet from them.
+///
+/// Returns the original PSBT, in which the signatures of the device are merged back
+/// with merge_signatures.
+pub fn sanitize_for(kind: DeviceKind, psbt: &mut Psbt) -> Psbt {
+    let original = psbt.clone();
+    psbt.unknown.clear();
+    psbt.proprietary.clear();
+    if kind != DeviceKind::Coldcard {
+        psbt.xpub.clear();
+    }
+    for input in &mut psbt.inputs {
+        input.unknown.clear();
+        input.proprietary.clear();
+        if input
+            .witness_utxo
+            .as_ref()
+            .is_some_and(|utxo| utxo.script_pubkey.is_p2tr())
+        {
+            input.non_witness_utxo = None;
+        }
+    }
+    for output in &mut psbt.outputs {
+        output.unknown.clear();
+        output.proprietary.clear();
+    }
+    original
+}
+
 pub fn bip86_path_child_numbers(path: DerivationPath) -> Result<Vec<ChildNumber>, Error> {
+    bip44_path_child_numbers(path, 86)
+}
+
+/// BIP44 account path of the single key script.
+pub fn account_path(
+    script: ScriptType,
+    network: Network,
+    account: u32,
+) -> Result<DerivationPath, Error> {
+    let purpose = match script {
+        ScriptType::P2PKH => 44,
+        ScriptType::P2SHWPKH => 49,
+        ScriptType::P2WPKH => 84,
+        ScriptType::P2TR => 86,
+    };
+    let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+    [purpose, coin_type, account]
+        .iter()
+        .map(|i| ChildNumber::from_hardened_idx(*i))
+        .collect::<Result<DerivationPath, _>>()
+        .map_err(|_| Error::InvalidParameter("account", account.to_string()))
+}
+
+/// Output descriptor of the single key account, with the receive and change keychains
+/// as a multipath key and the checksum.
+pub fn account_descriptor(
+    script: ScriptType,
+    fingerprint: Fingerprint,
+    path: &DerivationPath,
+    xpub: &Xpub,
+) -> String {
+    let key = format!(
+        "[{}{}]{}/<0;1>/*",
+        fingerprint,
+        path.to_string().trim_start_matches('m'),
+        xpub
+    );
+    let descriptor = match script {
+        ScriptType::P2PKH => format!("pkh({})", key),
+        ScriptType::P2SHWPKH => format!("sh(wpkh({}))", key),
+        ScriptType::P2WPKH => format!("wpkh({})", key),
+        ScriptType::P2TR => format!("tr({})", key),
+    };
+    match descriptor_checksum(&descriptor) {
+        Some(checksum) => format!("{}#{}", descriptor, checksum),
+        None => descriptor,
+    }
+}
+
+/// Highest account of the paths the devices export without warning.
+pub const MAX_USUAL_ACCOUNT: u32 = 100;
+
+/// Returns true if the path is a BIP44 account path, purpose'/coin'/account', or one
+/// of its keys, of a purpose and coin type the devices derive without warning:
+/// m/48'/coin'/account'/script' for the multisig purpose, with a P2SH-P2WSH or P2WSH
+/// script type. The devices display the xpubs of the other paths with a warning.
+pub fn is_usual_path(path: &DerivationPath) -> bool {
+    let (account, children) = match path.as_ref() {
+        [ChildNumber::Hardened { index: 48 }, ChildNumber::Hardened { index: coin }, ChildNumber::Hardened { index: account }, ChildNumber::Hardened { index: 1 | 2 }, children @ ..]
+        | [ChildNumber::Hardened {
+            index: 44 | 49 | 84 | 86,
+        }, ChildNumber::Hardened { index: coin }, ChildNumber::Hardened { index: account }, children @ ..]
+            if *coin <= 1 =>
+        {
+            (*account, children)
+        }
+        _ => return false,
+    };
+    account <= MAX_USUAL_ACCOUNT
+        && match children {
+            [] => true,
+            [ChildNumber::Normal { index: change }] => *change <= 1,
+            [ChildNumber::Normal { index: change }, ChildNumber::Normal { index }] => {
+                *change <= 1 && *index <= MAX_STANDARD_ADDRESS_INDEX
+            }
+            _ => false,
+        }
+}
+
+/// Purposes of the derivation paths with a coin type as second component.
+const COIN_TYPE_PURPOSES: [u32; 5] = [44, 48, 49, 84, 86];
+
+/// Checks the coin type of the path matches the network:
+/// 0' for mainnet and 1' for the test networks.
+pub fn check_coin_type(path: &DerivationPath, network: Network) -> Result<(), Error> {
+    let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+    match path.as_ref() {
+        [ChildNumber::Hardened { index: purpose }, ChildNumber::Hardened { index: coin }, ..]
+            if COIN_TYPE_PURPOSES.contains(purpose) && *coin != coin_type =>
+        {
+            Err(Error::NetworkMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Xpub encoded for the network, a tpub for the test networks. The key is unchanged:
+/// the apps of the test networks derive the same keys from the seed.
+pub fn normalize_xpub(xpub: &Xpub, network: Network) -> Xpub {
+    Xpub {
+        network: if network == Network::Bitcoin {
+            Network::Bitcoin
+        } else {
+            Network::Testnet
+        },
+        ..*xpub
+    }
+}
+
+/// Checks the wallet name is accepted by the devices of the kind, which otherwise
+/// reject the registration with an opaque error: the name must not be empty, be at most
+/// Capabilities::max_wallet_name_len long and, for the devices with a limit, be made of
+/// printable ASCII characters without leading or trailing space.
+pub fn validate_wallet_name(kind: DeviceKind, name: &str) -> Result<(), Error> {
+    let invalid = |reason: String| Err(Error::InvalidParameter("name", reason));
+    if name.is_empty() {
+        return invalid("the wallet name is empty".to_string());
+    }
+    let max_len = match crate::Capabilities::new(kind, None).max_wallet_name_len {
+        Some(max_len) => max_len,
+        None => return Ok(()),
+    };
+    if let Some(c) = name.chars().find(|c| !matches!(c, ' '..='~')) {
+        return invalid(format!("{} does not accept the character {:?}", kind, c));
+    }
+    if name.starts_with(' ') || name.ends_with(' ') {
+        return invalid(format!(
+            "{} does not accept leading or trailing spaces",
+            kind
+        ));
+    }
+    if name.len() > max_len {
+        return invalid(format!(
+            "{} accepts names of at most {} characters, got {}",
+            kind,
+            max_len,
+            name.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the address returned by the device is valid for the network.
+/// The Bitcoin Test app and the simulators return the tb1 addresses on regtest.
+pub fn check_address_network(
+    address: Address<NetworkUnchecked>,
+    network: Network,
+) -> Result<Address<NetworkUnchecked>, Error> {
+    if address.is_valid_for_network(network)
+        || (network == Network::Regtest && address.is_valid_for_network(Network::Testnet))
+    {
+        Ok(address)
+    } else {
+        Err(Error::NetworkMismatch)
+    }
+}
+
+/// Checks the path follows m/purpose'/coin_type'/account'/change/address_index.
+pub fn bip44_path_child_numbers(
+    path: DerivationPath,
+    purpose: u32,
+) -> Result<Vec<ChildNumber>, Error> {
     let children: Vec<ChildNumber> = path.into();
     if children.len() != 5
-        || children[0] != ChildNumber::from_hardened_idx(86).unwrap()
+        || Some(children[0]) != ChildNumber::from_hardened_idx(purpose).ok()
         || children[1].is_normal()
         || children[2].is_normal()
         || children[3].is_hardened()
@@ -123,37 +544,229 @@ pub fn bip86_path_child_numbers(path: DerivationPath) -> Result<Vec<ChildNumber>
     {
         Err(Error::InvalidParameter(
             "derivation_path",
-            "path is not bip86 compatible".to_string(),
+            format!("path is not bip{} compatible", purpose),
         ))
     } else {
         Ok(children)
     }
 }
 
+/// Characters of the descriptors, in the order of the BIP380 checksum.
+const CHECKSUM_INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// BIP380 checksum of the descriptor, None if it has a character invalid in descriptors.
+pub fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    fn polymod(chk: u64, value: u64) -> u64 {
+        const GENERATOR: [u64; 5] = [
+            0xf5dee51989,
+            0xa9fdca3312,
+            0x1bab10e32d,
+            0x3706b1677a,
+            0x644d626ffd,
+        ];
+        let top = chk >> 35;
+        let mut chk = ((chk & 0x7ffffffff) << 5) ^ value;
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+        chk
+    }
+
+    let mut chk = 1;
+    let mut groups = Vec::with_capacity(3);
+    for c in descriptor.chars() {
+        let v = CHECKSUM_INPUT_CHARSET.find(c)? as u64;
+        chk = polymod(chk, v & 31);
+        groups.push(v >> 5);
+        if groups.len() == 3 {
+            chk = polymod(chk, groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups[..] {
+        [a] => chk = polymod(chk, a),
+        [a, b] => chk = polymod(chk, a * 3 + b),
+        _ => {}
+    }
+    for _ in 0..8 {
+        chk = polymod(chk, 0);
+    }
+    chk ^= 1;
+    Some(
+        (0..8)
+            .map(|i| CHECKSUM_CHARSET[((chk >> (5 * (7 - i))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+/// Splits the checksum from the descriptor and verifies it, if the descriptor has one.
+pub fn strip_checksum(descriptor: &str) -> Result<&str, Error> {
+    match descriptor.rsplit_once('#') {
+        Some((descriptor, checksum)) => {
+            if descriptor_checksum(descriptor).as_deref() == Some(checksum) {
+                Ok(descriptor)
+            } else {
+                Err(Error::InvalidParameter(
+                    "policy",
+                    format!("invalid descriptor checksum {}", checksum),
+                ))
+            }
+        }
+        None => Ok(descriptor),
+    }
+}
+
+/// Appends the checksum to the descriptor, an existing checksum is verified and replaced.
+pub fn add_checksum(descriptor: &str) -> Result<String, Error> {
+    let descriptor = strip_checksum(descriptor)?;
+    let checksum = descriptor_checksum(descriptor).ok_or_else(|| {
+        Error::InvalidParameter("policy", "invalid character in descriptor".to_string())
+    })?;
+    Ok(format!("{}#{}", descriptor, checksum))
+}
+
+/// Replaces the keys of the policy by their index, the checksum is verified and removed.
 #[cfg(feature = "regex")]
 pub fn extract_keys_and_template<T: FromStr>(policy: &str) -> Result<(String, Vec<T>), Error> {
+    let policy = strip_checksum(policy)?;
     let re = regex::Regex::new(r"((\[.+?\])?[xyYzZtuUvV]pub[1-9A-HJ-NP-Za-km-z]{79,108})").unwrap();
-    let mut descriptor_template = policy.to_string();
+    let mut descriptor_template = String::with_capacity(policy.len());
     let mut pubkeys_str: Vec<&str> = Vec::new();
+    let mut end = 0;
+    // Keys are replaced where they are matched: a key without origin must not be
+    // replaced inside the same key with an origin.
     for capture in re.find_iter(policy) {
-        if !pubkeys_str.contains(&capture.as_str()) {
-            pubkeys_str.push(capture.as_str());
-        }
+        let i = match pubkeys_str.iter().position(|k| *k == capture.as_str()) {
+            Some(i) => i,
+            None => {
+                pubkeys_str.push(capture.as_str());
+                pubkeys_str.len() - 1
+            }
+        };
+        descriptor_template.push_str(&policy[end..capture.start()]);
+        descriptor_template.push_str(&format!("@{}", i));
+        end = capture.end();
     }
+    descriptor_template.push_str(&policy[end..]);
 
     let mut pubkeys: Vec<T> = Vec::new();
-    for (i, key_str) in pubkeys_str.iter().enumerate() {
-        descriptor_template = descriptor_template.replace(key_str, &format!("@{}", i));
+    for key_str in pubkeys_str {
         let pubkey = T::from_str(key_str).map_err(|_| Error::UnsupportedInput)?;
         pubkeys.push(pubkey);
     }
+    Ok((descriptor_template, pubkeys))
+}
 
-    // Do not include the hash in the descriptor template.
-    if let Some((descriptor_template, _hash)) = descriptor_template.rsplit_once('#') {
-        Ok((descriptor_template.to_string(), pubkeys))
-    } else {
-        Ok((descriptor_template, pubkeys))
+/// Parses a policy key of the form [fingerprint/path]xpub, the origin being optional.
+pub fn parse_key_with_origin(key: &str) -> Result<(Option<KeySource>, Xpub), Error> {
+    let (origin, xpub) = match key.strip_prefix('[').and_then(|s| s.rsplit_once(']')) {
+        Some((origin, xpub)) => {
+            let (fg, path) = origin.split_once('/').unwrap_or((origin, ""));
+            let fg = Fingerprint::from_str(fg)
+                .map_err(|e| Error::InvalidParameter("policy", e.to_string()))?;
+            let path = if path.is_empty() {
+                DerivationPath::master()
+            } else {
+                DerivationPath::from_str(&format!("m/{}", path))
+                    .map_err(|e| Error::InvalidParameter("policy", e.to_string()))?
+            };
+            (Some((fg, path)), xpub)
+        }
+        None => (None, key),
+    };
+    let xpub =
+        Xpub::from_str(xpub).map_err(|e| Error::InvalidParameter("policy", e.to_string()))?;
+    Ok((origin, xpub))
+}
+
+/// Fetches the account xpubs of the device used by the policy keys and checks they match.
+/// Network is ignored, a device may encode an xpub where the policy has a tpub.
+#[cfg(feature = "regex")]
+pub fn device_account_xpubs<T: HWI + ?Sized>(
+    device: &T,
+    policy: &str,
+) -> Result<Vec<(DerivationPath, Xpub)>, Error> {
+    let (_, keys) = extract_keys_and_template::<String>(policy)?;
+    let fg = device.get_master_fingerprint()?;
+    let mut xpubs = Vec::new();
+    for key in keys {
+        if let (Some((key_fg, path)), xpub) = parse_key_with_origin(&key)? {
+            if key_fg != fg {
+                continue;
+            }
+            let device_xpub = device.get_extended_pubkey(&path)?;
+            if device_xpub.public_key != xpub.public_key
+                || device_xpub.chain_code != xpub.chain_code
+            {
+                return Err(Error::InvalidParameter(
+                    "policy",
+                    format!("key {} does not match the device xpub", key),
+                ));
+            }
+            xpubs.push((path, device_xpub));
+        }
+    }
+    Ok(xpubs)
+}
+
+/// Registers the wallet on the device and captures in the registration record
+/// the device account xpubs used by the policy, so that later integrity checks
+/// do not require the device.
+#[cfg(feature = "regex")]
+pub fn register_wallet_with_xpubs<T: HWI + ?Sized>(
+    device: &T,
+    name: &str,
+    policy: &str,
+) -> Result<crate::WalletRegistration, Error> {
+    let xpubs = device_account_xpubs(device, policy)?;
+    let mut registration = device.register_wallet(name, policy)?;
+    registration.xpubs = xpubs;
+    Ok(registration)
+}
+
+/// Returns the first device with the master fingerprint, to route a PSBT to the right
+/// cosigner. The devices are only connected as the iterator is consumed, those before the
+/// match are dropped, and those failing to answer, like the locked ones, are skipped.
+/// See Connector::connect_fingerprint for the devices of all the backends.
+pub fn find_device_by_fingerprint<T: HWI>(
+    devices: impl IntoIterator<Item = T>,
+    fingerprint: Fingerprint,
+) -> Option<T> {
+    devices
+        .into_iter()
+        .find(|device| device.get_master_fingerprint().ok() == Some(fingerprint))
+}
+
+/// udev rules giving the user of the session access to the supported devices on
+/// Linux, to save in /etc/udev/rules.d/ when the devices fail with
+/// Error::PermissionDenied. The Jade is a USB serial device, its rules match the
+/// serial chips of the Jade models, see jade::JADE_DEVICE_IDS.
+pub fn udev_rules() -> String {
+    let mut rules = String::new();
+    for (name, kernel, vid, pid) in [
+        ("Ledger", "hidraw", 0x2c97, None),
+        ("Coldcard", "hidraw", 0xd13e, Some(0xcc10)),
+        ("BitBox02", "hidraw", 0x03eb, Some(0x2403)),
+        ("Specter", "tty", 0xf055, None),
+        ("Jade", "tty", 0x10c4, Some(0xea60)),
+        ("Jade", "tty", 0x1a86, Some(0x55d4)),
+        ("Jade", "tty", 0x0403, Some(0x6001)),
+        ("Jade", "tty", 0x1a86, Some(0x7523)),
+    ] {
+        rules.push_str(&format!(
+            "# {}\nSUBSYSTEMS==\"usb\", KERNEL==\"{}*\", ATTRS{{idVendor}}==\"{:04x}\", ",
+            name, kernel, vid
+        ));
+        if let Some(pid) = pid {
+            rules.push_str(&format!("ATTRS{{idProduct}}==\"{:04x}\", ", pid));
+        }
+        rules.push_str("TAG+=\"uaccess\"\n");
     }
+    rules
 }
 
 #[cfg(test)]
@@ -161,6 +774,7 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::mock::MockDevice;
 
     #[test]
     fn test_sort_bip32_derivations() {
@@ -174,6 +788,143 @@ mod tests {
         assert_eq!(psbt.inputs[1].bip32_derivation.len(), 2);
     }
 
+    #[test]
+    fn test_check_coin_type() {
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        assert!(check_coin_type(&path, Network::Testnet).is_ok());
+        assert!(check_coin_type(&path, Network::Signet).is_ok());
+        assert!(check_coin_type(&path, Network::Bitcoin).is_err());
+        let path = DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+        assert!(check_coin_type(&path, Network::Bitcoin).is_ok());
+        assert!(check_coin_type(&path, Network::Regtest).is_err());
+        // Paths without coin type are not checked.
+        let path = DerivationPath::from_str("m/45'/0").unwrap();
+        assert!(check_coin_type(&path, Network::Testnet).is_ok());
+        assert!(check_coin_type(&DerivationPath::master(), Network::Testnet).is_ok());
+    }
+
+    #[test]
+    fn test_normalize_xpub() {
+        let tpub = Xpub::from_str("tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP").unwrap();
+        let xpub = normalize_xpub(&tpub, Network::Bitcoin);
+        assert!(xpub.to_string().starts_with("xpub"));
+        assert_eq!(xpub.public_key, tpub.public_key);
+        assert_eq!(normalize_xpub(&xpub, Network::Signet), tpub);
+    }
+
+    #[test]
+    fn test_check_address_network() {
+        let address = |s| Address::from_str(s).unwrap();
+        let testnet = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        assert!(check_address_network(address(testnet), Network::Testnet).is_ok());
+        assert!(check_address_network(address(testnet), Network::Regtest).is_ok());
+        assert!(check_address_network(
+            address("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"),
+            Network::Regtest
+        )
+        .is_ok());
+        assert!(matches!(
+            check_address_network(address(testnet), Network::Bitcoin),
+            Err(Error::NetworkMismatch)
+        ));
+        assert!(matches!(
+            check_address_network(
+                address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+                Network::Regtest
+            ),
+            Err(Error::NetworkMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_bip44_path_child_numbers() {
+        let path = DerivationPath::from_str("m/84'/1'/0'/1/3").unwrap();
+        assert_eq!(bip44_path_child_numbers(path.clone(), 84).unwrap().len(), 5);
+        assert!(bip44_path_child_numbers(path, 49).is_err());
+        assert!(bip44_path_child_numbers(
+            DerivationPath::from_str("m/84'/1'/0'/1'/3").unwrap(),
+            84
+        )
+        .is_err());
+        assert!(
+            bip44_path_child_numbers(DerivationPath::from_str("m/84'/1'/0'").unwrap(), 84).is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_key_with_origin() {
+        let (origin, xpub) = parse_key_with_origin("[f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP").unwrap();
+        assert_eq!(
+            origin,
+            Some((
+                Fingerprint::from_str("f5acc2fd").unwrap(),
+                DerivationPath::from_str("m/49'/1'/0'").unwrap()
+            ))
+        );
+        assert_eq!(xpub.to_string(), "tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP");
+
+        let (origin, _) = parse_key_with_origin("tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S").unwrap();
+        assert!(origin.is_none());
+
+        assert!(parse_key_with_origin("[f5acc2fd/49'/1'/0'tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S").is_err());
+    }
+
+    #[test]
+    fn test_is_usual_path() {
+        let path = |s| DerivationPath::from_str(s).unwrap();
+        assert!(is_usual_path(&path("m/84'/0'/0'")));
+        assert!(is_usual_path(&path("m/86'/1'/100'/1/50000")));
+        assert!(is_usual_path(&path("m/48'/1'/0'/2'")));
+        assert!(is_usual_path(&path("m/48'/1'/0'/1'/0/3")));
+        assert!(!is_usual_path(&path("m")));
+        assert!(!is_usual_path(&path("m/84'/0'")));
+        assert!(!is_usual_path(&path("m/84'/0'/101'")));
+        assert!(!is_usual_path(&path("m/84'/2'/0'")));
+        assert!(!is_usual_path(&path("m/84'/0'/0'/2")));
+        assert!(!is_usual_path(&path("m/84'/0'/0'/0/50001")));
+        assert!(!is_usual_path(&path("m/84/0/0")));
+        assert!(!is_usual_path(&path("m/48'/1'/0'/3'")));
+        assert!(!is_usual_path(&path("m/0'/1'/0'")));
+    }
+
+    #[test]
+    fn test_validate_wallet_name() {
+        assert!(validate_wallet_name(DeviceKind::Ledger, "Cold storage").is_ok());
+        assert!(validate_wallet_name(DeviceKind::Ledger, &"a".repeat(64)).is_ok());
+        assert!(validate_wallet_name(DeviceKind::Ledger, &"a".repeat(65)).is_err());
+        assert!(validate_wallet_name(DeviceKind::Ledger, "").is_err());
+        assert!(validate_wallet_name(DeviceKind::Ledger, "épargne").is_err());
+        assert!(validate_wallet_name(DeviceKind::Ledger, "vault ").is_err());
+        assert!(validate_wallet_name(DeviceKind::BitBox02, &"a".repeat(31)).is_err());
+        assert!(validate_wallet_name(DeviceKind::Specter, "épargne ").is_ok());
+        assert!(validate_wallet_name(DeviceKind::Specter, "").is_err());
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        // Test vectors of BIP380.
+        assert_eq!(
+            descriptor_checksum("raw(deadbeef)").as_deref(),
+            Some("89f8spxm")
+        );
+        assert!(strip_checksum("raw(deadbeef)#89f8spxm").is_ok());
+        assert!(strip_checksum("raw(deadbeef)#89f8spxn").is_err());
+        assert!(strip_checksum("raw(Ü)#00000000").is_err());
+        assert_eq!(strip_checksum("raw(deadbeef)").unwrap(), "raw(deadbeef)");
+
+        assert_eq!(
+            add_checksum("raw(deadbeef)").unwrap(),
+            "raw(deadbeef)#89f8spxm"
+        );
+        assert_eq!(
+            add_checksum("raw(deadbeef)#89f8spxm").unwrap(),
+            "raw(deadbeef)#89f8spxm"
+        );
+        assert!(add_checksum("raw(deadbeef)#89f8spxn").is_err());
+        assert!(add_checksum("raw(Ü)").is_err());
+    }
+
+    #[cfg(feature = "regex")]
     #[test]
     fn test_extract_keys_and_template() {
         let res = extract_keys_and_template::<String>("wsh(or_d(pk([f5acc2fd/49'/1'/0']tpubDCbK3Ysvk8HjcF6mPyrgMu3KgLiaaP19RjKpNezd8GrbAbNg6v5BtWLaCt8FNm6QkLseopKLf5MNYQFtochDTKHdfgG6iqJ8cqnLNAwtXuP/**),and_v(v:pkh(tpubDDtb2WPYwEWw2WWDV7reLV348iJHw2HmhzvPysKKrJw3hYmvrd4jasyoioVPdKGQqjyaBMEvTn1HvHWDSVqQ6amyyxRZ5YjpPBBGjJ8yu8S/**),older(100))))").unwrap();
@@ -189,4 +940,261 @@ mod tests {
         assert_eq!(res.1[1], "[7fc39c07/48'/1'/0'/2']tpubDEvjgXtrUuH3Qtkapny9aE8gN847xiXsf9MDM5XueGf9nrvStqAuBSva3ajGyTvtp8Ti55FvVXsgYSXuS1tQkBeopFuodx2hRUDmQbvKxbZ".to_string());
         assert_eq!(res.1[2], "[1a1ffd98/48'/1'/0'/2']tpubDFZqzTvGijYb13BC73CkS1er8DrP5YdzMhziN3kWCKUFaW51Yj6ggvf99YpdrkTJy4RT85mxQMHXDiFAKRxzf6BykQgT4pRRBNPshSJJcKo".to_string());
     }
+
+    #[test]
+    fn test_check_op_return() {
+        use bitcoin::{transaction, ScriptBuf, Transaction, TxOut};
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(95_000),
+                    script_pubkey: ScriptBuf::new(),
+                },
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: ScriptBuf::new_op_return(b"data"),
+                },
+            ],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(op_return_outputs(&psbt), vec![1]);
+
+        let version = crate::Version::new(2, 1, 0);
+        let ledger = Capabilities::new(DeviceKind::Ledger, Some(&version));
+        assert!(check_op_return(&ledger, &psbt).is_ok());
+        let bitbox = Capabilities::new(DeviceKind::BitBox02, None);
+        assert!(matches!(
+            check_op_return(&bitbox, &psbt),
+            Err(Error::InvalidParameter("psbt", _))
+        ));
+    }
+
+    #[test]
+    fn test_review_warnings() {
+        use bitcoin::{
+            transaction, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::from_height(800_000).unwrap(),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(95_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new(),
+        });
+        assert_eq!(
+            review_warnings(&psbt),
+            vec![SignWarning::LockTime(
+                LockTime::from_height(800_000).unwrap()
+            )]
+        );
+
+        psbt.unsigned_tx.lock_time = LockTime::ZERO;
+        psbt.unsigned_tx.input[0].sequence = Sequence::from_height(10);
+        psbt.unsigned_tx.output[0].value = Amount::from_sat(80_000);
+        assert_eq!(
+            review_warnings(&psbt),
+            vec![
+                SignWarning::RelativeLockTime {
+                    input: 0,
+                    sequence: Sequence::from_height(10),
+                },
+                SignWarning::HighFee {
+                    fee: Amount::from_sat(20_000),
+                    spent: Amount::from_sat(100_000),
+                },
+            ]
+        );
+
+        // Without the spent output the fee is unknown.
+        psbt.inputs[0].witness_utxo = None;
+        assert_eq!(review_warnings(&psbt).len(), 1);
+        let key = PublicKey::from_str(
+            "0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c",
+        )
+        .unwrap();
+        let path = |s| DerivationPath::from_str(s).unwrap();
+        let wpkh = ScriptBuf::new_p2wpkh(&bitcoin::PublicKey::new(key).wpubkey_hash().unwrap());
+        psbt.unsigned_tx.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        psbt.unsigned_tx.output[0].script_pubkey = wpkh.clone();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(81_000),
+            script_pubkey: wpkh,
+        });
+        let fg = Fingerprint::default();
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(key, (fg, path("m/84'/1'/0'/0/3")));
+        psbt.outputs[0]
+            .bip32_derivation
+            .insert(key, (fg, path("m/84'/1'/0'/1/3")));
+        assert!(review_warnings(&psbt).is_empty());
+
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(key, (fg, path("m/49'/1'/0'/0/3")));
+        psbt.outputs[0]
+            .bip32_derivation
+            .insert(key, (fg, path("m/84'/1'/0'/1/50001")));
+        assert_eq!(
+            review_warnings(&psbt),
+            vec![
+                SignWarning::UnusualPath {
+                    input: 0,
+                    path: path("m/49'/1'/0'/0/3"),
+                },
+                SignWarning::UnusualChange {
+                    output: 0,
+                    path: path("m/84'/1'/0'/1/50001"),
+                },
+            ]
+        );
+
+        // Amounts overflowing the fee ratio and the sum of the outputs.
+        let high_fee = |psbt: &Psbt| {
+            review_warnings(psbt)
+                .into_iter()
+                .filter(|w| matches!(w, SignWarning::HighFee { .. }))
+                .count()
+        };
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value = Amount::from_sat(u64::MAX);
+        psbt.unsigned_tx.output[0].value = Amount::from_sat(u64::MAX / 2);
+        assert_eq!(high_fee(&psbt), 1);
+        psbt.unsigned_tx
+            .output
+            .push(psbt.unsigned_tx.output[0].clone());
+        psbt.unsigned_tx
+            .output
+            .push(psbt.unsigned_tx.output[0].clone());
+        psbt.outputs.push(Default::default());
+        psbt.outputs.push(Default::default());
+        assert_eq!(high_fee(&psbt), 0);
+    }
+
+    /// Device answering only with its fingerprint, None if locked.
+    fn cosigner(fingerprint: Option<Fingerprint>) -> MockDevice {
+        MockDevice {
+            fingerprint: fingerprint.ok_or(Error::DeviceLocked { code: None }),
+            ..MockDevice::new(crate::DeviceKind::Ledger)
+        }
+    }
+
+    #[test]
+    fn test_find_device_by_fingerprint() {
+        let fg = |s| Fingerprint::from_str(s).unwrap();
+        let devices = || {
+            vec![
+                cosigner(None),
+                cosigner(Some(fg("f5acc2fd"))),
+                cosigner(Some(fg("b0822927"))),
+            ]
+        };
+        let device = find_device_by_fingerprint(devices(), fg("b0822927")).unwrap();
+        assert_eq!(device.get_master_fingerprint().unwrap(), fg("b0822927"));
+        assert!(find_device_by_fingerprint(devices(), fg("00000000")).is_none());
+
+        // Boxed devices of different backends.
+        let boxed = devices()
+            .into_iter()
+            .map(|d| Box::new(d) as Box<dyn HWI + Send>);
+        assert!(find_device_by_fingerprint(boxed, fg("f5acc2fd")).is_some());
+    }
+
+    #[test]
+    fn test_sanitize_for() {
+        use bitcoin::{
+            key::{Secp256k1, UntweakedPublicKey},
+            psbt::raw,
+            transaction, ScriptBuf, Transaction, TxIn, TxOut,
+        };
+
+        let prevout = |script_pubkey: ScriptBuf| TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        };
+        let secp = Secp256k1::verification_only();
+        let internal_key = UntweakedPublicKey::from_str(
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let p2tr = ScriptBuf::new_p2tr(&secp, internal_key, None);
+        let p2wpkh = ScriptBuf::new_p2wpkh(
+            &bitcoin::PublicKey::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap()
+            .wpubkey_hash()
+            .unwrap(),
+        );
+        let prev_tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![prevout(p2tr.clone()), prevout(p2wpkh.clone())],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![prevout(ScriptBuf::new())],
+        })
+        .unwrap();
+        for (input, script_pubkey) in psbt.inputs.iter_mut().zip([p2tr, p2wpkh]) {
+            input.witness_utxo = Some(prevout(script_pubkey));
+            input.non_witness_utxo = Some(prev_tx.clone());
+        }
+        let key = raw::Key {
+            type_value: 0xf0,
+            key: vec![0; 1000],
+        };
+        psbt.unknown.insert(key.clone(), vec![0; 1000]);
+        psbt.inputs[0].unknown.insert(key.clone(), vec![0; 1000]);
+        psbt.outputs[0].unknown.insert(key, vec![0; 1000]);
+
+        let size = psbt.serialize().len();
+        let original = sanitize_for(DeviceKind::Jade, &mut psbt);
+        assert_eq!(original.serialize().len(), size);
+        assert!(psbt.serialize().len() < size);
+        assert!(psbt.unknown.is_empty());
+        assert!(psbt.inputs[0].unknown.is_empty());
+        assert!(psbt.outputs[0].unknown.is_empty());
+        // Only the segwit v0 input requires the previous transaction.
+        assert!(psbt.inputs[0].non_witness_utxo.is_none());
+        assert!(psbt.inputs[1].non_witness_utxo.is_some());
+
+        // Signatures of the device are merged back in the original.
+        let mut original = original;
+        psbt.inputs[0].tap_key_sig =
+            Some(bitcoin::taproot::Signature::from_slice(&[1; 64]).unwrap());
+        merge_signatures(&mut original, &psbt);
+        assert!(original.inputs[0].tap_key_sig.is_some());
+        assert!(original.inputs[0].non_witness_utxo.is_some());
+        assert!(!original.unknown.is_empty());
+    }
+
+    #[test]
+    fn test_udev_rules() {
+        let rules = udev_rules();
+        assert!(rules.contains(
+            "SUBSYSTEMS==\"usb\", KERNEL==\"hidraw*\", ATTRS{idVendor}==\"2c97\", TAG+=\"uaccess\"\n"
+        ));
+        assert!(rules.contains("ATTRS{idVendor}==\"03eb\", ATTRS{idProduct}==\"2403\""));
+        assert_eq!(rules.lines().filter(|l| l.starts_with('#')).count(), 8);
+    }
 }